@@ -0,0 +1,57 @@
+#ifndef SKYRIM_SEARCH_SE_H
+#define SKYRIM_SEARCH_SE_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#include <stdbool.h>
+#include <stdint.h>
+
+// What the functions that other plugins find with `GetProcAddress` return. Unlike requests
+// through the messaging interface, they may be called from any thread, and the result lives
+// until `SkyrimSearch_FreeResult`.
+typedef struct SkyrimSearchResult {
+  uint32_t version;
+  // UTF-8, null on success
+  char *error;
+  // `{"columns": [...], "rows": [[...], ...]}`, null on error
+  char *json;
+} SkyrimSearchResult;
+
+// Whether this DLL implements version `version` of the functions, and can answer queries.
+bool SkyrimSearch_Init(uint32_t version);
+
+// Runs the read-only statement `sql`, as `ss raw` without `--write` does.
+//
+// # Safety
+//
+// `sql` must be null or a NUL-terminated string.
+struct SkyrimSearchResult *SkyrimSearch_Query(const char *sql);
+
+// Searches `terms` as `ss grep` does, only among the forms of `kind` (e.g. `npc`) unless it is
+// null.
+//
+// # Safety
+//
+// `terms` and `kind` must be null or NUL-terminated strings.
+struct SkyrimSearchResult *SkyrimSearch_Search(const char *terms, const char *kind);
+
+// Writes what the index knows of `form_id` to `buffer` as a NUL-terminated UTF-8 string, e.g.
+// `npc HousecarlWhiterun "Lydia" (Skyrim.esm)`, truncated to `buffer_len` bytes. Returns its
+// length without the NUL, or 0 if the form is not indexed.
+//
+// Meant for crash loggers as they handle an exception: it allocates no result, and returns 0
+// rather than waiting if the database is in use, as it may be by the thread that crashed.
+//
+// # Safety
+//
+// `buffer` must be null or point to `buffer_len` writable bytes.
+uintptr_t SkyrimSearch_DescribeForm(uint32_t form_id, char *buffer, uintptr_t buffer_len);
+
+// Frees a result of `SkyrimSearch_Query` or `SkyrimSearch_Search`.
+//
+// # Safety
+//
+// `result` must be null or a result that has not been freed yet.
+void SkyrimSearch_FreeResult(struct SkyrimSearchResult *result);
+
+#endif /* SKYRIM_SEARCH_SE_H */
@@ -0,0 +1,261 @@
+use crate::config::CONFIG;
+use crate::{console, log, pattern};
+use anyhow::Context;
+use late_static::LateStatic;
+use std::collections::HashMap;
+
+/// Where the Address Library for SKSE Plugins installs its databases.
+const ADDRESS_LIBRARY_DIR: &str = "Data\\SKSE\\Plugins";
+
+/// The first runtime whose game structures have the layouts this plugin reads.
+const MIN_RUNTIME_VERSION: u32 = make_version(1, 6, 629, 0);
+
+/// The one runtime of Skyrim VR.
+const VR_RUNTIME_VERSION: u32 = make_version(1, 4, 15, 0);
+/// The VR Address Library for SKSEVR, which has the offsets in VR of the address ids of SE 1.5.97.
+const VR_ADDRESS_LIBRARY: &str = "version-1-4-15-0.csv";
+
+/// Every ID of the `versionlib!` calls. They are checked before any hook is installed, so that an
+/// Address Library without one of them does not leave the hooks half installed.
+const ADDRESS_IDS: [u64; 23] = [
+    11141, 14617, 14720, 16040, 25259, 51109, 68115, 68117, 69161, 69192, 104480, 104481, 190143,
+    190259, 195816, 195890, 207886, 400269, 400507, 401203, 402776, 403521, 441582,
+];
+
+/// The address ids of `ADDRESS_IDS` as SE 1.5.97 numbers them, which the VR Address Library does.
+const VR_IDS: [(u64, u64); 16] = [
+    (11141, 11045),   // MemoryManager::GetSingleton
+    (14617, 14461),   // TESForm::LookupByID
+    (16040, 15802),   // TESObjectREFR::GetInventoryChanges
+    (51109, 50179),   // ConsoleLog::Print
+    (68115, 66859),   // MemoryManager::Allocate
+    (68117, 66861),   // MemoryManager::Deallocate
+    (69161, 67819),   // BSStringPool::GetEntry<char>
+    (69192, 67847),   // BSStringPool::Entry::release
+    (104480, 97742),  // BSScript::Stack::GetPageForFrame
+    (104481, 97743),  // BSScript::Stack::GetStackFrameVariable
+    (400269, 514141), // TESDataHandler singleton
+    (400507, 514351), // TESForm::allForms
+    (401203, 515064), // ConsoleLog singleton
+    (402776, 516574), // BSInputDeviceManager singleton
+    (403521, 517014), // PlayerCharacter singleton
+    (441582, 21416),  // Script::CompileAndRunImpl
+];
+
+/// The ids of `ADDRESS_IDS` that SE 1.5.97 has no ids for, which VR goes without unless
+/// `offsets.ids` gives them: the names are read from the forms instead, see `TESForm::get_name`,
+/// and the log entries of quests have no texts.
+const VR_OPTIONAL_IDS: [u64; 2] = [14720, 25259];
+
+/// The vtables of `ADDRESS_IDS` by the RTTI names of their classes, by which they are found in VR.
+const VTABLE_IDS: [(u64, &str); 5] = [
+    (190143, ".?AVTESObjectCELL@@"),
+    (190259, ".?AVTESObjectREFR@@"),
+    (195816, ".?AVTESNPC@@"),
+    (195890, ".?AVTESQuest@@"),
+    (207886, ".?AVCharacter@@"),
+];
+
+/// The executables whose addresses this plugin knows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Profile {
+    /// SE 1.6.629 or later, with the Address Library for SKSE Plugins.
+    Ae,
+    /// VR 1.4.15, with the VR Address Library and the vtables found by RTTI.
+    Vr,
+}
+
+struct State {
+    profile: Profile,
+    /// The offsets of the address ids in the running game, unless it is the version in
+    /// `target_version.txt`, whose offsets are compiled in.
+    offsets: Option<HashMap<u64, u64>>,
+    runtime_version: u32,
+    /// Where `data` was loaded from.
+    path: Option<String>,
+}
+static S: LateStatic<State> = LateStatic::new();
+
+/// Packs a version as SKSE does.
+const fn make_version(major: u32, minor: u32, build: u32, sub: u32) -> u32 {
+    (major << 24) | ((minor & 0xFF) << 16) | ((build & 0xFFF) << 4) | (sub & 0xF)
+}
+
+/// Formats a version packed as SKSE does, e.g. `1.6.1170.0`.
+pub(crate) fn format_version(version: u32, separator: &str) -> String {
+    [
+        version >> 24,
+        (version >> 16) & 0xFF,
+        (version >> 4) & 0xFFF,
+        version & 0xF,
+    ]
+    .map(|part| part.to_string())
+    .join(separator)
+}
+
+/// The profile of the runtime that SKSE reports, or why it has none.
+pub(crate) fn profile(runtime_version: u32, is_editor: bool) -> anyhow::Result<Profile> {
+    if is_editor {
+        anyhow::bail!("the Creation Kit is not supported");
+    }
+    if runtime_version == VR_RUNTIME_VERSION {
+        Ok(Profile::Vr)
+    } else if runtime_version >= MIN_RUNTIME_VERSION {
+        Ok(Profile::Ae)
+    } else {
+        anyhow::bail!(
+            "Skyrim {} is not supported: {} or later, or VR {}, is required",
+            format_version(runtime_version, "."),
+            format_version(MIN_RUNTIME_VERSION, "."),
+            format_version(VR_RUNTIME_VERSION, ".")
+        );
+    }
+}
+
+/// Loads the address database of `runtime_version`, the version of the running game, unless the
+/// offsets compiled in are for it. Fails if the version has no profile, or the database lacks an
+/// id that `offsets` in the config does not give either.
+pub(crate) fn init(runtime_version: u32, is_editor: bool, image_base: usize) -> anyhow::Result<()> {
+    let profile = profile(runtime_version, is_editor)?;
+    let (offsets, path) = match profile {
+        Profile::Ae if runtime_version == versionlib_macro::target_version!() => (None, None),
+        Profile::Ae => {
+            let path = format!(
+                "{}\\versionlib-{}.bin",
+                ADDRESS_LIBRARY_DIR,
+                format_version(runtime_version, "-")
+            );
+            let data = versionlib::load(&path).with_context(|| {
+                format!(
+                    "the Address Library for {} is not installed: {}",
+                    format_version(runtime_version, "."),
+                    path
+                )
+            })?;
+            (Some(data.data.into_iter().collect()), Some(path))
+        }
+        Profile::Vr => {
+            let path = format!("{}\\{}", ADDRESS_LIBRARY_DIR, VR_ADDRESS_LIBRARY);
+            let offsets = unsafe { load_vr(&path, image_base) }?;
+            (Some(offsets), Some(path))
+        }
+    };
+    if let (Some(offsets), Some(path)) = (&offsets, &path) {
+        let overridden = console::overridden_ids();
+        let missing = ADDRESS_IDS
+            .iter()
+            .filter(|id| {
+                !offsets.contains_key(*id)
+                    && !overridden.contains(*id)
+                    && configured(**id).is_none()
+                    && !(profile == Profile::Vr && VR_OPTIONAL_IDS.contains(*id))
+            })
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Skyrim {} is not supported: {} lacks the address ids {}; offsets.ids gives them",
+                format_version(runtime_version, "."),
+                path,
+                missing.join(", ")
+            );
+        }
+        log::info(format!("address: loaded {}", path).as_str());
+    }
+    unsafe {
+        LateStatic::assign(
+            &S,
+            State {
+                profile,
+                offsets,
+                runtime_version,
+                path,
+            },
+        )
+    };
+    Ok(())
+}
+
+/// Loads the offsets of `ADDRESS_IDS` from the VR Address Library at `path`, and finds the vtables
+/// of `VTABLE_IDS` in the executable at `image_base`.
+unsafe fn load_vr(path: &str, image_base: usize) -> anyhow::Result<HashMap<u64, u64>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("the VR Address Library is not installed: {}", path))?;
+    let library = parse_vr_library(&text).with_context(|| path.to_string())?;
+    let mut offsets = VR_IDS
+        .iter()
+        .filter_map(|&(id, se_id)| library.get(&se_id).map(|&offset| (id, offset)))
+        .collect::<HashMap<_, _>>();
+    for &(id, name) in &VTABLE_IDS {
+        match pattern::find_vtable(image_base, name) {
+            Ok(offset) => {
+                offsets.insert(id, offset as u64);
+            }
+            Err(err) => log::warn(format!("address: {:#}", err).as_str()),
+        }
+    }
+    Ok(offsets)
+}
+
+/// Parses the VR Address Library, whose first line has the number of ids and the version, and
+/// whose other lines each have an id and its offset in hex.
+fn parse_vr_library(text: &str) -> anyhow::Result<HashMap<u64, u64>> {
+    text.lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (id, offset) = line
+                .split_once(',')
+                .with_context(|| format!("not an id and an offset: {}", line))?;
+            let id = id
+                .trim()
+                .parse()
+                .with_context(|| format!("not an id: {}", id))?;
+            let offset = offset.trim().trim_start_matches("0x");
+            let offset = u64::from_str_radix(offset, 16)
+                .with_context(|| format!("not an offset: {}", offset))?;
+            Ok((id, offset))
+        })
+        .collect()
+}
+
+/// The offset of the address id `id` that `offsets.ids` in the config gives.
+fn configured(id: u64) -> Option<usize> {
+    CONFIG.offsets.ids.get(&id.to_string()).copied()
+}
+
+/// The offset of the Address Library ID `id` from the image base in the running game. `compiled`
+/// is its offset in the version of `target_version.txt`.
+pub(crate) fn offset(id: u64, compiled: usize) -> anyhow::Result<usize> {
+    if let Some(offset) = configured(id) {
+        return Ok(offset);
+    }
+    match &S.offsets {
+        None => Ok(compiled),
+        Some(offsets) => offsets
+            .get(&id)
+            .map(|&offset| offset as usize)
+            .with_context(|| format!("address id {} is not in the Address Library", id)),
+    }
+}
+
+/// The profile of the running game.
+pub(crate) fn current_profile() -> Profile {
+    S.profile
+}
+
+/// The version of the running game.
+pub(crate) fn runtime_version() -> u32 {
+    S.runtime_version
+}
+
+/// Where the offsets come from, for `ss version`.
+pub(crate) fn describe() -> String {
+    match &S.path {
+        None => format!(
+            "compiled in for {}",
+            format_version(versionlib_macro::target_version!(), ".")
+        ),
+        Some(path) => path.clone(),
+    }
+}
@@ -0,0 +1,180 @@
+use crate::config::CONFIG;
+use crate::{app, console, db};
+use anyhow::Context;
+use rusqlite::types::{Value, ValueRef};
+use serde::Deserialize;
+use std::ffi::{CStr, CString};
+use std::ptr;
+use winapi::ctypes::{c_char, c_void};
+
+/// The message type of a `Request`, which other plugins dispatch to "Skyrim Search SE".
+pub(crate) const MESSAGE_REQUEST: u32 = 0x5353_5101;
+const API_VERSION: u32 = 1;
+
+/// `Request::kind`: `text` is an SQL statement, or the terms of a search as by `ss grep`.
+const KIND_SQL: u32 = 0;
+const KIND_SEARCH: u32 = 1;
+
+type Callback = extern "C" fn(*const Response, *mut c_void);
+
+#[repr(C)]
+struct Request {
+    version: u32,
+    kind: u32,                  // 04
+    text: *const c_char,        // 08, UTF-8
+    callback: Option<Callback>, // 10
+    context: *mut c_void,       // 18, passed to the callback
+}
+
+/// What the callback gets. The strings are freed once it returns.
+#[repr(C)]
+struct Response {
+    version: u32,
+    error: *const c_char, // 08, null on success
+    json: *const c_char,  // 10, null on error
+}
+
+/// Runs the request of another plugin, and passes the result to its callback before `Dispatch`
+/// returns to it.
+pub(crate) unsafe fn on_request(data: *const c_void, data_len: u32) {
+    if (data_len as usize) < std::mem::size_of::<Request>() {
+        return;
+    }
+    let request = match (data as *const Request).as_ref() {
+        Some(request) => request,
+        None => return,
+    };
+    let callback = match request.callback {
+        Some(callback) => callback,
+        None => return,
+    };
+    let result: anyhow::Result<String> = (|| {
+        if request.version != API_VERSION {
+            anyhow::bail!("unsupported version {}", request.version);
+        }
+        if request.text.is_null() {
+            anyhow::bail!("no text");
+        }
+        let text = CStr::from_ptr(request.text).to_str().context("text")?;
+        match request.kind {
+            KIND_SQL => query_json(text, &[]),
+            KIND_SEARCH => search_json(text, None),
+            kind => anyhow::bail!("unknown kind {}", kind),
+        }
+    })();
+    let (json, error) = match result {
+        Ok(json) => (CString::new(json).ok(), None),
+        Err(err) => (None, CString::new(format!("{:#}", err)).ok()),
+    };
+    let response = Response {
+        version: API_VERSION,
+        error: error.as_ref().map_or(ptr::null(), |error| error.as_ptr()),
+        json: json.as_ref().map_or(ptr::null(), |json| json.as_ptr()),
+    };
+    callback(&response, request.context);
+}
+
+/// A request of the pipe and the HTTP server: `{"sql": "SELECT ..."}`, or `{"search": "terms"}` to
+/// search as `ss grep` does, optionally with `"kind": "npc"` to search only NPCs.
+#[derive(Deserialize)]
+struct JsonRequest {
+    sql: Option<String>,
+    search: Option<String>,
+    kind: Option<String>,
+}
+
+/// The rows that a request in JSON asks for, as `query_json` returns them.
+pub(crate) fn answer(request: &str) -> anyhow::Result<String> {
+    let request: JsonRequest = serde_json::from_str(request).context("request")?;
+    match (request.sql, request.search) {
+        (Some(sql), None) => query_json(&sql, &[]),
+        (None, Some(search)) => search_json(&search, request.kind.as_deref()),
+        _ => anyhow::bail!("request: either sql or search is required"),
+    }
+}
+
+/// Answers a request in JSON as `answer` does, or with `{"error": "..."}`.
+pub(crate) fn answer_json(request: &str) -> String {
+    answer(request).unwrap_or_else(|err| error_json(&err))
+}
+
+pub(crate) fn error_json(err: &anyhow::Error) -> String {
+    serde_json::json!({ "error": format!("{:#}", err) }).to_string()
+}
+
+/// The forms matching `terms` as `ss grep` finds them, as `query_json` returns them. With `kind`,
+/// only the forms of that kind, e.g. `npc`, `cell` or `quest`.
+pub(crate) fn search_json(terms: &str, kind: Option<&str>) -> anyhow::Result<String> {
+    let mut values = vec![
+        Value::from(db::fts_query(terms)),
+        Value::from(db::substring_query(terms)),
+    ];
+    values.extend(kind.map(|kind| Value::from(kind.to_string())));
+    query_json(&app::grep_sql(kind.is_some()), &values)
+}
+
+/// The rows of `sql` as `{"columns": [...], "rows": [[...], ...]}`. Statements that would modify
+/// the database fail, and so do those that run longer than `query_timeout_secs`.
+pub(crate) fn query_json(sql: &str, values: &[Value]) -> anyhow::Result<String> {
+    if !console::is_enabled() {
+        anyhow::bail!("Skyrim Search SE is disabled");
+    }
+    let db = db::lock()?;
+    let _guard = db::ReadOnlyGuard::new(&db);
+    let _timeout = db::QueryTimeout::start(CONFIG.query_timeout());
+    let mut stmt = db.prepare(sql).context("prepare error")?;
+    let columns = stmt
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut rows = stmt.query(values).context("query error")?;
+    let mut json_rows = vec![];
+    while let Some(row) = rows.next().context("rows.next() error")? {
+        json_rows.push(
+            (0..row.column_count())
+                .map(|i| json_value(row.get_raw(i)))
+                .collect::<serde_json::Value>(),
+        );
+    }
+    Ok(serde_json::json!({ "columns": columns, "rows": json_rows }).to_string())
+}
+
+/// The first column of the rows of `sql`, of the first `limit` rows if given, checked as
+/// `query_json` checks its statements.
+pub(crate) fn query_column(sql: &str, limit: Option<usize>) -> anyhow::Result<Vec<Value>> {
+    if !console::is_enabled() {
+        anyhow::bail!("Skyrim Search SE is disabled");
+    }
+    let db = db::lock()?;
+    let _guard = db::ReadOnlyGuard::new(&db);
+    let _timeout = db::QueryTimeout::start(CONFIG.query_timeout());
+    let mut stmt = db.prepare(sql).context("prepare error")?;
+    if stmt.column_count() == 0 {
+        anyhow::bail!("the statement returns no columns");
+    }
+    let mut rows = stmt.query(rusqlite::NO_PARAMS).context("query error")?;
+    let mut values = vec![];
+    while let Some(row) = rows.next().context("rows.next() error")? {
+        if limit.map_or(false, |limit| values.len() >= limit) {
+            break;
+        }
+        values.push(row.get::<_, Value>(0)?);
+    }
+    Ok(values)
+}
+
+/// Blobs become strings of hex digits.
+pub(crate) fn json_value(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(v) => v.into(),
+        ValueRef::Real(v) => v.into(),
+        ValueRef::Text(v) => String::from_utf8_lossy(v).into(),
+        ValueRef::Blob(v) => v
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+            .into(),
+    }
+}
@@ -1,24 +1,49 @@
-use crate::db::Job;
+use crate::config::CONFIG;
+use crate::db::{Job, TaskQueue};
 use crate::form::qust::TESQuest;
 use crate::form::TESForm;
 use crate::log::Loggable;
-use crate::{console, db};
+use crate::{
+    address, api, command, config, console, db, form, log, messages, modevent, patch, path,
+    plugins, records, strings, task,
+};
 use anyhow::{anyhow, Context};
-use clap::{AppSettings, Arg, SubCommand};
+use clap::{AppSettings, Arg};
 use late_static::LateStatic;
-use rusqlite::params;
-use rusqlite::types::ValueRef;
-use rusqlite::{Statement, NO_PARAMS};
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Condvar, Mutex};
+use rusqlite::types::Value;
+use rusqlite::{params, OptionalExtension, Statement, StatementStatus, NO_PARAMS};
+use ss_db::function;
+use ss_db::table::{convert_row, hex_ids, repr_column, rows_table, titled_table};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 pub(crate) enum ProcessResult {
     Processed,
     Fallback,
     FallbackAndPrintUsage,
+    /// Lets the game process the command, and prints the message after its output.
+    FallbackAndPrint(String),
+    /// Runs the lines of a batch file one by one, as if they were entered in the console, each
+    /// with the reference of its form id selected if it has one. `file` is the path of the batch
+    /// file, or `None` for the commands of `--exec`.
+    Batch {
+        file: Option<String>,
+        lines: Vec<(String, Option<u32>)>,
+    },
 }
 
+const SCRIPTS_DIR: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE\\scripts";
+const IMPORTS_DIR: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE\\imports";
+const DATABASES_DIR: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE\\databases";
+const DUMPS_DIR: &str = "\\SKSE\\dumps";
+
+/// The default of `command_prefixes`.
 pub const SKYRIM_SEARCH_COMMANDS: [&str; 4] = ["ss", "sss", "skyrimsearch", "skyrimsearchse"];
+/// The game's command that runs the lines of a text file in the game folder.
+const BATCH_COMMANDS: [&str; 2] = ["bat", "runbatchscript"];
 
 pub fn get_clap<'a, 'b>() -> clap::App<'a, 'b> {
     clap::App::new("skyrim-search-se")
@@ -28,339 +53,828 @@ pub fn get_clap<'a, 'b>() -> clap::App<'a, 'b> {
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .arg(Arg::with_name("debug").long("debug").global(true))
-        .subcommand(
-            SubCommand::with_name("raw")
-                .about("execute raw query. quote your query as in unix shell if needed.")
-                .setting(AppSettings::TrailingVarArg)
-                .arg(
-                    Arg::with_name("sql")
-                        .help("SQLite SQL")
-                        .required(true)
-                        .multiple(true),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name("npc")
-                .alias("npcs")
-                .about("search npc/reference")
-                .arg(
-                    Arg::with_name("query")
-                        .help("search query (e.g. name, edid, form_id, ref_id)")
-                        .required(true)
-                        .multiple(true),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name("cell")
-                .alias("cells")
-                .about("search cell (location)")
-                .arg(
-                    Arg::with_name("query")
-                        .help("search query (e.g. name, edid, form_id)")
-                        .required(true)
-                        .multiple(true),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name("quest")
-                .alias("quests")
-                .about("search quest")
-                .arg(
-                    Arg::with_name("query")
-                        .help("search query (e.g. name, edid, form_id)")
-                        .required(true)
-                        .multiple(true),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name("quest_stage")
-                .alias("quest_stages")
-                .alias("qs")
-                .alias("queststage")
-                .alias("queststages")
-                .about("search quest (prints additional stage information)")
-                .arg(
-                    Arg::with_name("query")
-                        .help("search query (e.g. name, edid, form_id)")
-                        .required(true)
-                        .multiple(true),
-                ),
-        )
+        .subcommands(ss_db::cli::subcommands())
 }
 
 struct State {
-    task_queue: Sender<Job>,
+    task_queue: TaskQueue,
 }
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
 
-pub(crate) fn process_console_input(input: &str) -> anyhow::Result<ProcessResult> {
-    if input.is_empty() {
+/// The line of the last command that failed and its error in full, with the causes and the
+/// backtrace if there is one, for `ss lasterror`.
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+pub(crate) fn process_console_input(
+    input: &str,
+    selected: Option<u32>,
+) -> anyhow::Result<ProcessResult> {
+    let result = process_input(input, selected);
+    if let Err(err) = &result {
+        let is_help = err.downcast_ref::<clap::Error>().map_or(false, |err| {
+            matches!(
+                err.kind,
+                clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed
+            )
+        });
+        if !is_help {
+            if let Ok(mut last_error) = LAST_ERROR.lock() {
+                *last_error = Some(format!("> {}\n{:?}", input, err));
+            }
+        }
+    }
+    result
+}
+
+thread_local! {
+    /// The parser of the commands, built once per thread rather than for every line.
+    static CLAP: RefCell<clap::App<'static, 'static>> = RefCell::new(get_clap());
+    /// The form id of the reference selected in the console as the command runs, which `:sel`
+    /// stands for in its SQL.
+    static SELECTED: Cell<Option<u32>> = Cell::new(None);
+}
+
+/// Whether the line starting with `command` may be for this plugin rather than the game: a
+/// command prefix, a batch command that may run `ss` lines, or `help`.
+fn is_own_command(command: &str) -> bool {
+    let command = command.trim_matches(|c| c == '"' || c == '\'');
+    CONFIG.is_command_prefix(command)
+        || BATCH_COMMANDS
+            .iter()
+            .any(|batch| batch.eq_ignore_ascii_case(command))
+        || command.eq_ignore_ascii_case("help")
+}
+
+fn process_input(input: &str, selected: Option<u32>) -> anyhow::Result<ProcessResult> {
+    // most lines are commands of the game, which need neither shlex nor clap
+    let first_word = input.split_ascii_whitespace().next().unwrap_or_default();
+    if !is_own_command(first_word) {
         return Ok(ProcessResult::Fallback);
     }
     let input = match shlex::split(input) {
         Some(result) => result,
         None => {
             if let Some(command) = input.trim_start().split_ascii_whitespace().next() {
-                if SKYRIM_SEARCH_COMMANDS.contains(&command) {
-                    console::print("skyrim-search-se: parse failed; falling back to skyrim engine");
+                if CONFIG.is_command_prefix(command) {
+                    console::print(messages::get(
+                        "$SkyrimSearchSE_ParseFailed",
+                        "skyrim-search-se: parse failed; falling back to skyrim engine",
+                    ));
                 }
             }
             return Ok(ProcessResult::Fallback);
         }
     };
     let command = input[0].to_ascii_lowercase();
-    if !SKYRIM_SEARCH_COMMANDS.contains(&command.as_str()) {
+    if CONFIG.is_command_prefix(&command) {
+        log::write_command(&input.join(" "));
+    }
+    if !CONFIG.is_command_prefix(&command) {
+        if BATCH_COMMANDS.contains(&command.as_str()) {
+            if let Some((path, lines)) = input
+                .get(1)
+                .and_then(|file| batch_lines(file).context("batch_lines").logging_ok())
+                .flatten()
+            {
+                let lines = lines.into_iter().map(|line| (line, None)).collect();
+                return Ok(ProcessResult::Batch {
+                    file: Some(path),
+                    lines,
+                });
+            }
+        }
         return if command == "help" {
-            Ok(ProcessResult::FallbackAndPrintUsage)
+            let matches = input
+                .get(1)
+                .and_then(|term| help_matches(term).context("help_matches").logging_ok())
+                .flatten();
+            Ok(matches.map_or(
+                ProcessResult::FallbackAndPrintUsage,
+                ProcessResult::FallbackAndPrint,
+            ))
         } else {
             Ok(ProcessResult::Fallback)
         };
     }
 
-    let matches = get_clap().get_matches_from_safe(input)?;
+    let input = expand_alias(input, &CONFIG.aliases);
+    let input = substitute_selected(input, selected)?;
+    let input = add_default_flags(input, &CONFIG.default_flags);
+    SELECTED.with(|cell| cell.set(selected));
+
+    let args = input.get(2..).unwrap_or_default().join(" ");
+    let matches = CLAP.with(|clap| clap.borrow_mut().get_matches_from_safe_borrow(input))?;
 
     if matches.is_present("debug") {
         console::print(format!("ArgMatches: {:?}", matches));
     }
 
-    static CREATE_INDEX: std::sync::Once = std::sync::Once::new();
-    CREATE_INDEX.call_once(|| {
-        let pair = Arc::new((Mutex::new(()), Condvar::new()));
-        let pair2 = Arc::clone(&pair);
-        S.task_queue
-            .send(Box::new(move |db| {
-                db::init_index(db).logging_ok();
+    if matches.subcommand_matches("disable").is_some() {
+        suspend()?;
+        return Ok(ProcessResult::Processed);
+    }
+    if matches.subcommand_matches("enable").is_some() {
+        console::print(messages::get(
+            "$SkyrimSearchSE_AlreadyEnabled",
+            "Skyrim Search SE is already enabled",
+        ));
+        return Ok(ProcessResult::Processed);
+    }
+    if matches.subcommand_matches("cancel").is_some() {
+        if console::cancel_pending() {
+            console::print(messages::get("$SkyrimSearchSE_Cancelled", "cancelled"));
+        } else {
+            console::print(messages::get(
+                "$SkyrimSearchSE_NothingToCancel",
+                "nothing to cancel",
+            ));
+        }
+        return Ok(ProcessResult::Processed);
+    }
+    if let Some(matches) = matches.subcommand_matches("profile") {
+        process_profile_command(matches)?;
+        return Ok(ProcessResult::Processed);
+    }
+    if let Some(matches) = matches.subcommand_matches("config") {
+        process_config_command(matches)?;
+        return Ok(ProcessResult::Processed);
+    }
+    if let Some(matches) = matches.subcommand_matches("log") {
+        process_log_command(matches)?;
+        return Ok(ProcessResult::Processed);
+    }
+    if matches.subcommand_matches("lasterror").is_some() {
+        match LAST_ERROR
+            .lock()
+            .ok()
+            .and_then(|last_error| last_error.clone())
+        {
+            Some(last_error) => console::print(last_error),
+            None => console::print("no command has failed in this session"),
+        }
+        return Ok(ProcessResult::Processed);
+    }
+    if matches.subcommand_matches("rebuild").is_some() {
+        match db::rebuild()? {
+            db::Rebuild::Opened => console::print("the database is open"),
+            db::Rebuild::Unchanged => console::print("no plugin changed since the index was built"),
+            db::Rebuild::Reindexing(plugins) => console::print(format!(
+                "indexing {} changed plugins again in the background, ss status shows the progress",
+                plugins
+            )),
+        }
+        return Ok(ProcessResult::Processed);
+    }
+    // reports the progress of the worker, so it must not wait for it
+    if let Some(matches) = matches.subcommand_matches("status") {
+        process_status_command(matches)?;
+        return Ok(ProcessResult::Processed);
+    }
 
-                pair2.1.notify_one();
+    // the worker waits for the database to open, so do not wait for the index if it failed to
+    db::check()?;
+    wait_for_index();
+    build_lazy_tables(matches.subcommand_name().unwrap_or_default(), &args);
+
+    // the command may query dynamic_form, so wait for the worker to record the forms
+    record_dynamic_forms(false)
+        .and_then(|done| done.recv().map_err(|e| anyhow!(e.to_string())))
+        .context("record_dynamic_forms")
+        .logging_ok();
+
+    let timeout = CONFIG.query_timeout();
+    PRINTED_ROWS.with(|rows| rows.set(None));
+    let start = Instant::now();
+    let (result, cancelled) = {
+        let timeout = db::QueryTimeout::start(timeout);
+        let result = process_subcommand(&matches);
+        (result, timeout.is_cancelled())
+    };
+    record_query(
+        matches.subcommand_name().unwrap_or_default(),
+        &args,
+        start.elapsed(),
+        result.as_ref().err(),
+    );
+    let result = match result {
+        Err(err) if db::is_interrupt_error(&err) => {
+            if cancelled {
+                anyhow::bail!("the query was cancelled");
+            }
+            anyhow::bail!(
+                "the query was aborted after {} seconds. raise query_timeout_secs in the config if needed",
+                timeout.map_or(0, |timeout| timeout.as_secs())
+            );
+        }
+        result => result?,
+    };
+    Ok(result)
+}
 
-                Ok(())
-            }))
-            .map_err(|e| anyhow!(e.to_string()))
-            .logging_ok();
-        let (lock, cond) = &*pair;
-        if let Ok(guard) = lock.lock() {
-            cond.wait(guard)
-                .map_err(|e| anyhow!(e.to_string()))
-                .logging_ok();
-        };
+/// Replaces the subcommand of `input` with its arguments if it is one of `aliases`, e.g.
+/// `ss n lydia` with `ss npc lydia`. Aliases are matched ignoring case, and not expanded again.
+fn expand_alias(input: Vec<String>, aliases: &BTreeMap<String, String>) -> Vec<String> {
+    let expansion = input.get(1).and_then(|subcommand| {
+        aliases
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(subcommand))
+            .map(|(_, expansion)| expansion)
     });
-
-    if let Some(matches) = matches.subcommand_matches("raw") {
-        process_raw_query_command(matches)?;
-    } else if let Some(matches) = matches.subcommand_matches("npc") {
-        process_npc_command(matches)?;
-    } else if let Some(matches) = matches.subcommand_matches("cell") {
-        process_cell_command(matches)?;
-    } else if let Some(matches) = matches.subcommand_matches("quest") {
-        process_quest_command(matches)?;
-    } else if let Some(matches) = matches.subcommand_matches("quest_stage") {
-        process_quest_log_command(matches)?;
+    match expansion {
+        Some(expansion) => {
+            let mut expanded = vec![input[0].clone()];
+            expanded.extend(shlex::split(expansion).unwrap_or_else(|| {
+                expansion
+                    .split_ascii_whitespace()
+                    .map(String::from)
+                    .collect()
+            }));
+            expanded.extend(input.into_iter().skip(2));
+            expanded
+        }
+        None => input,
     }
-    Ok(ProcessResult::Processed)
 }
 
-pub fn process_raw_query_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
-    let sql = matches
-        .values_of("sql")
-        .unwrap()
-        .collect::<Vec<&str>>()
-        .join(" ");
-    let db = db::DB.lock().unwrap();
-    let mut stmt: Statement = db.prepare(sql.as_str()).context("prepare error")?;
-
-    if matches.is_present("debug") {
-        console::print(format!("stmt: {:?}", stmt));
+/// Adds the flags of `default_flags` for the subcommand of `input` right after it, leaving out
+/// those that `input` gives itself. A flag followed by a word that is not a flag takes it as its
+/// value, e.g. `--limit 50`.
+fn add_default_flags(input: Vec<String>, default_flags: &BTreeMap<String, String>) -> Vec<String> {
+    let defaults = input.get(1).and_then(|subcommand| {
+        default_flags
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(subcommand))
+            .and_then(|(_, flags)| shlex::split(flags))
+    });
+    let defaults = match defaults {
+        Some(defaults) => defaults,
+        None => return input,
+    };
+    let is_given = |flag: &str| {
+        input[2..].iter().any(|arg| {
+            arg == flag
+                || arg
+                    .strip_prefix(flag)
+                    .map_or(false, |rest| rest.starts_with('='))
+        })
+    };
+    let mut flags = Vec::new();
+    let mut i = 0;
+    while i < defaults.len() {
+        let mut end = i + 1;
+        if defaults[i].starts_with('-')
+            && !defaults[i].contains('=')
+            && defaults
+                .get(end)
+                .map_or(false, |value| !value.starts_with('-'))
+        {
+            end += 1;
+        }
+        let flag = defaults[i].split('=').next().unwrap_or_default();
+        if !is_given(flag) {
+            flags.extend_from_slice(&defaults[i..end]);
+        }
+        i = end;
     }
-
-    let rows = stmt.query(NO_PARAMS).context("query error")?;
-    print_rows(rows, convert_row)?;
-
-    Ok(())
+    let mut input = input;
+    input.splice(2..2, flags);
+    input
 }
 
-pub fn process_npc_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
-    let db = db::DB.lock().unwrap();
-    let query: String = matches
-        .values_of("query")
-        .unwrap()
-        .collect::<Vec<&str>>()
-        .join(" ");
+/// Set once the worker has created the indexes of the form tables.
+static INDEX_CREATED: Mutex<bool> = Mutex::new(false);
+static INDEX_CREATED_COND: Condvar = Condvar::new();
 
-    let mut stmt;
-    let rows;
+/// The path and the lines of the batch `file` (`.txt` may be omitted), if any of them is an `ss`
+/// command. Batch files without one are left to the game.
+fn batch_lines(file: &str) -> anyhow::Result<Option<(String, Vec<String>)>> {
+    let path = if std::path::Path::new(file).is_file() {
+        file.to_string()
+    } else {
+        format!("{}.txt", file)
+    };
+    let content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| path.clone()),
+    };
+    let lines: Vec<String> = String::from_utf8_lossy(&content)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+    let has_ss_command = lines.iter().any(|line| {
+        line.split_ascii_whitespace()
+            .next()
+            .map_or(false, |command| CONFIG.is_command_prefix(command))
+    });
+    Ok(if has_ss_command {
+        Some((path, lines))
+    } else {
+        None
+    })
+}
 
-    if let Ok(id) = i64::from_str_radix(query.trim_start_matches("0x"), 16) {
-        stmt = db
-            .prepare_cached(
-                "SELECT npc.*, actor.form_id as ref_id FROM npc \
-             LEFT JOIN actor ON npc.form_id = actor.base_form_id \
-             WHERE npc.editor_id LIKE ?1 OR npc.name LIKE ?1 \
-             OR npc.form_id=?2 OR actor.form_id=?2",
-            )
-            .context("prepare error")?;
+/// Number of indexed forms listed after the output of the game's `help`.
+const HELP_MATCHES: usize = 10;
 
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+/// A table of the indexed forms matching the `help` search term, to be printed after the game's
+/// own output, or `None` if there are none. The index is not waited for.
+fn help_matches(term: &str) -> anyhow::Result<Option<String>> {
+    if !is_index_created() && !db::is_cached() {
+        return Ok(None);
+    }
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare_cached(
+            "SELECT kind, rowid, editor_id, name FROM form_fts WHERE form_fts MATCH ? \
+             ORDER BY rank LIMIT ?",
+        )
+        .context("prepare error")?;
+    let rows = stmt
+        .query_map(
+            params![db::fts_query(term), HELP_MATCHES as i64 + 1],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            },
+        )
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("rows error")?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
 
-        rows = stmt
-            .query(params![format!("%{}%", query), id])
-            .context("query error")?;
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row!["kind", "form_id", "editor_id", "name"]);
+    for (kind, form_id, editor_id, name) in rows.iter().take(HELP_MATCHES) {
+        table.add_row(prettytable::row![
+            kind,
+            format!("{:08X}", form_id),
+            editor_id.as_deref().unwrap_or_default(),
+            name.as_deref().unwrap_or_default()
+        ]);
+    }
+    let more = if rows.len() > HELP_MATCHES {
+        format!(" (first {}; `ss grep {}` lists all)", HELP_MATCHES, term)
     } else {
-        stmt = db
-            .prepare_cached(
-                "SELECT npc.*, actor.form_id as ref_id FROM npc \
-             LEFT JOIN actor ON npc.form_id = actor.base_form_id \
-             WHERE npc.editor_id LIKE ?1 OR npc.name LIKE ?1",
-            )
-            .context("prepare error")?;
+        String::new()
+    };
+    Ok(Some(format!(
+        "skyrim-search-se: indexed forms matching \"{}\"{}\n{}",
+        term, more, table
+    )))
+}
 
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+/// Subcommands that look up references in `refr` without a query naming it.
+const REFR_SUBCOMMANDS: [&str; 8] = [
+    "actor", "inv", "loaded", "near", "papyrus", "refs", "rel", "team",
+];
 
-        rows = stmt
-            .query(params![format!("%{}%", query)])
-            .context("query error")?;
+/// Builds the tables that `index.lazy` defers, the first time a command needs them: `refr`, and
+/// `record_blob` if `store_record_blobs` is set. Says so once per table.
+fn build_lazy_tables(subcommand: &str, args: &str) {
+    if !CONFIG.index.lazy {
+        return;
+    }
+    let args = args.to_ascii_lowercase();
+    if (REFR_SUBCOMMANDS.contains(&subcommand) || args.contains("refr")) && form::refr::build_refs()
+    {
+        console::print(messages::get(
+            "$SkyrimSearchSE_BuildingRefs",
+            "building the refr index...",
+        ));
+    }
+    if (args.contains("record_blob") || args.contains("hexdump")) && records::build() {
+        console::print(messages::get(
+            "$SkyrimSearchSE_BuildingRecordBlobs",
+            "building the record_blob index in the background, ss status shows the progress",
+        ));
+    }
+    if subcommand == "grep" && records::build_texts() {
+        console::print(messages::get(
+            "$SkyrimSearchSE_BuildingTexts",
+            "indexing book and dialogue texts in the background, ss status shows the progress",
+        ));
     }
+}
 
-    print_rows(rows, convert_row)?;
+/// Has the worker create the indexes of the form tables, once all forms are loaded: when the game
+/// has loaded its data, or at the first command at the latest. Does not wait for the worker, so
+/// the game goes on to the main menu meanwhile.
+pub(crate) fn create_index() {
+    static CREATE_INDEX: std::sync::Once = std::sync::Once::new();
+    CREATE_INDEX.call_once(|| {
+        S.task_queue
+            .send(Box::new(move |db| {
+                let start = Instant::now();
+                db::init_index(db).logging_ok();
+                db::save_load_order_hash(db)
+                    .context("save_load_order_hash")
+                    .logging_ok();
+                db::throttle_worker();
+                log::event(
+                    "index_complete",
+                    "db: the indexes are created",
+                    serde_json::json!({
+                        "duration_ms": start.elapsed().as_millis() as u64,
+                        "cached": db::is_cached(),
+                    }),
+                );
+
+                if let Ok(mut created) = INDEX_CREATED.lock() {
+                    *created = true;
+                }
+                INDEX_CREATED_COND.notify_all();
 
-    Ok(())
+                Ok(())
+            }))
+            .map_err(|e| anyhow!(e.to_string()))
+            .logging_ok();
+    });
 }
 
-pub fn process_cell_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
-    let db = db::DB.lock().unwrap();
-    let query: String = matches
-        .values_of("query")
-        .unwrap()
-        .collect::<Vec<&str>>()
-        .join(" ");
+fn is_index_created() -> bool {
+    INDEX_CREATED.lock().map_or(false, |created| *created)
+}
+
+fn wait_for_index() {
+    create_index();
+    let mut created = match INDEX_CREATED.lock() {
+        Ok(created) => created,
+        Err(_) => return,
+    };
+    while !*created {
+        created = match INDEX_CREATED_COND
+            .wait(created)
+            .map_err(|e| anyhow!(e.to_string()))
+            .logging_ok()
+        {
+            Some(created) => created,
+            None => return,
+        };
+    }
+}
 
-    let mut stmt;
-    let rows;
+/// Creates the index and records the globals before a save changes them, once the game has loaded
+/// its data.
+pub(crate) fn on_data_loaded() {
+    create_index();
+    S.task_queue
+        .send(globals_job())
+        .map_err(|e| anyhow!(e.to_string()))
+        .context("globals_job")
+        .logging_ok();
+}
 
-    if let Ok(id) = i64::from_str_radix(query.trim_start_matches("0x"), 16) {
-        stmt = db
-            .prepare_cached(
-                "SELECT * FROM cell WHERE editor_id LIKE ?1 OR name LIKE ?1 OR form_id=?2",
+/// A job that records the current values of the globals in `global`. The first values recorded
+/// are kept as the baseline.
+fn globals_job() -> Job {
+    let globals = form::glob::globals();
+    Box::new(move |db| {
+        let tx = db.unchecked_transaction().context("transaction")?;
+        for global in &globals {
+            tx.prepare_cached(
+                "INSERT INTO global (form_id, editor_id, type, baseline, value) \
+                 VALUES (?1, ?2, ?3, ?4, ?4) \
+                 ON CONFLICT(form_id) DO UPDATE SET value = excluded.value",
             )
-            .context("prepare error")?;
-
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
+            .context("prepare error")?
+            .execute(params![
+                global.form_id,
+                global.editor_id,
+                global.kind,
+                f64::from(global.value)
+            ])
+            .context("execute error")?;
         }
+        tx.commit().context("commit")?;
+        Ok(())
+    })
+}
 
-        rows = stmt
-            .query(params![format!("%{}%", query), id])
-            .context("query error")?;
-    } else {
-        stmt = db
-            .prepare_cached("SELECT * FROM cell WHERE editor_id LIKE ?1 OR name LIKE ?1")
-            .context("prepare error")?;
+/// Refreshes the tables of the running game after a save is loaded or a new game is started.
+/// Does not wait for the worker, which may still be creating the index.
+pub(crate) fn on_game_loaded() {
+    record_dynamic_forms(true)
+        .context("record_dynamic_forms")
+        .logging_ok();
+}
 
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+/// Has the worker add the forms created in game since the last command to `dynamic_form`.
+/// `reset` forgets the forms of the previous game first. The receiver gets a message when the
+/// forms are recorded.
+fn record_dynamic_forms(reset: bool) -> anyhow::Result<std::sync::mpsc::Receiver<()>> {
+    let dynamic_forms = form::dynamic_forms();
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    S.task_queue
+        .send_first(Box::new(move |db| {
+            let tx = db.unchecked_transaction().context("transaction")?;
+            if reset {
+                tx.execute("DELETE FROM dynamic_form", NO_PARAMS)
+                    .context("reset dynamic_form")?;
+            }
+            for dynamic_form in &dynamic_forms {
+                dynamic_form.insert(&tx, "form map")?;
+            }
+            tx.commit().context("commit")?;
+            done_tx.send(()).ok();
+            Ok(())
+        }))
+        .map_err(|e| anyhow!(e.to_string()))?;
+    Ok(done_rx)
+}
 
-        rows = stmt
-            .query(params![format!("%{}%", query)])
-            .context("query error")?;
+fn process_subcommand(matches: &clap::ArgMatches) -> anyhow::Result<ProcessResult> {
+    // the searches may return console commands to run with --exec
+    if let Some(matches) = matches.subcommand_matches("npc") {
+        return process_npc_command(matches);
+    } else if let Some(matches) = matches.subcommand_matches("cell") {
+        return process_form_command(matches, "cell");
+    } else if let Some(matches) = matches.subcommand_matches("quest") {
+        return process_form_command(matches, "quest");
+    } else if let Some(matches) = matches.subcommand_matches("quest_stage") {
+        return process_quest_log_command(matches);
+    } else if let Some(matches) = matches.subcommand_matches("grep") {
+        return process_grep_command(matches);
     }
 
-    print_rows(rows, convert_row)?;
-
-    Ok(())
+    if let Some(matches) = matches.subcommand_matches("raw") {
+        process_raw_query_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("globals") {
+        process_globals_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("history") {
+        process_history_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("actor") {
+        process_actor_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("papyrus") {
+        process_papyrus_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("rel") {
+        process_rel_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("inv") {
+        process_inv_command(matches)?;
+    } else if matches.subcommand_matches("bounty").is_some() {
+        process_bounty_command()?;
+    } else if matches.subcommand_matches("team").is_some() {
+        process_team_command()?;
+    } else if matches.subcommand_matches("time").is_some() {
+        process_time_command()?;
+    } else if matches.subcommand_matches("version").is_some() {
+        process_version_command()?;
+    } else if matches.subcommand_matches("hooks").is_some() {
+        console::print(console::describe_hooks().join("\n"));
+    } else if matches.subcommand_matches("loaded").is_some() {
+        process_loaded_command()?;
+    } else if matches.subcommand_matches("stats").is_some() {
+        process_stats_command()?;
+    } else if matches.subcommand_matches("mem").is_some() {
+        process_mem_command()?;
+    } else if let Some(matches) = matches.subcommand_matches("bench") {
+        process_bench_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("near") {
+        process_near_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("refs") {
+        process_refs_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("save") {
+        process_save_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("run") {
+        process_run_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("runfile") {
+        process_runfile_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("attach") {
+        process_attach_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("detach") {
+        process_detach_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("temp") {
+        process_temp_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
+        process_watch_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("export") {
+        process_export_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("dump") {
+        process_dump_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("import") {
+        process_import_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("integrity") {
+        process_integrity_command(matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("explain") {
+        process_explain_command(matches)?;
+    }
+    Ok(ProcessResult::Processed)
 }
 
-pub fn process_quest_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
-    let db = db::DB.lock().unwrap();
-    let query: String = matches
-        .values_of("query")
+pub fn process_raw_query_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let sql = matches
+        .values_of("sql")
         .unwrap()
         .collect::<Vec<&str>>()
         .join(" ");
+    let db = db::lock()?;
+    execute_sql(&db, &sql, matches)
+}
 
-    let mut stmt;
-    let rows;
+/// Runs `sql` given by the user, binding `--param` values and rejecting changes to the database
+/// unless `--write` is given.
+fn execute_sql(
+    db: &rusqlite::Connection,
+    sql: &str,
+    matches: &clap::ArgMatches,
+) -> anyhow::Result<()> {
+    let values: Vec<Value> = matches
+        .values_of("param")
+        .map(|params| params.map(|param| Value::from(param.to_string())).collect())
+        .unwrap_or_else(Vec::new);
+    let _guard = if matches.is_present("write") {
+        None
+    } else {
+        Some(db::ReadOnlyGuard::new(db))
+    };
+    let mut stmt: Statement = match db.prepare(sql) {
+        Err(rusqlite::Error::SqliteFailure(err, _))
+            if err.code == rusqlite::ErrorCode::AuthorizationForStatementDenied =>
+        {
+            anyhow::bail!("the query modifies the database; pass --write to allow it")
+        }
+        result => result.context("prepare error")?,
+    };
+    let index = stmt
+        .parameter_index(SELECTED_PARAM)
+        .context("parameter_index")?;
+    let values = bind_selected(values, index, SELECTED.with(Cell::get))?;
 
-    if let Ok(id) = i64::from_str_radix(query.trim_start_matches("0x"), 16) {
-        stmt = db
-            .prepare_cached(
-                "SELECT * FROM quest WHERE editor_id LIKE ?1 OR name LIKE ?1 OR form_id=?2",
-            )
-            .context("prepare error")?;
+    let rows = query_rows(&mut stmt, matches, &values)?;
+    print_rows(rows, convert_row)?;
 
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+    Ok(())
+}
 
-        rows = stmt
-            .query(params![format!("%{}%", query), id])
-            .context("query error")?;
+pub fn process_npc_command(matches: &clap::ArgMatches) -> anyhow::Result<ProcessResult> {
+    let db = db::lock()?;
+    let query = get_query(matches);
+    let columns = format!("{}, actor.form_id as ref_id", name_columns("npc"));
+
+    let (sql, values) = if matches.is_present("fuzzy") {
+        (
+            format!(
+                "SELECT {}, fuzzydist(?1, npc.name, npc.name_localized, npc.editor_id) AS distance \
+                 FROM npc LEFT JOIN actor ON npc.form_id = actor.base_form_id \
+                 WHERE distance <= ?2 ORDER BY distance",
+                columns
+            ),
+            vec![
+                Value::from(query.clone()),
+                Value::from(function::fuzzy_threshold(&query)),
+            ],
+        )
+    } else if let Some(id) = parse_form_id(&query) {
+        (
+            format!(
+                "SELECT {} FROM npc LEFT JOIN actor ON npc.form_id = actor.base_form_id \
+                 WHERE {} OR npc.form_id=?3 OR actor.form_id=?3 ORDER BY {}",
+                columns,
+                search_condition("npc"),
+                search_order("npc")
+            ),
+            search_values(&query, Some(id)),
+        )
     } else {
-        stmt = db
-            .prepare_cached("SELECT * FROM quest WHERE editor_id LIKE ?1 OR name LIKE ?1")
-            .context("prepare error")?;
-
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+        (
+            format!(
+                "SELECT {} FROM npc LEFT JOIN actor ON npc.form_id = actor.base_form_id \
+                 WHERE {} ORDER BY {}",
+                columns,
+                search_condition("npc"),
+                search_order("npc")
+            ),
+            search_values(&query, None),
+        )
+    };
 
-        rows = stmt
-            .query(params![format!("%{}%", query)])
-            .context("query error")?;
+    let mut stmt = prepare_search(&db, sql.as_str())?;
+    if let Some(result) = exec_rows(&mut stmt, matches, &values)? {
+        return Ok(result);
     }
-
+    let rows = query_rows(&mut stmt, matches, &values)?;
     print_rows(rows, convert_row)?;
 
-    Ok(())
+    Ok(ProcessResult::Processed)
 }
 
-pub fn process_quest_log_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
-    let db = db::DB.lock().unwrap();
-    let query: String = matches
-        .values_of("query")
-        .unwrap()
-        .collect::<Vec<&str>>()
-        .join(" ");
-
-    let mut stmt;
-    let rows;
+/// Searches the form `table` (e.g. `cell`, `quest`) by editor id, name or form id.
+pub fn process_form_command(
+    matches: &clap::ArgMatches,
+    table: &str,
+) -> anyhow::Result<ProcessResult> {
+    let db = db::lock()?;
+    let query = get_query(matches);
+    let columns = name_columns(table);
+
+    let (sql, values) = if matches.is_present("fuzzy") {
+        (
+            format!(
+                "SELECT {0}, \
+                 fuzzydist(?1, {1}.name, {1}.name_localized, {1}.editor_id) AS distance \
+                 FROM {1} WHERE distance <= ?2 ORDER BY distance",
+                columns, table
+            ),
+            vec![
+                Value::from(query.clone()),
+                Value::from(function::fuzzy_threshold(&query)),
+            ],
+        )
+    } else if let Some(id) = parse_form_id(&query) {
+        (
+            format!(
+                "SELECT {0} FROM {1} WHERE {2} OR {1}.form_id=?3 ORDER BY {3}",
+                columns,
+                table,
+                search_condition(table),
+                search_order(table)
+            ),
+            search_values(&query, Some(id)),
+        )
+    } else {
+        (
+            format!(
+                "SELECT {} FROM {} WHERE {} ORDER BY {}",
+                columns,
+                table,
+                search_condition(table),
+                search_order(table)
+            ),
+            search_values(&query, None),
+        )
+    };
 
-    if let Ok(id) = i64::from_str_radix(query.trim_start_matches("0x"), 16) {
-        stmt = db.prepare_cached(
-            "SELECT quest.*, stage, log FROM quest LEFT JOIN quest_stage ON quest.form_id = quest_stage.form_id \
-             WHERE log IS NOT NULL AND (quest.editor_id LIKE ?1 OR quest.name LIKE ?1 OR quest.form_id=?2)"
-        ).context("prepare error")?;
+    let mut stmt = prepare_search(&db, sql.as_str())?;
+    if let Some(result) = exec_rows(&mut stmt, matches, &values)? {
+        return Ok(result);
+    }
+    let rows = query_rows(&mut stmt, matches, &values)?;
+    print_rows(rows, convert_row)?;
 
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+    Ok(ProcessResult::Processed)
+}
 
-        rows = stmt
-            .query(params![format!("%{}%", query), id])
-            .context("query error")?;
+pub fn process_quest_log_command(matches: &clap::ArgMatches) -> anyhow::Result<ProcessResult> {
+    let db = db::lock()?;
+    let query = get_query(matches);
+    let columns = name_columns("quest");
+
+    // log must be the last column; it is replaced by the log description below.
+    let (sql, values) = if matches.is_present("fuzzy") {
+        (
+            format!(
+                "SELECT {}, \
+                 fuzzydist(?1, quest.name, quest.name_localized, quest.editor_id) AS distance, \
+                 stage, log FROM quest \
+                 LEFT JOIN quest_stage ON quest.form_id = quest_stage.form_id \
+                 WHERE log IS NOT NULL AND distance <= ?2 ORDER BY distance",
+                columns
+            ),
+            vec![
+                Value::from(query.clone()),
+                Value::from(function::fuzzy_threshold(&query)),
+            ],
+        )
+    } else if let Some(id) = parse_form_id(&query) {
+        (
+            format!(
+                "SELECT {}, stage, log FROM quest \
+                 LEFT JOIN quest_stage ON quest.form_id = quest_stage.form_id \
+                 WHERE log IS NOT NULL AND ({} OR quest.form_id=?3) ORDER BY {}",
+                columns,
+                search_condition("quest"),
+                search_order("quest")
+            ),
+            search_values(&query, Some(id)),
+        )
     } else {
-        stmt = db
-            .prepare_cached(
-                "SELECT quest.*, stage, log FROM quest LEFT JOIN quest_stage \
-             ON quest.form_id = quest_stage.form_id \
-             WHERE log IS NOT NULL AND (quest.editor_id LIKE ?1 OR quest.name LIKE ?1)",
-            )
-            .context("prepare error")?;
-
-        if matches.is_present("debug") {
-            console::print(format!("stmt: {:?}", *stmt));
-        }
+        (
+            format!(
+                "SELECT {}, stage, log FROM quest \
+                 LEFT JOIN quest_stage ON quest.form_id = quest_stage.form_id \
+                 WHERE log IS NOT NULL AND {} ORDER BY {}",
+                columns,
+                search_condition("quest"),
+                search_order("quest")
+            ),
+            search_values(&query, None),
+        )
+    };
 
-        rows = stmt
-            .query(params![format!("%{}%", query)])
-            .context("query error")?;
+    let mut stmt = prepare_search(&db, sql.as_str())?;
+    if let Some(result) = exec_rows(&mut stmt, matches, &values)? {
+        return Ok(result);
     }
-
-    let rows: rusqlite::Rows = rows;
+    let rows = query_rows(&mut stmt, matches, &values)?;
 
     let num_rows = print_rows(rows, |row: &rusqlite::Row| {
         let column_count = row.column_count();
@@ -395,81 +909,2346 @@ pub fn process_quest_log_command(matches: &clap::ArgMatches) -> anyhow::Result<(
     })?;
 
     if num_rows == 0 {
-        console::print("Change your query or try loading a save?");
+        console::print(messages::get(
+            "$SkyrimSearchSE_NoQuestLog",
+            "Change your query or try loading a save?",
+        ));
     }
 
-    Ok(())
+    Ok(ProcessResult::Processed)
 }
 
-fn print_rows<F>(mut rows: rusqlite::Rows, f: F) -> anyhow::Result<usize>
-where
-    F: Fn(&rusqlite::Row) -> prettytable::Row,
-{
-    let mut num_rows = 0;
-
-    if rows.column_count().is_none() {
-        anyhow::bail!("no data");
-    }
-
-    let mut ptable = prettytable::Table::new();
-    set_titles(&mut rows, &mut ptable);
-    loop {
-        let row = match rows.next().map_err(anyhow::Error::new) {
-            Ok(Some(row)) => row,
-            Ok(None) => break,
-            Err(err) => anyhow::bail!(err.context("rows.next() error")),
-        };
-        ptable.add_row(f(row));
-        num_rows += 1;
-    }
-
-    if num_rows > 0 {
-        console::print(ptable.to_string());
+/// Searches that reused a statement the console connection prepared before, and those that
+/// prepared one, for `ss stats`.
+static STATEMENT_HITS: AtomicUsize = AtomicUsize::new(0);
+static STATEMENT_MISSES: AtomicUsize = AtomicUsize::new(0);
+
+/// `prepare_cached` for the statements of the search subcommands, which counts whether the
+/// statement came from the cache: only then has it run before.
+fn prepare_search<'conn>(
+    db: &'conn rusqlite::Connection,
+    sql: &str,
+) -> anyhow::Result<rusqlite::CachedStatement<'conn>> {
+    let stmt = db.prepare_cached(sql).context("prepare error")?;
+    let counter = if stmt.get_status(StatementStatus::Run) > 0 {
+        &STATEMENT_HITS
     } else {
-        console::print("No result");
-    }
+        &STATEMENT_MISSES
+    };
+    counter.fetch_add(1, Ordering::SeqCst);
+    Ok(stmt)
+}
 
-    Ok(num_rows)
+/// The condition of the searches of the form table `table`, e.g. `npc`, as `ss_db::grep_sql`
+/// searches `form_fts` for the forms of its kind, with the values of `search_values`.
+fn search_condition(table: &str) -> String {
+    format!(
+        "({0}.form_id IN (SELECT rowid FROM form_fts WHERE form_fts MATCH ?1 AND kind = '{0}') \
+         OR {0}.form_id IN ({1}))",
+        table,
+        ss_db::substring_sql()
+    )
 }
 
-fn convert_row(row: &rusqlite::Row) -> prettytable::Row {
-    let column_count = row.column_count();
-    let mut cells = Vec::with_capacity(column_count);
-    for i in 0..column_count {
-        let column = row.get_raw(i);
-        let repr = repr_column(row.column_name(i).ok(), column);
-        cells.push(prettytable::Cell::new(repr.as_str()));
-    }
-    prettytable::Row::new(cells)
+/// Orders the forms of `search_condition` whose words start with the words of the query first.
+fn search_order(table: &str) -> String {
+    format!(
+        "{0}.form_id NOT IN (SELECT rowid FROM form_fts WHERE form_fts MATCH ?1 AND kind = '{0}')",
+        table
+    )
 }
 
-fn repr_column(name: Option<&str>, column: ValueRef) -> String {
-    match column {
-        ValueRef::Null => String::from("<null>"),
-        ValueRef::Integer(v) => match name {
-            Some(name) if name.contains("id") => format!("{:08X}", v),
-            _ => v.to_string(),
-        },
-        ValueRef::Real(v) => v.to_string(),
-        ValueRef::Text(v) => String::from_utf8_lossy(v).to_string(),
-        ValueRef::Blob(v) => format!("<{}-byte blob>", v.len()),
-    }
+/// The values of `search_condition` and `ss_db::grep_sql` for `query`, with the form id `?3` it
+/// may be.
+fn search_values(query: &str, id: Option<i64>) -> Vec<Value> {
+    let mut values = vec![
+        Value::from(db::fts_query(query)),
+        Value::from(db::substring_query(query)),
+    ];
+    values.extend(id.map(Value::from));
+    values
 }
 
-fn set_titles(rows: &mut rusqlite::Rows, table: &mut prettytable::Table) -> Option<()> {
-    let names = rows.column_names()?;
-    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-    table.set_titles(names.into_iter().map(prettytable::Cell::new).collect());
-    Some(())
+/// The query of `ss grep` for the localization of the game, see `ss_db::grep_sql`.
+pub(crate) fn grep_sql(by_kind: bool) -> String {
+    ss_db::grep_sql(strings::is_localized(), by_kind)
 }
 
-pub(crate) unsafe fn init(_image_base: usize) -> anyhow::Result<()> {
-    LateStatic::assign(
-        &S,
-        State {
-            task_queue: db::TASK_QUEUE.lock().unwrap().clone(),
-        },
-    );
+pub fn process_grep_command(matches: &clap::ArgMatches) -> anyhow::Result<ProcessResult> {
+    let db = db::lock()?;
+    let query = get_query(matches);
 
-    Ok(())
+    let mut stmt = prepare_search(&db, grep_sql(false).as_str())?;
+    let values = search_values(&query, None);
+    if let Some(result) = exec_rows(&mut stmt, matches, &values)? {
+        return Ok(result);
+    }
+    let rows = query_rows(&mut stmt, matches, &values)?;
+    print_rows(rows, convert_row)?;
+
+    Ok(ProcessResult::Processed)
+}
+
+/// With `--exec`, lists the console commands made from the rows of `stmt`, or has them run if
+/// `--yes` is given, too. `None` without `--exec`.
+fn exec_rows(
+    stmt: &mut Statement,
+    matches: &clap::ArgMatches,
+    values: &[Value],
+) -> anyhow::Result<Option<ProcessResult>> {
+    let template = match matches.value_of("exec") {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let mut rows = query_rows(stmt, matches, values)?;
+    let mut commands = Vec::new();
+    let mut selected = Vec::new();
+    while let Some(row) = rows.next().context("rows.next() error")? {
+        let columns = (0..row.column_count())
+            .map(|i| {
+                let name = row.column_name(i).ok();
+                (
+                    name.unwrap_or_default().to_string(),
+                    repr_column(name, row.get_raw(i)),
+                )
+            })
+            .collect::<Vec<_>>();
+        commands.push(fill_template(template, &columns)?);
+        if let Some(column) = matches.value_of("select") {
+            // the form id as the template would put it, e.g. 000A2C8E
+            let form_id = fill_template(&format!("{{{}}}", column), &columns)?;
+            let radix = if hex_ids() { 16 } else { 10 };
+            let form_id = u32::from_str_radix(&form_id, radix)
+                .with_context(|| format!("{} is not a form id", form_id))?;
+            selected.push(Some(form_id));
+        } else {
+            selected.push(None);
+        }
+    }
+
+    if commands.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+        return Ok(Some(ProcessResult::Processed));
+    }
+    if !matches.is_present("yes") {
+        console::print(commands.join("\n"));
+        console::print(messages::format(
+            "$SkyrimSearchSE_CommandsNotRun",
+            "{} commands not run. repeat with --yes to run them",
+            &[&commands.len()],
+        ));
+        return Ok(Some(ProcessResult::Processed));
+    }
+    let lines = commands.into_iter().zip(selected).collect::<Vec<_>>();
+    if modevent::is_console_util_installed() {
+        console::print(messages::format(
+            "$SkyrimSearchSE_CommandsSent",
+            "running {} commands with ConsoleUtilSSE",
+            &[&lines.len()],
+        ));
+        modevent::send_commands(lines);
+        return Ok(Some(ProcessResult::Processed));
+    }
+    Ok(Some(ProcessResult::Batch { file: None, lines }))
+}
+
+/// Replaces each `{column}` in `template` with the value of the column. Column names are matched
+/// ignoring case and underscores, so `{formid}` is `form_id`.
+fn fill_template(template: &str, columns: &[(String, String)]) -> anyhow::Result<String> {
+    let normalize = |name: &str| name.replace('_', "").to_ascii_lowercase();
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .with_context(|| format!("unclosed {{ in {}", template))?;
+        let name = &rest[start + 1..end];
+        let value = columns
+            .iter()
+            .find(|(column, _)| normalize(column) == normalize(name))
+            .map(|(_, value)| value)
+            .with_context(|| {
+                format!(
+                    "no column {} (columns: {})",
+                    name,
+                    columns
+                        .iter()
+                        .map(|(column, _)| column.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+        result.push_str(&rest[..start]);
+        result.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Whether `line` is `ss enable`, the one line processed while the plugin is disabled.
+pub(crate) fn is_enable_command(line: &str) -> bool {
+    match shlex::split(line).as_deref() {
+        Some([command, subcommand]) => {
+            CONFIG.is_command_prefix(command) && subcommand.eq_ignore_ascii_case("enable")
+        }
+        _ => false,
+    }
+}
+
+/// Puts the plugin to sleep for `ss disable`: the console works as without it, and its database
+/// connection is closed, until `ss enable`.
+fn suspend() -> anyhow::Result<()> {
+    db::release().context("release")?;
+    console::suspend();
+    console::print(messages::get(
+        "$SkyrimSearchSE_Suspended",
+        "Skyrim Search SE is disabled; ss enable restores it",
+    ));
+    Ok(())
+}
+
+/// Undoes `suspend`, for `ss enable`.
+pub(crate) fn resume() {
+    let result: anyhow::Result<()> = (|| {
+        console::resume()?;
+        db::reopen().context("reopen")?;
+        Ok(())
+    })();
+    match result {
+        Ok(()) => console::print(messages::get(
+            "$SkyrimSearchSE_Enabled",
+            "Skyrim Search SE is enabled",
+        )),
+        Err(err) => console::print(format!("ss enable: {:#}", err)),
+    }
+}
+
+/// Records a line entered in the console in the user database, which keeps it across sessions.
+pub(crate) fn record_history(line: &str) {
+    let result: anyhow::Result<()> = (|| {
+        db::lock()?
+            .prepare_cached("INSERT INTO user.history (line) VALUES (?)")
+            .context("prepare error")?
+            .execute(params![line])
+            .context("execute error")?;
+        Ok(())
+    })();
+    result.context("record_history").logging_ok();
+}
+
+thread_local! {
+    /// The rows that `print_rows` printed last on this thread, for `query_log`.
+    static PRINTED_ROWS: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// Records a command that ran, with the rows it printed if it printed a result set, in
+/// `query_log` of the user database.
+fn record_query(subcommand: &str, args: &str, duration: Duration, error: Option<&anyhow::Error>) {
+    let rows = PRINTED_ROWS.with(Cell::get).map(|rows| rows as i64);
+    let error = error.map(|err| format!("{:#}", err));
+    log::event(
+        "query",
+        format!("ss {}: {} ms", subcommand, duration.as_millis()).as_str(),
+        serde_json::json!({
+            "subcommand": subcommand,
+            "args": args,
+            "duration_ms": duration.as_millis() as u64,
+            "rows": rows,
+            "error": error,
+        }),
+    );
+    let result: anyhow::Result<()> = (|| {
+        db::lock()?
+            .prepare_cached(
+                "INSERT INTO user.query_log (subcommand, args, duration_ms, rows, error) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .context("prepare error")?
+            .execute(params![
+                subcommand,
+                args,
+                duration.as_millis() as i64,
+                rows,
+                error
+            ])
+            .context("execute error")?;
+        Ok(())
+    })();
+    result.context("record_query").logging_ok();
+}
+
+/// The last line of the console history that starts with one of the command prefixes, for the
+/// hotkey that runs it again.
+pub(crate) fn last_ss_command() -> Option<String> {
+    let result: anyhow::Result<Option<String>> = (|| {
+        let db = db::lock()?;
+        let mut stmt = db
+            .prepare_cached("SELECT line FROM user.history ORDER BY id DESC")
+            .context("prepare error")?;
+        let mut rows = stmt.query(NO_PARAMS).context("query error")?;
+        while let Some(row) = rows.next().context("rows.next() error")? {
+            let line: String = row.get(0).context("get error")?;
+            if line
+                .split_whitespace()
+                .next()
+                .map_or(false, |command| CONFIG.is_command_prefix(command))
+            {
+                return Ok(Some(line));
+            }
+        }
+        Ok(None)
+    })();
+    result.context("last_ss_command").logging_ok().flatten()
+}
+
+const DEFAULT_HISTORY_LIMIT: u32 = 20;
+
+pub fn process_history_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let limit = matches
+        .value_of("limit")
+        .map_or(Ok(DEFAULT_HISTORY_LIMIT), str::parse)?;
+    let pattern = matches
+        .values_of("pattern")
+        .map(|pattern| pattern.collect::<Vec<&str>>().join(" "))
+        .unwrap_or_default();
+    let db = db::lock()?;
+    // the most recent lines, oldest first
+    let sql = if matches.is_present("stats") {
+        "SELECT time, subcommand, args, duration_ms, rows, error FROM (SELECT * FROM user.query_log \
+         WHERE fold(subcommand || ' ' || args) LIKE ?1 ORDER BY id DESC LIMIT ?2) ORDER BY id"
+    } else {
+        "SELECT time, line FROM (SELECT id, time, line FROM user.history \
+         WHERE fold(line) LIKE ?1 ORDER BY id DESC LIMIT ?2) ORDER BY id"
+    };
+    let mut stmt = db.prepare_cached(sql).context("prepare error")?;
+    let values = [
+        Value::from(format!("%{}%", function::fold(&pattern))),
+        Value::from(limit),
+    ];
+    let rows = query_rows(&mut stmt, matches, &values)?;
+    print_rows(rows, convert_row)?;
+
+    Ok(())
+}
+
+const PLAYER_REF_ID: u32 = 0x14;
+/// The base NPC of the player.
+const PLAYER_NPC_ID: u32 = 0x7;
+
+/// The reference `query` stands for: the ref_id of a loaded reference, or the name of a reference
+/// in `refr`.
+fn find_ref(db: &rusqlite::Connection, query: &str) -> anyhow::Result<u32> {
+    if let Some(id) = parse_form_id(query) {
+        if form::refr::position_of(id as u32).is_some() {
+            return Ok(id as u32);
+        }
+    }
+    db.query_row(
+        "SELECT form_id FROM refr WHERE fold(name) = ? LIMIT 1",
+        params![function::fold(query)],
+        |row| row.get(0),
+    )
+    .optional()
+    .context("query error")?
+    .with_context(|| format!("no loaded reference is named {}", query))
+}
+
+/// The base NPC of `query`: the form id of an NPC or of a loaded reference, the name of a loaded
+/// reference, or the name or editor id of an indexed NPC.
+fn find_npc(db: &rusqlite::Connection, query: &str) -> anyhow::Result<u32> {
+    let id = parse_form_id(query)
+        .map(|id| id as u32)
+        .or_else(|| find_ref(db, query).ok());
+    if let Some(form) = id.and_then(|id| unsafe { TESForm::look_up_by_id(id).as_ref() }) {
+        if form.form_type == form::FORM_TYPE_NPC {
+            return Ok(form.form_id());
+        }
+        if let Some(base_form) =
+            form::refr::as_refr(form).and_then(|refr| unsafe { refr.base_form.as_ref() })
+        {
+            return Ok(base_form.form_id());
+        }
+    }
+    db.query_row(
+        "SELECT form_id FROM npc WHERE fold(name) = ?1 OR fold(editor_id) = ?1 LIMIT 1",
+        params![function::fold(query)],
+        |row| row.get(0),
+    )
+    .optional()
+    .context("query error")?
+    .with_context(|| format!("no npc is named {}", query))
+}
+
+/// Runs `job` on the worker and waits for it, so that the command can read what it wrote. The
+/// indexing jobs in the queue of the worker wait for it.
+fn run_job(job: Job) -> anyhow::Result<()> {
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    S.task_queue
+        .send_first(Box::new(move |db| {
+            done_tx.send(job(db)).ok();
+            Ok(())
+        }))
+        .map_err(|e| anyhow!(e.to_string()))?;
+    done_rx.recv().map_err(|e| anyhow!(e.to_string()))?
+}
+
+pub fn process_globals_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    run_job(globals_job())?;
+
+    let db = db::lock()?;
+    let pattern = matches.value_of("pattern").unwrap_or_default();
+    let mut stmt = db
+        .prepare_cached(
+            "SELECT form_id, editor_id, type, baseline, value FROM global \
+             WHERE fold(editor_id) LIKE ?1 AND (?2 = 0 OR value IS NOT baseline) \
+             ORDER BY editor_id",
+        )
+        .context("prepare error")?;
+    let values = [
+        Value::from(format!("%{}%", function::fold(pattern))),
+        Value::from(matches.is_present("changed")),
+    ];
+    let rows = query_rows(&mut stmt, matches, &values)?;
+    print_rows(rows, convert_row)?;
+
+    Ok(())
+}
+
+pub fn process_papyrus_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let query = matches
+        .values_of("form")
+        .unwrap()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let form_id = match parse_form_id(&query) {
+        Some(id) => id as u32,
+        None => find_ref(&*db::lock()?, &query)?,
+    };
+    // a reference runs the scripts of its base form, too
+    let base_form_id = unsafe { TESForm::look_up_by_id(form_id).as_ref() }
+        .and_then(form::refr::as_refr)
+        .and_then(|refr| unsafe { refr.base_form.as_ref() })
+        .map(TESForm::form_id);
+
+    if !records::are_scripts_stored() {
+        let msg = if records::build_scripts() {
+            "reading the scripts of the plugins in the background, ss status shows the progress"
+        } else {
+            "still reading the scripts of the plugins, ss status shows the progress"
+        };
+        console::print(messages::get("$SkyrimSearchSE_ReadingScripts", msg));
+        return Ok(());
+    }
+
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare_cached(
+            "SELECT s.plugin_id, p.name, s.script, s.property, s.value FROM script s \
+             LEFT JOIN plugin p ON p.id = s.plugin_id \
+             WHERE s.form_id = ? ORDER BY s.plugin_id, s.rowid",
+        )
+        .context("prepare error")?;
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row![
+        "form_id", "plugin", "script", "property", "value"
+    ]);
+    for form_id in std::iter::once(form_id).chain(base_form_id) {
+        let rows = stmt
+            .query_map(params![form_id], |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                ))
+            })
+            .context("query error")?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("query error")?;
+        // overrides without scripts only matter if another plugin has some
+        if rows.iter().all(|(_, _, script, ..)| script.is_none()) {
+            continue;
+        }
+        let last = rows.last().map(|(plugin_id, ..)| *plugin_id);
+        for (plugin_id, plugin, script, property, value) in rows {
+            let plugin = plugin.unwrap_or_default();
+            // the game uses the record of the last plugin
+            let plugin = if Some(plugin_id) == last {
+                format!("{} *", plugin)
+            } else {
+                plugin
+            };
+            table.add_row(prettytable::row![
+                format!("{:08X}", form_id),
+                plugin,
+                script.unwrap_or_default(),
+                property.unwrap_or_default(),
+                value.unwrap_or_default()
+            ]);
+        }
+    }
+    if table.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+    } else {
+        console::print(table.to_string());
+    }
+
+    Ok(())
+}
+
+pub fn process_rel_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let query = matches
+        .values_of("npc")
+        .unwrap()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let npc_form_id = find_npc(&db, &query)?;
+    drop(db);
+
+    let relationships = form::rela::relationships_of(npc_form_id);
+    let with_player = relationships.iter().find(|relationship| {
+        relationship.npc1_form_id == Some(PLAYER_NPC_ID)
+            || relationship.npc2_form_id == Some(PLAYER_NPC_ID)
+    });
+    // without a relationship, Papyrus reports rank 0
+    console::print(match with_player {
+        Some(relationship) => format!(
+            "rank with the player: {} ({})",
+            relationship.rank, relationship.level
+        ),
+        None => String::from("rank with the player: 0 (no relationship)"),
+    });
+
+    if relationships.is_empty() {
+        return Ok(());
+    }
+    let npc = |form_id: Option<u32>| match form_id {
+        Some(form_id) => {
+            let name = unsafe { TESForm::look_up_by_id(form_id).as_ref() }
+                .and_then(TESForm::get_name)
+                .map(|name| name.to_string())
+                .unwrap_or_default();
+            format!("{:08X} {}", form_id, name)
+        }
+        None => String::from("<null>"),
+    };
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row![
+        "relationship_id",
+        "npc1",
+        "npc2",
+        "association_id",
+        "rank",
+        "level"
+    ]);
+    for relationship in relationships {
+        table.add_row(prettytable::row![
+            format!("{:08X}", relationship.form_id),
+            npc(relationship.npc1_form_id),
+            npc(relationship.npc2_form_id),
+            relationship
+                .association_form_id
+                .map_or(String::from("<null>"), |id| format!("{:08X}", id)),
+            relationship.rank,
+            relationship.level
+        ]);
+    }
+    console::print(table.to_string());
+
+    Ok(())
+}
+
+pub fn process_actor_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let query = matches
+        .values_of("ref")
+        .unwrap()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let form_id = find_ref(&db, &query)?;
+    let actor = form::actor::actor_info(form_id)?;
+
+    // the base record, as indexed
+    let mut stmt = db
+        .prepare_cached(
+            format!(
+                "SELECT ?1 AS ref_id, {} FROM npc WHERE form_id = ?2",
+                name_columns("npc")
+            )
+            .as_str(),
+        )
+        .context("prepare error")?;
+    let rows = query_rows(
+        &mut stmt,
+        matches,
+        &[Value::from(form_id), Value::from(actor.base_form_id)],
+    )?;
+    print_rows(rows, convert_row)?;
+    console::print(format!("state: {}", actor.state.join(", ")));
+
+    let mut values = prettytable::Table::new();
+    values.set_titles(prettytable::row!["actor value", "current", "base"]);
+    for value in actor.values {
+        values.add_row(prettytable::row![
+            value.name,
+            format!("{:.0}", value.current),
+            format!("{:.0}", value.base)
+        ]);
+    }
+    console::print(values.to_string());
+
+    if actor.effects.is_empty() {
+        console::print(messages::get(
+            "$SkyrimSearchSE_NoActiveEffects",
+            "no active effects",
+        ));
+        return Ok(());
+    }
+    let mut effects = prettytable::Table::new();
+    effects.set_titles(prettytable::row![
+        "spell",
+        "effect",
+        "magnitude",
+        "elapsed",
+        "duration"
+    ]);
+    for effect in actor.effects {
+        effects.add_row(prettytable::row![
+            effect.spell.unwrap_or_default(),
+            effect.effect.unwrap_or_default(),
+            format!("{:.1}", effect.magnitude),
+            format!("{:.0}", effect.elapsed),
+            format!("{:.0}", effect.duration)
+        ]);
+    }
+    console::print(effects.to_string());
+
+    Ok(())
+}
+
+pub fn process_inv_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let form_id = match matches.values_of("ref") {
+        Some(query) => find_ref(&db, &query.collect::<Vec<&str>>().join(" "))?,
+        None => PLAYER_REF_ID,
+    };
+    let items = form::inventory::inventory_of(form_id)?;
+
+    run_job(Box::new(move |db| {
+        let tx = db.unchecked_transaction().context("transaction")?;
+        tx.execute(
+            "DELETE FROM inventory WHERE owner_form_id = ?",
+            params![form_id],
+        )
+        .context("delete error")?;
+        for item in &items {
+            tx.prepare_cached(
+                "INSERT INTO inventory \
+                 (owner_form_id, item_form_id, name, count, equipped, charge) \
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .context("prepare error")?
+            .execute(params![
+                form_id,
+                item.form_id,
+                item.name,
+                item.count,
+                item.equipped,
+                item.charge.map(f64::from)
+            ])
+            .context("insert error")?;
+        }
+        tx.commit().context("commit")?;
+        Ok(())
+    }))?;
+
+    let mut stmt = db
+        .prepare_cached(
+            "SELECT item_form_id, name, count, equipped, charge FROM inventory \
+             WHERE owner_form_id = ? ORDER BY equipped DESC, name",
+        )
+        .context("prepare error")?;
+    let rows = query_rows(&mut stmt, matches, &[Value::from(form_id)])?;
+    print_rows(rows, convert_row)?;
+
+    Ok(())
+}
+
+const DEFAULT_NEAR_DISTANCE: f32 = 1000.0;
+
+/// Lists the references in `refr` within the given distance of the player. Distances are
+/// measured between the current positions, so actors are found where they are now.
+pub fn process_near_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let max_distance = matches
+        .value_of("distance")
+        .map_or(Ok(DEFAULT_NEAR_DISTANCE), str::parse)?;
+    let player = form::refr::player_position().context("the player is not loaded")?;
+
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare(
+            "SELECT refr.form_id, refr.base_form_id, refr.name, cell.name FROM refr \
+             LEFT JOIN cell ON refr.cell_form_id = cell.form_id",
+        )
+        .context("prepare error")?;
+    let mut near = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, Option<u32>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("rows error")?
+        .into_iter()
+        .filter_map(|(form_id, base_form_id, name, cell)| {
+            let position = form::refr::position_of(form_id)?;
+            let distance = distance(player, position);
+            Some((distance, form_id, base_form_id, name, cell))
+        })
+        .filter(|(distance, ..)| *distance > 0.0 && *distance <= max_distance)
+        .collect::<Vec<_>>();
+    near.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    if near.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+        return Ok(());
+    }
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row![
+        "ref_id",
+        "base_form_id",
+        "name",
+        "cell",
+        "distance"
+    ]);
+    for (distance, form_id, base_form_id, name, cell) in near {
+        table.add_row(prettytable::row![
+            format!("{:08X}", form_id),
+            base_form_id.map_or(String::from("<null>"), |id| format!("{:08X}", id)),
+            name.unwrap_or_default(),
+            cell.unwrap_or_default(),
+            format!("{:.0}", distance)
+        ]);
+    }
+    console::print(table.to_string());
+
+    Ok(())
+}
+
+pub fn process_bounty_command() -> anyhow::Result<()> {
+    if form::refr::player().is_none() {
+        anyhow::bail!("the player is not loaded");
+    }
+    let mut bounties = form::fact::player_bounties();
+    if bounties.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoBounty", "No bounty"));
+        return Ok(());
+    }
+    bounties.sort_by_key(|bounty| -(bounty.violent + bounty.non_violent));
+
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row![
+        "faction_id",
+        "name",
+        "bounty",
+        "violent",
+        "non_violent"
+    ]);
+    for bounty in bounties {
+        table.add_row(prettytable::row![
+            format!("{:08X}", bounty.faction_form_id),
+            bounty.name.unwrap_or_default(),
+            bounty.violent + bounty.non_violent,
+            bounty.violent,
+            bounty.non_violent
+        ]);
+    }
+    console::print(table.to_string());
+
+    Ok(())
+}
+
+pub fn process_team_command() -> anyhow::Result<()> {
+    form::actor::check_layout()?;
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare(
+            "SELECT refr.form_id, refr.base_form_id, refr.name, cell.name FROM refr \
+             LEFT JOIN cell ON refr.cell_form_id = cell.form_id ORDER BY refr.name",
+        )
+        .context("prepare error")?;
+    let team = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, Option<u32>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("rows error")?
+        .into_iter()
+        .filter_map(|(form_id, base_form_id, name, cell)| {
+            let info = form::actor::teammate_info(form_id)?;
+            Some((form_id, base_form_id, name, cell, info))
+        })
+        .collect::<Vec<_>>();
+
+    if team.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+        return Ok(());
+    }
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row![
+        "ref_id",
+        "base_form_id",
+        "name",
+        "cell",
+        "package_id",
+        "state"
+    ]);
+    for (form_id, base_form_id, name, cell, info) in team {
+        table.add_row(prettytable::row![
+            format!("{:08X}", form_id),
+            base_form_id.map_or(String::from("<null>"), |id| format!("{:08X}", id)),
+            name.unwrap_or_default(),
+            cell.unwrap_or_default(),
+            info.package_form_id
+                .map_or(String::from("<null>"), |id| format!("{:08X}", id)),
+            if info.waiting { "waiting" } else { "following" }
+        ]);
+    }
+    console::print(table.to_string());
+
+    Ok(())
+}
+
+/// Globals of Skyrim.esm that hold the date and time of the game.
+const GLOBAL_GAME_YEAR: u32 = 0x35;
+const GLOBAL_GAME_MONTH: u32 = 0x36;
+const GLOBAL_GAME_DAY: u32 = 0x37;
+const GLOBAL_GAME_HOUR: u32 = 0x38;
+const GLOBAL_GAME_DAYS_PASSED: u32 = 0x39;
+const GLOBAL_TIME_SCALE: u32 = 0x3A;
+
+pub fn process_time_command() -> anyhow::Result<()> {
+    let value = |form_id| form::glob::value_of(form_id).context("no such global");
+    console::print(game_date(
+        value(GLOBAL_GAME_YEAR)? as i32,
+        value(GLOBAL_GAME_MONTH)? as usize,
+        value(GLOBAL_GAME_DAY)? as u32,
+        value(GLOBAL_GAME_HOUR)?,
+    ));
+    console::print(format!(
+        "days passed: {:.2}, timescale: {}",
+        value(GLOBAL_GAME_DAYS_PASSED)?,
+        value(GLOBAL_TIME_SCALE)?
+    ));
+    Ok(())
+}
+
+const MONTHS: [(&str, u32); 12] = [
+    ("Morning Star", 31),
+    ("Sun's Dawn", 28),
+    ("First Seed", 31),
+    ("Rain's Hand", 30),
+    ("Second Seed", 31),
+    ("Midyear", 30),
+    ("Sun's Height", 31),
+    ("Last Seed", 31),
+    ("Hearthfire", 30),
+    ("Frostfall", 31),
+    ("Sun's Dusk", 30),
+    ("Evening Star", 31),
+];
+const WEEKDAYS: [&str; 7] = [
+    "Sundas", "Morndas", "Tirdas", "Middas", "Turdas", "Fredas", "Loredas",
+];
+
+/// Formats the date of the game globals like the wait menu does, e.g. `Morndas, 10:04 AM, 17th of
+/// Last Seed, 4E 201`. `month` counts from 0 and `day` from 1.
+fn game_date(year: i32, month: usize, day: u32, hour: f32) -> String {
+    // days since the 1st of Morning Star, 4E 201, which was a Turdas
+    let days = (year - 201) * 365
+        + MONTHS[..month.min(12)]
+            .iter()
+            .map(|&(_, len)| len as i32)
+            .sum::<i32>()
+        + day as i32
+        - 1;
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    let minutes = (hour * 60.0) as u32;
+    let (h, m) = ((minutes / 60) % 24, minutes % 60);
+    let suffix = match (day % 10, day % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!(
+        "{}, {}:{:02} {}, {}{} of {}, 4E {}",
+        weekday,
+        if h % 12 == 0 { 12 } else { h % 12 },
+        m,
+        if h < 12 { "AM" } else { "PM" },
+        day,
+        suffix,
+        MONTHS.get(month).map_or("?", |&(name, _)| name),
+        year
+    )
+}
+
+pub fn process_version_command() -> anyhow::Result<()> {
+    console::print(format!(
+        "skyrim-search-se {} (plugin version {})",
+        env!("CARGO_PKG_VERSION"),
+        crate::SKSEPlugin_Version.pluginVersion
+    ));
+    console::print(format!(
+        "Skyrim {}, SKSE {}",
+        address::format_version(address::runtime_version(), "."),
+        address::format_version(crate::skse_version(), ".")
+    ));
+    console::print(format!("addresses: {}", address::describe()));
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row!["function", "address", "found by"]);
+    for &(name, addr, source) in console::resolved_addresses() {
+        table.add_row(prettytable::row![
+            name,
+            patch::describe_address(addr),
+            source
+        ]);
+    }
+    console::print(table.to_string());
+    console::print(format!(
+        "console command: {}",
+        if command::is_registered() {
+            "registered"
+        } else {
+            "not registered"
+        }
+    ));
+    console::print(format!("database: {}", db::describe()?));
+    Ok(())
+}
+
+pub fn process_stats_command() -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare(
+            "SELECT name, rows, jobs, insert_ms, index_ms FROM build_stats \
+             UNION ALL SELECT * FROM (SELECT 'total', sum(rows) AS rows, sum(jobs), \
+             sum(insert_ms), sum(index_ms) FROM build_stats) WHERE rows IS NOT NULL",
+        )
+        .context("prepare error")?;
+    let rows = stmt.query(NO_PARAMS).context("query error")?;
+    print_rows(rows, convert_row)?;
+    if db::is_cached() {
+        console::print("the index was reused, so these are of the launch that built it");
+    }
+    let hits = STATEMENT_HITS.load(Ordering::SeqCst);
+    let misses = STATEMENT_MISSES.load(Ordering::SeqCst);
+    if hits + misses > 0 {
+        console::print(format!(
+            "searches reused a prepared statement {} of {} times ({:.0}%)",
+            hits,
+            hits + misses,
+            hits as f64 * 100.0 / (hits + misses) as f64
+        ));
+    }
+    Ok(())
+}
+
+pub fn process_mem_command() -> anyhow::Result<()> {
+    const MB: f64 = 1024.0 * 1024.0;
+    let (used, highwater) = db::memory_used();
+    let limit = match CONFIG.memory_limit_mb {
+        0 => "no limit".to_string(),
+        limit => format!("limit {} MB", limit),
+    };
+    console::print(format!(
+        "SQLite uses {:.1} MB, {:.1} MB at most so far, {}",
+        used as f64 / MB,
+        highwater as f64 / MB,
+        limit
+    ));
+    let spilled = db::spilled_tables();
+    if !spilled.is_empty() {
+        console::print(format!("moved to disk: {}", spilled.join(", ")));
+    }
+
+    let db = db::lock()?;
+    let mut schemas = vec!["main"];
+    if !spilled.is_empty() {
+        schemas.push("spill");
+    }
+    // dbstat counts the pages of each table and index, which a memory database holds all of
+    let sql = schemas
+        .iter()
+        .map(|schema| {
+            format!(
+                "SELECT '{0}' AS schema, s.name, m.tbl_name AS table_name, count(*) AS pages, \
+                 sum(s.pgsize) / 1024 AS kb FROM dbstat('{0}') s \
+                 LEFT JOIN {0}.sqlite_master m ON m.name = s.name GROUP BY s.name",
+                schema
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ")
+        + " ORDER BY kb DESC";
+    let mut stmt = db.prepare(&sql).context("prepare error")?;
+    let rows = stmt.query(NO_PARAMS).context("query error")?;
+    print_rows(rows, convert_row)?;
+    Ok(())
+}
+
+/// Queries `ss bench` times, which stand for the searches of the subcommands.
+const BENCH_QUERIES: [(&str, &str); 6] = [
+    (
+        "fts match",
+        "SELECT kind, editor_id, name FROM form_fts WHERE form_fts MATCH 'a*'",
+    ),
+    (
+        "npc name like",
+        "SELECT form_id, name FROM npc WHERE fold(name) LIKE '%an%'",
+    ),
+    (
+        "npc editor id",
+        "SELECT form_id FROM npc WHERE editor_id = 'HousecarlWhiterun'",
+    ),
+    (
+        "fuzzy npc name",
+        "SELECT form_id FROM npc WHERE editdist(fold(name), 'lydia') <= 2",
+    ),
+    (
+        "cells per plugin",
+        "SELECT plugin.name, count(*) FROM cell JOIN plugin ON plugin.id = cell.plugin_id \
+         GROUP BY cell.plugin_id",
+    ),
+    (
+        "quest stages",
+        "SELECT quest.form_id, stage.value FROM quest, json_each(quest.stages) AS stage",
+    ),
+];
+
+/// Index operations `ss bench` times, on temporary tables so that the index stays as it is.
+const BENCH_INDEX_OPERATIONS: [(&str, &str); 2] = [
+    (
+        "fts insert",
+        "DROP TABLE IF EXISTS temp.bench_fts; \
+         CREATE VIRTUAL TABLE temp.bench_fts USING fts5 (name); \
+         INSERT INTO temp.bench_fts SELECT text FROM form_name LIMIT 10000; \
+         DROP TABLE temp.bench_fts;",
+    ),
+    (
+        "name index",
+        "DROP TABLE IF EXISTS temp.bench_name; \
+         CREATE TEMP TABLE bench_name AS SELECT text FROM form_name LIMIT 10000; \
+         CREATE INDEX temp.bench_name_text ON bench_name (text COLLATE NOCASE); \
+         DROP TABLE temp.bench_name;",
+    ),
+];
+
+const DEFAULT_BENCH_RUNS: u32 = 3;
+
+/// The fastest of `runs` runs of `f`, in milliseconds.
+fn time_runs(runs: u32, mut f: impl FnMut() -> rusqlite::Result<()>) -> rusqlite::Result<f64> {
+    let mut best = f64::INFINITY;
+    for _ in 0..runs {
+        let start = Instant::now();
+        f()?;
+        best = best.min(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    Ok(best)
+}
+
+pub fn process_bench_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let runs = matches
+        .value_of("runs")
+        .map_or(Ok(DEFAULT_BENCH_RUNS), str::parse)?;
+    let db = db::lock()?;
+    let mut results = Vec::new();
+    for &(name, sql) in &BENCH_QUERIES {
+        let mut stmt = db.prepare(sql).context(name)?;
+        let ms = time_runs(runs, || {
+            let mut rows = stmt.query(NO_PARAMS)?;
+            while rows.next()?.is_some() {}
+            Ok(())
+        })
+        .context(name)?;
+        results.push((name, ms));
+    }
+    for &(name, sql) in &BENCH_INDEX_OPERATIONS {
+        let ms = time_runs(runs, || db.execute_batch(sql)).context(name)?;
+        results.push((name, ms));
+    }
+
+    let tx = db.unchecked_transaction().context("transaction error")?;
+    let run: i64 = tx
+        .query_row(
+            "SELECT coalesce(max(run), 0) + 1 FROM user.bench_result",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+        .context("query error")?;
+    for (name, ms) in results {
+        tx.execute(
+            "INSERT INTO user.bench_result (run, version, name, ms) VALUES (?, ?, ?, ?)",
+            params![run, env!("CARGO_PKG_VERSION"), name, ms],
+        )
+        .context("insert error")?;
+    }
+    tx.commit().context("commit error")?;
+
+    // each result next to that of the last run before, e.g. of an earlier version
+    let mut stmt = db
+        .prepare(
+            "SELECT b.name AS benchmark, round(b.ms, 2) AS ms, round(p.ms, 2) AS previous_ms, \
+             p.version AS previous_version, \
+             CASE WHEN p.ms IS NOT NULL \
+             THEN printf('%+.0f%%', (b.ms - p.ms) * 100 / max(p.ms, 0.01)) END AS change \
+             FROM user.bench_result b LEFT JOIN user.bench_result p ON p.name = b.name \
+             AND p.run = (SELECT max(run) FROM user.bench_result WHERE name = b.name AND run < b.run) \
+             WHERE b.run = ? ORDER BY b.id",
+        )
+        .context("prepare error")?;
+    let rows = stmt.query(&[run]).context("query error")?;
+    print_rows(rows, convert_row)?;
+    Ok(())
+}
+
+pub fn process_loaded_command() -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare(
+            "SELECT refr.form_id, refr.cell_form_id, cell.editor_id, cell.name FROM refr \
+             LEFT JOIN cell ON refr.cell_form_id = cell.form_id",
+        )
+        .context("prepare error")?;
+    let rows = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, Option<u32>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("rows error")?;
+
+    // (editor_id, name, references, actors) per cell
+    let mut cells = std::collections::BTreeMap::new();
+    for (form_id, cell_form_id, editor_id, name) in rows {
+        let cell = cells.entry(cell_form_id).or_insert((editor_id, name, 0, 0));
+        cell.2 += 1;
+        let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() };
+        if form.map_or(false, |form| form.form_type == form::refr::FORM_TYPE_ACHR) {
+            cell.3 += 1;
+        }
+    }
+
+    let player_cell = form::refr::player_cell_form_id();
+    match player_cell.and_then(form::cell::cell_info) {
+        Some(form::cell::CellInfo {
+            grid: Some((x, y)),
+            world_space_form_id,
+            world_space_name,
+        }) => console::print(format!(
+            "worldspace: {} ({}), grid: {}, {}",
+            world_space_name.unwrap_or_default(),
+            world_space_form_id.map_or(String::from("<null>"), |id| format!("{:08X}", id)),
+            x,
+            y
+        )),
+        Some(_) => console::print("interior"),
+        None => {}
+    }
+
+    if cells.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+        return Ok(());
+    }
+    let mut cells = cells.into_iter().collect::<Vec<_>>();
+    cells.sort_by(|a, b| (b.1).2.cmp(&(a.1).2));
+    let mut table = prettytable::Table::new();
+    table.set_titles(prettytable::row![
+        "cell_id",
+        "editor_id",
+        "name",
+        "grid",
+        "refs",
+        "actors"
+    ]);
+    for (form_id, (editor_id, name, refs, actors)) in cells {
+        let grid = form_id
+            .and_then(form::cell::cell_info)
+            .and_then(|cell| cell.grid)
+            .map_or(String::new(), |(x, y)| format!("{}, {}", x, y));
+        let marker = if form_id.is_some() && form_id == player_cell {
+            " *"
+        } else {
+            ""
+        };
+        table.add_row(prettytable::row![
+            form_id.map_or(String::from("<null>"), |id| format!("{:08X}{}", id, marker)),
+            editor_id.unwrap_or_default(),
+            name.unwrap_or_default(),
+            grid,
+            refs,
+            actors
+        ]);
+    }
+    console::print(table.to_string());
+
+    Ok(())
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+pub fn process_refs_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let query = get_query(matches);
+    let columns = "refr.form_id AS ref_id, refr.base_form_id, refr.name, cell.name AS cell, \
+                   refr.x, refr.y, refr.z";
+
+    let (sql, values) = if let Some(id) = parse_form_id(&query) {
+        (
+            format!(
+                "SELECT {} FROM refr LEFT JOIN cell ON refr.cell_form_id = cell.form_id \
+                 WHERE fold(refr.name) LIKE ?1 OR refr.base_form_id = ?2 \
+                 OR refr.base_form_id = (SELECT base_form_id FROM refr WHERE form_id = ?2)",
+                columns
+            ),
+            vec![
+                Value::from(format!("%{}%", function::fold(&query))),
+                Value::from(id),
+            ],
+        )
+    } else {
+        (
+            format!(
+                "SELECT {} FROM refr LEFT JOIN cell ON refr.cell_form_id = cell.form_id \
+                 WHERE fold(refr.name) LIKE ?1",
+                columns
+            ),
+            vec![Value::from(format!("%{}%", function::fold(&query)))],
+        )
+    };
+
+    let mut stmt = db.prepare_cached(sql.as_str()).context("prepare error")?;
+    let rows = query_rows(&mut stmt, matches, &values)?;
+    print_rows(rows, convert_row)?;
+
+    Ok(())
+}
+
+pub fn process_save_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let name = matches.value_of("name").unwrap();
+    let sql = matches
+        .values_of("sql")
+        .unwrap()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let db = db::lock()?;
+    // catch syntax errors now rather than when the query is run
+    db.prepare(sql.as_str()).context("prepare error")?;
+    db.execute(
+        "INSERT OR REPLACE INTO user.saved_query (name, sql) VALUES (?, ?)",
+        params![name, sql],
+    )
+    .context("save error")?;
+    console::print(format!("saved {}", name));
+
+    Ok(())
+}
+
+pub fn process_run_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let name = match matches.value_of("name") {
+        Some(name) => name,
+        None => {
+            let mut stmt = db
+                .prepare_cached("SELECT name, sql FROM user.saved_query ORDER BY name")
+                .context("prepare error")?;
+            let rows = stmt.query(NO_PARAMS).context("query error")?;
+            print_rows(rows, convert_row)?;
+            return Ok(());
+        }
+    };
+    let sql: String = db
+        .query_row(
+            "SELECT sql FROM user.saved_query WHERE name = ?",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("query error")?
+        .ok_or_else(|| anyhow!("no saved query named {}", name))?;
+
+    execute_sql(&db, &sql, matches)
+}
+
+pub fn process_runfile_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let path = data_file_path(SCRIPTS_DIR, matches.value_of("file").unwrap(), "sql")?;
+    let script = std::fs::read_to_string(&path)
+        .with_context(|| format!("read error: {}", path.display()))?;
+
+    let db = db::lock()?;
+    let statements = db::split_statements(&script);
+    for (i, statement) in statements.iter().enumerate() {
+        console::print(format!("[{}/{}] {}", i + 1, statements.len(), statement));
+        execute_sql(&db, statement, matches)
+            .with_context(|| format!("statement {} of {}", i + 1, path.display()))?;
+    }
+
+    Ok(())
+}
+
+pub fn process_attach_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let path = data_file_path(DATABASES_DIR, matches.value_of("file").unwrap(), "db")?;
+    let alias = matches.value_of("alias").unwrap();
+    check_schema_name(alias)?;
+    if !path.is_file() {
+        anyhow::bail!("{} does not exist", path.display());
+    }
+
+    let db = db::lock()?;
+    db.execute(
+        format!("ATTACH DATABASE ? AS {}", alias).as_str(),
+        params![path.to_string_lossy().to_string()],
+    )
+    .context("attach error")?;
+    console::print(format!("attached {} as {}", path.display(), alias));
+
+    Ok(())
+}
+
+pub fn process_detach_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let alias = matches.value_of("alias").unwrap();
+    check_schema_name(alias)?;
+
+    let db = db::lock()?;
+    db.execute(format!("DETACH DATABASE {}", alias).as_str(), NO_PARAMS)
+        .context("detach error")?;
+    console::print(format!("detached {}", alias));
+
+    Ok(())
+}
+
+pub fn process_status_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+
+    let mut tables = prettytable::Table::new();
+    tables.set_titles(prettytable::row!["table", "rows", "loaded"]);
+    for (table, form_type) in [
+        ("npc", Some(form::FORM_TYPE_NPC)),
+        ("actor", None),
+        ("cell", Some(form::FORM_TYPE_CELL)),
+        ("quest", Some(form::FORM_TYPE_QUEST)),
+        ("quest_stage", None),
+        ("record_blob", None),
+        ("script", None),
+        ("refr", None),
+        ("dynamic_form", None),
+    ] {
+        let mut stmt = db
+            .prepare(format!("SELECT count(*) FROM {}", table).as_str())
+            .context("prepare error")?;
+        if matches.is_present("debug") {
+            console::print(format!("stmt: {:?}", stmt));
+        }
+        let rows: i64 = stmt
+            .query_row(NO_PARAMS, |row| row.get(0))
+            .context("query error")?;
+        let loaded = form_type.map_or(String::new(), |form_type| {
+            form::form_ids_of_type(form_type).len().to_string()
+        });
+        tables.add_row(prettytable::row![table, rows, loaded]);
+    }
+    console::print(tables.to_string());
+
+    let index = if db::is_cached() {
+        "reused from the last session"
+    } else if is_index_created() {
+        "created"
+    } else {
+        "creating"
+    };
+    console::print(format!("index: {}", index));
+    console::print(format!(
+        "worker: {} jobs pending, {} processed",
+        db::pending_jobs(),
+        db::processed_jobs()
+    ));
+    if let Some((read, total)) = records::progress() {
+        console::print(format!("record_blob: {}/{} plugins read", read, total));
+    }
+    if let Some((read, total)) = records::script_progress() {
+        console::print(format!("script: {}/{} plugins read", read, total));
+    }
+    if let Some((read, total)) = records::text_progress() {
+        console::print(format!("texts: {}/{} plugins read", read, total));
+    }
+
+    Ok(())
+}
+
+pub fn process_integrity_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare("PRAGMA integrity_check")
+        .context("prepare error")?;
+    let rows = query_rows(&mut stmt, matches, &[])?;
+    print_rows(rows, convert_row)?;
+
+    let mut counts = prettytable::Table::new();
+    counts.set_titles(prettytable::row!["table", "loaded", "indexed", "missing"]);
+    // (plugin prefix, table) -> number of loaded forms missing from the index
+    let mut missing_by_plugin = std::collections::BTreeMap::new();
+    for (table, form_type) in [
+        ("npc", form::FORM_TYPE_NPC),
+        ("cell", form::FORM_TYPE_CELL),
+        ("quest", form::FORM_TYPE_QUEST),
+    ] {
+        let loaded = form::form_ids_of_type(form_type);
+        let indexed = db
+            .prepare(format!("SELECT form_id FROM {}", table).as_str())
+            .context("prepare error")?
+            .query_map(NO_PARAMS, |row| row.get::<_, u32>(0))
+            .context("query error")?
+            .collect::<rusqlite::Result<std::collections::HashSet<_>>>()
+            .context("rows error")?;
+        let mut missing = 0;
+        for id in loaded.iter().filter(|id| !indexed.contains(id)) {
+            missing += 1;
+            *missing_by_plugin
+                .entry((function::form_id_plugin(*id), table))
+                .or_insert(0) += 1;
+        }
+        counts.add_row(prettytable::row![
+            table,
+            loaded.len(),
+            indexed.len(),
+            missing
+        ]);
+    }
+    console::print(counts.to_string());
+
+    if missing_by_plugin.is_empty() {
+        console::print("no loaded forms are missing from the index");
+        return Ok(());
+    }
+    // name the plugins by their load order prefix
+    let plugin_names = plugins::load_order()
+        .map(|load_order| {
+            records::form_id_prefixes(&load_order)
+                .into_iter()
+                .map(|(plugin, (prefix, _))| (function::form_id_plugin(prefix), plugin))
+                .collect::<std::collections::HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+    let mut missing = prettytable::Table::new();
+    missing.set_titles(prettytable::row!["plugin", "table", "missing"]);
+    for ((prefix, table), count) in missing_by_plugin {
+        let plugin = plugin_names.get(&prefix).cloned().unwrap_or_default();
+        missing.add_row(prettytable::row![
+            format!("{} {}", prefix, plugin),
+            table,
+            count
+        ]);
+    }
+    console::print(missing.to_string());
+
+    Ok(())
+}
+
+pub fn process_export_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let dir = path::my_games_dir()? + "\\SKSE";
+    let path = data_file_path(&dir, matches.value_of("file").unwrap(), "db")?;
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+
+    let db = db::lock()?;
+    db.execute(
+        "VACUUM main INTO ?",
+        params![path.to_string_lossy().to_string()],
+    )
+    .context("export error")?;
+    console::print(format!("exported to {}", path.display()));
+
+    Ok(())
+}
+
+/// The form tables as xEdit export scripts write them: the signature of the record, the form id
+/// in hex, and the file that defines it.
+const DUMP_FORM_TABLES: [(&str, &str); 3] = [("npc", "NPC_"), ("cell", "CELL"), ("quest", "QUST")];
+
+/// Tables of the index that `ss dump all` leaves out, including those behind the views of the
+/// form tables.
+const DUMP_SKIPPED_TABLES: [&str; 10] = [
+    "meta",
+    "form_fts",
+    "form_trigram",
+    "trigram_offset",
+    "record_blob",
+    "form_plugin",
+    "form_name",
+    "npc_form",
+    "cell_form",
+    "quest_form",
+];
+
+pub fn process_dump_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let table = matches.value_of("table").unwrap();
+    let format = matches.value_of("format").unwrap();
+    let dir = path::my_games_dir()? + DUMPS_DIR;
+    std::fs::create_dir_all(&dir).with_context(|| format!("create_dir_all error: {}", dir))?;
+
+    let db = db::lock()?;
+    let tables = if table.eq_ignore_ascii_case("all") {
+        db.prepare(
+            "SELECT name FROM main.sqlite_master WHERE type IN ('table', 'view') \
+             AND name NOT LIKE 'sqlite_%' AND name NOT LIKE 'form_fts_%' ORDER BY name",
+        )
+        .context("prepare error")?
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(0))
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("rows error")?
+        .into_iter()
+        .filter(|table| !DUMP_SKIPPED_TABLES.contains(&table.as_str()))
+        .collect()
+    } else {
+        vec![table.to_ascii_lowercase()]
+    };
+    for table in tables {
+        let sql = dump_sql(&db, &table)?;
+        let mut stmt = db.prepare(&sql).context("prepare error")?;
+        let columns = stmt
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let mut rows = stmt.query(NO_PARAMS).context("query error")?;
+        let path = std::path::Path::new(&dir).join(format!("{}.{}", table, format));
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().context("rows.next() error")? {
+            values.push(
+                (0..row.column_count())
+                    .map(|i| row.get_raw(i))
+                    .map(api::json_value)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("create error: {}", path.display()))?;
+        if format == "json" {
+            let objects = values
+                .into_iter()
+                .map(|record| columns.iter().cloned().zip(record).collect())
+                .collect::<Vec<serde_json::Map<String, serde_json::Value>>>();
+            serde_json::to_writer_pretty(file, &objects).context("write error")?;
+        } else {
+            let mut writer = csv::Writer::from_writer(file);
+            writer.write_record(&columns).context("write error")?;
+            for record in values.iter() {
+                writer
+                    .write_record(record.iter().map(csv_field))
+                    .context("write error")?;
+            }
+            writer.flush().context("write error")?;
+        }
+        console::print(format!("dumped {} to {}", table, path.display()));
+    }
+
+    Ok(())
+}
+
+/// The query of `ss dump` for `table`, whose form ids are in hex as xEdit shows them.
+fn dump_sql(db: &rusqlite::Connection, table: &str) -> anyhow::Result<String> {
+    if let Some((table, signature)) = DUMP_FORM_TABLES.iter().find(|(name, _)| *name == table) {
+        return Ok(format!(
+            "SELECT '{1}' AS Signature, formid_hex(form_id) AS FormID, editor_id AS EditorID, \
+             {0}.name AS \"FULL - Name\", plugin.name AS File \
+             FROM {0} LEFT JOIN plugin ON plugin.id = {0}.plugin_id ORDER BY form_id",
+            table, signature
+        ));
+    }
+    let columns = db
+        .prepare(format!("PRAGMA main.table_info({})", quote_identifier(table)).as_str())
+        .context("prepare error")?
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(1))
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("rows error")?;
+    if columns.is_empty() {
+        anyhow::bail!("no such table: {}", table);
+    }
+    let columns = columns
+        .iter()
+        .map(|column| {
+            if column == "form_id" || column.ends_with("_form_id") {
+                format!("formid_hex({0}) AS {0}", quote_identifier(column))
+            } else {
+                quote_identifier(column)
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(format!(
+        "SELECT {} FROM main.{}",
+        columns.join(", "),
+        quote_identifier(table)
+    ))
+}
+
+/// A value of `api::json_value` as a CSV field, where nulls are empty.
+fn csv_field(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// The path of `file` in `dir`. `file` must be a plain file name so that it cannot point outside
+/// of `dir`, and `extension` is appended if it has none.
+fn data_file_path(dir: &str, file: &str, extension: &str) -> anyhow::Result<std::path::PathBuf> {
+    if file.contains(|c| c == '/' || c == '\\' || c == ':') || file.starts_with('.') {
+        anyhow::bail!("{} is not a file name", file);
+    }
+    let mut path = std::path::Path::new(dir).join(file);
+    if path.extension().is_none() {
+        path.set_extension(extension);
+    }
+    Ok(path)
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes `s` as an SQL identifier.
+fn quote_identifier(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Schema names are formatted into ATTACH/DETACH, so only plain identifiers are accepted, and
+/// the built-in databases cannot be replaced.
+fn check_schema_name(alias: &str) -> anyhow::Result<()> {
+    if !is_identifier(alias) {
+        anyhow::bail!("{} is not a valid alias", alias);
+    }
+    if ["main", "temp", "user"]
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(alias))
+    {
+        anyhow::bail!("{} is reserved", alias);
+    }
+    Ok(())
+}
+
+/// Whether `table` is one of the tables of the user database that the plugin creates.
+fn is_user_table(table: &str) -> bool {
+    db::user_tables()
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(table))
+}
+
+pub fn process_import_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let path = data_file_path(IMPORTS_DIR, matches.value_of("file").unwrap(), "csv")?;
+    let table = matches.value_of("table").unwrap();
+    if !is_identifier(table) {
+        anyhow::bail!("{} is not a valid table name", table);
+    }
+    if is_user_table(table) {
+        anyhow::bail!(
+            "{} is a table of the plugin, which import must not replace",
+            table
+        );
+    }
+
+    let mut reader =
+        csv::Reader::from_path(&path).with_context(|| format!("open error: {}", path.display()))?;
+    let columns = reader
+        .headers()
+        .context("header error")?
+        .iter()
+        .map(quote_identifier)
+        .collect::<Vec<_>>();
+    if columns.is_empty() {
+        anyhow::bail!("{} has no columns", path.display());
+    }
+
+    let mut db = db::lock()?;
+    let tx = db.transaction().context("transaction error")?;
+    if matches.is_present("replace") {
+        tx.execute_batch(format!("DROP TABLE IF EXISTS user.{}", table).as_str())
+            .context("drop error")?;
+    }
+    // columns have no type, so that each value keeps the type it is parsed as
+    tx.execute_batch(format!("CREATE TABLE user.{} ({})", table, columns.join(", ")).as_str())
+        .context("create error")?;
+    let mut num_rows = 0;
+    {
+        let mut stmt = tx
+            .prepare(
+                format!(
+                    "INSERT INTO user.{} VALUES ({})",
+                    table,
+                    vec!["?"; columns.len()].join(", ")
+                )
+                .as_str(),
+            )
+            .context("prepare error")?;
+        for record in reader.records() {
+            let record = record.context("record error")?;
+            let values = (0..columns.len())
+                .map(|i| record.get(i).map_or(Value::Null, parse_csv_value))
+                .collect::<Vec<_>>();
+            stmt.execute(&values).context("insert error")?;
+            num_rows += 1;
+        }
+    }
+    tx.commit().context("commit error")?;
+    console::print(format!("imported {} rows into user.{}", num_rows, table));
+
+    Ok(())
+}
+
+/// Numbers in a CSV file become INTEGER or REAL, and everything else TEXT. Numbers with leading
+/// zeros, such as hex form ids like `00012345`, are kept as TEXT.
+fn parse_csv_value(s: &str) -> Value {
+    let leading_zero = s.len() > 1 && s.starts_with('0') && !s.starts_with("0.");
+    if leading_zero {
+        Value::Text(s.to_string())
+    } else if let Ok(v) = s.parse::<i64>() {
+        Value::Integer(v)
+    } else if let Ok(v) = s.parse::<f64>() {
+        Value::Real(v)
+    } else {
+        Value::Text(s.to_string())
+    }
+}
+
+fn process_config_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    if matches.subcommand_matches("reload").is_some() {
+        config::reload().context("reload")?;
+        console::print(messages::get(
+            "$SkyrimSearchSE_ConfigReloaded",
+            "config reloaded",
+        ));
+    } else if let Some(matches) = matches.subcommand_matches("set") {
+        let key = matches.value_of("key").unwrap();
+        let value = matches.value_of("value").unwrap();
+        console::print(config::set(key, value)?);
+    }
+    Ok(())
+}
+
+const DEFAULT_LOG_LINES: usize = 20;
+
+fn process_log_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let n = matches
+        .value_of("n")
+        .map_or(Ok(DEFAULT_LOG_LINES), str::parse)?;
+    let level = matches
+        .value_of("level")
+        .and_then(log::Level::from_name)
+        .unwrap_or(log::Level::Debug);
+    let lines = log::tail(n, level)?;
+    if lines.is_empty() {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+        return Ok(());
+    }
+    // printed without logging them, or each `ss log` would show the one before
+    for line in lines {
+        console::print_unlogged(&line);
+    }
+    Ok(())
+}
+
+fn process_profile_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    if let Some(name) = matches.value_of("name") {
+        if !CONFIG.profiles.contains_key(name) {
+            anyhow::bail!("no profile {}", name);
+        }
+        config::set("profile", name)?;
+        console::print(format!("profile {}", name));
+        return Ok(());
+    }
+    if CONFIG.profiles.is_empty() {
+        console::print(messages::get(
+            "$SkyrimSearchSE_NoProfiles",
+            "no profiles in the config",
+        ));
+    }
+    for name in CONFIG.profiles.keys() {
+        let current = CONFIG.profile.as_ref() == Some(name);
+        console::print(format!("{} {}", if current { "*" } else { " " }, name));
+    }
+    Ok(())
+}
+
+pub fn process_temp_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let db = db::lock()?;
+    if matches.subcommand_matches("list").is_some() {
+        let mut stmt = db
+            .prepare_cached(
+                "SELECT type, name, sql FROM temp.sqlite_master \
+                 WHERE type IN ('table', 'view') ORDER BY name",
+            )
+            .context("prepare error")?;
+        let rows = stmt.query(NO_PARAMS).context("query error")?;
+        print_rows(rows, convert_row)?;
+    } else if let Some(matches) = matches.subcommand_matches("drop") {
+        for name in matches.values_of("name").unwrap() {
+            let kind: String = db
+                .query_row(
+                    "SELECT type FROM temp.sqlite_master \
+                     WHERE type IN ('table', 'view') AND name = ? COLLATE NOCASE",
+                    params![name],
+                    |row| row.get(0),
+                )
+                .optional()
+                .context("query error")?
+                .ok_or_else(|| anyhow!("no temp table or view named {}", name))?;
+            db.execute_batch(
+                format!(
+                    "DROP {} temp.{}",
+                    kind.to_ascii_uppercase(),
+                    quote_identifier(name)
+                )
+                .as_str(),
+            )
+            .context("drop error")?;
+            console::print(format!("dropped {} {}", kind, name));
+        }
+    }
+
+    Ok(())
+}
+
+struct Watch {
+    id: u32,
+    sql: String,
+    /// The reference selected in the console as the watch was added, which `:sel` stands for.
+    selected: Option<u32>,
+    /// The result as printed last.
+    last: String,
+}
+
+static WATCHES: Mutex<Vec<Watch>> = Mutex::new(Vec::new());
+/// The id of the next watch, which is not reused after a watch is removed.
+static NEXT_WATCH_ID: AtomicU32 = AtomicU32::new(1);
+static WATCH_THREAD: std::sync::Once = std::sync::Once::new();
+/// Set while `run_watches` waits for the main thread, so that no more of it are queued meanwhile.
+static WATCHES_QUEUED: AtomicBool = AtomicBool::new(false);
+/// The cell of the player as the watched queries last ran, and when.
+static WATCHES_RUN: Mutex<Option<(Option<u32>, std::time::Instant)>> = Mutex::new(None);
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+pub fn process_watch_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    if let Some(matches) = matches.subcommand_matches("add") {
+        let sql = matches
+            .values_of("sql")
+            .unwrap()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let selected = SELECTED.with(Cell::get);
+        let result = evaluate_watch(&sql, selected)?;
+        let mut watches = WATCHES.lock().unwrap();
+        let id = NEXT_WATCH_ID.fetch_add(1, Ordering::SeqCst);
+        console::print(format!("watch {}: {}\n{}", id, sql, result));
+        watches.push(Watch {
+            id,
+            sql,
+            selected,
+            last: result,
+        });
+        WATCH_THREAD.call_once(|| {
+            std::thread::spawn(watch_loop);
+        });
+    } else if matches.subcommand_matches("list").is_some() {
+        let watches = WATCHES.lock().unwrap();
+        if watches.is_empty() {
+            console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+            return Ok(());
+        }
+        let mut table = prettytable::Table::new();
+        table.set_titles(prettytable::row!["id", "sql"]);
+        for watch in watches.iter() {
+            table.add_row(prettytable::row![watch.id, watch.sql]);
+        }
+        console::print(table.to_string());
+    } else if let Some(matches) = matches.subcommand_matches("remove") {
+        let id = matches.value_of("id").unwrap().parse::<u32>()?;
+        let mut watches = WATCHES.lock().unwrap();
+        let len = watches.len();
+        watches.retain(|watch| watch.id != id);
+        if watches.len() == len {
+            anyhow::bail!("no watch has the id {}", id);
+        }
+        console::print(format!("removed watch {}", id));
+    }
+    Ok(())
+}
+
+/// The result of the query of a watch, as the table to print.
+fn evaluate_watch(sql: &str, selected: Option<u32>) -> anyhow::Result<String> {
+    let db = db::lock()?;
+    let _guard = db::ReadOnlyGuard::new(&db);
+    let mut stmt = db.prepare(sql).context("prepare error")?;
+    let index = stmt
+        .parameter_index(SELECTED_PARAM)
+        .context("parameter_index")?;
+    let values = bind_selected(vec![], index, selected)?;
+    let rows = stmt.query(&values).context("query error")?;
+    let table = rows_table(rows, convert_row)?;
+    Ok(if table.is_empty() {
+        String::from("No result")
+    } else {
+        table.to_string()
+    })
+}
+
+/// Has the main thread run `run_watches` every `WATCH_POLL_INTERVAL`.
+fn watch_loop() {
+    loop {
+        std::thread::sleep(WATCH_POLL_INTERVAL);
+        if console::is_enabled() && !WATCHES_QUEUED.swap(true, Ordering::SeqCst) {
+            task::add_task(run_watches);
+        }
+    }
+}
+
+/// Runs the watched queries every `watch_interval_secs` and whenever the player enters another
+/// cell, and prints the results that changed. On the main thread as the console commands, with
+/// their timeout.
+fn run_watches() {
+    WATCHES_QUEUED.store(false, Ordering::SeqCst);
+    let cell = form::refr::player_cell_form_id();
+    {
+        let mut last_run = WATCHES_RUN.lock().unwrap();
+        if let Some((last_cell, time)) = *last_run {
+            if last_cell == cell && time.elapsed() < CONFIG.watch_interval() {
+                return;
+            }
+        }
+        *last_run = Some((cell, std::time::Instant::now()));
+    }
+    let mut watches = WATCHES.lock().unwrap();
+    for watch in watches.iter_mut() {
+        let result = {
+            let _timeout = db::QueryTimeout::start(CONFIG.query_timeout());
+            evaluate_watch(&watch.sql, watch.selected)
+                .unwrap_or_else(|err| format!("error: {:#}", err))
+        };
+        if result != watch.last {
+            console::print(format!("watch {}: {}\n{}", watch.id, watch.sql, result));
+            watch.last = result;
+        }
+    }
+}
+
+pub fn process_explain_command(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let sql = matches
+        .values_of("sql")
+        .unwrap()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let db = db::lock()?;
+    let mut stmt = db
+        .prepare(format!("EXPLAIN QUERY PLAN {}", sql).as_str())
+        .context("prepare error")?;
+    let plan = stmt
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(3)?)))
+        .context("query error")?
+        .collect::<rusqlite::Result<Vec<(i64, i64, String)>>>()
+        .context("rows error")?;
+
+    console::print(format_query_plan(&plan));
+
+    Ok(())
+}
+
+/// Formats the (id, parent, detail) rows of `EXPLAIN QUERY PLAN` as a tree, like the sqlite3
+/// shell does.
+fn format_query_plan(plan: &[(i64, i64, String)]) -> String {
+    fn format_children(plan: &[(i64, i64, String)], parent: i64, prefix: &str, out: &mut String) {
+        let children = plan
+            .iter()
+            .filter(|(_, p, _)| *p == parent)
+            .collect::<Vec<_>>();
+        for (i, (id, _, detail)) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            out.push('\n');
+            out.push_str(prefix);
+            out.push_str(if last { "`--" } else { "|--" });
+            out.push_str(detail);
+            let prefix = format!("{}{}", prefix, if last { "   " } else { "|  " });
+            format_children(plan, *id, &prefix, out);
+        }
+    }
+
+    let mut out = String::from("QUERY PLAN");
+    format_children(plan, 0, "", &mut out);
+    out
+}
+
+fn get_query(matches: &clap::ArgMatches) -> String {
+    matches
+        .values_of("query")
+        .unwrap()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+const SELECTED_PLACEHOLDER: &str = "%sel";
+/// The parameter of SQL that is bound to the form id of the reference selected in the console.
+const SELECTED_PARAM: &str = ":sel";
+
+/// The subcommands whose arguments are SQL, which is left as it is; `:sel` is bound instead.
+const SQL_SUBCOMMANDS: [&str; 5] = ["raw", "query", "save", "watch", "explain"];
+
+/// Replaces the arguments that are `%sel`, or the one argument if it is just `sel`, with the form
+/// id of the reference selected in the console, unless the subcommand takes SQL.
+fn substitute_selected(input: Vec<String>, selected: Option<u32>) -> anyhow::Result<Vec<String>> {
+    let is_sql = input.get(1).map_or(false, |subcommand| {
+        SQL_SUBCOMMANDS
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(subcommand))
+    });
+    if is_sql {
+        return Ok(input);
+    }
+    let is_only_argument = input.len() == 3;
+    input
+        .into_iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            if i < 2
+                || !(arg.eq_ignore_ascii_case(SELECTED_PLACEHOLDER)
+                    || (is_only_argument && arg.eq_ignore_ascii_case("sel")))
+            {
+                return Ok(arg);
+            }
+            let form_id = selected.context("no reference is selected in the console")?;
+            Ok(format!("0x{:08X}", form_id))
+        })
+        .collect()
+}
+
+/// `values` with the form id of `selected` inserted at `index`, the index of `:sel` among the
+/// parameters of a statement, if it has it. `?` parameters are numbered in the order they appear,
+/// and `:sel` takes the next number where it first appears.
+fn bind_selected(
+    mut values: Vec<Value>,
+    index: Option<usize>,
+    selected: Option<u32>,
+) -> anyhow::Result<Vec<Value>> {
+    if let Some(index) = index {
+        let form_id = selected.context("no reference is selected in the console")?;
+        values.insert((index - 1).min(values.len()), Value::from(form_id));
+    }
+    Ok(values)
+}
+
+/// The form id `query` stands for, if it is a hexadecimal number.
+fn parse_form_id(query: &str) -> Option<i64> {
+    i64::from_str_radix(query.trim_start_matches("0x"), 16).ok()
+}
+
+fn query_rows<'stmt>(
+    stmt: &'stmt mut Statement,
+    matches: &clap::ArgMatches,
+    values: &[Value],
+) -> anyhow::Result<rusqlite::Rows<'stmt>> {
+    if matches.is_present("debug") {
+        console::print(format!("stmt: {:?}", stmt));
+    }
+
+    stmt.query(values).context("query error")
+}
+
+/// Columns printed for the form `table` by the search commands. `name_localized` is only shown
+/// when the game runs a non-English localization.
+fn name_columns(table: &str) -> String {
+    if strings::is_localized() {
+        format!(
+            "{0}.form_id, {0}.editor_id, {0}.name, {0}.name_localized",
+            table
+        )
+    } else {
+        format!("{0}.form_id, {0}.editor_id, {0}.name", table)
+    }
+}
+
+/// Rows printed as one table, so that a large result is printed page by page rather than held in
+/// memory whole.
+const PAGE_ROWS: usize = 200;
+
+/// Prints the rows as tables of `PAGE_ROWS` rows, up to `row_limit`, and counts the rest without
+/// converting them. Returns the number of rows.
+fn print_rows<F>(mut rows: rusqlite::Rows, f: F) -> anyhow::Result<usize>
+where
+    F: Fn(&rusqlite::Row) -> prettytable::Row,
+{
+    let titles = match rows.column_names() {
+        Some(names) => names.into_iter().map(String::from).collect::<Vec<_>>(),
+        None => anyhow::bail!("no data"),
+    };
+    let mut page = titled_table(&titles);
+    let mut num_rows = 0;
+    let mut events = Vec::new();
+    while let Some(row) = rows.next().context("rows.next() error")? {
+        num_rows += 1;
+        let printed = CONFIG.row_limit == 0 || num_rows <= CONFIG.row_limit;
+        let sent = CONFIG.mod_events && events.len() < modevent::MAX_ROW_EVENTS;
+        if !printed && !sent {
+            continue;
+        }
+        let row = f(row);
+        if sent {
+            events.push(row.iter().map(|cell| cell.get_content()).collect());
+        }
+        if printed {
+            page.add_row(row);
+            if page.len() == PAGE_ROWS {
+                console::print(page.to_string());
+                page = titled_table(&[]);
+            }
+        }
+    }
+    PRINTED_ROWS.with(|rows| rows.set(Some(num_rows)));
+    modevent::send_results(events, num_rows);
+
+    let more = if CONFIG.row_limit != 0 && num_rows > CONFIG.row_limit {
+        messages::format(
+            "$SkyrimSearchSE_MoreRows",
+            "({} more rows, see row_limit)",
+            &[&(num_rows - CONFIG.row_limit)],
+        )
+    } else {
+        String::new()
+    };
+    if num_rows == 0 {
+        console::print(messages::get("$SkyrimSearchSE_NoResult", "No result"));
+    } else if !page.is_empty() || !more.is_empty() {
+        console::print(format!("{}{}", page, more));
+    }
+
+    Ok(num_rows)
+}
+
+pub(crate) unsafe fn init(_image_base: usize) -> anyhow::Result<()> {
+    LateStatic::assign(
+        &S,
+        State {
+            task_queue: db::TASK_QUEUE.lock().unwrap().clone(),
+        },
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_is_filled_from_columns() {
+        let columns = [
+            ("form_id".to_string(), "0001A6B4".to_string()),
+            ("name".to_string(), "Lydia".to_string()),
+        ];
+        assert_eq!(
+            "0001A6B4.disable",
+            fill_template("{formid}.disable", &columns).unwrap()
+        );
+        assert_eq!(
+            "0001A6B4.moveto player ; Lydia",
+            fill_template("{Form_ID}.moveto player ; {name}", &columns).unwrap()
+        );
+        assert!(fill_template("{ref_id}.disable", &columns).is_err());
+        assert!(fill_template("{form_id.disable", &columns).is_err());
+    }
+
+    #[test]
+    fn game_date_is_formatted_like_the_wait_menu() {
+        assert_eq!(
+            "Morndas, 10:04 AM, 17th of Last Seed, 4E 201",
+            game_date(201, 7, 17, 10.07)
+        );
+        assert_eq!(
+            "Tirdas, 12:00 AM, 18th of Last Seed, 4E 201",
+            game_date(201, 7, 18, 0.0)
+        );
+        assert_eq!(
+            "Fredas, 1:30 PM, 1st of Morning Star, 4E 202",
+            game_date(202, 0, 1, 13.5)
+        );
+    }
+
+    #[test]
+    fn selected_ref_is_substituted() {
+        let selected = Some(0x0001_A6B4);
+        let substitute = |line: &str, selected| {
+            substitute_selected(shlex::split(line).unwrap(), selected).map(|args| args.join(" "))
+        };
+        assert_eq!(
+            "ss npc 0x0001A6B4",
+            substitute("ss npc sel", selected).unwrap()
+        );
+        assert_eq!(
+            "ss actor 0x0001A6B4",
+            substitute("ss actor SEL", selected).unwrap()
+        );
+        assert_eq!(
+            "ss refs 0x0001A6B4 --limit 5",
+            substitute("ss refs %sel --limit 5", selected).unwrap()
+        );
+        // a search for sel with other terms
+        assert_eq!(
+            "ss grep sel guard",
+            substitute("ss grep sel guard", None).unwrap()
+        );
+        assert!(substitute("ss npc %sel", None).is_err());
+    }
+
+    #[test]
+    fn selected_ref_is_not_substituted_in_sql() {
+        let selected = Some(0x0001_A6B4);
+        for line in [
+            "ss raw \"SELECT * FROM npc WHERE name LIKE '%self%' OR form_id = :sel\"",
+            "ss save mine SELECT * FROM npc WHERE editor_id LIKE '%sel%'",
+            "ss raw SELECT name FROM npc WHERE name = sel",
+        ] {
+            let args = shlex::split(line).unwrap();
+            assert_eq!(args, substitute_selected(args.clone(), selected).unwrap());
+        }
+    }
+
+    #[test]
+    fn selected_ref_is_bound_to_sel() {
+        let values = vec![Value::from("a".to_string()), Value::from("b".to_string())];
+        // SELECT ... WHERE name = ? AND form_id = :sel AND editor_id = ?
+        assert_eq!(
+            vec![
+                Value::from("a".to_string()),
+                Value::Integer(0x0001_A6B4),
+                Value::from("b".to_string())
+            ],
+            bind_selected(values.clone(), Some(2), Some(0x0001_A6B4)).unwrap()
+        );
+        assert_eq!(values, bind_selected(values.clone(), None, None).unwrap());
+        assert!(bind_selected(values, Some(1), None).is_err());
+    }
+
+    #[test]
+    fn import_does_not_replace_the_tables_of_the_plugin() {
+        for table in [
+            "saved_query",
+            "History",
+            "query_log",
+            "bench_result",
+            "meta",
+        ] {
+            assert!(is_user_table(table), "{}", table);
+        }
+        assert!(!is_user_table("prices"));
+        assert!(!is_user_table("query"));
+    }
+
+    #[test]
+    fn query_plan_is_formatted_as_tree() {
+        let plan = [
+            (2, 0, "SCAN npc".to_string()),
+            (4, 2, "LIST SUBQUERY 1".to_string()),
+            (6, 4, "SCAN form_fts VIRTUAL TABLE INDEX 0:M2".to_string()),
+            (
+                9,
+                0,
+                "SEARCH actor USING INDEX actor_base_form_id (base_form_id=?)".to_string(),
+            ),
+        ];
+        assert_eq!(
+            "QUERY PLAN\n\
+             |--SCAN npc\n\
+             |  `--LIST SUBQUERY 1\n\
+             |     `--SCAN form_fts VIRTUAL TABLE INDEX 0:M2\n\
+             `--SEARCH actor USING INDEX actor_base_form_id (base_form_id=?)",
+            format_query_plan(&plan)
+        );
+    }
+
+    #[test]
+    fn alias_is_expanded_once() {
+        let aliases = [
+            ("n".to_string(), "npc".to_string()),
+            ("near5".to_string(), "near 500".to_string()),
+            ("npc".to_string(), "cell".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let input = |line: &str| shlex::split(line).unwrap();
+        assert_eq!(
+            input("ss npc lydia"),
+            expand_alias(input("ss N lydia"), &aliases)
+        );
+        assert_eq!(
+            input("ss near 500"),
+            expand_alias(input("ss near5"), &aliases)
+        );
+        assert_eq!(input("ss raw n"), expand_alias(input("ss raw n"), &aliases));
+    }
+
+    #[test]
+    fn default_flags_are_added_unless_given() {
+        let default_flags = [
+            ("npc".to_string(), "--fuzzy".to_string()),
+            ("history".to_string(), "--limit 50".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let input = |line: &str| shlex::split(line).unwrap();
+        assert_eq!(
+            input("ss npc --fuzzy lydia"),
+            add_default_flags(input("ss npc lydia"), &default_flags)
+        );
+        assert_eq!(
+            input("ss history --limit 50"),
+            add_default_flags(input("ss history"), &default_flags)
+        );
+        assert_eq!(
+            input("ss history --limit=5"),
+            add_default_flags(input("ss history --limit=5"), &default_flags)
+        );
+        assert_eq!(
+            input("ss cell lydia"),
+            add_default_flags(input("ss cell lydia"), &default_flags)
+        );
+    }
 }
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Context};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The version of the archives of Skyrim Special Edition, whose files are compressed with LZ4,
+/// and that of the original Skyrim, whose files are compressed with zlib.
+const VERSION_SE: u32 = 105;
+const VERSION_LE: u32 = 104;
+const FLAG_DIRECTORY_NAMES: u32 = 0x1;
+const FLAG_FILE_NAMES: u32 = 0x2;
+const FLAG_COMPRESSED: u32 = 0x4;
+const FLAG_EMBEDDED_NAMES: u32 = 0x100;
+/// Set in the size of a file that is compressed when the archive is not, or the other way round.
+const SIZE_COMPRESSION_TOGGLE: u32 = 0x4000_0000;
+const SIZE_MASK: u32 = 0x3FFF_FFFF;
+const LZ4_FRAME_MAGIC: u32 = 0x184D_2204;
+
+/// The files of one folder of a `.bsa` archive, which are read on demand.
+pub(crate) struct Archive {
+    path: PathBuf,
+    version: u32,
+    flags: u32,
+    /// Maps the lower-cased file names to their sizes and offsets.
+    files: HashMap<String, (u32, u32)>,
+}
+
+impl Archive {
+    /// Reads the directory of `path`, keeping the files of the folder `folder`, e.g. `strings`.
+    pub(crate) fn open(path: &Path, folder: &str) -> anyhow::Result<Archive> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != b"BSA\0" {
+            anyhow::bail!("not an archive");
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != VERSION_SE && version != VERSION_LE {
+            anyhow::bail!("unsupported archive version {}", version);
+        }
+        let _folders_offset = reader.read_u32::<LittleEndian>()?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let folder_count = reader.read_u32::<LittleEndian>()?;
+        let file_count = reader.read_u32::<LittleEndian>()?;
+        let _folder_names_len = reader.read_u32::<LittleEndian>()?;
+        let _file_names_len = reader.read_u32::<LittleEndian>()?;
+        let _file_flags = reader.read_u32::<LittleEndian>()?;
+        if flags & FLAG_DIRECTORY_NAMES == 0 || flags & FLAG_FILE_NAMES == 0 {
+            anyhow::bail!("the archive has no file names");
+        }
+
+        // the folder records with the number of their files, followed by the names of the
+        // folders, each before the records of its files, and then by the names of the files
+        let mut file_counts = Vec::with_capacity(folder_count as usize);
+        for _ in 0..folder_count {
+            reader.read_u64::<LittleEndian>()?; // hash
+            file_counts.push(reader.read_u32::<LittleEndian>()?);
+            if version == VERSION_SE {
+                reader.read_u32::<LittleEndian>()?;
+                reader.read_u64::<LittleEndian>()?; // offset
+            } else {
+                reader.read_u32::<LittleEndian>()?; // offset
+            }
+        }
+        let mut records = Vec::with_capacity(file_count as usize);
+        for file_count in file_counts {
+            let len = reader.read_u8()? as usize;
+            let mut name = vec![0; len];
+            reader.read_exact(&mut name)?;
+            let name = nul_terminated(&name).to_ascii_lowercase();
+            for _ in 0..file_count {
+                reader.read_u64::<LittleEndian>()?; // hash
+                let size = reader.read_u32::<LittleEndian>()?;
+                let offset = reader.read_u32::<LittleEndian>()?;
+                records.push((name.clone(), size, offset));
+            }
+        }
+        let mut files = HashMap::new();
+        for (folder_name, size, offset) in records {
+            let mut name = Vec::new();
+            loop {
+                match reader.read_u8()? {
+                    0 => break,
+                    b => name.push(b),
+                }
+            }
+            if folder_name.eq_ignore_ascii_case(folder) {
+                let name = String::from_utf8_lossy(&name).to_ascii_lowercase();
+                files.insert(name, (size, offset));
+            }
+        }
+
+        Ok(Archive {
+            path: path.to_path_buf(),
+            version,
+            flags,
+            files,
+        })
+    }
+
+    /// The lower-cased names of the files of the folder.
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// Reads the file `name` of the folder, decompressed, or `None` if the archive has no such
+    /// file.
+    pub(crate) fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let (size, offset) = match self.files.get(&name.to_ascii_lowercase()) {
+            Some(&file) => file,
+            None => return Ok(None),
+        };
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let mut len = (size & SIZE_MASK) as usize;
+        if self.flags & FLAG_EMBEDDED_NAMES != 0 {
+            let name_len = reader.read_u8()? as usize;
+            reader.seek_relative(name_len as i64)?;
+            len = len
+                .checked_sub(name_len + 1)
+                .ok_or_else(|| anyhow!("invalid size of {}", name))?;
+        }
+        let compressed =
+            (self.flags & FLAG_COMPRESSED != 0) != (size & SIZE_COMPRESSION_TOGGLE != 0);
+        if !compressed {
+            let mut data = vec![0; len];
+            reader.read_exact(&mut data)?;
+            return Ok(Some(data));
+        }
+        let decompressed_len = reader.read_u32::<LittleEndian>()? as usize;
+        let mut data = vec![0; len.saturating_sub(4)];
+        reader.read_exact(&mut data)?;
+        let data = if self.version == VERSION_SE {
+            decompress_lz4_frame(&data, decompressed_len)
+        } else {
+            let mut decompressed = Vec::with_capacity(decompressed_len);
+            flate2::read::ZlibDecoder::new(data.as_slice())
+                .read_to_end(&mut decompressed)
+                .map(|_| decompressed)
+                .map_err(anyhow::Error::from)
+        };
+        data.with_context(|| format!("decompress {}", name))
+            .map(Some)
+    }
+}
+
+fn nul_terminated(s: &[u8]) -> String {
+    let s = &s[..s.iter().position(|&b| b == 0).unwrap_or(s.len())];
+    String::from_utf8_lossy(s).to_string()
+}
+
+/// Decompresses an LZ4 frame, whose blocks may refer to the data of the blocks before them.
+fn decompress_lz4_frame(data: &[u8], decompressed_len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut reader = std::io::Cursor::new(data);
+    if reader.read_u32::<LittleEndian>()? != LZ4_FRAME_MAGIC {
+        anyhow::bail!("not an LZ4 frame");
+    }
+    let frame_flags = reader.read_u8()?;
+    reader.read_u8()?; // block size
+    if frame_flags & 0x8 != 0 {
+        reader.read_u64::<LittleEndian>()?; // content size
+    }
+    if frame_flags & 0x1 != 0 {
+        reader.read_u32::<LittleEndian>()?; // dictionary id
+    }
+    reader.read_u8()?; // header checksum
+
+    let mut decompressed = Vec::with_capacity(decompressed_len);
+    loop {
+        let block_len = reader.read_u32::<LittleEndian>()?;
+        if block_len == 0 {
+            break;
+        }
+        let start = reader.position() as usize;
+        let end = start + (block_len & 0x7FFF_FFFF) as usize;
+        let block = data
+            .get(start..end)
+            .ok_or_else(|| anyhow!("truncated LZ4 block"))?;
+        if block_len & 0x8000_0000 != 0 {
+            decompressed.extend_from_slice(block);
+        } else {
+            decompress_lz4_block(block, &mut decompressed)?;
+        }
+        reader.set_position(end as u64);
+        if frame_flags & 0x10 != 0 {
+            reader.read_u32::<LittleEndian>()?; // block checksum
+        }
+    }
+    Ok(decompressed)
+}
+
+/// Appends the data of an LZ4 block to `out`: sequences of literals, each followed by a match
+/// that copies data from `out`, but the last.
+fn decompress_lz4_block(block: &[u8], out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let truncated = || anyhow!("truncated LZ4 block");
+    let mut pos = 0;
+    let read_len = |pos: &mut usize, len: usize| -> anyhow::Result<usize> {
+        let mut len = len;
+        if len == 15 {
+            loop {
+                let b = *block.get(*pos).ok_or_else(truncated)?;
+                *pos += 1;
+                len += b as usize;
+                if b != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    };
+    while pos < block.len() {
+        let token = block[pos];
+        pos += 1;
+        let literals_len = read_len(&mut pos, (token >> 4) as usize)?;
+        let literals = block.get(pos..pos + literals_len).ok_or_else(truncated)?;
+        out.extend_from_slice(literals);
+        pos += literals_len;
+        if pos == block.len() {
+            break;
+        }
+        let offset = block.get(pos..pos + 2).ok_or_else(truncated)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        pos += 2;
+        let match_len = read_len(&mut pos, (token & 0xF) as usize)? + 4;
+        if offset == 0 || offset > out.len() {
+            anyhow::bail!("invalid LZ4 match offset {}", offset);
+        }
+        // the match may overlap the data it appends
+        let match_start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[match_start + i]);
+        }
+    }
+    Ok(())
+}
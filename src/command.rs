@@ -0,0 +1,222 @@
+use crate::form::TESForm;
+use crate::patch::patch_bytes;
+use crate::{app, console, log, messages, pattern};
+use anyhow::Context;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use winapi::ctypes::{c_char, c_void};
+
+/// Debug commands of the retail game that do nothing useful, whose entries in the console
+/// command table are taken over by `ss` and `sss`, as SKSE plugins usually register commands.
+const UNUSED_COMMANDS: [&str; 4] = [
+    "TestLocalMap",
+    "ShowRenderPasses",
+    "DumpTexturePalette",
+    "TestSeenData",
+];
+
+/// The commands registered, as (long name, short name, help). Both run the whole line as
+/// `process_console_input` does.
+const COMMANDS: [(&str, &str, &str); 2] = [
+    (
+        "SkyrimSearchSE\0",
+        "ss\0",
+        "Searches the forms and runs SQL queries, see ss --help\0",
+    ),
+    (
+        "SkyrimSearch\0",
+        "sss\0",
+        "Searches the forms and runs SQL queries, see ss --help\0",
+    ),
+];
+
+/// `SCRIPT_PARAM_TYPE::kChar`, a string.
+const PARAM_TYPE_STRING: u32 = 0;
+
+/// The arguments are read from the text of the line instead, so they are only declared so that
+/// the game compiles lines with up to as many words.
+const NUM_PARAMS: usize = 8;
+
+#[repr(C)]
+struct ScriptParameter {
+    name: *const c_char,
+    param_type: u32, // 08
+    optional: u32,   // 0C
+}
+unsafe impl Sync for ScriptParameter {}
+
+const PARAM: ScriptParameter = ScriptParameter {
+    name: "String\0".as_ptr() as *const c_char,
+    param_type: PARAM_TYPE_STRING,
+    optional: 1,
+};
+static PARAMS: [ScriptParameter; NUM_PARAMS] = [PARAM; NUM_PARAMS];
+
+type Execute = extern "C" fn(
+    *const ScriptParameter,
+    *const c_void,
+    *const TESForm,
+    *const TESForm,
+    *const c_void,
+    *const c_void,
+    *mut f64,
+    *mut u32,
+) -> bool;
+
+/// `SCRIPT_FUNCTION`, an entry of the command table.
+#[repr(C)]
+struct ScriptFunction {
+    function_name: *const c_char,
+    short_name: *const c_char,         // 08
+    output: u32,                       // 10, the opcode
+    padding_14: u32,                   // 14
+    help_string: *const c_char,        // 18
+    reference_function: bool,          // 20
+    num_params: u16,                   // 22
+    params: *const ScriptParameter,    // 28
+    execute_function: Option<Execute>, // 30
+    compile_function: usize,           // 38
+    condition_function: usize,         // 40
+    editor_filter: bool,               // 48
+    invalidates_cell_list: bool,       // 49
+}
+
+/// Set once `ss` and `sss` are in the command table.
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Console commands have the opcodes from 0x100 on.
+const CONSOLE_OPCODE_START: u32 = 0x100;
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn execute(
+    _params: *const ScriptParameter,
+    _script_data: *const c_void,
+    this_obj: *const TESForm,
+    _containing_obj: *const TESForm,
+    script: *const c_void,
+    _locals: *const c_void,
+    _result: *mut f64,
+    _opcode_offset: *mut u32,
+) -> bool {
+    // `Script::text`, the line as it was entered, as the console hook reads it
+    let text = unsafe {
+        script
+            .cast::<u8>()
+            .add(0x38)
+            .cast::<*const c_char>()
+            .as_ref()
+    }
+    .filter(|text| !text.is_null())
+    .map(|&text| {
+        unsafe { CStr::from_ptr(text) }
+            .to_string_lossy()
+            .into_owned()
+    });
+    let text = match text {
+        Some(text) => text,
+        None => return false,
+    };
+    log::catch_panic("ss", || run(&text, this_obj)).unwrap_or_else(|| {
+        console::suspend_after_panic();
+        false
+    })
+}
+
+fn run(text: &str, this_obj: *const TESForm) -> bool {
+    if !console::is_enabled() {
+        if console::is_suspended() && app::is_enable_command(text) {
+            app::resume();
+            return true;
+        }
+        console::print(messages::get(
+            "$SkyrimSearchSE_Disabled",
+            "Skyrim Search SE is disabled",
+        ));
+        return false;
+    }
+    let selected = unsafe { this_obj.as_ref() }.map(TESForm::form_id);
+    match app::process_console_input(text, selected) {
+        Ok(app::ProcessResult::Processed) => true,
+        Err(err) => {
+            console::print(format!("{:#}", err));
+            false
+        }
+        Ok(_) => false,
+    }
+}
+
+/// The command table entry named `name`, found by the string of its name and the pointer to it.
+unsafe fn find_command(
+    image_base: usize,
+    name: &str,
+) -> anyhow::Result<Option<&'static mut ScriptFunction>> {
+    let (rdata_offset, rdata) = pattern::section(image_base, ".rdata")?;
+    let (data_offset, data) = pattern::section(image_base, ".data")?;
+    let needle = format!("\0{}\0", name);
+    let name_address = match rdata
+        .windows(needle.len())
+        .position(|window| window == needle.as_bytes())
+    {
+        Some(position) => image_base + rdata_offset + position + 1,
+        None => return Ok(None),
+    };
+    let entry = data
+        .chunks_exact(8)
+        .enumerate()
+        .filter(|(_, chunk)| usize::from_le_bytes((*chunk).try_into().unwrap()) == name_address)
+        .map(|(i, _)| &mut *((image_base + data_offset + i * 8) as *mut ScriptFunction))
+        .find(|entry| entry.output >= CONSOLE_OPCODE_START);
+    Ok(entry)
+}
+
+/// Registers `ss` and `sss` as console commands, so that `help` lists them and they run where
+/// the console hook does not see the line.
+pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
+    let mut entries = vec![];
+    for name in UNUSED_COMMANDS {
+        if let Some(entry) = find_command(image_base, name)? {
+            entries.push((name, entry));
+        }
+    }
+    if entries.len() < COMMANDS.len() {
+        anyhow::bail!("no unused console commands to take over");
+    }
+    for ((name, entry), (function_name, short_name, help)) in entries.into_iter().zip(COMMANDS) {
+        let command = ScriptFunction {
+            function_name: function_name.as_ptr() as *const c_char,
+            short_name: short_name.as_ptr() as *const c_char,
+            output: entry.output,
+            padding_14: 0,
+            help_string: help.as_ptr() as *const c_char,
+            reference_function: false,
+            num_params: NUM_PARAMS as u16,
+            params: PARAMS.as_ptr(),
+            execute_function: Some(execute),
+            // the parser of the game, which the taken-over command uses too
+            compile_function: entry.compile_function,
+            condition_function: 0,
+            editor_filter: false,
+            invalidates_cell_list: false,
+        };
+        patch_bytes(
+            &command,
+            entry as *mut ScriptFunction,
+            std::mem::size_of::<ScriptFunction>(),
+        )
+        .context("patch_bytes")?;
+        log::info(
+            format!(
+                "command: {} replaces {}",
+                function_name.trim_end_matches('\0'),
+                name
+            )
+            .as_str(),
+        );
+    }
+    REGISTERED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+pub(crate) fn is_registered() -> bool {
+    REGISTERED.load(Ordering::SeqCst)
+}
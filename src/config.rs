@@ -0,0 +1,422 @@
+use crate::app::SKYRIM_SEARCH_COMMANDS;
+use crate::log;
+use crate::log::{Level, Loggable};
+use crate::path;
+use anyhow::Context;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use ss_db::table::set_hex_ids;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+
+const CONFIG_PATH: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE.toml";
+/// Read if `CONFIG_PATH` does not exist, as it was named before.
+const LEGACY_CONFIG_PATH: &str = "Data\\SKSE\\Plugins\\skyrim-search-se.toml";
+const DB_PATH: &str = "\\SKSE\\skyrim-search-se.db";
+const GAME_DB_PATH: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE\\skyrim-search-se.db";
+
+lazy_static! {
+    /// The settings that `ss config set` overrides in this session, over those of the file.
+    static ref OVERRIDES: Mutex<toml::value::Table> = Mutex::new(toml::value::Table::new());
+}
+
+/// The current settings, which `ss config reload` and `ss config set` replace.
+pub(crate) static CONFIG: CurrentConfig = CurrentConfig;
+static CURRENT: AtomicPtr<Config> = AtomicPtr::new(ptr::null_mut());
+static LOADED: Once = Once::new();
+
+pub(crate) struct CurrentConfig;
+
+/// Loads the config, before the log is opened with its settings.
+pub(crate) fn init() {
+    let _ = CONFIG.deref();
+}
+
+impl Deref for CurrentConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        LOADED.call_once(|| replace(Config::load()));
+        unsafe { &*CURRENT.load(Ordering::Acquire) }
+    }
+}
+
+/// Makes `config` the current settings. The previous ones are leaked, as other threads may still
+/// hold references to them; they are small, and replaced only by a command.
+fn replace(config: Config) {
+    log::configure(
+        config.log_level,
+        config.log_to_debugger,
+        config.log_files,
+        config.log_format,
+    );
+    log::debug(format!("config: {:?}", config).as_str());
+    set_hex_ids(config.hex_ids);
+    CURRENT.store(Box::into_raw(Box::new(config)), Ordering::Release);
+}
+
+/// Settings read from `Data\SKSE\Plugins\SkyrimSearchSE.toml`, or `skyrim-search-se.toml` next to
+/// it. Missing keys take their default values, and so does everything if neither file exists.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    /// Keep the database in `Documents\My Games\Skyrim Special Edition\SKSE` instead of memory.
+    pub(crate) persist: bool,
+    /// Where to keep the database, which overrides `persist` if it is given.
+    pub(crate) db_location: Option<DbLocation>,
+    /// Abort queries of a console command that run longer than this. 0 disables the timeout.
+    pub(crate) query_timeout_secs: u64,
+    /// Store the raw data of NPC, cell and quest records in `record_blob`.
+    pub(crate) store_record_blobs: bool,
+    /// Megabytes SQLite may use before the large tables of the in-memory database move to a file.
+    /// 0 disables the limit.
+    pub(crate) memory_limit_mb: u64,
+    /// Run the queries of `ss watch` this often, and whenever the player enters another cell.
+    pub(crate) watch_interval_secs: u64,
+    /// Virtual-key code of the key that opens the console with `ss ` typed. 0 disables it.
+    pub(crate) hotkey: u32,
+    /// Virtual-key code of the key that runs the last `ss` command again. 0 disables it.
+    pub(crate) rerun_hotkey: u32,
+    /// Virtual-key code of the key that aborts the running console command. 0 disables it.
+    pub(crate) cancel_hotkey: u32,
+    /// Virtual-key code of the key the game opens the console with.
+    pub(crate) console_key: u32,
+    /// Answer queries on the named pipe `\\.\pipe\skyrim-search-se`.
+    pub(crate) pipe_server: bool,
+    /// Serve the queries and searches as JSON on `127.0.0.1:<http_port>`. 0 disables it.
+    pub(crate) http_port: u16,
+    /// The least severe messages written to `SkyrimSearchSE.log`: error, warn, info or debug.
+    pub(crate) log_level: Level,
+    /// Write the messages of the log to the debugger as well, e.g. for DebugView.
+    pub(crate) log_to_debugger: bool,
+    /// Logs of this many sessions are kept, as `SkyrimSearchSE.log`, `SkyrimSearchSE.1.log` and
+    /// so on.
+    pub(crate) log_files: usize,
+    /// `text`, or `json` for a JSON object per line.
+    pub(crate) log_format: log::Format,
+    /// Append every command and what it printed to `SkyrimSearchSE.results.log`.
+    pub(crate) results_log: bool,
+    /// Run the lines appended to `Data\SKSE\Plugins\SkyrimSearchSE\commands.txt`.
+    pub(crate) command_file: bool,
+    /// Send the ModEvent `SkyrimSearchSE_Results` to Papyrus after each search.
+    pub(crate) mod_events: bool,
+    /// Console commands that this plugin handles, compared ignoring case.
+    pub(crate) command_prefixes: Vec<String>,
+    /// Shorthands for subcommands and their arguments, e.g. `n = "npc"`, expanded once.
+    pub(crate) aliases: BTreeMap<String, String>,
+    /// Flags added to the subcommands as they are typed, e.g. `npc = "--fuzzy"`, unless the command
+    /// gives them itself.
+    pub(crate) default_flags: BTreeMap<String, String>,
+    /// Print at most this many rows of a search or query. 0 prints them all.
+    pub(crate) row_limit: usize,
+    /// Print form ids in hexadecimal, as the console takes them, rather than in decimal.
+    pub(crate) hex_ids: bool,
+    /// The file to keep the database in with `persist`, instead of the one of `db_location`.
+    pub(crate) db_path: Option<String>,
+    /// `PRAGMA page_size` of the database that `persist` creates. 0 keeps SQLite's default.
+    pub(crate) page_size: u32,
+    /// The language of `name_localized`, e.g. `german`, as the `Data\Strings` files are named.
+    /// Defaults to `sLanguage` of `Skyrim.ini`.
+    pub(crate) language: Option<String>,
+    /// The profile whose settings apply over the others, e.g. `author`.
+    pub(crate) profile: Option<String>,
+    /// Named sets of settings, e.g. `[profiles.author]` with its own `index` and `aliases`.
+    pub(crate) profiles: BTreeMap<String, toml::value::Table>,
+    /// The kinds of forms to index.
+    pub(crate) index: Index,
+    /// Offsets from the image base that replace those of the Address Library.
+    pub(crate) offsets: Offsets,
+    /// Byte patterns that find the console functions when neither offset is known.
+    pub(crate) signatures: Signatures,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DbLocation {
+    Memory,
+    /// `Data\SKSE\Plugins\SkyrimSearchSE` in the game folder, which may be read-only.
+    Game,
+    /// `Documents\My Games\Skyrim Special Edition\SKSE`
+    MyGames,
+}
+
+/// Switches of the kinds of forms to index, for machines where indexing everything takes too long
+/// or too much memory. The table of a kind that is switched off stays empty.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Index {
+    pub(crate) npc: bool,
+    pub(crate) cell: bool,
+    pub(crate) quest: bool,
+    /// The references of the attached cells in `refr`, which change with every cell the player
+    /// enters.
+    pub(crate) refs: bool,
+    /// The texts of books and the responses of dialogue in `form_fts`, which `ss grep` finds.
+    pub(crate) texts: bool,
+    /// Plugins whose forms are not indexed, e.g. one huge mod that drowns out the rest.
+    pub(crate) skip_plugins: Vec<String>,
+    /// If not empty, only the forms of these plugins are indexed.
+    pub(crate) only_plugins: Vec<String>,
+    /// Build `refr`, `record_blob` and the texts only once a command needs them, rather than as
+    /// the game loads.
+    pub(crate) lazy: bool,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            npc: true,
+            cell: true,
+            quest: true,
+            refs: true,
+            texts: true,
+            skip_plugins: Vec::new(),
+            only_plugins: Vec::new(),
+            lazy: true,
+        }
+    }
+}
+
+impl Index {
+    /// The kinds and plugins that are indexed, e.g. `npc,cell`, which a persisted index has to
+    /// match.
+    pub(crate) fn kinds(&self) -> String {
+        let mut kinds = [
+            ("npc", self.npc),
+            ("cell", self.cell),
+            ("quest", self.quest),
+            ("texts", self.texts),
+        ]
+        .iter()
+        .filter(|(_, indexed)| *indexed)
+        .map(|(kind, _)| *kind)
+        .collect::<Vec<_>>()
+        .join(",");
+        if !self.skip_plugins.is_empty() {
+            kinds += &format!(" skip:{}", self.skip_plugins.join(","));
+        }
+        if !self.only_plugins.is_empty() {
+            kinds += &format!(" only:{}", self.only_plugins.join(","));
+        }
+        kinds
+    }
+
+    /// Whether the forms of the plugin `name` are indexed, as `skip_plugins` and `only_plugins`
+    /// say. Names are compared ignoring case.
+    pub(crate) fn includes_plugin(&self, name: &str) -> bool {
+        let listed = |plugins: &[String]| plugins.iter().any(|p| p.eq_ignore_ascii_case(name));
+        !listed(&self.skip_plugins) && (self.only_plugins.is_empty() || listed(&self.only_plugins))
+    }
+}
+
+/// Offsets of the console functions, for game versions that this plugin or the Address Library
+/// does not know yet.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Offsets {
+    pub(crate) process_console_input: Option<usize>,
+    pub(crate) console_context: Option<usize>,
+    pub(crate) print_to_console: Option<usize>,
+    /// Offsets of other Address Library ids, by id, e.g. `ids = { 14720 = 0x1A2B30 }` for those
+    /// that the address profile of Skyrim VR goes without.
+    pub(crate) ids: BTreeMap<String, usize>,
+}
+
+/// Byte patterns like `48 8B C4 ?? 89 58` of the start of the console functions, searched for in
+/// the code of the game when the Address Library lacks a function and no offset is given.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Signatures {
+    pub(crate) process_console_input: Option<String>,
+    pub(crate) print_to_console: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            persist: false,
+            db_location: None,
+            query_timeout_secs: 10,
+            store_record_blobs: false,
+            memory_limit_mb: 0,
+            watch_interval_secs: 5,
+            hotkey: 0,
+            rerun_hotkey: 0,
+            // VK_PAUSE
+            cancel_hotkey: 0x13,
+            // VK_OEM_3, ~ on US keyboards
+            console_key: 0xC0,
+            pipe_server: false,
+            http_port: 0,
+            log_level: Level::Info,
+            log_to_debugger: true,
+            log_files: 3,
+            log_format: log::Format::Text,
+            results_log: true,
+            command_file: false,
+            mod_events: false,
+            command_prefixes: SKYRIM_SEARCH_COMMANDS
+                .iter()
+                .map(|command| command.to_string())
+                .collect(),
+            aliases: BTreeMap::new(),
+            default_flags: BTreeMap::new(),
+            row_limit: 0,
+            hex_ids: true,
+            db_path: None,
+            page_size: 0,
+            language: None,
+            profile: None,
+            profiles: BTreeMap::new(),
+            index: Index::default(),
+            offsets: Offsets::default(),
+            signatures: Signatures::default(),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn query_timeout(&self) -> Option<Duration> {
+        match self.query_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub(crate) fn watch_interval(&self) -> Duration {
+        Duration::from_secs(self.watch_interval_secs.max(1))
+    }
+
+    /// Whether `command` is one of `command_prefixes`, e.g. `ss`.
+    pub(crate) fn is_command_prefix(&self, command: &str) -> bool {
+        self.command_prefixes
+            .iter()
+            .any(|prefix| prefix.eq_ignore_ascii_case(command))
+    }
+
+    /// Whether the database is kept in a file rather than memory.
+    pub(crate) fn is_persisted(&self) -> bool {
+        match self.db_location {
+            Some(DbLocation::Memory) => false,
+            Some(_) => true,
+            None => self.persist,
+        }
+    }
+
+    /// The file of the database if `is_persisted`.
+    pub(crate) fn db_path(&self) -> anyhow::Result<String> {
+        match (&self.db_path, &self.db_location) {
+            (Some(db_path), _) => Ok(db_path.clone()),
+            (None, Some(DbLocation::Game)) => Ok(GAME_DB_PATH.to_string()),
+            (None, _) => Ok(path::my_games_dir()? + DB_PATH),
+        }
+    }
+
+    fn load() -> Self {
+        read_config()
+            .context("read_config")
+            .logging_ok()
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the file again, dropping the overrides of `ss config set`. Some settings, e.g.
+/// `persist` or `http_port`, only take effect on the next launch.
+pub(crate) fn reload() -> anyhow::Result<()> {
+    OVERRIDES.lock().unwrap().clear();
+    replace(read_config()?);
+    Ok(())
+}
+
+/// Overrides the setting `key`, e.g. `row_limit` or `aliases.n`, with `value` until the game
+/// quits, and returns it as TOML. `value` is TOML, or a string if it is not.
+pub(crate) fn set(key: &str, value: &str) -> anyhow::Result<String> {
+    let value = match toml::from_str::<toml::value::Table>(&format!("value = {}", value)) {
+        Ok(mut table) => table.remove("value").unwrap(),
+        Err(_) => toml::Value::String(value.to_string()),
+    };
+    let mut overrides = OVERRIDES.lock().unwrap();
+    let mut new_overrides = overrides.clone();
+    insert(&mut new_overrides, key, value);
+    let config = read_config_with(&new_overrides)?;
+    let set = lookup(&toml::Value::try_from(&config)?, key)
+        .with_context(|| format!("no setting {}", key))?;
+    *overrides = new_overrides;
+    replace(config);
+    Ok(format!("{} = {}", key, set))
+}
+
+fn read_config() -> anyhow::Result<Config> {
+    read_config_with(&OVERRIDES.lock().unwrap())
+}
+
+/// The settings of the file, or the defaults if there is none, with those of the selected profile
+/// and then `overrides` over them.
+fn read_config_with(overrides: &toml::value::Table) -> anyhow::Result<Config> {
+    let mut table = toml::value::Table::new();
+    for path in [CONFIG_PATH, LEGACY_CONFIG_PATH] {
+        match std::fs::read_to_string(path) {
+            Ok(s) => {
+                table = toml::from_str(&s).with_context(|| path.to_string())?;
+                break;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| path.to_string()),
+        }
+    }
+    merge(&mut table, overrides);
+    if let Some(profile) = table.get("profile").and_then(toml::Value::as_str) {
+        let settings = table
+            .get("profiles")
+            .and_then(|profiles| profiles.get(profile))
+            .and_then(toml::Value::as_table)
+            .cloned()
+            .with_context(|| format!("no profile {}", profile))?;
+        // the overrides of this session still win over the profile
+        merge(&mut table, &settings);
+        merge(&mut table, overrides);
+    }
+    Ok(toml::Value::Table(table).try_into()?)
+}
+
+/// Sets the dotted `key` of `table`, creating the tables on the way.
+fn insert(table: &mut toml::value::Table, key: &str, value: toml::Value) {
+    match key.split_once('.') {
+        Some((key, rest)) => {
+            let inner = table
+                .entry(key)
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+            if !inner.is_table() {
+                *inner = toml::Value::Table(toml::value::Table::new());
+            }
+            if let toml::Value::Table(inner) = inner {
+                insert(inner, rest, value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Copies `overrides` into `table`, keeping the keys of its tables that `overrides` lacks.
+fn merge(table: &mut toml::value::Table, overrides: &toml::value::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(overrides)) => {
+                merge(table, overrides)
+            }
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn lookup<'a>(value: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    key.split('.').try_fold(value, |value, key| value.get(key))
+}
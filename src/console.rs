@@ -1,24 +1,48 @@
+use crate::config::CONFIG;
+use crate::form::TESForm;
 use crate::log::Loggable;
-use crate::{app, log};
+use crate::{app, command, form, log, messages, patch, pattern, task};
 use anyhow::Context;
 use detour::GenericDetour;
 use late_static::LateStatic;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::ffi::{CStr, CString};
 use std::intrinsics::transmute;
-use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
 use winapi::_core::prelude::v1::Iterator;
 use winapi::ctypes::{c_char, c_void};
 
 fn new_process_console_input(param1: usize, param2: i64, param3: i64, param4: i64) {
+    if log::catch_panic("console", || {
+        run_console_input(param1, param2, param3, param4)
+    })
+    .is_none()
+    {
+        suspend_after_panic();
+    }
+}
+
+fn run_console_input(param1: usize, param2: i64, param3: i64, param4: i64) {
     let input = unsafe { CStr::from_ptr(*((param1 + 0x38) as *const *const c_char)).to_str() };
+    if !ENABLED.load(Ordering::SeqCst) {
+        if is_suspended() && input.map_or(false, app::is_enable_command) {
+            app::resume();
+        } else {
+            // a hook installed after this one still jumps here, see `disable`
+            S.process_console_input_hook
+                .call(param1, param2, param3, param4);
+        }
+        return;
+    }
     let result = match input {
         Ok(input) => {
-            {
-                let mut log = log::LOG.lock().unwrap();
-                log.write_all(input.as_bytes()).ok();
-                log.write_all("\n".as_bytes()).ok();
-            }
-            app::process_console_input(input)
+            log::info(input);
+            app::record_history(input);
+            // the console runs its scripts on the selected reference, if any
+            let selected = unsafe { (param4 as *const TESForm).as_ref() }.map(TESForm::form_id);
+            app::process_console_input(input, selected)
         }
         Err(err) => {
             print(err.to_string().as_str());
@@ -37,50 +61,327 @@ fn new_process_console_input(param1: usize, param2: i64, param3: i64, param4: i6
                 .call(param1, param2, param3, param4);
             print("skyrim-search-se usage: ss --help");
         }
+        Ok(app::ProcessResult::FallbackAndPrint(msg)) => {
+            S.process_console_input_hook
+                .call(param1, param2, param3, param4);
+            print(msg);
+        }
+        Ok(app::ProcessResult::Batch { file, lines }) => {
+            let file = file.map(|file| file.to_lowercase());
+            let refused = BATCHES.with(|batches| {
+                let batches = batches.borrow();
+                if batches.len() >= MAX_BATCH_DEPTH {
+                    Some(format!(
+                        "not running more than {} batches inside one another",
+                        MAX_BATCH_DEPTH
+                    ))
+                } else {
+                    file.as_ref()
+                        .filter(|file| batches.contains(&Some(file.to_string())))
+                        .map(|file| format!("{}: not running a batch file inside itself", file))
+                }
+            });
+            if let Some(msg) = refused {
+                print(msg);
+                return;
+            }
+            BATCHES.with(|batches| batches.borrow_mut().push(file));
+            // run each line as the script's text, so that the game runs the lines that are not
+            // ours as it runs console input
+            let text = (param1 + 0x38) as *mut *const c_char;
+            let original_text = unsafe { *text };
+            for (line, selected) in lines {
+                // the console runs the script on `param4`, as on a reference clicked in it
+                let selected = match selected.map(form::refr::look_up_reference) {
+                    Some(reference) if reference.is_null() => {
+                        print(format!("{}: not a loaded reference", line));
+                        continue;
+                    }
+                    Some(reference) => reference as i64,
+                    None => param4,
+                };
+                if let Some(line) = CString::new(line).logging_ok() {
+                    unsafe { *text = line.as_ptr() };
+                    new_process_console_input(param1, param2, param3, selected);
+                }
+            }
+            unsafe { *text = original_text };
+            BATCHES.with(|batches| batches.borrow_mut().pop());
+        }
     }
 }
 
+/// Batches run inside one another at most, e.g. by bat files that run each other.
+const MAX_BATCH_DEPTH: usize = 16;
+
+thread_local! {
+    /// The batches running, innermost last, with the lowercase path of their file if they have
+    /// one, so that a file that runs itself, directly or through another, is refused.
+    static BATCHES: RefCell<Vec<Option<String>>> = RefCell::new(Vec::new());
+}
+
+/// Whether the console hook processes the lines, which `disable` undoes.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Set by `ss disable`, after which only `ss enable` is processed.
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
 struct State {
+    process_console_input: usize,
     console_context: *const *const c_void,
     print_to_console: extern "C" fn(*const c_void, *const c_char, ...) -> (),
     process_console_input_hook: GenericDetour<fn(usize, i64, i64, i64)>,
+    resolved: Vec<(&'static str, usize, &'static str)>,
 }
 
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
 
+// The print_to_console's internal buffer size is 1024, including the terminating NUL.
+const MAX_LINE_LEN: usize = 1023;
+/// Calls of print_to_console per frame at most. The rest of a larger output is printed over the
+/// next frames, rather than stalling the game for seconds at once.
+const CHUNKS_PER_FRAME: usize = 64;
+
+/// The chunks waiting for the next frame to be printed, in order.
+static PENDING: Mutex<VecDeque<CString>> = Mutex::new(VecDeque::new());
+
+/// Splits `line` into chunks of at most `max_len` bytes without tearing multibyte characters
+/// apart. A chunk is broken after the last whitespace that fits, if there is one.
+fn split_line(line: &str, max_len: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = line;
+    while rest.len() > max_len {
+        let mut end = max_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some((pos, c)) = rest[..end]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+        {
+            if pos > 0 {
+                end = pos + c.len_utf8();
+            }
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks.push(rest);
+    chunks
+}
+
+/// Joins the `lines` with newlines into as few chunks of at most `max_len` bytes as there can
+/// be, each of them a call of print_to_console. A line longer than `max_len` is a chunk of its
+/// own.
+fn coalesce_lines<'a, I: IntoIterator<Item = &'a str>>(lines: I, max_len: usize) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    for line in lines {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() + 1 + line.len() <= max_len => {
+                chunk.push('\n');
+                chunk.push_str(line);
+            }
+            _ => chunks.push(line.to_string()),
+        }
+    }
+    chunks
+}
+
+thread_local! {
+    /// What `print` printed on this thread while `capture` runs.
+    static CAPTURED: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Runs `f`, and returns what it printed, which is printed to the console as well.
+pub(crate) fn capture<F: FnOnce()>(f: F) -> String {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(String::new()));
+    f();
+    CAPTURED
+        .with(|captured| captured.borrow_mut().take())
+        .unwrap_or_default()
+}
+
 pub(crate) fn print<T: Into<Vec<u8>>>(msg: T) {
     let msg = msg.into();
-    {
-        let mut log = log::LOG.lock().unwrap();
-        log.write_all(msg.as_slice()).ok();
-        log.write_all("\n".as_bytes()).ok();
-    }
     let msg = String::from_utf8_lossy(msg.as_ref());
+    log::info(&msg);
+    print_unlogged(&msg);
+}
+
+/// `print` without writing `msg` to `SkyrimSearchSE.log`, e.g. as it is read from there.
+pub(crate) fn print_unlogged(msg: &str) {
+    log::write_result(msg);
+    CAPTURED.with(|captured| {
+        if let Some(captured) = captured.borrow_mut().as_mut() {
+            captured.push_str(msg);
+            captured.push('\n');
+        }
+    });
     let msgs = msg.split('\n');
-    // The print_to_console's internal buffer size is 1024.
     // ensure each lines not to overflow
-    let chunks = msgs.flat_map(|msg| msg.as_bytes().chunks(1024));
-    let chunks: Vec<Result<CString, _>> = chunks.map(CString::new).collect();
+    let lines = msgs.flat_map(|msg| split_line(msg, MAX_LINE_LEN));
+    let chunks = coalesce_lines(lines, MAX_LINE_LEN)
+        .into_iter()
+        .filter_map(|chunk| CString::new(chunk).logging_ok());
+
+    let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+    // a flush is scheduled already unless nothing was pending
+    let flushing = !pending.is_empty();
+    pending.extend(chunks);
+    drop(pending);
+    if !flushing {
+        flush_pending();
+    }
+}
 
+/// Prints up to `CHUNKS_PER_FRAME` of the pending chunks, and schedules the rest for the next
+/// frame.
+fn flush_pending() {
+    let batch = {
+        let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+        let len = pending.len().min(CHUNKS_PER_FRAME);
+        pending.drain(..len).collect::<Vec<_>>()
+    };
     unsafe {
         let console_context = S.console_context;
-        if !console_context.is_null() {
-            for chunk in chunks {
-                if let Some(msg) = chunk.logging_ok() {
-                    (S.print_to_console)(
-                        *console_context,
-                        "%s\0".as_ptr() as *const c_char,
-                        msg.as_c_str().as_ptr(),
-                    );
-                }
-            }
+        if console_context.is_null() {
+            PENDING
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+            return;
+        }
+        for msg in batch {
+            (S.print_to_console)(
+                *console_context,
+                "%s\0".as_ptr() as *const c_char,
+                msg.as_c_str().as_ptr(),
+            );
         }
     }
+    if !PENDING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_empty()
+    {
+        task::add_task(flush_pending);
+    }
+}
+
+/// Drops the output that is still to be printed over the next frames. Returns whether there was
+/// any.
+pub(crate) fn cancel_pending() -> bool {
+    let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+    let cancelled = !pending.is_empty();
+    pending.clear();
+    cancelled
+}
+
+/// The Address Library IDs that the config overrides with an offset or a signature, so that
+/// the Address Library may lack them.
+pub(crate) fn overridden_ids() -> Vec<u64> {
+    let offsets = &CONFIG.offsets;
+    let signatures = &CONFIG.signatures;
+    [
+        (
+            441582,
+            offsets.process_console_input.is_some() || signatures.process_console_input.is_some(),
+        ),
+        (401203, offsets.console_context.is_some()),
+        (
+            51109,
+            offsets.print_to_console.is_some() || signatures.print_to_console.is_some(),
+        ),
+    ]
+    .iter()
+    .filter(|(_, overridden)| *overridden)
+    .map(|&(id, _)| id)
+    .collect()
+}
+
+/// The offset of a console function, and what it was found by: the config, else the Address
+/// Library, else its signature.
+unsafe fn resolve(
+    image_base: usize,
+    name: &str,
+    offset: Option<usize>,
+    signature: Option<&str>,
+    address_library: impl FnOnce() -> anyhow::Result<usize>,
+) -> anyhow::Result<(usize, &'static str)> {
+    if let Some(offset) = offset {
+        return Ok((offset, "config"));
+    }
+    let err = match address_library() {
+        Ok(offset) => return Ok((offset, "address library")),
+        Err(err) => err,
+    };
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return Err(err),
+    };
+    let offset = pattern::scan(image_base, signature)
+        .with_context(|| format!("{} signature: {}", name, signature))?;
+    log::info(format!("console: found {} at {:#x}", name, offset).as_str());
+    Ok((offset, "signature"))
 }
 
 pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
-    let target_addr = transmute(image_base + versionlib!(441582));
+    let offsets = &CONFIG.offsets;
+    let signatures = &CONFIG.signatures;
+    let resolved = vec![
+        (
+            "process_console_input",
+            resolve(
+                image_base,
+                "process_console_input",
+                offsets.process_console_input,
+                signatures.process_console_input.as_deref(),
+                || Ok(versionlib!(441582)),
+            )?,
+        ),
+        (
+            "console_context",
+            resolve(
+                image_base,
+                "console_context",
+                offsets.console_context,
+                None,
+                || Ok(versionlib!(401203)),
+            )?,
+        ),
+        (
+            "print_to_console",
+            resolve(
+                image_base,
+                "print_to_console",
+                offsets.print_to_console,
+                signatures.print_to_console.as_deref(),
+                || Ok(versionlib!(51109)),
+            )?,
+        ),
+    ]
+    .into_iter()
+    .map(|(name, (offset, source))| (name, image_base + offset, source))
+    .collect::<Vec<_>>();
+    let process_console_input = resolved[0].1;
+
+    // another plugin has hooked it already; the trampoline of this hook then leads to that one
+    let chain = patch::jump_chain(process_console_input);
+    if let Some(&hook) = chain.last().filter(|_| chain.len() > 1) {
+        log::warn(
+            format!(
+                "console: process_console_input is hooked by {}, which runs after this hook",
+                patch::describe_address(hook)
+            )
+            .as_str(),
+        );
+    }
+    let target_addr = transmute(process_console_input);
     let process_console_input_hook =
         GenericDetour::<fn(usize, i64, i64, i64)>::new(target_addr, new_process_console_input)
             .context("initialize")?;
@@ -88,13 +389,150 @@ pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
     LateStatic::assign(
         &S,
         State {
-            console_context: transmute(image_base + versionlib!(401203)),
-            print_to_console: transmute(image_base + versionlib!(51109)),
+            process_console_input,
+            console_context: transmute(resolved[1].1),
+            print_to_console: transmute(resolved[2].1),
             process_console_input_hook,
+            resolved,
         },
     );
 
     S.process_console_input_hook.enable().context("enable")?;
+    ENABLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// The addresses of the console functions, as (name, address, what it was found by).
+pub(crate) fn resolved_addresses() -> &'static [(&'static str, usize, &'static str)] {
+    &S.resolved
+}
+
+/// Removes the console hook, if it is installed, so that the console works as without the plugin.
+/// If another plugin has hooked the console after this one, its trampoline jumps to this hook, so
+/// the hook stays and only passes the lines on.
+pub(crate) fn disable() {
+    if ENABLED.swap(false, Ordering::SeqCst) && !hooked_after() {
+        unsafe { S.process_console_input_hook.disable() }
+            .context("disable")
+            .logging_ok();
+    }
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub(crate) fn is_suspended() -> bool {
+    SUSPENDED.load(Ordering::SeqCst)
+}
+
+/// Stops processing the lines for `ss disable`. The hook is removed too, unless it is all that
+/// still sees `ss enable`: when the command table has no `ss`, or another hook jumps to it.
+pub(crate) fn suspend() {
+    if !ENABLED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    SUSPENDED.store(true, Ordering::SeqCst);
+    if command::is_registered() && !hooked_after() {
+        unsafe { S.process_console_input_hook.disable() }
+            .context("disable")
+            .logging_ok();
+    }
+}
+
+/// Stops processing the lines as `ss disable` does, after a command panicked, which may have left
+/// the plugin in a state it does not expect.
+pub(crate) fn suspend_after_panic() {
+    suspend();
+    print(messages::get(
+        "$SkyrimSearchSE_Panicked",
+        "Skyrim Search SE hit an internal error, see SkyrimSearchSE.log. It is disabled; ss enable restores it",
+    ));
+}
 
+/// Undoes `suspend`, for `ss enable`.
+pub(crate) fn resume() -> anyhow::Result<()> {
+    if !SUSPENDED.load(Ordering::SeqCst) {
+        anyhow::bail!("not disabled by ss disable");
+    }
+    if !S.process_console_input_hook.is_enabled() {
+        unsafe { S.process_console_input_hook.enable() }.context("enable")?;
+    }
+    SUSPENDED.store(false, Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
     Ok(())
 }
+
+/// Whether the entry of `ProcessConsoleInput` no longer leads to this hook.
+fn hooked_after() -> bool {
+    !unsafe { patch::jump_chain(S.process_console_input) }
+        .contains(&(new_process_console_input as usize))
+}
+
+/// The hooks of `ProcessConsoleInput` in the order they run, one line each: those its entry
+/// jumps through to the first hook, then those that the trampoline of this plugin leads through
+/// to the code of the game.
+pub(crate) fn describe_hooks() -> Vec<String> {
+    let own = new_process_console_input as usize;
+    let describe = |chain: Vec<usize>| {
+        chain
+            .into_iter()
+            .map(|addr| {
+                let marker = if addr == own {
+                    " (skyrim-search-se)"
+                } else {
+                    ""
+                };
+                format!("  {}{}", patch::describe_address(addr), marker)
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut lines = vec![String::from("entry:")];
+    lines.extend(describe(unsafe {
+        patch::jump_chain(S.process_console_input)
+    }));
+    if hooked_after() {
+        lines.push(String::from(
+            "  ... (another hook that calls on through its trampoline)",
+        ));
+    }
+    lines.push(String::from("trampoline of skyrim-search-se:"));
+    let trampoline = S.process_console_input_hook.trampoline() as *const () as usize;
+    lines.extend(describe(unsafe { patch::jump_chain(trampoline) }));
+    lines.push(format!(
+        "skyrim-search-se processes the lines: {}",
+        ENABLED.load(Ordering::SeqCst)
+    ));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_line_respects_char_boundaries_and_words() {
+        assert_eq!(vec![""], split_line("", 8));
+        assert_eq!(vec!["Lydia"], split_line("Lydia", 8));
+        assert_eq!(
+            vec!["Jordis ", "the ", "Sword-Ma", "iden"],
+            split_line("Jordis the Sword-Maiden", 8)
+        );
+        // "é" is two bytes long and must not be split
+        assert_eq!(vec!["aaaaaaa", "éb"], split_line("aaaaaaaéb", 8));
+        for chunk in split_line("가나다라마바사", 8) {
+            assert!(chunk.len() <= 8);
+        }
+    }
+
+    #[test]
+    fn coalesce_lines_fills_chunks() {
+        assert_eq!(vec!["a\nbb", "ccc"], coalesce_lines(["a", "bb", "ccc"], 5));
+        assert_eq!(
+            vec!["", "toolong", "x"],
+            coalesce_lines(["", "toolong", "x"], 5)
+        );
+        assert!(coalesce_lines(Vec::<&str>::new(), 5).is_empty());
+    }
+}
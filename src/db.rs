@@ -1,121 +1,1589 @@
+use crate::config::CONFIG;
+use crate::hotkey;
+use crate::live;
+use crate::log;
 use crate::log::Loggable;
+use crate::path;
+use crate::plugins;
+use crate::records;
+use crate::strings;
+use crate::task;
 use anyhow::Context;
 use lazy_static::lazy_static;
-use std::sync::Mutex;
-use win_dbg_logger::output_debug_string;
+use rusqlite::{params, OptionalExtension, NO_PARAMS};
+use ss_db::function;
+pub(crate) use ss_db::{fts_query, split_statements, substring_query, ReadOnlyGuard};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+use winapi::um::winbase::THREAD_PRIORITY_BELOW_NORMAL;
 
 lazy_static! {
+    /// The connection of the console commands. The worker that indexes forms has its own
+    /// connection, so neither waits for the other. If the database fails to open, e.g. as
+    /// another process locks the file, it is an empty one until `rebuild` succeeds.
     pub static ref DB: Mutex<rusqlite::Connection> = {
         match init_db().context("init_db error") {
             Ok(db) => Mutex::new(db),
             Err(err) => {
-                let s = format!("{:#}", err);
-                output_debug_string(s.as_str());
-                panic!("{}", s);
+                set_open_error(&err);
+                Mutex::new(
+                    rusqlite::Connection::open_in_memory().expect("open_in_memory error"),
+                )
             }
         }
     };
-    pub static ref TASK_QUEUE: Mutex<std::sync::mpsc::Sender<Job>> = {
+    /// Why `DB` failed to open, until `rebuild` opens it.
+    static ref OPEN_ERROR: Mutex<Option<String>> = Mutex::new(None);
+    pub static ref TASK_QUEUE: Mutex<TaskQueue> = {
         let (tx, rx) = std::sync::mpsc::channel();
         std::thread::spawn(|| Worker(rx).worker());
-        Mutex::new(tx)
+        Mutex::new(TaskQueue(tx))
     };
+    static ref LOAD_ORDER_HASH: Option<String> = plugins::load_order_hash()
+        .context("load_order_hash")
+        .logging_ok();
+    /// The time the worker spent on the jobs that index each table, and their number, for
+    /// `build_stats`.
+    static ref BUILD_TIMES: Mutex<BTreeMap<&'static str, (Duration, u64)>> =
+        Mutex::new(BTreeMap::new());
+    /// The forms to index again, when the cached index is reused but some plugins changed.
+    static ref STALE: RwLock<Option<Stale>> = RwLock::new(None);
 }
 
+/// The forms of the plugins that changed since the cached index was built, or left the load
+/// order, and the forms the changed ones override.
+#[derive(Default)]
+struct Stale {
+    /// The ids of the plugins that changed in the cached `plugin` table.
+    plugin_ids: Vec<u32>,
+    /// The mask and the prefix of the form ids of each plugin.
+    prefixes: Vec<(u32, u32)>,
+    form_ids: HashSet<u32>,
+}
+
+impl Stale {
+    fn add_plugin(&mut self, form_id_prefix: u32, light: bool) {
+        let mask = if light { 0xFFFF_F000 } else { 0xFF00_0000 };
+        self.prefixes.push((mask, form_id_prefix));
+    }
+
+    fn contains(&self, form_id: u32) -> bool {
+        self.form_ids.contains(&form_id)
+            || self
+                .prefixes
+                .iter()
+                .any(|&(mask, prefix)| form_id & mask == prefix)
+    }
+}
+
+/// Notified when `rebuild` opens the database, which the worker waits for.
+static OPENED: Condvar = Condvar::new();
+
+/// Set when the persisted database has been indexed with the current load order, in which case
+/// forms loaded from the plugins do not need to be indexed again.
+static CACHED: AtomicBool = AtomicBool::new(false);
+
+/// The in-memory database is shared by the connections of the console and the worker.
+const MEMORY_DB_URI: &str = "file:skyrim-search-se?mode=memory&cache=shared";
+const USER_DB_PATH: &str = "\\SKSE\\skyrim-search-se-user.db";
+/// Where the tables of `SPILL_TABLES` move once the in-memory database exceeds
+/// `memory_limit_mb`, attached as `spill`.
+const SPILL_DB_PATH: &str = "\\SKSE\\skyrim-search-se-spill.db";
+/// The large tables of the in-memory database that `memory_limit_mb` moves to a file. Nothing
+/// else refers to them, so queries find them in `spill` once they are gone from `main`.
+const SPILL_TABLES: [&str; 1] = ["record_blob"];
+
+/// Set once the worker moved `SPILL_TABLES` to `spill`.
+static SPILLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The `QueryTimeout` of the statements this thread runs, which the progress handler checks
+    /// on the thread of the statement. The pipe, HTTP and other threads share the console
+    /// connection, but each has its own.
+    static INTERRUPT: RefCell<Option<Interrupt>> = RefCell::new(None);
+}
+
+/// Number of virtual machine instructions between two checks of the query deadline.
+const PROGRESS_OPS: c_int = 10000;
+
+/// Version of the index schema created by `init_db`. Bump it whenever the schema changes, so
+/// that persisted indexes of older versions are rebuilt rather than reused.
+const INDEX_SCHEMA_VERSION: u32 = 11;
+
+/// Migrations of the user database, which is never rebuilt. `USER_MIGRATIONS[i]` upgrades
+/// schema version `i` to `i + 1`. Append new migrations, and never change released ones.
+const USER_MIGRATIONS: [&str; 4] = [
+    r#"
+    CREATE TABLE IF NOT EXISTS user.saved_query (
+        name TEXT PRIMARY KEY NOT NULL COLLATE NOCASE,
+        sql TEXT NOT NULL
+    );
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS user.history (
+        id INTEGER PRIMARY KEY,
+        time TEXT NOT NULL DEFAULT (datetime('now', 'localtime')),
+        line TEXT NOT NULL
+    );
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS user.query_log (
+        id INTEGER PRIMARY KEY,
+        time TEXT NOT NULL DEFAULT (datetime('now', 'localtime')),
+        subcommand TEXT NOT NULL,
+        args TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        rows INTEGER,
+        error TEXT
+    );
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS user.bench_result (
+        id INTEGER PRIMARY KEY,
+        time TEXT NOT NULL DEFAULT (datetime('now', 'localtime')),
+        run INTEGER NOT NULL,
+        version TEXT NOT NULL,
+        name TEXT NOT NULL,
+        ms REAL NOT NULL
+    );
+    "#,
+];
+
+/// The tables of the user database that `USER_MIGRATIONS` and `init_meta` create, e.g. for
+/// `ss import`, which must not replace them.
+pub(crate) fn user_tables() -> Vec<&'static str> {
+    USER_MIGRATIONS
+        .iter()
+        .flat_map(|migration| migration.split("CREATE TABLE IF NOT EXISTS user.").skip(1))
+        .filter_map(|rest| {
+            rest.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .next()
+        })
+        .chain(std::iter::once("meta"))
+        .collect()
+}
+
+/// Form tables whose names and editor ids are indexed by `form_fts`.
+const FTS_TABLES: [&str; 3] = ["npc", "cell", "quest"];
+
 pub(crate) type Job = Box<dyn FnOnce(&rusqlite::Connection) -> anyhow::Result<()> + Send + 'static>;
 
-fn init_db() -> anyhow::Result<rusqlite::Connection> {
-    let conn = if crate::DEBUG {
-        rusqlite::Connection::open("skyrim_search_se.db").context("open error")?
+/// Jobs sent to the worker that it has not processed yet, and those it has.
+static PENDING_JOBS: AtomicUsize = AtomicUsize::new(0);
+static PROCESSED_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Indexing jobs the worker runs in one transaction at most, so that the console connection sees
+/// the progress in between.
+const JOBS_PER_TRANSACTION: usize = 10000;
+
+/// Set by `throttle_worker` once the indexes are created, from when on the forms are indexed as
+/// the game is played, e.g. the references of a city as the player walks in.
+static THROTTLED: AtomicBool = AtomicBool::new(false);
+/// The longest the worker runs the jobs of `send_loaded` in one transaction once `THROTTLED`, and
+/// how long it pauses after each such batch, which keeps it to a fraction of a core between frames.
+const THROTTLED_BATCH_TIME: Duration = Duration::from_millis(2);
+const THROTTLED_PAUSE: Duration = Duration::from_millis(8);
+
+/// The jobs sent by `send_first`, which the worker runs before those waiting in its queue.
+static FIRST_JOBS: Mutex<VecDeque<Job>> = Mutex::new(VecDeque::new());
+
+struct QueuedJob {
+    /// `None` only wakes the worker for `FIRST_JOBS`.
+    job: Option<Job>,
+    /// Sent by `send_indexing`, so that it may share a transaction with the jobs around it.
+    indexing: bool,
+    /// Sent by `send_loaded`, so that it is paced once `THROTTLED`.
+    paced: bool,
+}
+
+/// The queue of the worker, which counts the jobs for `ss status`.
+#[derive(Clone)]
+pub(crate) struct TaskQueue(std::sync::mpsc::Sender<QueuedJob>);
+
+impl TaskQueue {
+    pub(crate) fn send(&self, job: Job) -> Result<(), std::sync::mpsc::SendError<Job>> {
+        self.send_queued(job, false, false)
+    }
+
+    /// `send` for a job that a command waits for, which the worker runs after the job it is
+    /// running rather than after the indexing jobs in its queue.
+    pub(crate) fn send_first(&self, job: Job) -> Result<(), std::sync::mpsc::SendError<Job>> {
+        PENDING_JOBS.fetch_add(1, Ordering::SeqCst);
+        // held until the worker is woken, so that a failed send takes back this job
+        let mut first_jobs = FIRST_JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+        first_jobs.push_back(job);
+        let wake = QueuedJob {
+            job: None,
+            indexing: false,
+            paced: false,
+        };
+        self.0.send(wake).map_err(|_| {
+            PENDING_JOBS.fetch_sub(1, Ordering::SeqCst);
+            std::sync::mpsc::SendError(first_jobs.pop_back().unwrap())
+        })
+    }
+
+    fn send_queued(
+        &self,
+        job: Job,
+        indexing: bool,
+        paced: bool,
+    ) -> Result<(), std::sync::mpsc::SendError<Job>> {
+        PENDING_JOBS.fetch_add(1, Ordering::SeqCst);
+        let job = QueuedJob {
+            job: Some(job),
+            indexing,
+            paced,
+        };
+        self.0.send(job).map_err(|err| {
+            PENDING_JOBS.fetch_sub(1, Ordering::SeqCst);
+            std::sync::mpsc::SendError(err.0.job.unwrap())
+        })
+    }
+
+    /// `send` for a job that indexes the form table `table`, whose time goes to `build_stats`.
+    /// The worker runs the indexing jobs in a row in a transaction, so they must not begin one.
+    pub(crate) fn send_indexing(
+        &self,
+        table: &'static str,
+        job: Job,
+    ) -> Result<(), std::sync::mpsc::SendError<Job>> {
+        self.send_queued(Self::timed(table, job), true, false)
+    }
+
+    /// `send_indexing` for a job that indexes what the game loaded as it is played, which the
+    /// worker paces once `THROTTLED`, unlike the jobs of commands.
+    pub(crate) fn send_loaded(
+        &self,
+        table: &'static str,
+        job: Job,
+    ) -> Result<(), std::sync::mpsc::SendError<Job>> {
+        self.send_queued(Self::timed(table, job), true, true)
+    }
+
+    /// `job`, whose time goes to the `build_stats` of `table`.
+    fn timed(table: &'static str, job: Job) -> Job {
+        Box::new(move |db| {
+            let start = Instant::now();
+            let result = job(db);
+            if let Ok(mut times) = BUILD_TIMES.lock() {
+                let (time, jobs) = times.entry(table).or_default();
+                *time += start.elapsed();
+                *jobs += 1;
+            }
+            result
+        })
+    }
+}
+
+pub(crate) fn pending_jobs() -> usize {
+    PENDING_JOBS.load(Ordering::SeqCst)
+}
+
+pub(crate) fn processed_jobs() -> usize {
+    PROCESSED_JOBS.load(Ordering::SeqCst)
+}
+
+fn open_connection() -> anyhow::Result<rusqlite::Connection> {
+    let conn = if CONFIG.is_persisted() {
+        let db_path = CONFIG.db_path()?;
+        if let Some(dir) = std::path::Path::new(&db_path).parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("create_dir_all: {}", dir.display()))?;
+        }
+        let conn = rusqlite::Connection::open(&db_path)
+            .with_context(|| format!("open error: {}", db_path))?;
+        // only takes effect before the first table is created
+        if CONFIG.page_size != 0 {
+            conn.execute_batch(&format!("PRAGMA page_size={};", CONFIG.page_size))
+                .context("page_size error")?;
+        }
+        // WAL lets the console read while the worker writes
+        conn.execute_batch("PRAGMA journal_mode=WAL;")
+            .context("journal_mode error")?;
+        conn
     } else {
-        rusqlite::Connection::open("").context("open error")?
+        let conn = rusqlite::Connection::open(MEMORY_DB_URI).context("open error")?;
+        // the shared cache locks tables, so read what the worker has not committed yet instead
+        // of waiting for it
+        conn.execute_batch("PRAGMA read_uncommitted=ON;")
+            .context("read_uncommitted error")?;
+        conn
     };
 
+    function::register(&conn).context("register functions error")?;
+    conn.busy_timeout(Duration::from_secs(5))
+        .context("busy_timeout error")?;
     conn.execute_batch(
         r#"
         PRAGMA mmap_size=268435456;
         PRAGMA synchronous=OFF;
-        PRAGMA journal_mode=OFF;
+        "#,
+    )
+    .context("init_pragma error")?;
+    if is_spillable() {
+        conn.execute(
+            "ATTACH DATABASE ? AS spill",
+            params![path::my_games_dir()? + SPILL_DB_PATH],
+        )
+        .context("attach spill error")?;
+        conn.execute_batch("PRAGMA spill.journal_mode=WAL; PRAGMA spill.synchronous=OFF;")
+            .context("spill pragma error")?;
+    }
+
+    Ok(conn)
+}
+
+/// Whether the in-memory database may move its large tables to a file, as `memory_limit_mb` is
+/// set. A persisted database is on disk already, and SQLite keeps only its cache in memory.
+fn is_spillable() -> bool {
+    CONFIG.memory_limit_mb != 0 && !CONFIG.is_persisted()
+}
+
+/// Limits the memory SQLite uses to `memory_limit_mb`, which it keeps to by shrinking the caches
+/// of the connections. Only moving tables out of the in-memory database frees what they hold.
+pub(crate) fn init_memory_limit() {
+    let limit = (CONFIG.memory_limit_mb * 1024 * 1024) as i64;
+    unsafe {
+        rusqlite::ffi::sqlite3_soft_heap_limit64(limit);
+    }
+}
+
+/// The bytes SQLite holds now, and the most it held since the game started.
+pub(crate) fn memory_used() -> (i64, i64) {
+    unsafe {
+        (
+            rusqlite::ffi::sqlite3_memory_used(),
+            rusqlite::ffi::sqlite3_memory_highwater(0),
+        )
+    }
+}
+
+/// The tables that were moved to `spill` as the database exceeded `memory_limit_mb`.
+pub(crate) fn spilled_tables() -> &'static [&'static str] {
+    if SPILLED.load(Ordering::SeqCst) {
+        &SPILL_TABLES
+    } else {
+        &[]
+    }
+}
+
+/// Moves `SPILL_TABLES` to `spill`, once SQLite holds more than `memory_limit_mb`, and frees the
+/// memory they took by VACUUMing the in-memory database.
+fn spill_if_over_limit(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    if SPILLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let (used, _) = memory_used();
+    if (used as u64) < CONFIG.memory_limit_mb * 1024 * 1024 {
+        return Ok(());
+    }
+    let start = Instant::now();
+    let tx = db.unchecked_transaction()?;
+    for table in SPILL_TABLES {
+        let sql: String = tx.query_row(
+            "SELECT sql FROM main.sqlite_master WHERE type = 'table' AND name = ?",
+            &[table],
+            |row| row.get(0),
+        )?;
+        tx.execute_batch(&format!(
+            "DROP TABLE IF EXISTS spill.{0}; {1}; \
+             INSERT INTO spill.{0} SELECT * FROM main.{0}; DROP TABLE main.{0};",
+            table,
+            sql.replacen(
+                &format!("CREATE TABLE {}", table),
+                &format!("CREATE TABLE spill.{}", table),
+                1
+            )
+        ))?;
+    }
+    tx.commit()?;
+    SPILLED.store(true, Ordering::SeqCst);
+    // the pages of the dropped tables stay allocated until then
+    db.execute_batch("VACUUM main;")
+        .context("vacuum error")
+        .logging_ok();
+    log::info(
+        format!(
+            "db: moved {} to {} at {} MB in {} ms",
+            SPILL_TABLES.join(", "),
+            SPILL_DB_PATH,
+            used / (1024 * 1024),
+            start.elapsed().as_millis()
+        )
+        .as_str(),
+    );
+    Ok(())
+}
+
+fn open_console_connection() -> anyhow::Result<rusqlite::Connection> {
+    let conn = open_connection()?;
+    conn.progress_handler(PROGRESS_OPS, Some(is_interrupted));
+    // the statements of the searches, a few per subcommand, and those of history and the like
+    conn.set_prepared_statement_cache_capacity(64);
+    live::register(&conn).context("live::register error")?;
+
+    attach_user_db(&conn)
+        .context("attach_user_db error")
+        .logging_ok();
+
+    Ok(conn)
+}
 
-        DROP TABLE IF EXISTS npc;
-        CREATE TABLE npc (
+fn init_db() -> anyhow::Result<rusqlite::Connection> {
+    let conn = open_console_connection()?;
+
+    init_meta(&conn, "main").context("init_meta error")?;
+
+    if CONFIG.is_persisted() && is_cache_valid(&conn).context("is_cache_valid error")? {
+        log::info("db: reusing the cached index");
+        CACHED.store(true, Ordering::SeqCst);
+        init_runtime_tables(&conn).context("init_runtime_tables error")?;
+        return Ok(conn);
+    }
+    if CONFIG.is_persisted() {
+        let active = plugins::active_plugins();
+        if let Some(stale) = find_stale(&conn, active).context("find_stale error")? {
+            remove_stale(&conn, &stale, active).context("remove_stale error")?;
+            *STALE.write().unwrap_or_else(PoisonError::into_inner) = Some(stale);
+            init_runtime_tables(&conn).context("init_runtime_tables error")?;
+            return Ok(conn);
+        }
+    }
+    // the index is incomplete until save_load_order_hash is called
+    conn.execute(
+        "DELETE FROM main.meta WHERE key IN ('load_order_hash', 'record_blob', 'texts')",
+        NO_PARAMS,
+    )
+    .context("invalidate_cache error")?;
+    drop_index(&conn).context("drop_index error")?;
+    if is_spillable() {
+        // the tables an earlier launch moved there, which the new index replaces
+        for table in SPILL_TABLES {
+            conn.execute_batch(&format!("DROP TABLE IF EXISTS spill.{};", table))
+                .context("drop spill error")?;
+        }
+        SPILLED.store(false, Ordering::SeqCst);
+    }
+
+    conn.execute_batch(
+        r#"
+        -- the active plugins, whose id is their position in the load order
+        CREATE TABLE plugin (
+            id INTEGER PRIMARY KEY NOT NULL,
+            name TEXT COLLATE NOCASE,
+            master INTEGER, -- ESM
+            light INTEGER, -- ESL
+            form_id_prefix INTEGER,
+            size INTEGER,
+            modified TEXT,
+            hash TEXT -- of the contents, to tell whether a plugin changed once it is modified
+        );
+
+        -- the names of the forms, stored once however many forms share them, e.g. Bandit
+        CREATE TABLE form_name (
+            id INTEGER PRIMARY KEY NOT NULL,
+            text TEXT NOT NULL
+        );
+        CREATE INDEX form_name_text ON form_name (text COLLATE fold);
+
+        -- npc, cell and quest are views of these, with the names looked up in form_name
+        CREATE TABLE npc_form (
             form_id INTEGER PRIMARY KEY NOT NULL,
-            editor_id TEXT COLLATE NOCASE,
-            name TEXT COLLATE NOCASE
+            plugin_id INTEGER, -- the plugin that defines it
+            editor_id TEXT COLLATE fold,
+            name_id INTEGER,
+            name_localized_id INTEGER
         );
+        CREATE VIEW npc AS
+        SELECT npc_form.form_id, npc_form.plugin_id, npc_form.editor_id,
+            n.text COLLATE fold AS name, l.text COLLATE fold AS name_localized
+        FROM npc_form
+        LEFT JOIN form_name n ON n.id = npc_form.name_id
+        LEFT JOIN form_name l ON l.id = npc_form.name_localized_id;
 
-        DROP TABLE IF EXISTS actor;
         CREATE TABLE actor (
             form_id INTEGER PRIMARY KEY NOT NULL,
             base_form_id INTEGER
         );
 
-        DROP TABLE IF EXISTS cell;
-        CREATE TABLE cell (
+        CREATE TABLE cell_form (
             form_id INTEGER PRIMARY KEY NOT NULL,
-            editor_id TEXT COLLATE NOCASE,
-            name TEXT COLLATE NOCASE
+            plugin_id INTEGER, -- the plugin that defines it
+            editor_id TEXT COLLATE fold,
+            name_id INTEGER,
+            name_localized_id INTEGER
         );
+        CREATE VIEW cell AS
+        SELECT cell_form.form_id, cell_form.plugin_id, cell_form.editor_id,
+            n.text COLLATE fold AS name, l.text COLLATE fold AS name_localized
+        FROM cell_form
+        LEFT JOIN form_name n ON n.id = cell_form.name_id
+        LEFT JOIN form_name l ON l.id = cell_form.name_localized_id;
 
-        DROP TABLE IF EXISTS quest;
-        CREATE TABLE quest (
+        CREATE TABLE quest_form (
             form_id INTEGER PRIMARY KEY NOT NULL,
-            editor_id TEXT COLLATE NOCASE,
-            name TEXT COLLATE NOCASE
+            plugin_id INTEGER, -- the plugin that defines it
+            editor_id TEXT COLLATE fold,
+            name_id INTEGER,
+            name_localized_id INTEGER,
+            stages TEXT -- JSON
         );
+        CREATE VIEW quest AS
+        SELECT quest_form.form_id, quest_form.plugin_id, quest_form.editor_id,
+            n.text COLLATE fold AS name, l.text COLLATE fold AS name_localized,
+            quest_form.stages
+        FROM quest_form
+        LEFT JOIN form_name n ON n.id = quest_form.name_id
+        LEFT JOIN form_name l ON l.id = quest_form.name_localized_id;
 
-        DROP TABLE IF EXISTS quest_stage;
         CREATE TABLE quest_stage (
             form_id TEXT COLLATE NOCASE,
             stage INTEGER,
             log INTEGER,
             PRIMARY KEY (form_id, stage)
         );
+
+        CREATE TABLE record_blob (
+            form_id INTEGER PRIMARY KEY NOT NULL,
+            plugin_id INTEGER, -- the last plugin that overrides it
+            type TEXT,
+            flags INTEGER,
+            data BLOB,
+            subrecords TEXT -- JSON
+        );
+
+        -- the plugins that have records of the forms of the form tables, whether they define or
+        -- override them, to tell which forms a plugin overrode once it changed. Those of a plugin
+        -- are stored along with its hash.
+        CREATE TABLE form_plugin (
+            form_id INTEGER NOT NULL,
+            plugin_id INTEGER NOT NULL,
+            PRIMARY KEY (plugin_id, form_id)
+        ) WITHOUT ROWID;
+
+        CREATE VIRTUAL TABLE form_fts USING fts5 (
+            kind UNINDEXED,
+            editor_id,
+            name,
+            name_localized,
+            text -- of the books and dialogue responses, see records::build_texts
+        );
+
+        -- how long indexing took, per form table, as of when the indexes were created
+        CREATE TABLE build_stats (
+            name TEXT PRIMARY KEY NOT NULL,
+            rows INTEGER,
+            jobs INTEGER, -- the jobs of the worker that inserted the rows
+            insert_ms INTEGER, -- the time the worker spent on them
+            index_ms INTEGER -- the time creating the indexes of the table took
+        );
         "#,
     )
     .context("init_schema error")?;
+    conn.execute_batch(&ss_db::trigram_schema())
+        .context("init_trigram_schema error")?;
+    set_meta(
+        &conn,
+        "main",
+        "schema_version",
+        &INDEX_SCHEMA_VERSION.to_string(),
+    )
+    .context("set_meta error")?;
+    insert_plugins(&conn, plugins::active_plugins()).context("insert_plugins error")?;
+
+    // keep form_fts and form_trigram in sync with the form tables. rowid of form_fts is the
+    // form_id.
+    for table in FTS_TABLES {
+        conn.execute_batch(
+            format!(
+                r#"
+                CREATE TRIGGER {0}_fts_insert AFTER INSERT ON {0}_form BEGIN
+                    DELETE FROM form_fts WHERE rowid = new.form_id;
+                    INSERT INTO form_fts (rowid, kind, editor_id, name, name_localized)
+                    SELECT form_id, '{0}', editor_id, name, name_localized
+                    FROM {0} WHERE form_id = new.form_id;
+                    DELETE FROM form_trigram WHERE form_id = new.form_id;
+                    {1};
+                END;
+                CREATE TRIGGER {0}_fts_update AFTER UPDATE ON {0}_form BEGIN
+                    DELETE FROM form_fts WHERE rowid = new.form_id;
+                    INSERT INTO form_fts (rowid, kind, editor_id, name, name_localized)
+                    SELECT form_id, '{0}', editor_id, name, name_localized
+                    FROM {0} WHERE form_id = new.form_id;
+                    DELETE FROM form_trigram WHERE form_id = new.form_id;
+                    {1};
+                END;
+                CREATE TRIGGER {0}_fts_delete AFTER DELETE ON {0}_form BEGIN
+                    DELETE FROM form_fts WHERE rowid = old.form_id;
+                    DELETE FROM form_trigram WHERE form_id = old.form_id;
+                END;
+                -- e.g. for the plugins that changed since the index was cached
+                CREATE TRIGGER {0}_delete INSTEAD OF DELETE ON {0} BEGIN
+                    DELETE FROM {0}_form WHERE form_id = old.form_id;
+                END;
+                "#,
+                table,
+                ss_db::insert_trigrams_sql("new.form_id")
+            )
+            .as_str(),
+        )
+        .with_context(|| format!("init_fts_triggers error: {}", table))?;
+    }
+    init_runtime_tables(&conn).context("init_runtime_tables error")?;
 
     Ok(conn)
 }
 
-pub(crate) fn init_index(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-    db.execute_batch(
+fn insert_plugins(conn: &rusqlite::Connection, active: &[plugins::Plugin]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO plugin (id, name, master, light, form_id_prefix, size, modified) \
+         VALUES (?, ?, ?, ?, ?, ?, datetime(?, 'unixepoch', 'localtime'))",
+    )?;
+    for (id, plugin) in active.iter().enumerate() {
+        stmt.execute(params![
+            id as u32,
+            plugin.name,
+            plugin.master,
+            plugin.light,
+            plugin.form_id_prefix,
+            plugin.size as i64,
+            plugin.modified.map(|modified| modified as i64)
+        ])?;
+    }
+    Ok(())
+}
+
+/// Tables of the running game rather than the plugins, which start empty in every session even
+/// if the index is reused.
+fn init_runtime_tables(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
         r#"
-        CREATE INDEX IF NOT EXISTS npc_editor_id ON npc (editor_id);
-        CREATE INDEX IF NOT EXISTS npc_name ON npc (name);
+        -- references in the attached cells; x, y, z as of when they were attached
+        CREATE TABLE IF NOT EXISTS refr (
+            form_id INTEGER PRIMARY KEY NOT NULL,
+            base_form_id INTEGER,
+            name TEXT COLLATE fold,
+            cell_form_id INTEGER,
+            x REAL,
+            y REAL,
+            z REAL
+        );
+        DELETE FROM refr;
+        CREATE INDEX IF NOT EXISTS refr_base_form_id ON refr (base_form_id);
+
+        -- forms created in game (FF), named after the base form if they are references
+        CREATE TABLE IF NOT EXISTS dynamic_form (
+            form_id INTEGER PRIMARY KEY NOT NULL,
+            form_type INTEGER,
+            name TEXT COLLATE fold,
+            base_form_id INTEGER,
+            cell_form_id INTEGER,
+            first_seen TEXT DEFAULT (datetime('now', 'localtime')),
+            creator TEXT -- 'attached' for references seen as their cell was attached, 'form map' for the others
+        );
+        DELETE FROM dynamic_form;
+
+        -- snapshots taken by `ss inv`, one per owner
+        CREATE TABLE IF NOT EXISTS inventory (
+            owner_form_id INTEGER NOT NULL,
+            item_form_id INTEGER NOT NULL,
+            name TEXT COLLATE fold,
+            count INTEGER,
+            equipped INTEGER,
+            charge REAL,
+            snapshot_time TEXT DEFAULT (datetime('now', 'localtime')),
+            PRIMARY KEY (owner_form_id, item_form_id)
+        );
+        DELETE FROM inventory;
+
+        -- globals as the plugins define them (baseline) and as of the last `ss globals` (value)
+        CREATE TABLE IF NOT EXISTS global (
+            form_id INTEGER PRIMARY KEY NOT NULL,
+            editor_id TEXT COLLATE fold,
+            type TEXT,
+            baseline REAL,
+            value REAL
+        );
+        DELETE FROM global;
+
+        -- the scripts the plugins attach to forms with VMAD, read at the first `ss papyrus`, a
+        -- row per property; a row without script is an override that removes the scripts
+        CREATE TABLE IF NOT EXISTS script (
+            form_id INTEGER NOT NULL,
+            plugin_id INTEGER NOT NULL,
+            script TEXT,
+            property TEXT,
+            value TEXT
+        );
+        DELETE FROM script;
+        CREATE INDEX IF NOT EXISTS script_form_id ON script (form_id);
+        "#,
+    )
+}
+
+/// Locks the console connection. If a panic poisoned the lock, the connection is replaced with a
+/// fresh one, because the panic may have left it in the middle of something. The index itself
+/// lives on in the shared database.
+pub(crate) fn lock() -> anyhow::Result<MutexGuard<'static, rusqlite::Connection>> {
+    check()?;
+    match DB.lock() {
+        Ok(db) => Ok(db),
+        Err(poisoned) => {
+            let mut db = poisoned.into_inner();
+            let msg = "db: the lock was poisoned by a panic, reopening the connection";
+            log::warn(msg);
+            *db = open_console_connection().context("reopen error")?;
+            DB.clear_poison();
+            Ok(db)
+        }
+    }
+}
+
+fn set_open_error(err: &anyhow::Error) {
+    let msg = format!("{:#}", err);
+    log::error(msg.as_str());
+    *OPEN_ERROR.lock().unwrap_or_else(PoisonError::into_inner) = Some(msg);
+}
+
+/// Fails with the reason if the database could not be opened, rather than letting the command
+/// wait for the worker, which waits for `rebuild`.
+pub(crate) fn check() -> anyhow::Result<()> {
+    lazy_static::initialize(&DB);
+    match OPEN_ERROR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+    {
+        Some(err) => anyhow::bail!(
+            "the database could not be opened: {}. ss rebuild tries again",
+            err
+        ),
+        None => Ok(()),
+    }
+}
+
+/// What `rebuild` did.
+pub(crate) enum Rebuild {
+    /// The database failed to open, and opened now.
+    Opened,
+    /// No plugin changed since the index was built.
+    Unchanged,
+    /// The forms of this many changed plugins, and those they override, are indexed again.
+    Reindexing(usize),
+}
 
-        CREATE INDEX IF NOT EXISTS actor_base_form_id ON actor (base_form_id);
+/// Tries to open the database again for `ss rebuild`, if it failed to open. If it is open, the
+/// forms of the plugins that changed since the index was built are indexed again from the plugin
+/// files, as they are at the next launch otherwise.
+pub(crate) fn rebuild() -> anyhow::Result<Rebuild> {
+    lazy_static::initialize(&DB);
+    if OPEN_ERROR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_none()
+    {
+        return reindex_stale();
+    }
+    let conn = match init_db().context("init_db error") {
+        Ok(conn) => conn,
+        Err(err) => {
+            set_open_error(&err);
+            return Err(err);
+        }
+    };
+    *DB.lock().unwrap_or_else(PoisonError::into_inner) = conn;
+    DB.clear_poison();
+    *OPEN_ERROR.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    OPENED.notify_all();
+    log::info("db: opened by ss rebuild");
+    Ok(Rebuild::Opened)
+}
 
-        CREATE INDEX IF NOT EXISTS cell_editor_id ON cell (editor_id);
-        CREATE INDEX IF NOT EXISTS cell_name ON cell (name);
+/// Has the worker delete the forms of the plugins that changed, and those the plugins override
+/// or overrode, and index them again from the plugins as they are now. Once it is done, the index
+/// is saved for the plugins as they are now, which the game itself only loads at the next launch.
+fn reindex_stale() -> anyhow::Result<Rebuild> {
+    if !CONFIG.is_persisted() {
+        anyhow::bail!("only a persisted index knows the plugins it was built of");
+    }
+    let active = plugins::reread_plugins();
+    let stale = {
+        let db = lock()?;
+        if get_meta(&db, "main", "load_order_hash")?.is_none() {
+            anyhow::bail!("the index is not complete yet, ss status shows the progress");
+        }
+        find_stale(&db, &active).context("find_stale error")?
+    };
+    let stale = match stale {
+        Some(stale) if stale.prefixes.is_empty() => return Ok(Rebuild::Unchanged),
+        Some(stale) => Arc::new(stale),
+        None => anyhow::bail!("all plugins changed, the next launch builds the index again"),
+    };
+    let changed = stale.plugin_ids.len();
+    let task_queue = TASK_QUEUE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let removed = stale.clone();
+    task_queue
+        .send(Box::new(move |db| {
+            remove_stale(db, &removed, &active).context("remove_stale")
+        }))
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    std::thread::spawn(move || {
+        records::reindex(&task_queue, |form_id| stale.contains(form_id))
+            .context("reindex")
+            .logging_ok();
+        task_queue
+            .send(Box::new(|db| {
+                let hash = plugins::load_order_hash().context("load_order_hash")?;
+                set_load_order_hash(db, &hash).context("set_load_order_hash")?;
+                log::info("db: indexed the changed plugins again");
+                Ok(())
+            }))
+            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            .logging_ok();
+    });
+    Ok(Rebuild::Reindexing(changed))
+}
+
+/// The SQLite version, where the index is stored and its schema version, for `ss version`.
+pub(crate) fn describe() -> anyhow::Result<String> {
+    let storage = if CONFIG.is_persisted() {
+        CONFIG.db_path()?
+    } else {
+        String::from("memory")
+    };
+    Ok(format!(
+        "SQLite {}, {}, index schema {}{}",
+        rusqlite::version(),
+        storage,
+        INDEX_SCHEMA_VERSION,
+        if is_cached() {
+            ", reused"
+        } else if STALE
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+        {
+            ", partly reused"
+        } else {
+            ""
+        }
+    ))
+}
 
-        CREATE INDEX IF NOT EXISTS quest_editor_id ON quest (editor_id);
-        CREATE INDEX IF NOT EXISTS quest_name ON quest (name);
+/// Closes the console connection for `ss disable`, and with it the user database and the
+/// attached ones. The index lives on in the connection of the worker.
+pub(crate) fn release() -> anyhow::Result<()> {
+    *lock()? = rusqlite::Connection::open_in_memory().context("open_in_memory error")?;
+    Ok(())
+}
 
-        CREATE INDEX IF NOT EXISTS quest_stage_form_id ON quest_stage (form_id);
-     "#,
+/// Opens the console connection again for `ss enable`.
+pub(crate) fn reopen() -> anyhow::Result<()> {
+    *lock()? = open_console_connection().context("reopen error")?;
+    Ok(())
+}
+
+/// Attaches the database that keeps what users save, e.g. by `ss save`, as `user`. Unlike the
+/// index, it is always persisted.
+fn attach_user_db(conn: &rusqlite::Connection) -> anyhow::Result<()> {
+    conn.execute(
+        "ATTACH DATABASE ? AS user",
+        params![path::my_games_dir()? + USER_DB_PATH],
+    )?;
+    init_meta(conn, "user")?;
+    migrate(conn, "user", &USER_MIGRATIONS)
+}
+
+fn init_meta(conn: &rusqlite::Connection, schema: &str) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        format!(
+            "CREATE TABLE IF NOT EXISTS {}.meta (key TEXT PRIMARY KEY NOT NULL, value TEXT);",
+            schema
+        )
+        .as_str(),
     )
 }
 
-struct Worker(std::sync::mpsc::Receiver<Job>);
+fn get_meta(
+    conn: &rusqlite::Connection,
+    schema: &str,
+    key: &str,
+) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        format!("SELECT value FROM {}.meta WHERE key = ?", schema).as_str(),
+        params![key],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+fn set_meta(
+    conn: &rusqlite::Connection,
+    schema: &str,
+    key: &str,
+    value: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        format!(
+            "INSERT OR REPLACE INTO {}.meta (key, value) VALUES (?, ?)",
+            schema
+        )
+        .as_str(),
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+/// Applies the migrations that `schema` has not seen yet, each in its own transaction.
+fn migrate(conn: &rusqlite::Connection, schema: &str, migrations: &[&str]) -> anyhow::Result<()> {
+    let version = get_meta(conn, schema, "schema_version")?
+        .and_then(|version| version.parse::<usize>().ok())
+        .unwrap_or(0);
+    if version > migrations.len() {
+        anyhow::bail!(
+            "{} has schema version {}, but this plugin only knows {}",
+            schema,
+            version,
+            migrations.len()
+        );
+    }
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(migration)
+            .with_context(|| format!("{} migration {}", schema, i + 1))?;
+        set_meta(&tx, schema, "schema_version", &(i + 1).to_string())?;
+        tx.commit()?;
+        log::info(format!("db: migrated {} to version {}", schema, i + 1).as_str());
+    }
+    Ok(())
+}
+
+/// Drops everything in the main database but `meta`, including tables of older versions.
+fn drop_index(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // virtual tables first, which also drops their shadow tables
+    for virtual_only in [true, false] {
+        let names = conn
+            .prepare(
+                "SELECT type, name FROM main.sqlite_master \
+                 WHERE type IN ('table', 'view') AND name <> 'meta' AND name NOT LIKE 'sqlite_%' \
+                 AND (? = 0 OR sql LIKE 'CREATE VIRTUAL TABLE%')",
+            )?
+            .query_map(params![virtual_only], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (kind, name) in names {
+            conn.execute_batch(
+                format!(
+                    "DROP {} IF EXISTS main.\"{}\";",
+                    kind.to_ascii_uppercase(),
+                    name.replace('"', "\"\"")
+                )
+                .as_str(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn is_cache_valid(conn: &rusqlite::Connection) -> rusqlite::Result<bool> {
+    let hash = match LOAD_ORDER_HASH.as_ref() {
+        Some(hash) => hash,
+        None => return Ok(false),
+    };
+    if !is_cache_compatible(conn)? {
+        return Ok(false);
+    }
+    Ok(get_meta(conn, "main", "load_order_hash")?.as_ref() == Some(hash))
+}
+
+/// Whether the cached index was built by this schema, of the same kinds and language.
+fn is_cache_compatible(conn: &rusqlite::Connection) -> rusqlite::Result<bool> {
+    let schema_version = get_meta(conn, "main", "schema_version")?;
+    if schema_version != Some(INDEX_SCHEMA_VERSION.to_string()) {
+        return Ok(false);
+    }
+    // an index of fewer kinds is not enough, and one of more would drift from the plugins
+    if get_meta(conn, "main", "index_kinds")? != Some(CONFIG.index.kinds()) {
+        return Ok(false);
+    }
+    Ok(get_meta(conn, "main", "language")?.as_deref() == Some(strings::language()))
+}
+
+/// Compares the plugins of the cached index with the `active` ones, if the index is complete but
+/// the load order changed, or the plugins did since. Returns None if no plugin is unchanged, or
+/// the index cannot be reused at all. A plugin is unchanged if it is still at the same position,
+/// and its size and modification time, or else its contents, are the same.
+fn find_stale(
+    conn: &rusqlite::Connection,
+    active: &[plugins::Plugin],
+) -> anyhow::Result<Option<Stale>> {
+    if !is_cache_compatible(conn)? || get_meta(conn, "main", "load_order_hash")?.is_none() {
+        return Ok(None);
+    }
+    let cached = conn
+        .prepare(
+            "SELECT id, name, form_id_prefix, light, size, \
+             CAST(strftime('%s', modified, 'utc') AS INTEGER), hash FROM plugin ORDER BY id",
+        )?
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u32>(2)?,
+                row.get::<_, bool>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stale = Stale::default();
+    for (id, name, prefix, light, size, modified, hash) in &cached {
+        let unchanged = active.get(*id as usize).map_or(false, |plugin| {
+            plugin.name.eq_ignore_ascii_case(name)
+                && plugin.form_id_prefix == *prefix
+                && plugin.light == *light
+                && ((plugin.size as i64 == *size
+                    && plugin.modified.map(|modified| modified as i64) == *modified)
+                    || (hash.is_some()
+                        && plugins::content_hash(&plugin.name).logging_ok() == *hash))
+        });
+        if !unchanged {
+            stale.add_plugin(*prefix, *light);
+            stale.plugin_ids.push(*id);
+        }
+    }
+    if stale.plugin_ids.len() == cached.len() {
+        return Ok(None);
+    }
+    for (id, plugin) in active.iter().enumerate() {
+        if id >= cached.len() || stale.plugin_ids.contains(&(id as u32)) {
+            stale.add_plugin(plugin.form_id_prefix, plugin.light);
+            if let Some(form_ids) = records::indexed_form_ids(&plugin.name)
+                .with_context(|| plugin.name.clone())
+                .logging_ok()
+            {
+                stale.form_ids.extend(form_ids);
+            }
+        }
+    }
+    // the forms the old versions defined or overrode, as far as form_plugin knows them, which
+    // lacks the plugins the game quit before hashing, and record_blob if it is stored
+    for sql in [
+        "SELECT form_id FROM form_plugin WHERE plugin_id = ?",
+        "SELECT form_id FROM record_blob WHERE plugin_id = ?",
+    ] {
+        let mut stmt = conn.prepare(sql)?;
+        for id in &stale.plugin_ids {
+            for form_id in stmt.query_map(&[id], |row| row.get::<_, u32>(0))? {
+                stale.form_ids.insert(form_id?);
+            }
+        }
+    }
+    log::info(
+        format!(
+            "db: reusing the cached index but for {} changed plugins and {} overridden forms",
+            stale.plugin_ids.len() + active.len().saturating_sub(cached.len()),
+            stale.form_ids.len()
+        )
+        .as_str(),
+    );
+    Ok(Some(stale))
+}
+
+/// Deletes the stale forms from the form tables, and replaces the plugins with the `active` ones.
+fn remove_stale(
+    conn: &rusqlite::Connection,
+    stale: &Stale,
+    active: &[plugins::Plugin],
+) -> anyhow::Result<()> {
+    let mut hashes = conn
+        .prepare("SELECT id, name, hash FROM plugin WHERE hash IS NOT NULL")?
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // the changed plugins are hashed again once they are indexed
+    hashes.retain(|(id, name, _)| {
+        !stale.plugin_ids.contains(id)
+            && active
+                .get(*id as usize)
+                .map_or(false, |plugin| plugin.name.eq_ignore_ascii_case(name))
+    });
+
+    let tx = conn.unchecked_transaction()?;
+    // the index is incomplete until save_load_order_hash is called
+    tx.execute(
+        "DELETE FROM main.meta WHERE key = 'load_order_hash'",
+        NO_PARAMS,
+    )?;
+    for (table, _) in INDEXES {
+        for (mask, prefix) in &stale.prefixes {
+            tx.execute(
+                format!("DELETE FROM {} WHERE (form_id & ?) = ?", table).as_str(),
+                params![mask, prefix],
+            )?;
+        }
+        let mut stmt = tx.prepare(format!("DELETE FROM {} WHERE form_id = ?", table).as_str())?;
+        for form_id in &stale.form_ids {
+            stmt.execute(&[form_id])?;
+        }
+    }
+    tx.execute("DELETE FROM plugin", NO_PARAMS)?;
+    insert_plugins(&tx, active)?;
+    for (id, _, hash) in hashes {
+        tx.execute("UPDATE plugin SET hash = ? WHERE id = ?", params![hash, id])?;
+    }
+    // and their forms are stored again along with their hashes
+    tx.execute(
+        "DELETE FROM form_plugin WHERE plugin_id NOT IN \
+         (SELECT id FROM plugin WHERE hash IS NOT NULL)",
+        NO_PARAMS,
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Hashes the plugins the index has no hash of yet, on a thread of its own as it reads them
+/// whole, so that the next launch can tell whether they changed, and stores the forms they have
+/// records of in `form_plugin`, so that it can tell which forms they overrode.
+fn store_plugin_hashes(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    let missing = db
+        .prepare("SELECT id, name FROM plugin WHERE hash IS NULL ORDER BY id")?
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let task_queue = TASK_QUEUE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    std::thread::spawn(move || {
+        for (id, name) in missing {
+            let hash = match plugins::content_hash(&name)
+                .with_context(|| name.clone())
+                .logging_ok()
+            {
+                Some(hash) => hash,
+                None => continue,
+            };
+            let form_ids = match records::indexed_form_ids(&name)
+                .with_context(|| name.clone())
+                .logging_ok()
+            {
+                Some(form_ids) => form_ids,
+                None => continue,
+            };
+            task_queue
+                .send(Box::new(move |db| {
+                    let tx = db.unchecked_transaction()?;
+                    tx.execute("DELETE FROM form_plugin WHERE plugin_id = ?", &[id])?;
+                    let mut stmt = tx.prepare(
+                        "INSERT OR IGNORE INTO form_plugin (form_id, plugin_id) VALUES (?, ?)",
+                    )?;
+                    for form_id in form_ids {
+                        stmt.execute(params![form_id, id]).context("form_plugin")?;
+                    }
+                    drop(stmt);
+                    tx.execute("UPDATE plugin SET hash = ? WHERE id = ?", params![hash, id])
+                        .context("plugin hash")?;
+                    tx.commit()?;
+                    Ok(())
+                }))
+                .map_err(|e| anyhow::anyhow!(e.to_string()))
+                .logging_ok();
+        }
+    });
+    Ok(())
+}
+
+/// Whether the forms loaded from the plugins are already in the persisted database.
+pub(crate) fn is_cached() -> bool {
+    CACHED.load(Ordering::SeqCst)
+}
+
+/// The id in `form_name` of `text`, which is added unless it is there already, for the `name_id` and
+/// `name_localized_id` columns of the form tables. Forms of the same name share its row.
+pub(crate) fn intern(
+    db: &rusqlite::Connection,
+    text: Option<&str>,
+) -> rusqlite::Result<Option<i64>> {
+    let text = match text {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    // form_name_text finds the names that fold to the same, of which only the exact one is taken
+    let id = db
+        .prepare_cached("SELECT id FROM form_name WHERE text = ?1 COLLATE fold AND text = ?1")?
+        .query_row(&[text], |row| row.get(0))
+        .optional()?;
+    if id.is_some() {
+        return Ok(id);
+    }
+    db.prepare_cached("INSERT INTO form_name (text) VALUES (?)")?
+        .execute(&[text])?;
+    Ok(Some(db.last_insert_rowid()))
+}
+
+/// Whether the form `form_id` loaded from the plugins is already in the persisted database: all
+/// are if `is_cached`, and those of the unchanged plugins are if only some plugins changed.
+pub(crate) fn is_form_cached(form_id: u32) -> bool {
+    is_cached()
+        || STALE
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map_or(false, |stale| !stale.contains(form_id))
+}
+
+/// Whether `record_blob` holds the records of all plugins, as of when the index was built.
+pub(crate) fn is_record_blob_complete() -> bool {
+    lock()
+        .ok()
+        .and_then(|db| get_meta(&db, "main", "record_blob").ok().flatten())
+        .as_deref()
+        == Some("complete")
+}
+
+/// Records that `record_blob` holds the records of all plugins, which a cached index may lack
+/// if the game quit before they were read, or `index.lazy` never had them read.
+pub(crate) fn set_record_blob_complete(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    set_meta(db, "main", "record_blob", "complete")
+}
+
+/// Whether `form_fts` holds the texts of all plugins, as of when the index was built.
+pub(crate) fn are_texts_complete() -> bool {
+    lock()
+        .ok()
+        .and_then(|db| get_meta(&db, "main", "texts").ok().flatten())
+        .as_deref()
+        == Some("complete")
+}
+
+/// Records that `form_fts` holds the texts of all plugins, see `set_record_blob_complete`.
+pub(crate) fn set_texts_complete(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    set_meta(db, "main", "texts", "complete")
+}
+
+/// Records that the database is fully indexed for the current load order, so that the next
+/// launch can reuse it.
+pub(crate) fn save_load_order_hash(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    if !CONFIG.is_persisted() {
+        return Ok(());
+    }
+    match LOAD_ORDER_HASH.as_ref() {
+        Some(hash) => set_load_order_hash(db, hash),
+        None => {
+            store_plugin_hashes(db)
+                .context("store_plugin_hashes")
+                .logging_ok();
+            Ok(())
+        }
+    }
+}
+
+/// `save_load_order_hash` with the hash of the load order `hash`.
+fn set_load_order_hash(db: &rusqlite::Connection, hash: &str) -> rusqlite::Result<()> {
+    set_meta(db, "main", "index_kinds", &CONFIG.index.kinds())?;
+    set_meta(db, "main", "language", strings::language())?;
+    set_meta(db, "main", "load_order_hash", hash)?;
+    store_plugin_hashes(db)
+        .context("store_plugin_hashes")
+        .logging_ok();
+    Ok(())
+}
+
+/// The form tables with the indexes created once all forms are loaded.
+const INDEXES: [(&str, &str); 6] = [
+    (
+        "npc",
+        r#"
+        CREATE INDEX IF NOT EXISTS npc_editor_id ON npc_form (editor_id);
+        CREATE INDEX IF NOT EXISTS npc_name ON npc_form (name_id);
+        CREATE INDEX IF NOT EXISTS npc_name_localized ON npc_form (name_localized_id);
+        "#,
+    ),
+    (
+        "actor",
+        "CREATE INDEX IF NOT EXISTS actor_base_form_id ON actor (base_form_id);",
+    ),
+    (
+        "cell",
+        r#"
+        CREATE INDEX IF NOT EXISTS cell_editor_id ON cell_form (editor_id);
+        CREATE INDEX IF NOT EXISTS cell_name ON cell_form (name_id);
+        CREATE INDEX IF NOT EXISTS cell_name_localized ON cell_form (name_localized_id);
+        "#,
+    ),
+    (
+        "quest",
+        r#"
+        CREATE INDEX IF NOT EXISTS quest_editor_id ON quest_form (editor_id);
+        CREATE INDEX IF NOT EXISTS quest_name ON quest_form (name_id);
+        CREATE INDEX IF NOT EXISTS quest_name_localized ON quest_form (name_localized_id);
+        "#,
+    ),
+    (
+        "quest_stage",
+        "CREATE INDEX IF NOT EXISTS quest_stage_form_id ON quest_stage (form_id);",
+    ),
+    ("record_blob", ""),
+];
+
+/// Creates the indexes of the form tables, and records how long each table took in
+/// `build_stats`, unless the index is reused from an earlier launch.
+pub(crate) fn init_index(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let mut index_times = Vec::with_capacity(INDEXES.len());
+    for (table, sql) in INDEXES {
+        let start = Instant::now();
+        db.execute_batch(sql)?;
+        index_times.push((table, start.elapsed()));
+    }
+    if is_cached() {
+        return Ok(());
+    }
+    let build_times = BUILD_TIMES
+        .lock()
+        .map(|times| times.clone())
+        .unwrap_or_default();
+    for (table, index_time) in index_times {
+        let rows: i64 = db.query_row(
+            format!("SELECT count(*) FROM {}", table).as_str(),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        let (insert_time, jobs) = build_times.get(table).copied().unwrap_or_default();
+        db.execute(
+            "INSERT OR REPLACE INTO build_stats (name, rows, jobs, insert_ms, index_ms) \
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                table,
+                rows,
+                jobs as i64,
+                insert_time.as_millis() as i64,
+                index_time.as_millis() as i64
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Has the worker, which calls this once it created the indexes, index what the game loads from
+/// then on in short transactions with pauses in between, at a lower priority than the threads
+/// of the game, so that it keeps out of their way rather than finishing as soon as it can.
+pub(crate) fn throttle_worker() {
+    THROTTLED.store(true, Ordering::SeqCst);
+    unsafe {
+        SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL as c_int);
+    }
+}
+
+/// What aborts the statements of a `QueryTimeout`.
+struct Interrupt {
+    timeout: Option<Duration>,
+    /// Set once a statement runs, so that the time spent waiting for the connection, e.g. while
+    /// another thread's query holds it, does not count.
+    deadline: Cell<Option<Instant>>,
+    cancelled: Cell<bool>,
+}
+
+/// Makes the statements that this thread runs on the console connection fail with
+/// `OperationInterrupted` once they have run for `timeout`, until the guard is dropped. Guards of
+/// other threads are independent of it.
+pub(crate) struct QueryTimeout {
+    /// The guard of this thread that this one replaced, if it is nested in one.
+    outer: Option<Interrupt>,
+}
+
+impl QueryTimeout {
+    pub(crate) fn start(timeout: Option<Duration>) -> Self {
+        let interrupt = Interrupt {
+            timeout,
+            deadline: Cell::new(None),
+            cancelled: Cell::new(false),
+        };
+        QueryTimeout {
+            outer: INTERRUPT.with(|current| current.replace(Some(interrupt))),
+        }
+    }
+
+    /// Whether the statements were aborted by `cancel` rather than by the timeout.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        INTERRUPT.with(|current| {
+            current
+                .borrow()
+                .as_ref()
+                .map_or(false, |interrupt| interrupt.cancelled.get())
+        })
+    }
+}
+
+impl Drop for QueryTimeout {
+    fn drop(&mut self) {
+        INTERRUPT.with(|current| *current.borrow_mut() = self.outer.take());
+    }
+}
+
+fn is_interrupted() -> bool {
+    INTERRUPT.with(|current| {
+        let current = current.borrow();
+        let interrupt = match current.as_ref() {
+            Some(interrupt) => interrupt,
+            None => return false,
+        };
+        // the console commands run on the main thread
+        if task::is_main_thread() && hotkey::is_cancel_key_down() {
+            interrupt.cancelled.set(true);
+        }
+        if interrupt.cancelled.get() {
+            return true;
+        }
+        let now = Instant::now();
+        let deadline = interrupt
+            .deadline
+            .get()
+            .or_else(|| interrupt.timeout.map(|timeout| now + timeout));
+        interrupt.deadline.set(deadline);
+        deadline.map_or(false, |deadline| now >= deadline)
+    })
+}
+
+/// Whether `err` was caused by a statement aborted by `QueryTimeout`.
+pub(crate) fn is_interrupt_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::OperationInterrupted
+        )
+    })
+}
+
+struct Worker(std::sync::mpsc::Receiver<QueuedJob>);
 
 impl Worker {
     fn worker(self) {
         let task_queue = self.0;
+        // the console connection creates the schema, and the jobs wait in the queue until it has
+        lazy_static::initialize(&DB);
+        {
+            let mut error = OPEN_ERROR.lock().unwrap_or_else(PoisonError::into_inner);
+            while error.is_some() {
+                error = OPENED.wait(error).unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+        let db = match open_connection()
+            .context("worker open_connection error")
+            .logging_ok()
+        {
+            Some(db) => db,
+            None => return,
+        };
+        // the indexing jobs use a statement or two each, and the others some more
+        db.set_prepared_statement_cache_capacity(64);
+        // as the connection attached `spill`, whatever `ss config set` changes later, until
+        // moving the tables fails
+        let mut spillable = is_spillable();
         loop {
             let job = task_queue.recv().unwrap();
-            let db = DB.lock().unwrap();
-            let mut num_jobs = 1;
-            Self::process_job(&db, job).logging_ok();
-            for job in task_queue.try_iter() {
+            let mut num_jobs = 0;
+            // indexing jobs in the transaction so far, which commits once a job of another kind
+            // comes, the queue is empty, or it reaches `JOBS_PER_TRANSACTION`, or once
+            // `THROTTLED`, `THROTTLED_BATCH_TIME` after it began
+            let mut transaction_jobs = 0;
+            let mut transaction_start = Instant::now();
+            for job in std::iter::once(job).chain(task_queue.try_iter()) {
+                let first_jobs =
+                    std::mem::take(&mut *FIRST_JOBS.lock().unwrap_or_else(PoisonError::into_inner));
+                if !first_jobs.is_empty() {
+                    // they may begin transactions of their own
+                    Self::commit(&db);
+                    transaction_jobs = 0;
+                    for first_job in first_jobs {
+                        num_jobs += 1;
+                        Self::process_job(&db, first_job).logging_ok();
+                    }
+                }
+                let QueuedJob {
+                    job,
+                    indexing,
+                    paced,
+                } = job;
+                let job = match job {
+                    Some(job) => job,
+                    None => continue,
+                };
                 num_jobs += 1;
+                let throttled = paced && THROTTLED.load(Ordering::SeqCst);
+                if transaction_jobs > 0
+                    && (!indexing
+                        || transaction_jobs == JOBS_PER_TRANSACTION
+                        || (throttled && transaction_start.elapsed() >= THROTTLED_BATCH_TIME))
+                {
+                    Self::commit(&db);
+                    transaction_jobs = 0;
+                    if throttled {
+                        std::thread::sleep(THROTTLED_PAUSE);
+                    }
+                }
+                if indexing {
+                    if transaction_jobs == 0 {
+                        db.execute_batch("BEGIN")
+                            .context("worker begin")
+                            .logging_ok();
+                        transaction_start = Instant::now();
+                    }
+                    transaction_jobs += 1;
+                }
                 Self::process_job(&db, job).logging_ok();
             }
-            output_debug_string(format!("processed {} jobs", num_jobs).as_str());
+            if transaction_jobs > 0 {
+                Self::commit(&db);
+            }
+            if spillable
+                && spill_if_over_limit(&db)
+                    .context("spill_if_over_limit")
+                    .logging_ok()
+                    .is_none()
+            {
+                spillable = false;
+            }
+            log::debug(format!("processed {} jobs", num_jobs).as_str());
+        }
+    }
+
+    fn commit(db: &rusqlite::Connection) {
+        if !db.is_autocommit() {
+            db.execute_batch("COMMIT")
+                .context("worker commit")
+                .logging_ok();
         }
     }
 
     fn process_job(db: &rusqlite::Connection, msg: Job) -> anyhow::Result<()> {
-        msg(db)
+        // a job that panics must not take the worker, and the jobs after it, down with it
+        let result = log::catch_panic("worker", || msg(db))
+            .unwrap_or_else(|| Err(anyhow::anyhow!("the job panicked")));
+        PENDING_JOBS.fetch_sub(1, Ordering::SeqCst);
+        PROCESSED_JOBS.fetch_add(1, Ordering::SeqCst);
+        result
     }
 }
@@ -0,0 +1,201 @@
+use late_static::LateStatic;
+use std::mem::transmute;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use winapi::ctypes::c_void;
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+
+/// `BSEventNotifyControl::kContinue` and `kStop`, which a sink returns to pass the event on to the
+/// sinks after it or not.
+pub(crate) const CONTINUE: u32 = 0;
+pub(crate) const STOP: u32 = 1;
+
+/// Sinks an array holds once it first grows.
+const MIN_CAPACITY: u32 = 4;
+
+struct State {
+    /// `MemoryManager::GetSingleton`
+    memory_manager: extern "C" fn() -> *mut c_void,
+    /// `MemoryManager::Allocate(size, alignment, aligned)`
+    allocate: extern "C" fn(*mut c_void, usize, i32, bool) -> *mut c_void,
+    /// `MemoryManager::Deallocate(memory, aligned)`
+    deallocate: extern "C" fn(*mut c_void, *mut c_void, bool),
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+/// A `BSTEventSink<T>`, whose `process_event` gets a `*const T`.
+#[repr(C)]
+pub(crate) struct EventSink {
+    pub(crate) vtable: *const EventSinkVtable,
+}
+unsafe impl Sync for EventSink {}
+
+#[repr(C)]
+pub(crate) struct EventSinkVtable {
+    pub(crate) destructor: usize,
+    /// Returns `STOP` to stop the other sinks from getting the event.
+    pub(crate) process_event:
+        extern "C" fn(*const EventSink, *const c_void, *mut EventSource) -> u32,
+}
+
+/// `BSTArray<BSTEventSink<T>*>`, whose memory belongs to the game's allocator.
+#[repr(C)]
+struct SinkArray {
+    data: *mut *const EventSink,
+    capacity: u32,   // 08
+    padding_0c: u32, // 0C
+    len: u32,        // 10
+    padding_14: u32, // 14
+}
+
+impl SinkArray {
+    unsafe fn as_slice(&self) -> &[*const EventSink] {
+        if self.data.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(self.data, self.len as usize)
+        }
+    }
+
+    unsafe fn contains(&self, sink: *const EventSink) -> bool {
+        self.as_slice().contains(&sink)
+    }
+
+    /// Appends `sink`, growing the array as `BSTArray::push_back` does.
+    unsafe fn push(&mut self, sink: *const EventSink) {
+        if self.len == self.capacity {
+            let capacity = (self.capacity * 2).max(MIN_CAPACITY);
+            let memory_manager = (S.memory_manager)();
+            let data = (S.allocate)(
+                memory_manager,
+                capacity as usize * std::mem::size_of::<*const EventSink>(),
+                0,
+                false,
+            ) as *mut *const EventSink;
+            if data.is_null() {
+                return;
+            }
+            if !self.data.is_null() {
+                ptr::copy_nonoverlapping(self.data, data, self.len as usize);
+                (S.deallocate)(memory_manager, self.data as *mut c_void, false);
+            }
+            self.data = data;
+            self.capacity = capacity;
+        }
+        *self.data.add(self.len as usize) = sink;
+        self.len += 1;
+    }
+
+    unsafe fn remove(&mut self, sink: *const EventSink) {
+        if let Some(i) = self.as_slice().iter().position(|&other| other == sink) {
+            ptr::copy(
+                self.data.add(i + 1),
+                self.data.add(i),
+                self.len as usize - i - 1,
+            );
+            self.len -= 1;
+        }
+    }
+}
+
+/// `BSTEventSource<T>`, which passes the events of the game to its sinks.
+#[repr(C)]
+pub(crate) struct EventSource {
+    sinks: SinkArray,
+    /// The sinks registered and unregistered while `notifying`, which are added and removed
+    /// before the next event.
+    pending_registers: SinkArray, // 18
+    pending_unregisters: SinkArray, // 30
+    lock_owner: AtomicU32,          // 48, `BSSpinLock`
+    lock_count: AtomicU32,          // 4C
+    notifying: bool,                // 50
+}
+
+impl EventSource {
+    /// Takes the lock as `BSSpinLock::Lock` does, where the owning thread may take it again.
+    fn lock(&self) {
+        let thread_id = unsafe { GetCurrentThreadId() };
+        if self.lock_owner.load(Ordering::SeqCst) == thread_id {
+            self.lock_count.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+        while self
+            .lock_count
+            .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            std::thread::yield_now();
+        }
+        self.lock_owner.store(thread_id, Ordering::SeqCst);
+    }
+
+    fn unlock(&self) {
+        if self.lock_count.load(Ordering::SeqCst) == 1 {
+            self.lock_owner.store(0, Ordering::SeqCst);
+            self.lock_count.store(0, Ordering::SeqCst);
+        } else {
+            self.lock_count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Registers `sink` as `BSTEventSource::AddEventSink` does: while the source is notifying its
+    /// sinks, it gets the events from the next one on.
+    pub(crate) unsafe fn add_sink(&mut self, sink: *const EventSink) {
+        self.lock();
+        if self.notifying {
+            if !self.pending_registers.contains(sink) {
+                self.pending_registers.push(sink);
+            }
+        } else if !self.sinks.contains(sink) {
+            self.sinks.push(sink);
+        }
+        self.pending_unregisters.remove(sink);
+        self.unlock();
+    }
+
+    /// Passes `event` to the sinks, as `BSTEventSource::SendEvent` does: the sinks registered and
+    /// unregistered meanwhile are added and removed first, and those that the sinks register or
+    /// unregister as they get the event wait for the next one.
+    pub(crate) unsafe fn send(&mut self, event: *const c_void) {
+        self.lock();
+        let was_notifying = self.notifying;
+        self.notifying = true;
+        if !was_notifying {
+            for i in 0..self.pending_registers.len as usize {
+                let sink = *self.pending_registers.data.add(i);
+                if !self.sinks.contains(sink) {
+                    self.sinks.push(sink);
+                }
+            }
+            self.pending_registers.len = 0;
+            for i in 0..self.pending_unregisters.len as usize {
+                self.sinks.remove(*self.pending_unregisters.data.add(i));
+            }
+            self.pending_unregisters.len = 0;
+        }
+        // the array stays as it is while notifying
+        for i in 0..self.sinks.len as usize {
+            let sink = *self.sinks.data.add(i);
+            if let Some(sink_ref) = sink.as_ref() {
+                if ((*sink_ref.vtable).process_event)(sink, event, self) == STOP {
+                    break;
+                }
+            }
+        }
+        self.notifying = was_notifying;
+        self.unlock();
+    }
+}
+
+pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
+    LateStatic::assign(
+        &S,
+        State {
+            memory_manager: transmute(image_base + versionlib!(11141)),
+            allocate: transmute(image_base + versionlib!(68115)),
+            deallocate: transmute(image_base + versionlib!(68117)),
+        },
+    );
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use crate::{api, console, db, plugins};
+use rusqlite::OptionalExtension;
+use std::ffi::{CStr, CString};
+use std::ptr;
+use winapi::ctypes::c_char;
+
+/// Bumped whenever a function or `SkyrimSearchResult` changes incompatibly.
+const FFI_VERSION: u32 = 1;
+
+/// What the functions that other plugins find with `GetProcAddress` return. Unlike requests
+/// through the messaging interface, they may be called from any thread, and the result lives
+/// until `SkyrimSearch_FreeResult`.
+#[repr(C)]
+pub struct SkyrimSearchResult {
+    version: u32,
+    /// UTF-8, null on success
+    error: *mut c_char, // 08
+    /// `{"columns": [...], "rows": [[...], ...]}`, null on error
+    json: *mut c_char, // 10
+}
+
+fn into_result(result: anyhow::Result<String>) -> *mut SkyrimSearchResult {
+    let (json, error) = match result.and_then(|json| Ok(CString::new(json)?)) {
+        Ok(json) => (json.into_raw(), ptr::null_mut()),
+        Err(err) => (
+            ptr::null_mut(),
+            CString::new(format!("{:#}", err))
+                .unwrap_or_default()
+                .into_raw(),
+        ),
+    };
+    Box::into_raw(Box::new(SkyrimSearchResult {
+        version: FFI_VERSION,
+        error,
+        json,
+    }))
+}
+
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> anyhow::Result<&'a str> {
+    if s.is_null() {
+        anyhow::bail!("{} is null", name);
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+/// Whether this DLL implements version `version` of the functions, and can answer queries.
+#[no_mangle]
+pub extern "C" fn SkyrimSearch_Init(version: u32) -> bool {
+    version == FFI_VERSION && console::is_enabled()
+}
+
+/// Runs the read-only statement `sql`, as `ss raw` without `--write` does.
+///
+/// # Safety
+///
+/// `sql` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn SkyrimSearch_Query(sql: *const c_char) -> *mut SkyrimSearchResult {
+    into_result(to_str(sql, "sql").and_then(|sql| api::query_json(sql, &[])))
+}
+
+/// Searches `terms` as `ss grep` does, only among the forms of `kind` (e.g. `npc`) unless it is
+/// null.
+///
+/// # Safety
+///
+/// `terms` and `kind` must be null or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn SkyrimSearch_Search(
+    terms: *const c_char,
+    kind: *const c_char,
+) -> *mut SkyrimSearchResult {
+    into_result((|| {
+        let terms = to_str(terms, "terms")?;
+        let kind = if kind.is_null() {
+            None
+        } else {
+            Some(to_str(kind, "kind")?)
+        };
+        api::search_json(terms, kind)
+    })())
+}
+
+/// Writes what the index knows of `form_id` to `buffer` as a NUL-terminated UTF-8 string, e.g.
+/// `npc HousecarlWhiterun "Lydia" (Skyrim.esm)`, truncated to `buffer_len` bytes. Returns its
+/// length without the NUL, or 0 if the form is not indexed.
+///
+/// Meant for crash loggers as they handle an exception: it allocates no result, and returns 0
+/// rather than waiting if the database is in use, as it may be by the thread that crashed.
+///
+/// # Safety
+///
+/// `buffer` must be null or point to `buffer_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn SkyrimSearch_DescribeForm(
+    form_id: u32,
+    buffer: *mut c_char,
+    buffer_len: usize,
+) -> usize {
+    if buffer.is_null() || buffer_len == 0 || !console::is_enabled() {
+        return 0;
+    }
+    let description = match describe_form(form_id) {
+        Some(description) => description,
+        None => return 0,
+    };
+    let len = floor_char_boundary(&description, buffer_len - 1);
+    ptr::copy_nonoverlapping(description.as_ptr(), buffer as *mut u8, len);
+    *buffer.add(len) = 0;
+    len
+}
+
+fn describe_form(form_id: u32) -> Option<String> {
+    let db = db::DB.try_lock().ok()?;
+    let (kind, editor_id, name): (String, Option<String>, Option<String>) = db
+        .query_row(
+            "SELECT kind, editor_id, name FROM form_fts WHERE rowid = ?",
+            &[form_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .ok()??;
+    let mut description = kind;
+    if let Some(editor_id) = editor_id {
+        description += &format!(" {}", editor_id);
+    }
+    if let Some(name) = name {
+        description += &format!(" \"{}\"", name);
+    }
+    if let Some(plugin) =
+        plugins::plugin_id(form_id).and_then(|id| plugins::active_plugins().get(id as usize))
+    {
+        description += &format!(" ({})", plugin.name);
+    }
+    Some(description)
+}
+
+/// The largest length of at most `len` that does not split a character of `s`.
+fn floor_char_boundary(s: &str, len: usize) -> usize {
+    if len >= s.len() {
+        return s.len();
+    }
+    (0..=len)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// Frees a result of `SkyrimSearch_Query` or `SkyrimSearch_Search`.
+///
+/// # Safety
+///
+/// `result` must be null or a result that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn SkyrimSearch_FreeResult(result: *mut SkyrimSearchResult) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    for s in [result.error, result.json] {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::db;
-use crate::db::Job;
+use crate::db::TaskQueue;
 use crate::form::refr::TESObjectREFR;
+use crate::log;
 use crate::log::Loggable;
 use crate::patch::patch_bytes;
 use anyhow::{anyhow, Context};
@@ -9,8 +10,6 @@ use rusqlite::params;
 use std::fmt::Formatter;
 use std::mem::transmute;
 use std::ops::Deref;
-use std::sync::mpsc::Sender;
-use win_dbg_logger::output_debug_string;
 use winapi::ctypes::c_void;
 
 struct TESCharacter(TESObjectREFR);
@@ -18,7 +17,7 @@ struct TESCharacter(TESObjectREFR);
 struct State {
     character_vtable: usize,
     character_load: fn(&TESCharacter, u64) -> u64,
-    task_queue: Sender<Job>,
+    task_queue: TaskQueue,
 }
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
@@ -35,28 +34,35 @@ impl std::fmt::Debug for State {
 impl TESCharacter {
     fn new_load(&self, arg: u64) -> u64 {
         let ret = (S.character_load)(self, arg);
+        log::catch_panic("actor", || self.index());
+        ret
+    }
+
+    fn index(&self) {
         let base_form = if !self.0.base_form.is_null() {
             unsafe { &*self.0.base_form }
         } else {
-            return ret;
+            return;
         };
         let form_id = self.0.form.form_id;
         let result: anyhow::Result<()> = (|| {
             S.task_queue
-                .send(Box::new(move |db| {
-                    db.prepare_cached(
-                        "INSERT OR REPLACE INTO actor (form_id, base_form_id) VALUES (?, ?);",
-                    )
-                    .context("chracter_new_load prepare")?
-                    .execute(params![form_id, base_form.form_id])
-                    .context("character_new_load execute")?;
-                    Ok(())
-                }))
+                .send_loaded(
+                    "actor",
+                    Box::new(move |db| {
+                        db.prepare_cached(
+                            "INSERT OR REPLACE INTO actor (form_id, base_form_id) VALUES (?, ?);",
+                        )
+                        .context("chracter_new_load prepare")?
+                        .execute(params![form_id, base_form.form_id])
+                        .context("character_new_load execute")?;
+                        Ok(())
+                    }),
+                )
                 .map_err(|e| anyhow!(e.to_string()))?;
             Ok(())
         })();
         result.logging_ok();
-        ret
     }
 }
 
@@ -78,7 +84,7 @@ pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
         },
     );
 
-    output_debug_string(format!("S: {:#x?}", S.deref()).as_str());
+    log::debug(format!("S: {:#x?}", S.deref()).as_str());
 
     Ok(())
 }
@@ -0,0 +1,279 @@
+use crate::address::{self, Profile};
+use crate::form::inventory::SimpleList;
+use crate::form::refr::{self, TESObjectREFR, FORM_TYPE_ACHR};
+use crate::form::TESForm;
+use anyhow::Context;
+use std::mem::transmute;
+
+/// The `ActorValue`s shown by `ss actor`.
+const ACTOR_VALUES: [(&str, u32); 21] = [
+    ("health", 24),
+    ("magicka", 25),
+    ("stamina", 26),
+    ("one_handed", 6),
+    ("two_handed", 7),
+    ("archery", 8),
+    ("block", 9),
+    ("smithing", 10),
+    ("heavy_armor", 11),
+    ("light_armor", 12),
+    ("pickpocket", 13),
+    ("lockpicking", 14),
+    ("sneak", 15),
+    ("alchemy", 16),
+    ("speech", 17),
+    ("alteration", 18),
+    ("conjuration", 19),
+    ("destruction", 20),
+    ("illusion", 21),
+    ("restoration", 22),
+    ("enchanting", 23),
+];
+
+/// `ActorValue::kWaitingForPlayer`, which the follower dialogue sets when the player tells a
+/// follower to wait.
+const AV_WAITING_FOR_PLAYER: u32 = 95;
+
+/// `Actor::BOOL_BITS::kPlayerTeammate`
+const BOOL_BITS_PLAYER_TEAMMATE: u32 = 1 << 26;
+
+/// `ACTOR_LIFE_STATE`
+const LIFE_STATES: [&str; 9] = [
+    "alive",
+    "dying",
+    "dead",
+    "unconscious",
+    "reanimate",
+    "recycle",
+    "restrained",
+    "essential down",
+    "bleedout",
+];
+
+/// `SIT_SLEEP_STATE`
+const SIT_SLEEP_STATES: [&str; 9] = [
+    "",
+    "wants to sit",
+    "waiting to sit",
+    "sitting",
+    "wants to stand",
+    "wants to sleep",
+    "waiting to sleep",
+    "sleeping",
+    "waiting to wake",
+];
+
+/// `Actor`, past the `TESObjectREFR` it extends.
+#[repr(C)]
+struct Actor {
+    refr: TESObjectREFR,
+    unknown_68: [u64; 6],
+    magic_target: MagicTarget, // 98
+    unknown_a0: [u64; 2],
+    actor_value_owner: ActorValueOwner, // B0
+    actor_state: ActorState,            // B8
+    unknown_c8: [u64; 3],
+    bool_bits: u32, // E0
+    unknown_e4: u32,
+    unknown_e8: u64,
+    current_process: *const AIProcess, // F0
+}
+
+/// `AIProcess`, up to the package the actor runs.
+#[repr(C)]
+struct AIProcess {
+    unknown_00: [u64; 3],
+    package_lock: u64,       // 18, ActorPackage
+    package: *const TESForm, // 20
+}
+
+#[repr(C)]
+struct MagicTarget {
+    vtable: *const usize,
+}
+
+#[repr(C)]
+struct ActorValueOwner {
+    vtable: *const usize,
+}
+
+#[repr(C)]
+struct ActorState {
+    vtable: *const usize,
+    state1: u32, // 08
+    state2: u32, // 0C
+}
+
+#[repr(C)]
+struct ActiveEffect {
+    unknown_00: [u64; 8],
+    spell: *const TESForm, // 40
+    effect: *const Effect, // 48
+    unknown_50: [u64; 4],
+    elapsed: f32,   // 70
+    duration: f32,  // 74
+    magnitude: f32, // 78
+    flags: u32,     // 7C
+}
+
+#[repr(C)]
+struct Effect {
+    magnitude: f32,
+    area: u32,
+    duration: u32, // 08
+    padding_0c: u32,
+    base_effect: *const TESForm, // 10
+}
+
+/// Flag of `ActiveEffect` that is set while the effect does not apply, e.g. a worn-off potion.
+const ACTIVE_EFFECT_INACTIVE: u32 = 0x8000;
+
+pub(crate) struct ActorValue {
+    pub(crate) name: &'static str,
+    pub(crate) current: f32,
+    pub(crate) base: f32,
+}
+
+pub(crate) struct ActiveEffectInfo {
+    pub(crate) spell: Option<String>,
+    pub(crate) effect: Option<String>,
+    pub(crate) magnitude: f32,
+    pub(crate) elapsed: f32,
+    pub(crate) duration: f32,
+}
+
+pub(crate) struct TeammateInfo {
+    pub(crate) package_form_id: Option<u32>,
+    pub(crate) waiting: bool,
+}
+
+pub(crate) struct ActorInfo {
+    pub(crate) base_form_id: Option<u32>,
+    pub(crate) values: Vec<ActorValue>,
+    pub(crate) effects: Vec<ActiveEffectInfo>,
+    pub(crate) state: Vec<&'static str>,
+}
+
+impl Actor {
+    fn actor_value(&self, av: u32, index: usize) -> f32 {
+        unsafe {
+            let f: fn(&ActorValueOwner, u32) -> f32 =
+                transmute(*self.actor_value_owner.vtable.add(index));
+            f(&self.actor_value_owner, av)
+        }
+    }
+
+    fn active_effects(&self) -> Vec<ActiveEffectInfo> {
+        unsafe {
+            let get_active_effect_list: fn(&MagicTarget) -> *const SimpleList<*const ActiveEffect> =
+                transmute(*self.magic_target.vtable.add(7));
+            let list = match get_active_effect_list(&self.magic_target).as_ref() {
+                Some(list) => list,
+                None => return vec![],
+            };
+            list.items()
+                .into_iter()
+                .filter_map(|effect| effect.as_ref())
+                .filter(|effect| effect.flags & ACTIVE_EFFECT_INACTIVE == 0)
+                .map(|effect| ActiveEffectInfo {
+                    spell: effect
+                        .spell
+                        .as_ref()
+                        .and_then(TESForm::get_name)
+                        .map(|name| name.to_string()),
+                    effect: effect
+                        .effect
+                        .as_ref()
+                        .and_then(|effect| effect.base_effect.as_ref())
+                        .and_then(TESForm::get_name)
+                        .map(|name| name.to_string()),
+                    magnitude: effect.magnitude,
+                    elapsed: effect.elapsed,
+                    duration: effect.duration,
+                })
+                .collect()
+        }
+    }
+
+    fn state(&self) -> Vec<&'static str> {
+        let state1 = self.actor_state.state1;
+        let mut state = vec![];
+        state.push(
+            LIFE_STATES
+                .get(((state1 >> 21) & 0xF) as usize)
+                .copied()
+                .unwrap_or("unknown"),
+        );
+        if let Some(sit_sleep) = SIT_SLEEP_STATES.get(((state1 >> 14) & 0xF) as usize) {
+            if !sit_sleep.is_empty() {
+                state.push(sit_sleep);
+            }
+        }
+        for (bit, name) in [
+            (6, "walking"),
+            (7, "running"),
+            (8, "sprinting"),
+            (9, "sneaking"),
+            (10, "swimming"),
+        ] {
+            if state1 & (1 << bit) != 0 {
+                state.push(name);
+            }
+        }
+        state
+    }
+}
+
+/// Fails in VR, whose `Actor` is laid out differently from that of SE and AE past its
+/// `TESObjectREFR`, rather than reading its members at the offsets of the other layout.
+pub(crate) fn check_layout() -> anyhow::Result<()> {
+    if address::current_profile() == Profile::Vr {
+        anyhow::bail!("the actors of Skyrim VR are not supported");
+    }
+    Ok(())
+}
+
+fn as_actor(form_id: u32) -> anyhow::Result<&'static Actor> {
+    check_layout()?;
+    let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() }.context("no such form")?;
+    if form.form_type != FORM_TYPE_ACHR {
+        anyhow::bail!("not an actor");
+    }
+    let refr = refr::as_refr(form).context("not a reference")?;
+    Ok(unsafe { &*(refr as *const TESObjectREFR as *const Actor) })
+}
+
+/// The package and the waiting state of the loaded actor `form_id`, if it is a teammate of the
+/// player, as followers are.
+pub(crate) fn teammate_info(form_id: u32) -> Option<TeammateInfo> {
+    let actor = as_actor(form_id).ok()?;
+    if actor.bool_bits & BOOL_BITS_PLAYER_TEAMMATE == 0 {
+        return None;
+    }
+    let process = unsafe { actor.current_process.as_ref() };
+    Some(TeammateInfo {
+        package_form_id: process
+            .and_then(|process| unsafe { process.package.as_ref() })
+            .map(TESForm::form_id),
+        waiting: actor.actor_value(AV_WAITING_FOR_PLAYER, 1) != 0.0,
+    })
+}
+
+/// The live actor values, active effects and state of the loaded actor `form_id`.
+pub(crate) fn actor_info(form_id: u32) -> anyhow::Result<ActorInfo> {
+    let actor = as_actor(form_id)?;
+    Ok(ActorInfo {
+        base_form_id: unsafe { actor.refr.base_form.as_ref() }.map(TESForm::form_id),
+        values: ACTOR_VALUES
+            .iter()
+            .map(|&(name, av)| ActorValue {
+                name,
+                // GetActorValue and GetBaseActorValue
+                current: actor.actor_value(av, 1),
+                base: actor.actor_value(av, 3),
+            })
+            .collect(),
+        effects: actor.active_effects(),
+        state: actor.state(),
+    })
+}
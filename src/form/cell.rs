@@ -1,8 +1,12 @@
+use crate::config::CONFIG;
 use crate::db;
-use crate::db::Job;
-use crate::form::TESForm;
+use crate::db::TaskQueue;
+use crate::form::{self, TESForm, FORM_TYPE_CELL};
+use crate::log;
 use crate::log::Loggable;
 use crate::patch::patch_bytes;
+use crate::plugins;
+use crate::strings;
 use anyhow::{anyhow, Context};
 use late_static::LateStatic;
 use rusqlite::params;
@@ -10,17 +14,31 @@ use std::ffi::CStr;
 use std::fmt::Formatter;
 use std::mem::transmute;
 use std::ops::Deref;
-use std::sync::mpsc::Sender;
-use win_dbg_logger::output_debug_string;
 use winapi::ctypes::{c_char, c_void};
 
 struct TESObjectCELL(TESForm);
 
+/// `TESObjectCELL::Flag::kIsInteriorCell`
+const CELL_IS_INTERIOR: u16 = 0x1;
+
+/// `EXTERIOR_DATA`, the grid position of an exterior cell.
+#[repr(C)]
+struct ExteriorData {
+    x: i32,
+    y: i32,
+}
+
+pub(crate) struct CellInfo {
+    pub(crate) grid: Option<(i32, i32)>,
+    pub(crate) world_space_form_id: Option<u32>,
+    pub(crate) world_space_name: Option<String>,
+}
+
 struct State {
     cell_vtable: usize,
     cell_load: fn(&TESObjectCELL, u64) -> u64,
     cell_get_edid: fn(&TESObjectCELL) -> *const c_char,
-    task_queue: Sender<Job>,
+    task_queue: TaskQueue,
 }
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
@@ -45,29 +63,80 @@ impl TESObjectCELL {
         }
     }
 
+    fn field<T>(&self, offset: usize) -> *const T {
+        (self as *const TESObjectCELL as usize + offset) as *const T
+    }
+
+    fn is_interior(&self) -> bool {
+        unsafe { *self.field::<u16>(0x40) & CELL_IS_INTERIOR != 0 }
+    }
+
+    fn grid(&self) -> Option<(i32, i32)> {
+        if self.is_interior() {
+            return None;
+        }
+        unsafe { (*self.field::<*const ExteriorData>(0x60)).as_ref() }.map(|data| (data.x, data.y))
+    }
+
+    fn world_space(&self) -> Option<&TESForm> {
+        if self.is_interior() {
+            return None;
+        }
+        unsafe { (*self.field::<*const TESForm>(0x120)).as_ref() }
+    }
+
     fn new_load(&self, arg: u64) -> u64 {
         let ret = (S.cell_load)(self, arg);
+        form::guard_indexing("cell", || self.index());
+        ret
+    }
+
+    fn index(&self) {
+        if db::is_form_cached(self.0.form_id)
+            || !CONFIG.index.cell
+            || !plugins::is_indexed(self.0.form_id)
+        {
+            return;
+        }
         let form_id = self.0.form_id;
         let editor_id = self.get_edid().map(|name| name.to_string());
         let name = self.0.get_name().map(|name| name.to_string());
-        let result: anyhow::Result<()> =
-            (|| {
-                S.task_queue
-                    .send(Box::new(move |db| {
+        let result: anyhow::Result<()> = (|| {
+            S.task_queue
+                    .send_loaded("cell", Box::new(move |db| {
+                        let (name, name_localized) = strings::split_name(name);
+                        let name_id = db::intern(db, name.as_deref())?;
+                        let name_localized_id = db::intern(db, name_localized.as_deref())?;
                         db.prepare_cached(
-                "INSERT OR REPLACE INTO cell (form_id, editor_id, name) VALUES (?, ?, ?);",
+                "INSERT OR REPLACE INTO cell_form (form_id, plugin_id, editor_id, name_id, name_localized_id) VALUES (?, ?, ?, ?, ?);",
             ).context("cell_new_load prepare")?
-                .execute(params![form_id, editor_id, name]).context("cell_new_load execute")?;
+                .execute(params![form_id, plugins::plugin_id(form_id), editor_id, name_id, name_localized_id]).context("cell_new_load execute")?;
                         Ok(())
                     }))
                     .map_err(|e| anyhow!(e.to_string()))?;
-                Ok(())
-            })();
+            Ok(())
+        })();
         result.logging_ok();
-        ret
     }
 }
 
+/// The grid position and the worldspace of the cell `form_id`, which interior cells do not have.
+pub(crate) fn cell_info(form_id: u32) -> Option<CellInfo> {
+    let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() }?;
+    if form.form_type != FORM_TYPE_CELL {
+        return None;
+    }
+    let cell = unsafe { &*(form as *const TESForm as *const TESObjectCELL) };
+    let world_space = cell.world_space();
+    Some(CellInfo {
+        grid: cell.grid(),
+        world_space_form_id: world_space.map(TESForm::form_id),
+        world_space_name: world_space
+            .and_then(TESForm::get_name)
+            .map(|name| name.to_string()),
+    })
+}
+
 pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
     let cell_vtable = transmute(image_base + versionlib!(190143));
 
@@ -87,7 +156,7 @@ pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
         },
     );
 
-    output_debug_string(format!("S: {:#x?}", S.deref()).as_str());
+    log::debug(format!("S: {:#x?}", S.deref()).as_str());
 
     Ok(())
 }
@@ -0,0 +1,47 @@
+use crate::form::refr::{self, TESObjectREFR};
+use crate::form::{HashMap, TESForm};
+
+/// Offset of `PlayerCharacter::crimeGoldMap`.
+const CRIME_GOLD_MAP: usize = 0x3F0;
+
+/// `CrimeGoldStruct`
+#[repr(C)]
+struct CrimeGold {
+    violent: i32,
+    non_violent: i32,
+    non_violent_infamy: i32,
+    violent_infamy: i32,
+}
+
+pub(crate) struct Bounty {
+    pub(crate) faction_form_id: u32,
+    pub(crate) name: Option<String>,
+    pub(crate) violent: i32,
+    pub(crate) non_violent: i32,
+}
+
+/// The crime gold of the player per crime faction, leaving out the factions the player owes
+/// nothing.
+pub(crate) fn player_bounties() -> Vec<Bounty> {
+    let player = match refr::player() {
+        Some(player) => player,
+        None => return vec![],
+    };
+    unsafe {
+        let map = &*((player as *const TESObjectREFR as usize + CRIME_GOLD_MAP)
+            as *const HashMap<*const TESForm, CrimeGold>);
+        map.entries()
+            .into_iter()
+            .filter(|entry| entry.value.violent != 0 || entry.value.non_violent != 0)
+            .filter_map(|entry| {
+                let faction = entry.key.as_ref()?;
+                Some(Bounty {
+                    faction_form_id: faction.form_id,
+                    name: faction.get_name().map(|name| name.to_string()),
+                    violent: entry.value.violent,
+                    non_violent: entry.value.non_violent,
+                })
+            })
+            .collect()
+    }
+}
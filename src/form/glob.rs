@@ -0,0 +1,59 @@
+use crate::form::{form_ids_of_type, TESForm};
+use std::ffi::CStr;
+use winapi::ctypes::c_char;
+
+const FORM_TYPE_GLOB: u8 = 0x09;
+
+/// `TESGlobal`
+#[repr(C)]
+struct TESGlobal {
+    form: TESForm,
+    editor_id: *const c_char, // 20, BSString
+    editor_id_len: u16,
+    editor_id_capacity: u16,
+    padding_2c: u32,
+    kind: u8, // 30, 's'hort, 'l'ong or 'f'loat
+    padding_31: [u8; 3],
+    value: f32, // 34
+}
+
+pub(crate) struct Global {
+    pub(crate) form_id: u32,
+    pub(crate) editor_id: Option<String>,
+    pub(crate) kind: String,
+    pub(crate) value: f32,
+}
+
+/// The current value of the global `form_id`.
+pub(crate) fn value_of(form_id: u32) -> Option<f32> {
+    let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() }?;
+    if form.form_type != FORM_TYPE_GLOB {
+        return None;
+    }
+    Some(unsafe { &*(form as *const TESForm as *const TESGlobal) }.value)
+}
+
+/// The current values of all globals.
+pub(crate) fn globals() -> Vec<Global> {
+    form_ids_of_type(FORM_TYPE_GLOB)
+        .into_iter()
+        .filter_map(|form_id| unsafe {
+            let global = (TESForm::look_up_by_id(form_id) as *const TESGlobal).as_ref()?;
+            Some(Global {
+                form_id,
+                editor_id: (!global.editor_id.is_null()).then(|| {
+                    CStr::from_ptr(global.editor_id)
+                        .to_string_lossy()
+                        .to_string()
+                }),
+                kind: match global.kind {
+                    b's' => "short",
+                    b'l' => "long",
+                    _ => "float",
+                }
+                .to_string(),
+                value: global.value,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,203 @@
+use crate::form::refr::{self, TESObjectREFR};
+use crate::form::{TESForm, FORM_TYPE_NPC};
+use anyhow::Context;
+use late_static::LateStatic;
+use std::collections::BTreeMap;
+use std::mem::transmute;
+
+const FORM_TYPE_CONT: u8 = 0x1C;
+/// Leveled items in base containers, which are resolved into other items when the owner loads.
+const FORM_TYPE_LVLI: u8 = 0x35;
+
+/// `ExtraDataType`s read from the extra data of the inventory entries.
+const EXTRA_WORN: u32 = 0x16;
+const EXTRA_WORN_LEFT: u32 = 0x17;
+const EXTRA_CHARGE: u32 = 0x28;
+
+/// `BSSimpleList<T>`
+#[repr(C)]
+pub(crate) struct SimpleList<T> {
+    item: T,
+    next: *const SimpleList<T>,
+}
+
+impl<T: Copy> SimpleList<T> {
+    pub(crate) unsafe fn items(&self) -> Vec<T> {
+        let mut items = vec![self.item];
+        let mut node = self.next;
+        while let Some(next) = node.as_ref() {
+            items.push(next.item);
+            node = next.next;
+        }
+        items
+    }
+}
+
+#[repr(C)]
+struct InventoryChanges {
+    entries: *const SimpleList<*const InventoryEntryData>,
+    owner: *const TESObjectREFR, // 08
+}
+
+#[repr(C)]
+struct InventoryEntryData {
+    object: *const TESForm,
+    extra_lists: *const SimpleList<*const ExtraDataList>, // 08
+    count_delta: i32,                                     // 10
+}
+
+#[repr(C)]
+struct ExtraDataList {
+    data: *const BSExtraData,
+}
+
+#[repr(C)]
+struct BSExtraData {
+    vtable: *const extern "C" fn(),
+    next: *const BSExtraData, // 08
+                              // 10, the data of the type
+}
+
+impl BSExtraData {
+    fn get_type(&self) -> u32 {
+        unsafe {
+            let get_type: fn(&BSExtraData) -> u32 = transmute(*self.vtable.add(1));
+            get_type(self) & 0xFF
+        }
+    }
+}
+
+impl ExtraDataList {
+    unsafe fn find(&self, extra_type: u32) -> Option<&BSExtraData> {
+        let mut data = self.data;
+        while let Some(extra) = data.as_ref() {
+            if extra.get_type() == extra_type {
+                return Some(extra);
+            }
+            data = extra.next;
+        }
+        None
+    }
+}
+
+/// `TESContainer`, the items a base form starts with.
+#[repr(C)]
+struct Container {
+    vtable: usize,
+    entries: *const *const ContainerEntry, // 08
+    len: u32,                              // 10
+}
+
+#[repr(C)]
+struct ContainerEntry {
+    count: i32,
+    padding_04: u32,
+    object: *const TESForm, // 08
+}
+
+pub(crate) struct InventoryItem {
+    pub(crate) form_id: u32,
+    pub(crate) name: Option<String>,
+    pub(crate) count: i32,
+    pub(crate) equipped: bool,
+    pub(crate) charge: Option<f32>,
+}
+
+struct State {
+    get_inventory_changes: fn(&TESObjectREFR) -> *const InventoryChanges,
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+/// The base container of NPCs and containers. Other base forms have none.
+unsafe fn base_container(base_form: &TESForm) -> Option<&Container> {
+    let offset = match base_form.form_type {
+        FORM_TYPE_NPC => 0x88,
+        FORM_TYPE_CONT => 0x30,
+        _ => return None,
+    };
+    Some(&*((base_form as *const TESForm as usize + offset) as *const Container))
+}
+
+/// The items the reference `form_id` carries now: those of its base container, changed by what
+/// was added, removed, equipped or charged since.
+pub(crate) fn inventory_of(form_id: u32) -> anyhow::Result<Vec<InventoryItem>> {
+    let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() }.context("no such form")?;
+    let refr = refr::as_refr(form).context("not a reference")?;
+
+    let mut items = BTreeMap::new();
+    unsafe {
+        let base_form = refr.base_form.as_ref();
+        if let Some(container) = base_form.and_then(|form| base_container(form)) {
+            for i in 0..container.len as usize {
+                let entry = match (*container.entries.add(i)).as_ref() {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                match entry.object.as_ref() {
+                    Some(object) if object.form_type != FORM_TYPE_LVLI => {
+                        new_item(&mut items, object).count += entry.count;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let changes = (S.get_inventory_changes)(refr).as_ref();
+        let entries = changes.and_then(|changes| changes.entries.as_ref());
+        for entry in entries.map_or(vec![], |entries| entries.items()) {
+            let entry = match entry.as_ref() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let object = match entry.object.as_ref() {
+                Some(object) => object,
+                None => continue,
+            };
+            let item = new_item(&mut items, object);
+            item.count += entry.count_delta;
+            let extra_lists = entry.extra_lists.as_ref();
+            for extra_list in extra_lists.map_or(vec![], |lists| lists.items()) {
+                let extra_list = match extra_list.as_ref() {
+                    Some(extra_list) => extra_list,
+                    None => continue,
+                };
+                if extra_list.find(EXTRA_WORN).is_some()
+                    || extra_list.find(EXTRA_WORN_LEFT).is_some()
+                {
+                    item.equipped = true;
+                }
+                if let Some(charge) = extra_list.find(EXTRA_CHARGE) {
+                    let charge = *((charge as *const BSExtraData).add(1) as *const f32);
+                    item.charge.get_or_insert(charge);
+                }
+            }
+        }
+    }
+    Ok(items.into_values().filter(|item| item.count > 0).collect())
+}
+
+fn new_item<'a>(
+    items: &'a mut BTreeMap<u32, InventoryItem>,
+    object: &TESForm,
+) -> &'a mut InventoryItem {
+    items
+        .entry(object.form_id)
+        .or_insert_with(|| InventoryItem {
+            form_id: object.form_id,
+            name: object.get_name().map(|name| name.to_string()),
+            count: 0,
+            equipped: false,
+            charge: None,
+        })
+}
+
+pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
+    LateStatic::assign(
+        &S,
+        State {
+            get_inventory_changes: transmute(image_base + versionlib!(16040)),
+        },
+    );
+    Ok(())
+}
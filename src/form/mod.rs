@@ -1,14 +1,22 @@
+use crate::log::Loggable;
+use crate::{address, config, log};
 use anyhow::Context;
 use late_static::LateStatic;
+use rusqlite::params;
 use std::ffi::CStr;
 use std::mem::transmute;
 use winapi::ctypes::c_char;
 
 mod achr;
-mod cell;
+pub(crate) mod actor;
+pub(crate) mod cell;
+pub(crate) mod fact;
+pub(crate) mod glob;
+pub(crate) mod inventory;
 mod npc;
 pub(crate) mod qust;
-mod refr;
+pub(crate) mod refr;
+pub(crate) mod rela;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -24,17 +32,141 @@ pub(crate) struct TESForm {
     // 20
 }
 
+/// `FormType`s of the forms in the index.
+pub(crate) const FORM_TYPE_NPC: u8 = 0x2B;
+pub(crate) const FORM_TYPE_CELL: u8 = 0x3C;
+pub(crate) const FORM_TYPE_QUEST: u8 = 0x4D;
+
+/// `BSTArray<TESForm*>`
+#[repr(C)]
+struct FormArray {
+    data: *const *const TESForm,
+    capacity: u32,
+    padding_0c: u32,
+    size: u32,
+    padding_14: u32,
+    // 18
+}
+
+/// An entry of `BSTHashMap<K, V>`. Empty entries have no `next`.
+#[repr(C)]
+pub(crate) struct HashMapEntry<K, V> {
+    pub(crate) key: K,
+    pub(crate) value: V,
+    next: *const HashMapEntry<K, V>,
+}
+
+/// `BSTHashMap<K, V>`
+#[repr(C)]
+pub(crate) struct HashMap<K, V> {
+    unknown_00: u64,
+    unknown_08: u32,
+    capacity: u32, // 0C
+    free: u32,     // 10
+    good: u32,     // 14
+    sentinel: *const HashMapEntry<K, V>,
+    unknown_20: u64,
+    entries: *const HashMapEntry<K, V>, // 28
+}
+
+impl<K, V> HashMap<K, V> {
+    /// The entries in use. The map is read without its lock.
+    pub(crate) unsafe fn entries(&self) -> Vec<&HashMapEntry<K, V>> {
+        if self.entries.is_null() {
+            return vec![];
+        }
+        (0..self.capacity as usize)
+            .map(|i| &*self.entries.add(i))
+            .filter(|entry| !entry.next.is_null())
+            .collect()
+    }
+}
+
+/// The map of all forms.
+type FormMap = HashMap<u32, *const TESForm>;
+
+/// Load order index of the forms created in game.
+const DYNAMIC_FORM_INDEX: u32 = 0xFF;
+
+/// A form created in game, e.g. a reference spawned by `placeatme` or a custom enchantment.
+pub(crate) struct DynamicForm {
+    form_id: u32,
+    form_type: u8,
+    name: Option<String>,
+    base_form_id: Option<u32>,
+    cell_form_id: Option<u32>,
+}
+
+impl DynamicForm {
+    fn new(form: &TESForm) -> Option<DynamicForm> {
+        if form.form_id >> 24 != DYNAMIC_FORM_INDEX {
+            return None;
+        }
+        let refr = refr::as_refr(form);
+        let base_form = refr.and_then(|refr| unsafe { refr.base_form.as_ref() });
+        Some(DynamicForm {
+            form_id: form.form_id,
+            form_type: form.form_type,
+            // references are named after their base form
+            name: base_form
+                .unwrap_or(form)
+                .get_name()
+                .map(|name| name.to_string()),
+            base_form_id: base_form.map(|form| form.form_id),
+            cell_form_id: refr.and_then(refr::parent_cell_form_id),
+        })
+    }
+
+    /// Records the form unless it is known already, so that `first_seen` is kept. `creator` hints
+    /// at what created the form.
+    pub(crate) fn insert(&self, db: &rusqlite::Connection, creator: &str) -> anyhow::Result<()> {
+        db.prepare_cached(
+            "INSERT OR IGNORE INTO dynamic_form \
+             (form_id, form_type, name, base_form_id, cell_form_id, creator) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .context("dynamic_form prepare")?
+        .execute(params![
+            self.form_id,
+            self.form_type,
+            self.name,
+            self.base_form_id,
+            self.cell_form_id,
+            creator
+        ])
+        .context("dynamic_form execute")?;
+        Ok(())
+    }
+}
+
+/// Where the forms of the kinds in the index have their `TESFullName`, whose name follows its
+/// vtable, as SE 1.5.97 and VR lay them out.
+const FULL_NAME_OFFSETS: [(u8, usize); 3] = [
+    (FORM_TYPE_NPC, 0xD8),
+    (FORM_TYPE_CELL, 0x20),
+    (FORM_TYPE_QUEST, 0x28),
+];
+
 struct State {
-    get_name: fn(&TESForm) -> *const c_char,
+    /// `TESForm::GetName`, which VR has no address id for.
+    get_name: Option<fn(&TESForm) -> *const c_char>,
     look_up_by_id: fn(u32) -> *const TESForm,
+    /// `TESDataHandler**`. The handler keeps a `FormArray` per form type at +0x10.
+    data_handler: *const usize,
+    all_forms: *const *const FormMap,
 }
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
 
 impl TESForm {
+    /// The name of the form. Without `TESForm::GetName`, only the forms of the kinds in the index
+    /// have names, read from their `TESFullName`.
     pub(crate) fn get_name(&self) -> Option<std::borrow::Cow<str>> {
         unsafe {
-            let result = (S.get_name)(self);
+            let result = match S.get_name {
+                Some(get_name) => get_name(self),
+                None => self.full_name(),
+            };
             if result.is_null() {
                 return None;
             }
@@ -42,20 +174,90 @@ impl TESForm {
         }
     }
 
+    unsafe fn full_name(&self) -> *const c_char {
+        let offset = FULL_NAME_OFFSETS
+            .iter()
+            .find(|&&(form_type, _)| form_type == self.form_type)
+            .map(|&(_, offset)| offset);
+        match offset {
+            // the `BSFixedString`, which points at its text
+            Some(offset) => {
+                *((self as *const TESForm as usize + offset + 8) as *const *const c_char)
+            }
+            None => std::ptr::null(),
+        }
+    }
+
+    pub(crate) fn form_id(&self) -> u32 {
+        self.form_id
+    }
+
     pub(crate) fn look_up_by_id(id: u32) -> *const TESForm {
         (S.look_up_by_id)(id)
     }
 }
 
+/// The form ids of all loaded forms of `form_type`, as the data handler holds them.
+pub(crate) fn form_ids_of_type(form_type: u8) -> Vec<u32> {
+    unsafe {
+        let data_handler = *S.data_handler;
+        if data_handler == 0 {
+            return vec![];
+        }
+        let array = &*((data_handler + 0x10 + form_type as usize * 0x18) as *const FormArray);
+        (0..array.size as usize)
+            .map(|i| *array.data.add(i))
+            .filter(|form| !form.is_null())
+            .map(|form| (*form).form_id)
+            .collect()
+    }
+}
+
+/// The forms created in game, as the map of all forms holds them. The map is read without its
+/// lock, so this is only called from the main thread, which is the one that usually creates forms.
+pub(crate) fn dynamic_forms() -> Vec<DynamicForm> {
+    unsafe {
+        let map = match (*S.all_forms).as_ref() {
+            Some(map) => map,
+            None => return vec![],
+        };
+        map.entries()
+            .into_iter()
+            .filter_map(|entry| entry.value.as_ref())
+            .filter_map(DynamicForm::new)
+            .collect()
+    }
+}
+
+/// Runs `f`, which indexes a form as the game loads it, and stops indexing the forms of `kind`
+/// (as `index.<kind>` is named) until the game quits if it panics, rather than taking the game
+/// down with it.
+pub(crate) fn guard_indexing<T, F: FnOnce() -> T>(kind: &str, f: F) -> Option<T> {
+    let result = log::catch_panic(kind, f);
+    if result.is_none() {
+        log::error(format!("stopped indexing {} until the game quits", kind).as_str());
+        config::set(&format!("index.{}", kind), "false")
+            .context("guard_indexing")
+            .logging_ok();
+    }
+    result
+}
+
 pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
-    let get_name = transmute(image_base + versionlib!(14720));
+    let get_name = address::offset(14720, versionlib_macro::versionlib!(14720))
+        .map(|offset| transmute(image_base + offset))
+        .ok();
     let look_up_by_id = transmute(image_base + versionlib!(14617));
+    let data_handler = (image_base + versionlib!(400269)) as *const usize;
+    let all_forms = (image_base + versionlib!(400507)) as *const *const FormMap;
 
     LateStatic::assign(
         &S,
         State {
             get_name,
             look_up_by_id,
+            data_handler,
+            all_forms,
         },
     );
 
@@ -63,6 +265,8 @@ pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
     achr::init(image_base).context("achr::init")?;
     cell::init(image_base).context("cell::init")?;
     qust::init(image_base).context("qust::init")?;
+    refr::init(image_base).context("refr::init")?;
+    inventory::init(image_base).context("inventory::init")?;
 
     Ok(())
 }
@@ -1,8 +1,13 @@
+use crate::config::CONFIG;
 use crate::db;
-use crate::db::Job;
+use crate::db::TaskQueue;
+use crate::form;
 use crate::form::TESForm;
+use crate::log;
 use crate::log::Loggable;
 use crate::patch::patch_bytes;
+use crate::plugins;
+use crate::strings;
 use anyhow::{anyhow, Context};
 use late_static::LateStatic;
 use rusqlite::params;
@@ -10,8 +15,6 @@ use std::ffi::CStr;
 use std::fmt::Formatter;
 use std::mem::transmute;
 use std::ops::Deref;
-use std::sync::mpsc::Sender;
-use win_dbg_logger::output_debug_string;
 use winapi::ctypes::{c_char, c_void};
 
 #[allow(clippy::upper_case_acronyms)]
@@ -20,7 +23,7 @@ struct TESNPC(TESForm);
 struct State {
     npc_vtable: usize,
     npc_load: fn(&TESNPC, u64) -> u64,
-    task_queue: Sender<Job>,
+    task_queue: TaskQueue,
 }
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
@@ -36,24 +39,37 @@ impl std::fmt::Debug for State {
 
 impl TESNPC {
     fn new_set_edid(&self, edid: *const c_char) -> bool {
+        form::guard_indexing("npc", || self.set_edid(edid)).unwrap_or(false)
+    }
+
+    fn set_edid(&self, edid: *const c_char) -> bool {
         if edid.is_null() {
             return false;
         }
+        if db::is_form_cached(self.0.form_id)
+            || !CONFIG.index.npc
+            || !plugins::is_indexed(self.0.form_id)
+        {
+            return true;
+        }
         let result: anyhow::Result<()> = (|| {
             let form_id = self.0.form_id;
             let edid = unsafe { CStr::from_ptr(edid).to_str()? }.to_string();
 
             S.task_queue
-                .send(Box::new(move |db| {
-                    db.prepare_cached(
-                        "INSERT INTO npc (form_id, editor_id) VALUES (?, ?)\
+                .send_loaded(
+                    "npc",
+                    Box::new(move |db| {
+                        db.prepare_cached(
+                            "INSERT INTO npc_form (form_id, plugin_id, editor_id) VALUES (?, ?, ?)\
                      ON CONFLICT(form_id) DO UPDATE SET editor_id=excluded.editor_id",
-                    )
-                    .context("npc_set_edid prepare")?
-                    .execute(params![form_id, edid])
-                    .context("npc_set_edid execute")?;
-                    Ok(())
-                }))
+                        )
+                        .context("npc_set_edid prepare")?
+                        .execute(params![form_id, plugins::plugin_id(form_id), edid])
+                        .context("npc_set_edid execute")?;
+                        Ok(())
+                    }),
+                )
                 .map_err(|e| anyhow!(e.to_string()))?;
 
             Ok(())
@@ -63,35 +79,58 @@ impl TESNPC {
 
     fn new_load(&self, arg: u64) -> u64 {
         let result = (S.npc_load)(self, arg);
+        form::guard_indexing("npc", || self.index_name());
+        result
+    }
+
+    fn index_name(&self) {
+        if db::is_form_cached(self.0.form_id)
+            || !CONFIG.index.npc
+            || !plugins::is_indexed(self.0.form_id)
+        {
+            return;
+        }
         let form_id = self.0.form_id;
         if let Some(name) = self.0.get_name() {
             let result: anyhow::Result<()> = (|| {
                 let name = name.to_string();
                 S.task_queue
-                    .send(Box::new(move |db| {
-                        db.prepare_cached(
-                            "INSERT INTO npc (form_id, name) VALUES (?, ?)\
-                         ON CONFLICT(form_id) DO UPDATE SET name=excluded.name",
-                        )
-                        .context("npc_new_load prepare")?
-                        .execute(params![form_id, name])
-                        .context("npc_new_load execute")?;
-                        Ok(())
-                    }))
+                    .send_loaded(
+                        "npc",
+                        Box::new(move |db| {
+                            let (name, name_localized) = strings::split_name(Some(name));
+                            let name_id = db::intern(db, name.as_deref())?;
+                            let name_localized_id = db::intern(db, name_localized.as_deref())?;
+                            db.prepare_cached(
+                                "INSERT INTO npc_form (form_id, plugin_id, name_id, name_localized_id) \
+                         VALUES (?, ?, ?, ?)\
+                         ON CONFLICT(form_id) DO UPDATE SET name_id=excluded.name_id, \
+                         name_localized_id=excluded.name_localized_id",
+                            )
+                            .context("npc_new_load prepare")?
+                            .execute(params![
+                                form_id,
+                                plugins::plugin_id(form_id),
+                                name_id,
+                                name_localized_id
+                            ])
+                            .context("npc_new_load execute")?;
+                            Ok(())
+                        }),
+                    )
                     .map_err(|e| anyhow!(e.to_string()))?;
 
                 Ok(())
             })();
             result.logging_ok();
         }
-        result
     }
 }
 
 pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
     let npc_vtable = transmute(image_base + versionlib!(195816));
 
-    output_debug_string(format!("npc set_edid: {:#x}", npc_vtable + 0x198).as_str());
+    log::debug(format!("npc set_edid: {:#x}", npc_vtable + 0x198).as_str());
 
     patch_bytes(
         &(TESNPC::new_set_edid as usize),
@@ -113,7 +152,7 @@ pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
         },
     );
 
-    output_debug_string(format!("S: {:#x?}", S.deref()).as_str());
+    log::debug(format!("S: {:#x?}", S.deref()).as_str());
 
     Ok(())
 }
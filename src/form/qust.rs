@@ -1,8 +1,14 @@
+use crate::address;
+use crate::config::CONFIG;
 use crate::db;
-use crate::db::Job;
+use crate::db::TaskQueue;
+use crate::form;
 use crate::form::TESForm;
+use crate::log;
 use crate::log::Loggable;
 use crate::patch::patch_bytes;
+use crate::plugins;
+use crate::strings;
 use anyhow::{anyhow, Context};
 use late_static::LateStatic;
 use rusqlite::params;
@@ -10,8 +16,6 @@ use std::ffi::CStr;
 use std::fmt::Formatter;
 use std::mem::transmute;
 use std::ops::Deref;
-use std::sync::mpsc::Sender;
-use win_dbg_logger::output_debug_string;
 use winapi::ctypes::{c_char, c_void};
 
 #[derive(Debug)]
@@ -56,9 +60,10 @@ struct State {
     quest_vtable: usize,
     quest_load: fn(&TESQuest, u64) -> u64,
     quest_get_edid: fn(&TESQuest) -> *const c_char,
+    /// The text of a log entry, which VR has no address id for.
     #[allow(dead_code)]
-    quest_get_description: fn(&LogEntry, &TESQuest, u64, u64) -> *const c_char,
-    task_queue: Sender<Job>,
+    quest_get_description: Option<fn(&LogEntry, &TESQuest, u64, u64) -> *const c_char>,
+    task_queue: TaskQueue,
 }
 unsafe impl Sync for State {}
 static S: LateStatic<State> = LateStatic::new();
@@ -166,6 +171,23 @@ impl TESQuest {
         vec
     }
 
+    /// The stages as a JSON array of `{"stage", "flags", "logs"}` objects, where `logs` is the
+    /// number of log entries of the stage.
+    fn stages_json(&self) -> String {
+        let stages = self
+            .traverse()
+            .iter()
+            .map(|(index, log_entries)| {
+                serde_json::json!({
+                    "stage": index.stage,
+                    "flags": index.flags,
+                    "logs": log_entries.len(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::Value::Array(stages).to_string()
+    }
+
     pub(crate) fn get_log(&self, stage: u16) -> Option<&Index> {
         unsafe {
             let head = self.get_head()?;
@@ -181,7 +203,10 @@ impl TESQuest {
 
     // NOTE: This function only works when a save has been loaded.
     pub(crate) fn get_log_description(&self, log: &LogEntry) -> std::borrow::Cow<str> {
-        let s = (S.quest_get_description)(log, self, 0, 0);
+        let s = match S.quest_get_description {
+            Some(get_description) => get_description(log, self, 0, 0),
+            None => std::ptr::null(),
+        };
         if s.is_null() {
             return std::borrow::Cow::from("");
         }
@@ -190,52 +215,98 @@ impl TESQuest {
 
     fn new_load(&self, arg: u64) -> u64 {
         let ret = (S.quest_load)(self, arg);
+        form::guard_indexing("quest", || self.index());
+        ret
+    }
+
+    fn index(&self) {
+        if db::is_form_cached(self.0.form_id)
+            || !CONFIG.index.quest
+            || !plugins::is_indexed(self.0.form_id)
+        {
+            return;
+        }
         let form_id = self.0.form_id;
         let editor_id = self.get_edid().map(|name| name.to_string());
         let name = self.0.get_name().map(|name| name.to_string());
+        let stages = self.stages_json();
         let result: anyhow::Result<()> = (|| {
             S.task_queue
-                .send(Box::new(move |db| {
-                    db.prepare_cached(
-                        "INSERT OR REPLACE INTO quest (form_id, editor_id, name) VALUES (?, ?, ?);",
-                    )
-                    .context("quest_new_load prepare")?
-                    .execute(params![form_id, editor_id, name])
-                    .context("quest_new_load execute")?;
+                .send_loaded(
+                    "quest",
+                    Box::new(move |db| {
+                        let (name, name_localized) = strings::split_name(name);
+                        let name_id = db::intern(db, name.as_deref())?;
+                        let name_localized_id = db::intern(db, name_localized.as_deref())?;
+                        db.prepare_cached(
+                            "INSERT OR REPLACE INTO quest_form \
+                         (form_id, plugin_id, editor_id, name_id, name_localized_id, stages) \
+                         VALUES (?, ?, ?, ?, ?, ?);",
+                        )
+                        .context("quest_new_load prepare")?
+                        .execute(params![
+                            form_id,
+                            plugins::plugin_id(form_id),
+                            editor_id,
+                            name_id,
+                            name_localized_id,
+                            stages
+                        ])
+                        .context("quest_new_load execute")?;
 
-                    Ok(())
-                }))
+                        Ok(())
+                    }),
+                )
                 .map_err(|e| anyhow!(e.to_string()))?;
 
-            for (index, log_entries) in self.traverse().iter() {
-                for log in log_entries.iter() {
-                    let stage = index.stage;
-                    let log_string_offset = log.string_offset;
-                    if log_string_offset == 4294967295 {
-                        continue;
-                    }
-                    S.task_queue.send(Box::new(move |db| {
-                        db.prepare_cached(
-                            "INSERT OR REPLACE INTO quest_stage (form_id, stage, log) VALUES (?, ?, ?);",
-                        ).context("quest_new_load prepare")?
-                            .execute(params![form_id, stage, log_string_offset])
-                            .context("quest_new_load execute")?;
+            self.index_stages()
+        })();
+        result.logging_ok();
+    }
 
-                        Ok(())
-                    })).map_err(|e| anyhow!(e.to_string()))?;
+    /// Indexes the log entries of the stages in `quest_stage`.
+    fn index_stages(&self) -> anyhow::Result<()> {
+        let form_id = self.0.form_id;
+        for (index, log_entries) in self.traverse().iter() {
+            for log in log_entries.iter() {
+                let stage = index.stage;
+                let log_string_offset = log.string_offset;
+                if log_string_offset == 4294967295 {
+                    continue;
                 }
+                S.task_queue.send_loaded("quest_stage", Box::new(move |db| {
+                    db.prepare_cached(
+                        "INSERT OR REPLACE INTO quest_stage (form_id, stage, log) VALUES (?, ?, ?);",
+                    ).context("quest_new_load prepare")?
+                        .execute(params![form_id, stage, log_string_offset])
+                        .context("quest_new_load execute")?;
+
+                    Ok(())
+                })).map_err(|e| anyhow!(e.to_string()))?;
             }
+        }
+        Ok(())
+    }
+}
 
-            Ok(())
-        })();
-        result.logging_ok();
-        ret
+/// Indexes the log entries of the stages of the loaded quest `form_id` again, for
+/// `records::reindex`.
+pub(crate) fn index_stages(form_id: u32) {
+    let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() };
+    if let Some(form) = form.filter(|form| form.form_type == form::FORM_TYPE_QUEST) {
+        let quest = unsafe { &*(form as *const TESForm as *const TESQuest) };
+        quest
+            .index_stages()
+            .with_context(|| format!("{:08X}", form_id))
+            .logging_ok();
     }
 }
 
 pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
     let quest_vtable = transmute(image_base + versionlib!(195890));
-    let quest_get_description = transmute(image_base + versionlib!(25259));
+    let quest_get_description = address::offset(25259, versionlib_macro::versionlib!(25259))
+        .map(|offset| transmute(image_base + offset))
+        .ok();
 
     let original_quest_load = patch_bytes(
         &(TESQuest::new_load as usize),
@@ -254,7 +325,7 @@ pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
         },
     );
 
-    output_debug_string(format!("S: {:#x?}", S.deref()).as_str());
+    log::debug(format!("S: {:#x?}", S.deref()).as_str());
 
     Ok(())
 }
@@ -1,4 +1,65 @@
-use crate::form::TESForm;
+use crate::config::CONFIG;
+use crate::db;
+use crate::db::TaskQueue;
+use crate::form::{self, DynamicForm, TESForm};
+use crate::log;
+use crate::log::Loggable;
+use crate::patch::patch_bytes;
+use crate::plugins;
+use anyhow::{anyhow, Context};
+use late_static::LateStatic;
+use lazy_static::lazy_static;
+use rusqlite::params;
+use std::collections::BTreeSet;
+use std::fmt::Formatter;
+use std::mem::transmute;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use winapi::ctypes::c_void;
+
+const FORM_TYPE_REFR: u8 = 0x3D;
+pub(crate) const FORM_TYPE_ACHR: u8 = 0x3E;
+
+lazy_static! {
+    /// The references in the attached cells, as `refr` holds them once the worker catches up.
+    static ref LOADED: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+}
+
+/// Set once `refr` holds the references of the attached cells, which with `index.lazy` waits for
+/// the first command that needs them.
+static REFS_BUILT: AtomicBool = AtomicBool::new(false);
+
+/// Fills `refr` with the references of the attached cells, which are recorded as they are
+/// attached from then on. Returns whether it had to.
+pub(crate) fn build_refs() -> bool {
+    if !CONFIG.index.refs || REFS_BUILT.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    for form_id in loaded_form_ids() {
+        if let Some(refr) = unsafe { TESForm::look_up_by_id(form_id).as_ref() }.and_then(as_refr) {
+            form::guard_indexing("refs", || refr.index(false));
+        }
+    }
+    true
+}
+
+/// The form ids of the references in the attached cells, as of now.
+pub(crate) fn loaded_form_ids() -> Vec<u32> {
+    match LOADED.lock() {
+        Ok(loaded) => loaded.iter().copied().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The reference `form_id`, or null unless it is a loaded reference.
+pub(crate) fn look_up_reference(form_id: u32) -> *const TESForm {
+    match unsafe { TESForm::look_up_by_id(form_id).as_ref() } {
+        Some(form) if form.form_type == FORM_TYPE_REFR || form.form_type == FORM_TYPE_ACHR => form,
+        _ => ptr::null(),
+    }
+}
 
 #[repr(C)]
 pub(crate) struct TESObjectREFR {
@@ -8,4 +69,209 @@ pub(crate) struct TESObjectREFR {
     unknown30: u64,
     unknown38: u64,
     pub(crate) base_form: *const TESForm,
+    angle: [f32; 3],               // 48
+    pub(crate) location: [f32; 3], // 54
+    parent_cell: *const TESForm,   // 60
+}
+
+struct State {
+    refr_vtable: usize,
+    character_vtable: usize,
+    refr_load_3d: fn(&TESObjectREFR, u64) -> u64,
+    refr_release_3d: fn(&TESObjectREFR),
+    character_load_3d: fn(&TESObjectREFR, u64) -> u64,
+    character_release_3d: fn(&TESObjectREFR),
+    /// `PlayerCharacter**`
+    player: *const *const TESObjectREFR,
+    task_queue: TaskQueue,
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("refr_vtable", &(self.refr_vtable as usize))
+            .field("character_vtable", &(self.character_vtable as usize))
+            .field("refr_load_3d", &(self.refr_load_3d as usize))
+            .field("character_load_3d", &(self.character_load_3d as usize))
+            .finish()
+    }
+}
+
+impl TESObjectREFR {
+    fn new_refr_load_3d(&self, background_loading: u64) -> u64 {
+        let ret = (S.refr_load_3d)(self, background_loading);
+        form::guard_indexing("refs", || self.on_attach());
+        ret
+    }
+
+    fn new_refr_release_3d(&self) {
+        (S.refr_release_3d)(self);
+        form::guard_indexing("refs", || self.on_detach());
+    }
+
+    fn new_character_load_3d(&self, background_loading: u64) -> u64 {
+        let ret = (S.character_load_3d)(self, background_loading);
+        form::guard_indexing("refs", || self.on_attach());
+        ret
+    }
+
+    fn new_character_release_3d(&self) {
+        (S.character_release_3d)(self);
+        form::guard_indexing("refs", || self.on_detach());
+    }
+
+    /// Records the reference, whose cell has just been attached.
+    fn on_attach(&self) {
+        let form_id = self.form.form_id;
+        if let Ok(mut loaded) = LOADED.lock() {
+            loaded.insert(form_id);
+        }
+        if !CONFIG.index.lazy || REFS_BUILT.load(Ordering::SeqCst) {
+            self.index(true);
+        }
+    }
+
+    /// Indexes the reference, with `send_loaded` if `loaded` as the game attached its cell.
+    fn index(&self, loaded: bool) {
+        let form_id = self.form.form_id;
+        if !CONFIG.index.refs || !plugins::is_indexed(form_id) {
+            return;
+        }
+        let base_form = unsafe { self.base_form.as_ref() };
+        let base_form_id = base_form.map(|form| form.form_id);
+        let name = base_form
+            .and_then(TESForm::get_name)
+            .map(|name| name.to_string());
+        let cell_form_id = parent_cell_form_id(self);
+        let [x, y, z] = self.location.map(f64::from);
+        let dynamic_form = DynamicForm::new(&self.form);
+        // sent as indexing jobs, which the worker batches into transactions rather than committing
+        // each reference of a cell on its own
+        let send = if loaded {
+            TaskQueue::send_loaded
+        } else {
+            TaskQueue::send_indexing
+        };
+        send(
+            &S.task_queue,
+            "refr",
+            Box::new(move |db| {
+                db.prepare_cached(
+                    "INSERT OR REPLACE INTO refr \
+                     (form_id, base_form_id, name, cell_form_id, x, y, z) \
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                )
+                .context("refr_on_attach prepare")?
+                .execute(params![form_id, base_form_id, name, cell_form_id, x, y, z])
+                .context("refr_on_attach execute")?;
+                if let Some(dynamic_form) = dynamic_form {
+                    dynamic_form.insert(db, "attached")?;
+                }
+                Ok(())
+            }),
+        )
+        .map_err(|e| anyhow!(e.to_string()))
+        .logging_ok();
+    }
+
+    fn on_detach(&self) {
+        let form_id = self.form.form_id;
+        if let Ok(mut loaded) = LOADED.lock() {
+            loaded.remove(&form_id);
+        }
+        if !CONFIG.index.refs {
+            return;
+        }
+        S.task_queue
+            .send_loaded(
+                "refr",
+                Box::new(move |db| {
+                    db.prepare_cached("DELETE FROM refr WHERE form_id = ?")
+                        .context("refr_on_detach prepare")?
+                        .execute(params![form_id])
+                        .context("refr_on_detach execute")?;
+                    Ok(())
+                }),
+            )
+            .map_err(|e| anyhow!(e.to_string()))
+            .logging_ok();
+    }
+}
+
+/// The current position of the reference `form_id`, if it is loaded.
+/// The positions in `refr` are those of when the cell was attached, which actors have left since.
+pub(crate) fn position_of(form_id: u32) -> Option<[f32; 3]> {
+    let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() }?;
+    as_refr(form).map(|refr| refr.location)
+}
+
+pub(crate) fn as_refr(form: &TESForm) -> Option<&TESObjectREFR> {
+    if form.form_type != FORM_TYPE_REFR && form.form_type != FORM_TYPE_ACHR {
+        return None;
+    }
+    Some(unsafe { &*(form as *const TESForm as *const TESObjectREFR) })
+}
+
+pub(crate) fn parent_cell_form_id(refr: &TESObjectREFR) -> Option<u32> {
+    unsafe { refr.parent_cell.as_ref() }.map(|cell| cell.form_id)
+}
+
+/// The player, once a game is loaded.
+pub(crate) fn player() -> Option<&'static TESObjectREFR> {
+    unsafe { (*S.player).as_ref() }
+}
+
+pub(crate) fn player_position() -> Option<[f32; 3]> {
+    player().map(|player| player.location)
+}
+
+pub(crate) fn player_cell_form_id() -> Option<u32> {
+    player().and_then(parent_cell_form_id)
+}
+
+pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
+    let refr_vtable = transmute(image_base + versionlib!(190259));
+    let character_vtable = transmute(image_base + versionlib!(207886));
+
+    // Load3D and Release3DRelatedData are called as references enter and leave the loaded cells
+    let patch = |vtable: usize, offset: usize, f: usize| -> anyhow::Result<usize> {
+        let original = patch_bytes(&f, (vtable + offset) as *mut c_void, 8)?;
+        Ok(*(original.as_ptr() as *const usize))
+    };
+    let refr_load_3d = patch(refr_vtable, 0x350, TESObjectREFR::new_refr_load_3d as usize)?;
+    let refr_release_3d = patch(
+        refr_vtable,
+        0x358,
+        TESObjectREFR::new_refr_release_3d as usize,
+    )?;
+    let character_load_3d = patch(
+        character_vtable,
+        0x350,
+        TESObjectREFR::new_character_load_3d as usize,
+    )?;
+    let character_release_3d = patch(
+        character_vtable,
+        0x358,
+        TESObjectREFR::new_character_release_3d as usize,
+    )?;
+
+    LateStatic::assign(
+        &S,
+        State {
+            refr_vtable,
+            character_vtable,
+            refr_load_3d: transmute(refr_load_3d),
+            refr_release_3d: transmute(refr_release_3d),
+            character_load_3d: transmute(character_load_3d),
+            character_release_3d: transmute(character_release_3d),
+            player: transmute(image_base + versionlib!(403521)),
+            task_queue: db::TASK_QUEUE.lock().unwrap().clone(),
+        },
+    );
+
+    log::debug(format!("S: {:#x?}", S.deref()).as_str());
+
+    Ok(())
 }
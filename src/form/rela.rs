@@ -0,0 +1,64 @@
+use crate::form::{form_ids_of_type, TESForm};
+
+const FORM_TYPE_RELA: u8 = 0x79;
+
+/// `BGSRelationship::RELATIONSHIP_LEVEL`, from `kLover` to `kArchnemesis`. The rank Papyrus
+/// reports is 4 minus the level.
+const LEVELS: [&str; 9] = [
+    "lover",
+    "ally",
+    "confidant",
+    "friend",
+    "acquaintance",
+    "rival",
+    "foe",
+    "enemy",
+    "archnemesis",
+];
+
+/// `BGSRelationship`
+#[repr(C)]
+struct BGSRelationship {
+    form: TESForm,
+    npc1: *const TESForm,        // 20
+    npc2: *const TESForm,        // 28
+    association: *const TESForm, // 30
+    level: u32,                  // 38
+}
+
+pub(crate) struct Relationship {
+    pub(crate) form_id: u32,
+    pub(crate) npc1_form_id: Option<u32>,
+    pub(crate) npc2_form_id: Option<u32>,
+    pub(crate) association_form_id: Option<u32>,
+    pub(crate) rank: i32,
+    pub(crate) level: &'static str,
+}
+
+/// The relationships of the NPC `npc_form_id` with others, as the game has them now. Papyrus
+/// changes the level of these when it sets a relationship rank.
+pub(crate) fn relationships_of(npc_form_id: u32) -> Vec<Relationship> {
+    form_ids_of_type(FORM_TYPE_RELA)
+        .into_iter()
+        .filter_map(|form_id| unsafe {
+            let relationship =
+                (TESForm::look_up_by_id(form_id) as *const BGSRelationship).as_ref()?;
+            let npc1_form_id = relationship.npc1.as_ref().map(TESForm::form_id);
+            let npc2_form_id = relationship.npc2.as_ref().map(TESForm::form_id);
+            if npc1_form_id != Some(npc_form_id) && npc2_form_id != Some(npc_form_id) {
+                return None;
+            }
+            Some(Relationship {
+                form_id,
+                npc1_form_id,
+                npc2_form_id,
+                association_form_id: relationship.association.as_ref().map(TESForm::form_id),
+                rank: 4 - relationship.level as i32,
+                level: LEVELS
+                    .get(relationship.level as usize)
+                    .copied()
+                    .unwrap_or("unknown"),
+            })
+        })
+        .collect()
+}
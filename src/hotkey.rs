@@ -0,0 +1,198 @@
+use crate::config::CONFIG;
+use crate::event::{self, EventSink, EventSinkVtable, EventSource};
+use crate::{app, console, log};
+use late_static::LateStatic;
+use std::time::Duration;
+use winapi::ctypes::c_void;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::processthreadsapi::GetCurrentProcessId;
+use winapi::um::winuser::{
+    GetAsyncKeyState, GetForegroundWindow, GetWindowThreadProcessId, MapVirtualKeyW, SendInput,
+    INPUT, INPUT_KEYBOARD, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE, MAPVK_VK_TO_VSC,
+    MAPVK_VK_TO_VSC_EX,
+};
+
+/// Time the console takes to open before it takes the typed text.
+const CONSOLE_OPEN_DELAY: Duration = Duration::from_millis(150);
+
+/// `INPUT_DEVICE::kKeyboard` and `INPUT_EVENT_TYPE::kButton`.
+const DEVICE_KEYBOARD: u32 = 0;
+const EVENT_BUTTON: u32 = 0;
+
+struct State {
+    /// `BSInputDeviceManager::GetSingleton`, which is a `BSTEventSource<InputEvent*>`.
+    input_device_manager: *const *mut EventSource,
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+/// `ButtonEvent`, the `InputEvent` of a key.
+#[repr(C)]
+struct ButtonEvent {
+    vtable: usize,
+    device: u32,              // 08
+    event_type: u32,          // 0C
+    next: *const ButtonEvent, // 10
+    user_event: usize,        // 18, `BSFixedString`
+    /// The DirectInput scan code of a key, with the high bit set for the extended keys.
+    id_code: u32, // 20
+    padding_24: u32,          // 24
+    value: f32,               // 28
+    held_down_secs: f32,      // 2C
+}
+
+static SINK_VTABLE: EventSinkVtable = EventSinkVtable {
+    destructor: 0,
+    process_event,
+};
+static SINK: EventSink = EventSink {
+    vtable: &SINK_VTABLE,
+};
+
+/// Has the game pass its input to the hotkeys of the config, if any is set, once its input
+/// devices are created. The hotkeys act only on the main thread, as the game handles its input.
+pub(crate) fn on_input_loaded() {
+    if CONFIG.hotkey == 0 && CONFIG.rerun_hotkey == 0 {
+        return;
+    }
+    unsafe {
+        match (*S.input_device_manager).as_mut() {
+            Some(source) => source.add_sink(&SINK),
+            None => log::warn("hotkey: the game has no input device manager"),
+        }
+    }
+}
+
+extern "C" fn process_event(
+    _sink: *const EventSink,
+    event: *const c_void,
+    _source: *mut EventSource,
+) -> u32 {
+    log::catch_panic("hotkey", || {
+        if !console::is_enabled() {
+            return;
+        }
+        // the events of a frame, as a list
+        let mut event = unsafe { (event as *const *const ButtonEvent).as_ref() }
+            .map_or(std::ptr::null(), |event| *event);
+        while let Some(button) = unsafe { event.as_ref() } {
+            let pressed = button.device == DEVICE_KEYBOARD
+                && button.event_type == EVENT_BUTTON
+                && button.value > 0.0
+                && button.held_down_secs == 0.0;
+            if pressed && button.id_code == scan_code(CONFIG.hotkey) {
+                std::thread::spawn(|| open_console("ss "));
+            } else if pressed && button.id_code == scan_code(CONFIG.rerun_hotkey) {
+                rerun();
+            }
+            event = button.next;
+        }
+    });
+    event::CONTINUE
+}
+
+/// The DirectInput scan code of the virtual key `vk`, as `ButtonEvent::id_code` has it, or 0.
+fn scan_code(vk: u32) -> u32 {
+    if vk == 0 {
+        return 0;
+    }
+    let scan = unsafe { MapVirtualKeyW(vk, MAPVK_VK_TO_VSC_EX) };
+    // the extended keys are 0xE0 followed by their code
+    if scan & 0xFF00 == 0xE000 {
+        (scan & 0xFF) | 0x80
+    } else {
+        scan
+    }
+}
+
+/// Runs the last `ss` command again, which prints to the console as it runs.
+fn rerun() {
+    let line = match app::last_ss_command() {
+        Some(line) => line,
+        None => return,
+    };
+    log::info(line.as_str());
+    console::print(line.as_str());
+    app::record_history(&line);
+    match app::process_console_input(&line, None) {
+        Ok(app::ProcessResult::Processed) => {}
+        Ok(_) => console::print(format!("{}: run it in the console", line)),
+        Err(err) => console::print(format!("{:#}", err)),
+    }
+}
+
+/// Whether `cancel_hotkey` is held while the game has the focus. Polled by the statements of
+/// console commands as they run, which keep the game from handling its input until they are done.
+pub(crate) fn is_cancel_key_down() -> bool {
+    is_key_down(CONFIG.cancel_hotkey) && is_game_focused()
+}
+
+fn is_key_down(key: u32) -> bool {
+    // the high bit is set while the key is down
+    key != 0 && unsafe { GetAsyncKeyState(key as i32) } as u16 & 0x8000 != 0
+}
+
+fn is_game_focused() -> bool {
+    unsafe {
+        let mut process_id: DWORD = 0;
+        GetWindowThreadProcessId(GetForegroundWindow(), &mut process_id);
+        process_id == GetCurrentProcessId()
+    }
+}
+
+/// Opens the console with its key and types `text` into it.
+pub(crate) fn open_console(text: &str) {
+    // the game reads the console key through DirectInput, which only sees scan codes
+    send_inputs(&key_inputs(CONFIG.console_key as u16));
+    std::thread::sleep(CONSOLE_OPEN_DELAY);
+    let inputs = text
+        .encode_utf16()
+        .flat_map(|c| {
+            [false, true].map(|up| {
+                keyboard_input(c, KEYEVENTF_UNICODE | if up { KEYEVENTF_KEYUP } else { 0 })
+            })
+        })
+        .collect::<Vec<_>>();
+    send_inputs(&inputs);
+}
+
+/// Pressing and releasing the virtual key `vk`.
+fn key_inputs(vk: u16) -> [INPUT; 2] {
+    let scan = unsafe { MapVirtualKeyW(vk as u32, MAPVK_VK_TO_VSC) } as u16;
+    [
+        keyboard_input(scan, KEYEVENTF_SCANCODE),
+        keyboard_input(scan, KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP),
+    ]
+}
+
+/// A key event of the scan code or, with `KEYEVENTF_UNICODE`, the character `scan`.
+fn keyboard_input(scan: u16, flags: DWORD) -> INPUT {
+    unsafe {
+        let mut input: INPUT = std::mem::zeroed();
+        input.type_ = INPUT_KEYBOARD;
+        let ki = input.u.ki_mut();
+        ki.wScan = scan;
+        ki.dwFlags = flags;
+        input
+    }
+}
+
+fn send_inputs(inputs: &[INPUT]) {
+    unsafe {
+        SendInput(
+            inputs.len() as u32,
+            inputs.as_ptr() as *mut INPUT,
+            std::mem::size_of::<INPUT>() as i32,
+        );
+    }
+}
+
+pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
+    LateStatic::assign(
+        &S,
+        State {
+            input_device_manager: (image_base + versionlib!(402776)) as *const *mut EventSource,
+        },
+    );
+    Ok(())
+}
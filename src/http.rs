@@ -0,0 +1,200 @@
+use crate::api;
+use crate::config::CONFIG;
+use crate::log;
+use crate::log::Loggable;
+use anyhow::{anyhow, Context};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::time::Duration;
+
+/// A client that has not sent its request in this time is dropped, so that it does not block
+/// the others.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+const JSON: &str = "application/json";
+
+/// The page at `/`, which runs queries through `/query` and shows the rows as a table.
+const VIEWER_HTML: &str = include_str!("viewer.html");
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    host: Option<String>,
+    body: String,
+}
+
+/// Serves HTTP on `127.0.0.1:<http_port>` if `http_port` is set. One request is served at a time,
+/// and the connection is closed after it.
+pub(crate) fn init() {
+    if CONFIG.http_port == 0 {
+        return;
+    }
+    std::thread::spawn(|| {
+        let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, CONFIG.http_port))
+            .with_context(|| format!("http: bind 127.0.0.1:{}", CONFIG.http_port))
+            .logging_ok()
+        {
+            Some(listener) => listener,
+            None => return,
+        };
+        log::info(format!("http: listening on 127.0.0.1:{}", CONFIG.http_port).as_str());
+        for stream in listener.incoming() {
+            if let Some(stream) = stream.context("http: accept").logging_ok() {
+                serve(stream).context("http").logging_ok();
+            }
+        }
+    });
+}
+
+fn serve(mut stream: TcpStream) -> anyhow::Result<()> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .context("set_read_timeout")?;
+    let request = read_request(&stream)?;
+    let (status, content_type, body) = respond(&request);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )
+    .context("write")?;
+    stream.write_all(body.as_bytes()).context("write")?;
+    Ok(())
+}
+
+fn read_request(stream: &TcpStream) -> anyhow::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).context("read")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().context("no method")?.to_string();
+    let target = parts.next().context("no target")?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut host = None;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).context("read")?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("host") {
+                host = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().context("content-length")?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        anyhow::bail!("the body is too large");
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).context("read")?;
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect(),
+        host,
+        body: String::from_utf8(body).context("body")?,
+    })
+}
+
+/// Decodes a component of a query string, where `+` is a space.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = |byte: u8| (byte as char).to_digit(16);
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The status, content type and body answering `request`.
+fn respond(request: &Request) -> (&'static str, &'static str, String) {
+    // pages of other sites may send requests to localhost, but with their own host name
+    let port = CONFIG.http_port;
+    let local_hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    if !request
+        .host
+        .as_ref()
+        .map_or(false, |host| local_hosts.contains(host))
+    {
+        return (
+            "403 Forbidden",
+            JSON,
+            api::error_json(&anyhow!("forbidden host")),
+        );
+    }
+    let param = |name: &str| {
+        request
+            .query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let result = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => {
+            return (
+                "200 OK",
+                "text/html; charset=utf-8",
+                VIEWER_HTML.to_string(),
+            )
+        }
+        ("GET", "/query") => param("sql")
+            .context("sql is required")
+            .and_then(|sql| api::query_json(sql, &[])),
+        ("POST", "/query") => api::answer(&request.body),
+        ("GET", "/search") => param("q")
+            .context("q is required")
+            .and_then(|terms| api::search_json(terms, param("kind"))),
+        (_, "/" | "/query" | "/search") => {
+            return (
+                "405 Method Not Allowed",
+                JSON,
+                api::error_json(&anyhow!("method not allowed")),
+            )
+        }
+        _ => {
+            return (
+                "404 Not Found",
+                JSON,
+                api::error_json(&anyhow!("not found")),
+            )
+        }
+    };
+    match result {
+        Ok(json) => ("200 OK", JSON, json),
+        Err(err) => ("400 Bad Request", JSON, api::error_json(&err)),
+    }
+}
@@ -1,24 +1,48 @@
-#[macro_use]
-extern crate versionlib_macro;
+/// The offset of the Address Library ID `$id` from the image base in the running game, or the
+/// error that the ID is missing from its Address Library.
+macro_rules! versionlib {
+    ($id:literal) => {
+        crate::address::offset($id, versionlib_macro::versionlib!($id))?
+    };
+}
 
+mod address;
+mod api;
 mod app;
+mod bsa;
+mod command;
+mod config;
 mod console;
 mod db;
+mod event;
+mod ffi;
 mod form;
+mod hotkey;
+mod http;
+mod live;
 mod log;
+mod messages;
+mod messaging;
+mod modevent;
+mod papyrus;
 mod patch;
+mod path;
+mod pattern;
+mod pipe;
+mod plugins;
+mod records;
+mod remote;
+mod strings;
+mod task;
 
 use crate::log::Loggable;
 use anyhow::Context;
 use std::fmt::{Debug, Formatter};
-use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::{fmt, ptr};
-use win_dbg_logger::output_debug_string;
 use winapi::ctypes::c_void;
 use winapi::um::libloaderapi::GetModuleHandleA;
 
-const DEBUG: bool = false;
-
 type PluginHandle = u32;
 
 enum DataVersion {
@@ -52,6 +76,11 @@ const fn zero_pad_u8<const N: usize, const M: usize>(arr: &[u8; N]) -> [u8; M] {
     m
 }
 
+/// `kVersionIndependent_AddressLibraryPostAE`: addresses are looked up in the Address Library.
+const VERSION_INDEPENDENT_ADDRESS_LIBRARY_POST_AE: u32 = 1 << 0;
+/// `kVersionIndependent_StructsPost629`: the layouts are those of 1.6.629 and later.
+const VERSION_INDEPENDENT_STRUCTS_POST_629: u32 = 1 << 2;
+
 #[no_mangle]
 pub static SKSEPlugin_Version: SKSEPluginVersionData = SKSEPluginVersionData {
     dataVersion: DataVersion::KVersion as u32,
@@ -59,25 +88,9 @@ pub static SKSEPlugin_Version: SKSEPluginVersionData = SKSEPluginVersionData {
     name: zero_pad_u8(b"Skyrim Search SE\0"),
     author: zero_pad_u8(b"qbx2, lukasaldersley\0"),
     supportEmail: zero_pad_u8(b"open a GitHub issue on qbx2's GitHub\0"),
-    versionIndependence: 0,
-    compatibleVersions: [
-        target_version!(),
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-        0,
-    ],
+    versionIndependence: VERSION_INDEPENDENT_ADDRESS_LIBRARY_POST_AE
+        | VERSION_INDEPENDENT_STRUCTS_POST_629,
+    compatibleVersions: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
     seVersionRequired: 0,
 };
 
@@ -105,51 +118,109 @@ impl Debug for SKSEInterface {
     }
 }
 
+/// The version of the SKSE that loaded this plugin, for `ss version`.
+static SKSE_VERSION: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn skse_version() -> u32 {
+    SKSE_VERSION.load(Ordering::SeqCst)
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct PluginInfo {
+    infoVersion: u32,
+    name: *const u8,
+    version: u32,
+}
+
+/// Only SKSEVR and the SKSE of SE 1.5 query plugins; the SKSE of AE reads `SKSEPlugin_Version`
+/// instead. The plugin loads where `address` has a profile of the runtime, i.e. in VR, and
+/// declines to load in SE 1.5 and says why.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn SKSEPlugin_Query(skse: *const SKSEInterface, info: *mut PluginInfo) -> bool {
+    if let Some(info) = unsafe { info.as_mut() } {
+        info.infoVersion = 1;
+        info.name = b"Skyrim Search SE\0".as_ptr();
+        info.version = SKSEPlugin_Version.pluginVersion;
+    }
+    let skse = match unsafe { skse.as_ref() } {
+        Some(skse) => skse,
+        None => return false,
+    };
+    match address::profile(skse.runtime_version, skse.is_editor != 0) {
+        Ok(_) => true,
+        Err(err) => {
+            log::error(format!("Skyrim Search SE is disabled: {:#}", err).as_str());
+            false
+        }
+    }
+}
+
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn SKSEPlugin_Load(skse: *const SKSEInterface) -> bool {
+    // the panics are caught where the game calls into the plugin, see `log::catch_panic`
     std::panic::set_hook(Box::new(|info| {
-        let msg = info.to_string();
-        output_debug_string(msg.as_str());
-        if let Ok(mut w) = log::LOG.lock() {
-            w.write_all((msg + "\n").as_bytes()).ok();
-        }
+        log::error(format!("panic: {}", info).as_str());
     }));
 
     let skse = unsafe { &*skse };
+    SKSE_VERSION.store(skse.skse_version, Ordering::SeqCst);
+    config::init();
     lazy_static::initialize(&log::LOG);
-    output_debug_string(format!("ssse skse load: {:#?}", skse).as_str());
+    log::debug(format!("ssse skse load: {:#?}", skse).as_str());
 
     let result: anyhow::Result<()> = (|| {
         unsafe {
             let image_base = GetModuleHandleA(ptr::null()) as usize;
 
+            address::init(skse.runtime_version, skse.is_editor != 0, image_base)
+                .context("address::init")?;
+
             console::init(image_base).context("console::init")?;
+            // the console hook runs the commands without it, so it is not fatal
+            command::init(image_base)
+                .context("command::init")
+                .logging_ok();
             form::init(image_base).context("form::init")?;
             app::init(image_base).context("app::init")?;
+            modevent::init(image_base).context("modevent::init")?;
+            event::init(image_base).context("event::init")?;
+            hotkey::init(image_base).context("hotkey::init")?;
+            // only the scripts that call its functions need it, so it is not fatal
+            papyrus::init(skse, image_base)
+                .context("papyrus::init")
+                .logging_ok();
         }
+        messaging::init(skse).context("messaging::init")?;
+        modevent::listen();
+        task::init(skse).context("task::init")?;
+        pipe::init();
+        http::init();
+        remote::init();
 
         Ok(())
     })();
 
-    lazy_static::initialize(&db::DB);
-
     if let Err(err) = result {
-        log::LOG
-            .lock()
-            .unwrap()
-            .write_all(format!("error SKSEPlugin_Load: {}\n", err).as_bytes())
-            .unwrap();
+        // the console must not call into the half of the plugin that failed to initialize
+        console::disable();
+        let msg = format!("Skyrim Search SE is disabled: {:#}", err);
+        log::error(msg.as_str());
         return false;
     }
 
-    log::LOG
-        .lock()
-        .unwrap()
-        .write_all("SkyrimSearchSe is ready\n".as_bytes())
-        .logging_ok();
+    // a database that fails to open is reported by the commands, which `ss rebuild` retries
+    db::init_memory_limit();
+    lazy_static::initialize(&db::DB);
+    records::init();
 
-    output_debug_string("SkyrimSearchSe is ready");
+    log::event(
+        "init",
+        "SkyrimSearchSe is ready",
+        serde_json::json!({ "version": env!("CARGO_PKG_VERSION") }),
+    );
 
     true
 }
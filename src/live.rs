@@ -0,0 +1,138 @@
+use crate::form::{refr, TESForm};
+use crate::task;
+use rusqlite::vtab::{
+    eponymous_only_module, sqlite3_vtab, sqlite3_vtab_cursor, Context, IndexInfo, VTab,
+    VTabConnection, VTabCursor, Values,
+};
+use rusqlite::Connection;
+use std::marker::PhantomData;
+use std::os::raw::c_int;
+
+/// A row of `vt_loaded_refs`: a reference in the attached cells as it is now.
+struct LoadedRef {
+    form_id: u32,
+    base_form_id: Option<u32>,
+    name: Option<String>,
+    cell_form_id: Option<u32>,
+    location: [f32; 3],
+}
+
+impl LoadedRef {
+    fn of(form_id: u32) -> Option<LoadedRef> {
+        let form = unsafe { TESForm::look_up_by_id(form_id).as_ref() }?;
+        let refr = refr::as_refr(form)?;
+        let base_form = unsafe { refr.base_form.as_ref() };
+        Some(LoadedRef {
+            form_id,
+            base_form_id: base_form.map(TESForm::form_id),
+            name: base_form
+                .and_then(TESForm::get_name)
+                .map(|name| name.to_string()),
+            cell_form_id: refr::parent_cell_form_id(refr),
+            location: refr.location,
+        })
+    }
+}
+
+#[repr(C)]
+struct LoadedRefsTab {
+    /// must be first
+    base: sqlite3_vtab,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for LoadedRefsTab {
+    type Aux = ();
+    type Cursor = LoadedRefsCursor<'vtab>;
+
+    fn connect(
+        _: &mut VTabConnection,
+        _aux: Option<&()>,
+        _args: &[&[u8]],
+    ) -> rusqlite::Result<(String, LoadedRefsTab)> {
+        let sql = "CREATE TABLE x (form_id INTEGER, base_form_id INTEGER, name TEXT, \
+                   cell_form_id INTEGER, x REAL, y REAL, z REAL)";
+        Ok((
+            sql.to_string(),
+            LoadedRefsTab {
+                base: sqlite3_vtab::default(),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> rusqlite::Result<()> {
+        // a full scan, as no column is indexed
+        info.set_estimated_cost(1000.0);
+        Ok(())
+    }
+
+    fn open(&'vtab self) -> rusqlite::Result<LoadedRefsCursor<'vtab>> {
+        Ok(LoadedRefsCursor {
+            base: sqlite3_vtab_cursor::default(),
+            rows: Vec::new(),
+            i: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[repr(C)]
+struct LoadedRefsCursor<'vtab> {
+    /// must be first
+    base: sqlite3_vtab_cursor,
+    rows: Vec<LoadedRef>,
+    i: usize,
+    phantom: PhantomData<&'vtab LoadedRefsTab>,
+}
+
+unsafe impl VTabCursor for LoadedRefsCursor<'_> {
+    /// Reads the references as the statement starts, so that it sees them as of one moment. Only
+    /// the main thread may, as on others the game may free them while they are read, e.g. for a
+    /// query of the pipe or HTTP server.
+    fn filter(&mut self, _: c_int, _: Option<&str>, _: &Values<'_>) -> rusqlite::Result<()> {
+        if !task::is_main_thread() {
+            return Err(rusqlite::Error::ModuleError(
+                "vt_loaded_refs can only be read by console commands".to_string(),
+            ));
+        }
+        self.rows = refr::loaded_form_ids()
+            .into_iter()
+            .filter_map(LoadedRef::of)
+            .collect();
+        self.i = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> rusqlite::Result<()> {
+        self.i += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.i >= self.rows.len()
+    }
+
+    fn column(&self, ctx: &mut Context, i: c_int) -> rusqlite::Result<()> {
+        let row = &self.rows[self.i];
+        match i {
+            0 => ctx.set_result(&row.form_id),
+            1 => ctx.set_result(&row.base_form_id),
+            2 => ctx.set_result(&row.name),
+            3 => ctx.set_result(&row.cell_form_id),
+            i => ctx.set_result(&f64::from(row.location[i as usize - 4])),
+        }
+    }
+
+    fn rowid(&self) -> rusqlite::Result<i64> {
+        Ok(self.rows[self.i].form_id as i64)
+    }
+}
+
+/// Registers `vt_loaded_refs`, which reads the references of the attached cells from the game as
+/// each statement runs, unlike `refr`, which the worker updates as cells are attached.
+pub(crate) fn register(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_module(
+        "vt_loaded_refs",
+        eponymous_only_module::<LoadedRefsTab>(),
+        None,
+    )
+}
@@ -1,21 +1,19 @@
-use anyhow::{anyhow, Context};
+use crate::config::CONFIG;
+use crate::path;
+use anyhow::Context;
 use lazy_static::lazy_static;
-use std::ffi::CStr;
-use std::fs::File;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
 use std::io::LineWriter;
 use std::io::Write;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Mutex;
 use win_dbg_logger::output_debug_string;
-use winapi::shared::minwindef::MAX_PATH;
-use winapi::shared::ntdef::NULL;
-use winapi::shared::windef::HWND;
-use winapi::shared::winerror::S_OK;
-use winapi::um::errhandlingapi::GetLastError;
-use winapi::um::shlobj::{
-    SHGetFolderPathA, CSIDL_FLAG_CREATE, CSIDL_MYDOCUMENTS, SHGFP_TYPE_CURRENT,
-};
+use winapi::um::sysinfoapi::GetLocalTime;
 
-const LOG_PATH: &str = "\\My Games\\Skyrim Special Edition\\SKSE\\skyrim-search-se.log";
+const LOG_PATH: &str = "\\SKSE\\SkyrimSearchSE";
+const RESULTS_LOG_PATH: &str = "\\SKSE\\SkyrimSearchSE.results.log";
 
 lazy_static! {
     pub static ref LOG: Mutex<LineWriter<File>> = {
@@ -28,30 +26,250 @@ lazy_static! {
             }
         }
     };
+    /// Every command and what it printed, in full, unlike the console. Unlike `LOG`, it is
+    /// appended to in every session.
+    static ref RESULTS_LOG: Option<Mutex<LineWriter<File>>> = open_results_log()
+        .context("open_results_log error")
+        .logging_ok()
+        .map(Mutex::new);
 }
 
-fn open_log_file() -> anyhow::Result<LineWriter<File>> {
-    unsafe {
-        let mut path = Vec::with_capacity(MAX_PATH);
-        let result = SHGetFolderPathA(
-            NULL as HWND,
-            CSIDL_MYDOCUMENTS | CSIDL_FLAG_CREATE,
-            NULL,
-            SHGFP_TYPE_CURRENT,
-            path.as_mut_ptr(),
+/// How much goes to `SkyrimSearchSE.log`: the messages of this level and the more severe ones.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    /// The level named `name` as in the config, e.g. `warn`.
+    pub(crate) fn from_name(name: &str) -> Option<Level> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug]
+            .iter()
+            .copied()
+            .find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// How the lines of `SkyrimSearchSE.log` are written.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    /// `[2021-05-01 12:00:00] INFO  message`, continued on the next lines if it has several.
+    Text,
+    /// One JSON object per line, e.g. `{"time":"2021-05-01 12:00:00","level":"info",
+    /// "event":"query","message":"...","duration_ms":12}`, for tools that follow the log.
+    Json,
+}
+
+// Set from the config by `configure`, as the config logs its own errors as it loads.
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static TO_DEBUGGER: AtomicBool = AtomicBool::new(true);
+static FILES: AtomicUsize = AtomicUsize::new(3);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Applies the settings `log_level`, `log_to_debugger`, `log_files` and `log_format`.
+pub(crate) fn configure(level: Level, to_debugger: bool, files: usize, format: Format) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    TO_DEBUGGER.store(to_debugger, Ordering::Relaxed);
+    FILES.store(files, Ordering::Relaxed);
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// Writes `msg` to `SkyrimSearchSE.log` with the local time and `level`, and to the debugger if
+/// `log_to_debugger` is set, unless `log_level` leaves `level` out.
+pub(crate) fn write(level: Level, msg: &str) {
+    write_event(level, None, msg, serde_json::Map::new());
+}
+
+/// Logs the event `name`, e.g. `query`, at the info level. Unlike the other messages, the JSON
+/// format has its name and `fields` as well, which must be an object.
+pub(crate) fn event(name: &str, msg: &str, fields: serde_json::Value) {
+    let fields = match fields {
+        serde_json::Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    };
+    write_event(Level::Info, Some(name), msg, fields);
+}
+
+fn write_event(
+    level: Level,
+    event: Option<&str>,
+    msg: &str,
+    fields: serde_json::Map<String, serde_json::Value>,
+) {
+    if level as u8 > LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    if TO_DEBUGGER.load(Ordering::Relaxed) {
+        output_debug_string(msg);
+    }
+    let line = if JSON.load(Ordering::Relaxed) {
+        let mut object = serde_json::Map::new();
+        object.insert("time".to_string(), timestamp().into());
+        object.insert(
+            "level".to_string(),
+            level.name().to_ascii_lowercase().into(),
         );
-        if result != S_OK {
-            anyhow::bail!(
-                "failed to SHGetFolderPathA, ret = {}, err = {}",
-                result,
-                GetLastError()
-            );
+        if let Some(event) = event {
+            object.insert("event".to_string(), event.into());
+        }
+        object.insert("message".to_string(), msg.into());
+        object.extend(fields);
+        format!("{}\n", serde_json::Value::Object(object))
+    } else {
+        format!("[{}] {:5} {}\n", timestamp(), level.name(), msg)
+    };
+    match LOG.lock() {
+        Ok(mut log) => {
+            log.write_all(line.as_bytes()).ok();
+        }
+        Err(_) => output_debug_string(msg),
+    }
+}
+
+pub(crate) fn error(msg: &str) {
+    write(Level::Error, msg);
+}
+
+pub(crate) fn warn(msg: &str) {
+    write(Level::Warn, msg);
+}
+
+pub(crate) fn info(msg: &str) {
+    write(Level::Info, msg);
+}
+
+pub(crate) fn debug(msg: &str) {
+    write(Level::Debug, msg);
+}
+
+fn timestamp() -> String {
+    let mut time = unsafe { std::mem::zeroed() };
+    unsafe { GetLocalTime(&mut time) };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute, time.wSecond
+    )
+}
+
+/// Creates `SkyrimSearchSE.log` for this session, after renaming the logs of the earlier ones to
+/// `SkyrimSearchSE.1.log` and so on, keeping `log_files` of them including the new one.
+fn open_log_file() -> anyhow::Result<LineWriter<File>> {
+    let base = path::my_games_dir()? + LOG_PATH;
+    let name = |i: usize| match i {
+        0 => format!("{}.log", base),
+        i => format!("{}.{}.log", base, i),
+    };
+    let files = FILES.load(Ordering::Relaxed).max(1);
+    std::fs::remove_file(name(files - 1)).ok();
+    for i in (0..files - 1).rev() {
+        std::fs::rename(name(i), name(i + 1)).ok();
+    }
+
+    let file = File::create(name(0))?;
+    Ok(LineWriter::new(file))
+}
+
+/// The last `n` messages of this session in `SkyrimSearchSE.log`, oldest first, of `level` and
+/// the more severe ones. A message may span lines, as the tables printed to the console do.
+pub(crate) fn tail(n: usize, level: Level) -> anyhow::Result<Vec<String>> {
+    let path = path::my_games_dir()? + LOG_PATH + ".log";
+    let data = std::fs::read(&path).context(path)?;
+    let text = String::from_utf8_lossy(&data);
+    let mut messages: Vec<(Level, String)> = Vec::new();
+    for line in text.lines() {
+        if let Some(message) = json_message(line) {
+            messages.push(message);
+            continue;
         }
+        match line_level(line) {
+            Some(line_level) => messages.push((line_level, line.to_string())),
+            None => {
+                if let Some((_, message)) = messages.last_mut() {
+                    message.push('\n');
+                    message.push_str(line);
+                }
+            }
+        }
+    }
+    let messages = messages
+        .into_iter()
+        .filter(|(message_level, _)| *message_level <= level)
+        .map(|(_, message)| message)
+        .collect::<Vec<_>>();
+    Ok(messages[messages.len().saturating_sub(n)..].to_vec())
+}
+
+/// The level of a line of the JSON format, and the line as the text format has it.
+fn json_message(line: &str) -> Option<(Level, String)> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    let value = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    let level = Level::from_name(value.get("level")?.as_str()?)?;
+    let field = |name: &str| value.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+    let message = format!(
+        "[{}] {:5} {}",
+        field("time"),
+        level.name(),
+        field("message")
+    );
+    Some((level, message))
+}
+
+/// The level of `line` if it starts a message, as `[2021-05-01 12:00:00] WARN  ...`.
+fn line_level(line: &str) -> Option<Level> {
+    let rest = line.strip_prefix('[')?;
+    let (time, rest) = rest.split_at(rest.find("] ")?);
+    if time.len() != 19 {
+        return None;
+    }
+    Level::from_name(rest[2..].split_whitespace().next()?)
+}
+
+fn open_results_log() -> anyhow::Result<LineWriter<File>> {
+    let path = path::my_games_dir()? + RESULTS_LOG_PATH;
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    Ok(LineWriter::new(file))
+}
+
+/// Appends `text` to `SkyrimSearchSE.results.log`, unless `results_log` is unset.
+pub(crate) fn write_result(text: &str) {
+    if !CONFIG.results_log {
+        return;
+    }
+    if let Some(Ok(mut log)) = RESULTS_LOG.as_ref().map(Mutex::lock) {
+        log.write_all(format!("{}\n", text).as_bytes()).ok();
+    }
+}
 
-        let path = String::from(CStr::from_ptr(path.as_ptr()).to_str()?) + LOG_PATH;
+/// Appends the command `line` to `SkyrimSearchSE.results.log` with the local time.
+pub(crate) fn write_command(line: &str) {
+    write_result(&format!("[{}] {}", timestamp(), line));
+}
 
-        let file = File::create(&path)?;
-        Ok(LineWriter::new(file))
+/// Runs `f`, and returns `None` if it panics rather than unwinding into the game, which would
+/// take it down. The panic hook has logged the panic by then.
+pub(crate) fn catch_panic<T, F: FnOnce() -> T>(what: &str, f: F) -> Option<T> {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(v) => Some(v),
+        Err(_) => {
+            error(format!("{}: caught the panic", what).as_str());
+            None
+        }
     }
 }
 
@@ -64,17 +282,7 @@ impl<T, E: Into<anyhow::Error>> Loggable<T> for Result<T, E> {
         match self {
             Ok(v) => Some(v),
             Err(err) => {
-                let result: anyhow::Result<()> = (|| {
-                    let err = err.into();
-                    output_debug_string(format!("{:#}", err).as_str());
-                    LOG.lock()
-                        .map_err(|e| anyhow!(e.to_string()))?
-                        .write_all(format!("{:#}\n", err).as_bytes())?;
-                    Ok(())
-                })();
-                if let Err(err) = result {
-                    output_debug_string(format!("{:#}", err.context("Loggable::log")).as_str());
-                }
+                error(format!("{:#}", err.into()).as_str());
                 None
             }
         }
@@ -0,0 +1,70 @@
+use crate::log;
+use crate::log::Loggable;
+use crate::strings;
+use anyhow::Context;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Where SKSE plugins and SkyUI keep their `<name>_<LANGUAGE>.txt` translations.
+const TRANSLATIONS_DIR: &str = "Data\\Interface\\Translations";
+const TRANSLATIONS_NAME: &str = "SkyrimSearchSE";
+
+lazy_static! {
+    static ref MESSAGES: HashMap<String, String> =
+        load().context("messages").logging_ok().unwrap_or_default();
+}
+
+/// The message `key`, e.g. `$SkyrimSearchSE_NoResult`, in the language of the game, or `default`
+/// if the translations of that language lack it.
+pub(crate) fn get(key: &str, default: &'static str) -> &'static str {
+    MESSAGES.get(key).map_or(default, String::as_str)
+}
+
+/// `get` with each `{}` replaced by the next of `args`.
+pub(crate) fn format(key: &str, default: &'static str, args: &[&dyn Display]) -> String {
+    let mut args = args.iter();
+    let mut parts = get(key, default).split("{}");
+    let mut s = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            s += &arg.to_string();
+        }
+        s += part;
+    }
+    s
+}
+
+/// Reads `Data\Interface\Translations\SkyrimSearchSE_<LANGUAGE>.txt`, if there is one for the
+/// language of the game: UTF-16 lines of `$key`, a tab and the message, as SKSE reads them.
+fn load() -> anyhow::Result<HashMap<String, String>> {
+    let language = strings::game_language()?.unwrap_or_else(|| "english".to_string());
+    let path = format!(
+        "{}\\{}_{}.txt",
+        TRANSLATIONS_DIR,
+        TRANSLATIONS_NAME,
+        language.to_ascii_uppercase()
+    );
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err).context(path),
+    };
+    let text = match data.strip_prefix(&[0xFF, 0xFE]) {
+        Some(utf16) => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        None => String::from_utf8_lossy(&data).to_string(),
+    };
+    let messages = text
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(key, _)| key.starts_with('$'))
+        .map(|(key, message)| (key.to_string(), message.trim_end().to_string()))
+        .collect::<HashMap<_, _>>();
+    log::info(format!("messages: {} from {}", messages.len(), path).as_str());
+    Ok(messages)
+}
@@ -0,0 +1,93 @@
+use crate::{api, app, hotkey, log, SKSEInterface};
+use anyhow::Context;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use winapi::ctypes::{c_char, c_void};
+
+const INTERFACE_MESSAGING: u32 = 5;
+
+/// `SKSEMessagingInterface::kDispatcher_ModEvent`
+const DISPATCHER_MOD_EVENT: u32 = 0;
+
+static MOD_EVENT_DISPATCHER: AtomicUsize = AtomicUsize::new(0);
+
+/// `SKSEMessagingInterface::kPostLoadGame` and friends, the messages SKSE itself sends.
+const MESSAGE_POST_LOAD_GAME: u32 = 3;
+const MESSAGE_INPUT_LOADED: u32 = 6;
+const MESSAGE_NEW_GAME: u32 = 7;
+const MESSAGE_DATA_LOADED: u32 = 8;
+
+#[repr(C)]
+struct Message {
+    sender: *const c_char,
+    message_type: u32,
+    data_len: u32,
+    data: *const c_void,
+}
+
+type EventCallback = extern "C" fn(*const Message);
+
+#[repr(C)]
+struct SKSEMessagingInterface {
+    interface_version: u32,
+    register_listener: extern "C" fn(u32, *const c_char, EventCallback) -> bool,
+    dispatch: extern "C" fn(u32, u32, *const c_void, u32, *const c_char) -> bool,
+    get_event_dispatcher: extern "C" fn(u32) -> *const c_void,
+}
+
+extern "C" fn on_message(msg: *const Message) {
+    let msg = match unsafe { msg.as_ref() } {
+        Some(msg) => msg,
+        None => return,
+    };
+    log::catch_panic("on_message", || match msg.message_type {
+        // all forms are loaded, so the index is complete
+        MESSAGE_DATA_LOADED => app::on_data_loaded(),
+        MESSAGE_POST_LOAD_GAME | MESSAGE_NEW_GAME => app::on_game_loaded(),
+        MESSAGE_INPUT_LOADED => hotkey::on_input_loaded(),
+        _ => {}
+    });
+}
+
+/// Listens to the messages of all plugins, for the requests of `api`.
+extern "C" fn on_plugin_message(msg: *const Message) {
+    let msg = match unsafe { msg.as_ref() } {
+        Some(msg) => msg,
+        None => return,
+    };
+    if msg.message_type == api::MESSAGE_REQUEST {
+        log::catch_panic("on_plugin_message", || unsafe {
+            api::on_request(msg.data, msg.data_len)
+        });
+    }
+}
+
+/// The `BSTEventSource` of SKSE that sends ModEvents to Papyrus, or 0 if there is none.
+pub(crate) fn mod_event_dispatcher() -> usize {
+    MOD_EVENT_DISPATCHER.load(Ordering::SeqCst)
+}
+
+/// Listens to the messages of SKSE, which tell when the game has loaded its data or a save, and
+/// to the requests of other plugins.
+pub(crate) fn init(skse: &SKSEInterface) -> anyhow::Result<()> {
+    let messaging = (skse.query_interface)(INTERFACE_MESSAGING) as *const SKSEMessagingInterface;
+    let messaging = unsafe { messaging.as_ref() }.context("no messaging interface")?;
+    // SKSE sends its own messages as "SKSE"
+    if !(messaging.register_listener)(
+        (skse.get_plugin_handle)(),
+        "SKSE\0".as_ptr() as *const c_char,
+        on_message,
+    ) {
+        anyhow::bail!("register_listener failed");
+    }
+    // a null sender stands for every plugin
+    if !(messaging.register_listener)(
+        (skse.get_plugin_handle)(),
+        std::ptr::null(),
+        on_plugin_message,
+    ) {
+        anyhow::bail!("register_listener failed for the api");
+    }
+    let dispatcher = (messaging.get_event_dispatcher)(DISPATCHER_MOD_EVENT);
+    MOD_EVENT_DISPATCHER.store(dispatcher as usize, Ordering::SeqCst);
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use crate::config::CONFIG;
+use crate::event::{self, EventSink, EventSinkVtable, EventSource};
+use crate::form::TESForm;
+use crate::{hotkey, log, messaging, plugins, task};
+use late_static::LateStatic;
+use std::borrow::Cow;
+use std::ffi::{CStr, CString};
+use std::mem::transmute;
+use std::path::Path;
+use std::ptr;
+use winapi::ctypes::{c_char, c_void};
+
+/// The ModEvent sent after each search, if `mod_events` is set.
+const EVENT_NAME: &str = "SkyrimSearchSE_Results";
+/// The ModEvent sent for each row before `EVENT_NAME`, e.g. to fill a list menu.
+const ROW_EVENT_NAME: &str = "SkyrimSearchSE_Row";
+/// Rows sent as `ROW_EVENT_NAME` at most, which is as many as a list menu shows comfortably.
+pub(crate) const MAX_ROW_EVENTS: usize = 128;
+
+/// The ModEvent that `SkyrimSearchSE_ListMenu.psc` sends with the form id selected in its menu as
+/// `strArg`.
+const SELECT_EVENT_NAME: &str = "SkyrimSearchSE_Select";
+
+/// The ModEvent that `SkyrimSearchSE_ConsoleUtil.psc` runs `strArg` of with ConsoleUtilSSE, with
+/// the reference `sender` selected if there is one.
+const EXEC_EVENT_NAME: &str = "SkyrimSearchSE_Exec";
+
+/// The plugin that has the quest of the scripts in `papyrus`.
+const PLUGIN_NAME: &str = "SkyrimSearchSE.esl";
+const CONSOLE_UTIL_PATH: &str = "Data\\SKSE\\Plugins\\ConsoleUtilSSE.dll";
+
+/// Separates the columns of the first row in `strArg`, as `StringUtil.Split` takes it.
+const COLUMN_SEPARATOR: &str = "|";
+
+/// A `BSFixedString`, which points to the text of its `BSStringPool::Entry`.
+pub(crate) type StringEntry = *const c_void;
+
+struct State {
+    /// `BSStringPool::GetEntry<char>(entry, string, case_sensitive)`
+    get_entry: extern "C" fn(*mut StringEntry, *const c_char, bool),
+    /// `BSStringPool::Entry::release`
+    release: extern "C" fn(*mut StringEntry),
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+/// A `BSFixedString`, released when dropped.
+pub(crate) struct FixedString(StringEntry);
+
+impl FixedString {
+    pub(crate) fn new(s: &str) -> Self {
+        let s = CString::new(s.replace('\0', "")).unwrap_or_default();
+        let mut entry = ptr::null();
+        (S.get_entry)(&mut entry, s.as_ptr(), false);
+        FixedString(entry)
+    }
+
+    /// The entry, whose reference the game releases from then on, e.g. that of a Papyrus string.
+    pub(crate) fn into_entry(self) -> StringEntry {
+        std::mem::ManuallyDrop::new(self).0
+    }
+}
+
+impl Drop for FixedString {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            (S.release)(&mut self.0);
+        }
+    }
+}
+
+/// `SKSEModCallbackEvent`, which Papyrus gets as `(eventName, strArg, numArg, sender)`.
+#[repr(C)]
+struct ModCallbackEvent {
+    event_name: StringEntry,
+    str_arg: StringEntry,   // 08
+    num_arg: f32,           // 10
+    sender: *const TESForm, // 18
+}
+
+/// Sends `SkyrimSearchSE_Row` for each of `rows`, the first `MAX_ROW_EVENTS` rows of a result
+/// at most, with the index of the row as `numArg`, then `SkyrimSearchSE_Results` with the number
+/// of rows `num_rows` as `numArg` and the first row. `strArg` holds the columns of the row, and
+/// `sender` is the form of its first form id column. They are sent on the main thread, as Papyrus
+/// events are.
+pub(crate) fn send_results(mut rows: Vec<Vec<String>>, num_rows: usize) {
+    if !CONFIG.mod_events {
+        return;
+    }
+    rows.truncate(MAX_ROW_EVENTS);
+    task::add_task(move || unsafe {
+        let source = match (messaging::mod_event_dispatcher() as *mut EventSource).as_mut() {
+            Some(source) => source,
+            None => return,
+        };
+        for (i, row) in rows.iter().enumerate() {
+            send(source, ROW_EVENT_NAME, row, i as f32);
+        }
+        send(
+            source,
+            EVENT_NAME,
+            rows.first().map_or(&[], Vec::as_slice),
+            num_rows as f32,
+        );
+    });
+}
+
+/// Whether `--exec` runs its commands with ConsoleUtilSSE, which it does when both ConsoleUtilSSE
+/// and `SkyrimSearchSE.esl` are installed.
+pub(crate) fn is_console_util_installed() -> bool {
+    Path::new(CONSOLE_UTIL_PATH).exists() && plugins::id_of(PLUGIN_NAME).is_some()
+}
+
+/// Sends `SkyrimSearchSE_Exec` for each of `commands`, with the command as `strArg` and the form
+/// of its form id, if it has one, as `sender`. They are sent on the main thread in order, and the
+/// script runs them in the same order.
+pub(crate) fn send_commands(commands: Vec<(String, Option<u32>)>) {
+    task::add_task(move || unsafe {
+        let source = match (messaging::mod_event_dispatcher() as *mut EventSource).as_mut() {
+            Some(source) => source,
+            None => return,
+        };
+        for (command, form_id) in commands {
+            let sender = form_id.map_or(ptr::null(), TESForm::look_up_by_id);
+            send_event(source, EXEC_EVENT_NAME, &command, 0.0, sender);
+        }
+    });
+}
+
+unsafe fn send(source: &mut EventSource, name: &str, row: &[String], num_arg: f32) {
+    // the search commands print form ids as 8 hex digits, unless hex_ids is unset
+    let sender = row
+        .iter()
+        .filter(|cell| cell.len() == 8 || !CONFIG.hex_ids)
+        .find_map(|cell| u32::from_str_radix(cell, if CONFIG.hex_ids { 16 } else { 10 }).ok())
+        .map_or(ptr::null(), TESForm::look_up_by_id);
+    send_event(source, name, &row.join(COLUMN_SEPARATOR), num_arg, sender);
+}
+
+unsafe fn send_event(
+    source: &mut EventSource,
+    name: &str,
+    str_arg: &str,
+    num_arg: f32,
+    sender: *const TESForm,
+) {
+    let event_name = FixedString::new(name);
+    let str_arg = FixedString::new(str_arg);
+    let event = ModCallbackEvent {
+        event_name: event_name.0,
+        str_arg: str_arg.0,
+        num_arg,
+        sender,
+    };
+    source.send(&event as *const ModCallbackEvent as *const c_void);
+}
+
+static SINK_VTABLE: EventSinkVtable = EventSinkVtable {
+    destructor: 0,
+    process_event,
+};
+static SINK: EventSink = EventSink {
+    vtable: &SINK_VTABLE,
+};
+
+/// Listens for `SkyrimSearchSE_Select` on the ModEvent dispatcher of SKSE, if `mod_events` is set,
+/// and types the form id it has into the console.
+pub(crate) fn listen() {
+    if !CONFIG.mod_events {
+        return;
+    }
+    unsafe {
+        if let Some(source) = (messaging::mod_event_dispatcher() as *mut EventSource).as_mut() {
+            source.add_sink(&SINK);
+        }
+    }
+}
+
+extern "C" fn process_event(
+    _sink: *const EventSink,
+    event: *const c_void,
+    _source: *mut EventSource,
+) -> u32 {
+    log::catch_panic("modevent", || unsafe {
+        let event = match (event as *const ModCallbackEvent).as_ref() {
+            Some(event) => event,
+            None => return,
+        };
+        if text(event.event_name).eq_ignore_ascii_case(SELECT_EVENT_NAME) {
+            let form_id = text(event.str_arg).into_owned();
+            // the Papyrus thread that sent it must not wait for the console to open
+            std::thread::spawn(move || hotkey::open_console(&form_id));
+        }
+    });
+    event::CONTINUE
+}
+
+pub(crate) unsafe fn text<'a>(string: StringEntry) -> Cow<'a, str> {
+    if string.is_null() {
+        Cow::Borrowed("")
+    } else {
+        CStr::from_ptr(string as *const c_char).to_string_lossy()
+    }
+}
+
+pub(crate) unsafe fn init(image_base: usize) -> anyhow::Result<()> {
+    LateStatic::assign(
+        &S,
+        State {
+            get_entry: transmute(image_base + versionlib!(69161)),
+            release: transmute(image_base + versionlib!(69192)),
+        },
+    );
+    Ok(())
+}
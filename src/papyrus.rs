@@ -0,0 +1,298 @@
+use crate::modevent::{self, FixedString, StringEntry};
+use crate::{api, log, pattern, SKSEInterface};
+use late_static::LateStatic;
+use rusqlite::types::Value;
+use std::mem::transmute;
+use winapi::ctypes::c_void;
+
+const INTERFACE_PAPYRUS: u32 = 2;
+
+/// The script that declares the functions, `papyrus/SkyrimSearch.psc`.
+const CLASS_NAME: &str = "SkyrimSearch";
+/// The parameter each function takes.
+const PARAM_NAME: &str = "sql";
+
+/// The class whose vtable the functions copy, with `HasStub` and `MarshallAndDispatch` of their
+/// own.
+const NATIVE_FUNCTION_BASE: &str = ".?AVNativeFunctionBase@NF_util@BSScript@@";
+/// The virtual functions of `IFunction` and the two that `NativeFunctionBase` adds.
+const VTABLE_LEN: usize = 0x17;
+const HAS_STUB: usize = 0x15;
+const MARSHALL_AND_DISPATCH: usize = 0x16;
+
+/// `IVirtualMachine::CreateArray(element_type, size, array)` and
+/// `IVirtualMachine::BindNativeMethod(function)`.
+const CREATE_ARRAY: usize = 0x15;
+const BIND_NATIVE_METHOD: usize = 0x17;
+
+/// `BSScript::TypeInfo::RawType`
+const TYPE_NONE: usize = 0;
+const TYPE_STRING: usize = 2;
+const TYPE_INT: usize = 3;
+const TYPE_INT_ARRAY: usize = 13;
+
+#[repr(C)]
+struct SKSEPapyrusInterface {
+    interface_version: u32,
+    register: extern "C" fn(extern "C" fn(*mut VirtualMachine) -> bool) -> bool,
+}
+
+/// `BSScript::IVirtualMachine`, which SKSE passes to the functions that register natives.
+#[repr(C)]
+struct VirtualMachine {
+    vtable: *const usize,
+}
+
+/// `BSScript::Variable`: a `TypeInfo`, and the value, e.g. an int or a `BSFixedString`.
+#[repr(C)]
+struct Variable {
+    type_info: usize,
+    value: usize, // 08
+}
+
+/// `BSScript::Array`, whose elements follow it.
+#[repr(C)]
+struct Array {
+    ref_count: u32,
+    padding_04: u32,     // 04
+    element_type: usize, // 08
+    len: u32,            // 10
+    padding_14: u32,     // 14
+    lock: u64,           // 18
+}
+
+/// `BSScript::StackFrame`, of which only the stack is read; the arguments belong to the stack.
+#[repr(C)]
+struct StackFrame {
+    stack: *mut c_void,
+}
+
+/// An entry of `VDescTable`: the name and the type of a parameter.
+#[repr(C)]
+struct Param {
+    name: StringEntry,
+    type_info: usize, // 08
+}
+
+/// What a function returns for the first column of the rows of its statement.
+#[derive(Clone, Copy)]
+enum Native {
+    /// The value of the first row as an int, or 0.
+    Int,
+    /// The value of the first row as text, or "".
+    String,
+    /// The values of all rows as ints, e.g. form ids, which `QueryForms` looks up.
+    IntArray,
+}
+
+const NATIVES: [(&str, Native); 3] = [
+    ("QueryInt", Native::Int),
+    ("QueryString", Native::String),
+    ("QueryFormIDs", Native::IntArray),
+];
+
+/// `BSScript::NF_util::NativeFunctionBase`, followed by the function it runs.
+#[repr(C)]
+struct NativeFunction {
+    vtable: *const usize,
+    ref_count: u32,                 // 08
+    padding_0c: u32,                // 0C
+    name: StringEntry,              // 10
+    class_name: StringEntry,        // 18
+    state_name: StringEntry,        // 20
+    return_type: usize,             // 28
+    params: *const Param,           // 30, VDescTable
+    param_count: u16,               // 38
+    total_entries: u16,             // 3A
+    padding_3c: u32,                // 3C
+    is_static: bool,                // 40
+    is_callable_from_tasklet: bool, // 41
+    is_latent: bool,                // 42
+    user_flags: u32,                // 44
+    doc_string: StringEntry,        // 48
+    native: Native,                 // 50
+}
+
+struct State {
+    /// The vtable of `NativeFunctionBase` with `has_stub` and `marshall_and_dispatch`.
+    vtable: [usize; VTABLE_LEN],
+    /// `BSScript::Stack::GetPageForFrame(frame)`
+    get_page_for_frame: extern "C" fn(*mut c_void, *const StackFrame) -> u32,
+    /// `BSScript::Stack::GetStackFrameVariable(frame, index, page)`
+    get_stack_frame_variable:
+        extern "C" fn(*mut c_void, *const StackFrame, u32, u32) -> *mut Variable,
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+extern "C" fn has_stub(_function: *const NativeFunction) -> bool {
+    true
+}
+
+/// Runs the statement of the argument, and stores what the function returns for it in `result`.
+/// A statement that fails is logged, and the function returns 0, "" or an empty array.
+extern "C" fn marshall_and_dispatch(
+    function: *const NativeFunction,
+    _base: *mut Variable,
+    vm: *mut VirtualMachine,
+    _stack_id: u32,
+    result: *mut Variable,
+    frame: *const StackFrame,
+) -> bool {
+    log::catch_panic("papyrus", || unsafe {
+        let function = &*function;
+        let sql = argument(frame);
+        let limit = match function.native {
+            Native::Int | Native::String => Some(1),
+            Native::IntArray => None,
+        };
+        let values = api::query_column(&sql, limit).unwrap_or_else(|err| {
+            let name = modevent::text(function.name);
+            log::warn(format!("papyrus: {}.{}: {:#}", CLASS_NAME, name, err).as_str());
+            vec![]
+        });
+        *result = match function.native {
+            Native::Int => Variable {
+                type_info: TYPE_INT,
+                value: values.first().map_or(0, int) as u32 as usize,
+            },
+            Native::String => Variable {
+                type_info: TYPE_STRING,
+                value: FixedString::new(&values.first().map_or_else(String::new, text)).into_entry()
+                    as usize,
+            },
+            Native::IntArray => match int_array(vm, &values) {
+                Some(array) => Variable {
+                    type_info: TYPE_INT_ARRAY,
+                    value: array as usize,
+                },
+                None => Variable {
+                    type_info: TYPE_NONE,
+                    value: 0,
+                },
+            },
+        };
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// The string that the function got as its one argument.
+unsafe fn argument(frame: *const StackFrame) -> String {
+    let stack = (*frame).stack;
+    let page = (S.get_page_for_frame)(stack, frame);
+    match (S.get_stack_frame_variable)(stack, frame, 0, page).as_ref() {
+        Some(variable) if variable.type_info == TYPE_STRING => {
+            modevent::text(variable.value as StringEntry).into_owned()
+        }
+        _ => String::new(),
+    }
+}
+
+/// A new array of `values`, whose reference the variable it is stored in takes.
+unsafe fn int_array(vm: *mut VirtualMachine, values: &[Value]) -> Option<*mut Array> {
+    let create: extern "C" fn(*mut VirtualMachine, *const usize, u32, *mut *mut Array) -> bool =
+        transmute(*(*vm).vtable.add(CREATE_ARRAY));
+    let mut array = std::ptr::null_mut();
+    if !create(vm, &TYPE_INT, values.len() as u32, &mut array) || array.is_null() {
+        log::warn("papyrus: could not create an array");
+        return None;
+    }
+    let elements = array.add(1) as *mut Variable;
+    for (i, value) in values.iter().enumerate().take((*array).len as usize) {
+        *elements.add(i) = Variable {
+            type_info: TYPE_INT,
+            value: int(value) as u32 as usize,
+        };
+    }
+    Some(array)
+}
+
+/// Papyrus ints have 32 bits, so form ids of 0x80000000 and above are negative, as
+/// `Game.GetFormEx` takes them.
+fn int(value: &Value) -> i32 {
+    match value {
+        Value::Integer(v) => *v as i32,
+        Value::Real(v) => *v as i32,
+        Value::Text(v) => v.trim().parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn text(value: &Value) -> String {
+    match api::json_value(value.into()) {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s,
+        value => value.to_string(),
+    }
+}
+
+/// Binds the functions of `NATIVES` as global functions of `SkyrimSearch`, each with a string
+/// parameter. They may run on the threads of Papyrus, so a query does not hold up the frame.
+extern "C" fn register(vm: *mut VirtualMachine) -> bool {
+    log::catch_panic("papyrus", || unsafe {
+        let bind: extern "C" fn(*mut VirtualMachine, *mut NativeFunction) -> bool =
+            transmute(*(*vm).vtable.add(BIND_NATIVE_METHOD));
+        for &(name, native) in &NATIVES {
+            let params = Box::leak(Box::new(Param {
+                name: FixedString::new(PARAM_NAME).into_entry(),
+                type_info: TYPE_STRING,
+            }));
+            // the reference of its own keeps the VM from ever freeing the function
+            let function = Box::leak(Box::new(NativeFunction {
+                vtable: S.vtable.as_ptr(),
+                ref_count: 1,
+                padding_0c: 0,
+                name: FixedString::new(name).into_entry(),
+                class_name: FixedString::new(CLASS_NAME).into_entry(),
+                state_name: FixedString::new("").into_entry(),
+                return_type: match native {
+                    Native::Int => TYPE_INT,
+                    Native::String => TYPE_STRING,
+                    Native::IntArray => TYPE_INT_ARRAY,
+                },
+                params,
+                param_count: 1,
+                total_entries: 1,
+                padding_3c: 0,
+                is_static: true,
+                is_callable_from_tasklet: true,
+                is_latent: false,
+                user_flags: 0,
+                doc_string: FixedString::new("").into_entry(),
+                native,
+            }));
+            if !bind(vm, function) {
+                log::warn(format!("papyrus: could not bind {}.{}", CLASS_NAME, name).as_str());
+            }
+        }
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// Registers the functions of `papyrus/SkyrimSearch.psc` with the Papyrus interface of SKSE, which
+/// binds them once the VM is created.
+pub(crate) unsafe fn init(skse: &SKSEInterface, image_base: usize) -> anyhow::Result<()> {
+    let papyrus = (skse.query_interface)(INTERFACE_PAPYRUS) as *const SKSEPapyrusInterface;
+    if papyrus.is_null() {
+        anyhow::bail!("no papyrus interface");
+    }
+    let base = image_base + pattern::find_vtable(image_base, NATIVE_FUNCTION_BASE)?;
+    let mut vtable = [0; VTABLE_LEN];
+    vtable.copy_from_slice(std::slice::from_raw_parts(base as *const usize, VTABLE_LEN));
+    vtable[HAS_STUB] = has_stub as usize;
+    vtable[MARSHALL_AND_DISPATCH] = marshall_and_dispatch as usize;
+    LateStatic::assign(
+        &S,
+        State {
+            vtable,
+            get_page_for_frame: transmute(image_base + versionlib!(104480)),
+            get_stack_frame_variable: transmute(image_base + versionlib!(104481)),
+        },
+    );
+    if !((*papyrus).register)(register) {
+        anyhow::bail!("the papyrus interface did not take the functions");
+    }
+    Ok(())
+}
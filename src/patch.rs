@@ -1,4 +1,10 @@
 use anyhow::Context;
+use winapi::shared::minwindef::MAX_PATH;
+use winapi::um::libloaderapi::{
+    GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+    GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+};
+use winapi::um::winnt::LPCWSTR;
 
 pub(crate) unsafe fn patch_bytes<T, U>(
     src: *const T,
@@ -14,3 +20,56 @@ pub(crate) unsafe fn patch_bytes<T, U>(
 
     Ok(original_bytes)
 }
+
+/// Where the jump at `addr` goes, if the code there is a jump as hooks write them: `jmp rel32`,
+/// `jmp [rip+disp32]` or `mov rax, imm64; jmp rax`.
+unsafe fn jump_target(addr: usize) -> Option<usize> {
+    let code = addr as *const u8;
+    let rel32 = |offset: usize| (code.add(offset) as *const i32).read_unaligned() as isize;
+    match (*code, *code.add(1)) {
+        (0xE9, _) => Some(addr.wrapping_add(5).wrapping_add_signed(rel32(1))),
+        (0xFF, 0x25) => {
+            let slot = addr.wrapping_add(6).wrapping_add_signed(rel32(2));
+            Some((slot as *const usize).read_unaligned())
+        }
+        (0x48, 0xB8) if *code.add(10) == 0xFF && *code.add(11) == 0xE0 => {
+            Some((code.add(2) as *const usize).read_unaligned())
+        }
+        _ => None,
+    }
+}
+
+/// The addresses the jumps from `addr` lead through, starting with `addr` and ending with the
+/// first one that is not a jump, i.e. the code that runs.
+pub(crate) unsafe fn jump_chain(addr: usize) -> Vec<usize> {
+    let mut chain = vec![addr];
+    // a cycle would be a bug of some hook, but must not hang the game
+    while chain.len() < 16 {
+        match jump_target(*chain.last().unwrap()) {
+            Some(target) if target != 0 => chain.push(target),
+            _ => break,
+        }
+    }
+    chain
+}
+
+/// `addr` as `module+offset`, e.g. `SkyrimSE.exe+0x33D6A0`, or as is if no module contains it,
+/// e.g. the trampolines hooks allocate.
+pub(crate) fn describe_address(addr: usize) -> String {
+    let mut module = std::ptr::null_mut();
+    let found = unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            addr as LPCWSTR,
+            &mut module,
+        )
+    };
+    if found == 0 {
+        return format!("{:#x}", addr);
+    }
+    let mut path = [0u16; MAX_PATH];
+    let len = unsafe { GetModuleFileNameW(module, path.as_mut_ptr(), path.len() as u32) };
+    let path = String::from_utf16_lossy(&path[..len as usize]);
+    let name = path.rsplit('\\').next().unwrap_or_default();
+    format!("{}+{:#x}", name, addr - module as usize)
+}
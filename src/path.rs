@@ -0,0 +1,44 @@
+use std::ffi::CStr;
+use winapi::shared::minwindef::MAX_PATH;
+use winapi::shared::ntdef::NULL;
+use winapi::shared::windef::HWND;
+use winapi::shared::winerror::S_OK;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::shlobj::{
+    SHGetFolderPathA, CSIDL_FLAG_CREATE, CSIDL_LOCAL_APPDATA, CSIDL_MYDOCUMENTS, SHGFP_TYPE_CURRENT,
+};
+
+const MY_GAMES_PATH: &str = "\\My Games\\Skyrim Special Edition";
+const LOCAL_APP_DATA_PATH: &str = "\\Skyrim Special Edition";
+
+fn get_folder_path(csidl: i32) -> anyhow::Result<String> {
+    unsafe {
+        let mut path = Vec::with_capacity(MAX_PATH);
+        let result = SHGetFolderPathA(
+            NULL as HWND,
+            csidl | CSIDL_FLAG_CREATE,
+            NULL,
+            SHGFP_TYPE_CURRENT,
+            path.as_mut_ptr(),
+        );
+        if result != S_OK {
+            anyhow::bail!(
+                "failed to SHGetFolderPathA, ret = {}, err = {}",
+                result,
+                GetLastError()
+            );
+        }
+
+        Ok(String::from(CStr::from_ptr(path.as_ptr()).to_str()?))
+    }
+}
+
+/// `Documents\My Games\Skyrim Special Edition`
+pub(crate) fn my_games_dir() -> anyhow::Result<String> {
+    Ok(get_folder_path(CSIDL_MYDOCUMENTS)? + MY_GAMES_PATH)
+}
+
+/// `AppData\Local\Skyrim Special Edition`, where `plugins.txt` lives.
+pub(crate) fn local_app_data_dir() -> anyhow::Result<String> {
+    Ok(get_folder_path(CSIDL_LOCAL_APPDATA)? + LOCAL_APP_DATA_PATH)
+}
@@ -0,0 +1,125 @@
+use anyhow::Context;
+
+/// Parses a pattern like `48 8B C4 ?? 89 58`, where `??` matches any byte.
+fn parse(pattern: &str) -> anyhow::Result<Vec<Option<u8>>> {
+    pattern
+        .split_whitespace()
+        .map(|byte| match byte {
+            "?" | "??" => Ok(None),
+            _ => u8::from_str_radix(byte, 16)
+                .map(Some)
+                .with_context(|| format!("not a byte: {}", byte)),
+        })
+        .collect()
+}
+
+/// The positions of the first two matches of `pattern` in `haystack`.
+fn find(haystack: &[u8], pattern: &[Option<u8>]) -> Vec<usize> {
+    if pattern.is_empty() {
+        return vec![];
+    }
+    haystack
+        .windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| {
+            window
+                .iter()
+                .zip(pattern)
+                .all(|(byte, expected)| expected.map_or(true, |expected| *byte == expected))
+        })
+        .map(|(i, _)| i)
+        .take(2)
+        .collect()
+}
+
+/// The section `name`, e.g. `.text`, of the image at `image_base`, and its offset from the image
+/// base.
+pub(crate) unsafe fn section(
+    image_base: usize,
+    name: &str,
+) -> anyhow::Result<(usize, &'static [u8])> {
+    let read_u16 = |addr: usize| *(addr as *const u16);
+    let read_u32 = |addr: usize| *(addr as *const u32);
+    if read_u16(image_base) != 0x5A4D {
+        anyhow::bail!("no MZ header");
+    }
+    // IMAGE_NT_HEADERS64
+    let nt_headers = image_base + read_u32(image_base + 0x3C) as usize;
+    if read_u32(nt_headers) != 0x4550 {
+        anyhow::bail!("no PE header");
+    }
+    let number_of_sections = read_u16(nt_headers + 6) as usize;
+    let size_of_optional_header = read_u16(nt_headers + 20) as usize;
+    let sections = nt_headers + 24 + size_of_optional_header;
+    // IMAGE_SECTION_HEADER
+    (0..number_of_sections)
+        .map(|i| sections + i * 40)
+        .find(|&section| {
+            let section_name = std::slice::from_raw_parts(section as *const u8, 8);
+            section_name.split(|&byte| byte == 0).next() == Some(name.as_bytes())
+        })
+        .map(|section| {
+            let virtual_size = read_u32(section + 8) as usize;
+            let virtual_address = read_u32(section + 12) as usize;
+            (
+                virtual_address,
+                std::slice::from_raw_parts(
+                    (image_base + virtual_address) as *const u8,
+                    virtual_size,
+                ),
+            )
+        })
+        .with_context(|| format!("no {} section", name))
+}
+
+/// The offset from `image_base` of the code that `pattern` matches. It must match exactly once, so
+/// that a pattern which has become ambiguous in a game update does not hook the wrong function.
+pub(crate) unsafe fn scan(image_base: usize, pattern: &str) -> anyhow::Result<usize> {
+    let pattern = parse(pattern)?;
+    let (offset, text) = section(image_base, ".text")?;
+    match find(text, &pattern)[..] {
+        [] => anyhow::bail!("the pattern matches nothing"),
+        [position] => Ok(offset + position),
+        _ => anyhow::bail!("the pattern matches more than once"),
+    }
+}
+
+/// The offset from `image_base` of the vtable of the class whose RTTI name is `name`, e.g.
+/// `.?AVTESNPC@@`, found as MSVC lays it out: the name in a type descriptor, the complete object
+/// locator of the class that points to the type descriptor, and the vtable that follows a pointer
+/// to the locator.
+pub(crate) unsafe fn find_vtable(image_base: usize, name: &str) -> anyhow::Result<usize> {
+    let read_u32 = |bytes: &[u8], i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let (data_offset, data) = section(image_base, ".data")?;
+    let (rdata_offset, rdata) = section(image_base, ".rdata")?;
+
+    let name = format!("{}\0", name);
+    // TypeDescriptor: the vtable of type_info and a spare pointer before the name
+    let type_descriptor = data
+        .windows(name.len())
+        .position(|window| window == name.as_bytes())
+        .filter(|&position| position >= 0x10)
+        .map(|position| data_offset + position - 0x10)
+        .with_context(|| format!("no type descriptor of {}", name.trim_end_matches('\0')))?;
+
+    // RTTICompleteObjectLocator: signature 1, offset 0 in the class (its primary vtable),
+    // constructor displacement, then the type descriptor, class descriptor and itself as offsets
+    // from the image base.
+    let locator = (0..rdata.len().saturating_sub(24))
+        .step_by(4)
+        .find(|&i| {
+            read_u32(rdata, i) == 1
+                && read_u32(rdata, i + 4) == 0
+                && read_u32(rdata, i + 12) as usize == type_descriptor
+                && read_u32(rdata, i + 20) as usize == rdata_offset + i
+        })
+        .map(|i| rdata_offset + i)
+        .with_context(|| format!("no object locator of {}", name.trim_end_matches('\0')))?;
+
+    let locator_address = (image_base + locator) as u64;
+    (0..rdata.len().saturating_sub(8))
+        .step_by(8)
+        .find(|&i| u64::from_le_bytes(rdata[i..i + 8].try_into().unwrap()) == locator_address)
+        .map(|i| rdata_offset + i + 8)
+        .with_context(|| format!("no vtable of {}", name.trim_end_matches('\0')))
+}
@@ -0,0 +1,82 @@
+use crate::api;
+use crate::config::CONFIG;
+use crate::log;
+use crate::log::Loggable;
+use anyhow::Context;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::windows::io::FromRawHandle;
+use std::ptr;
+use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+use winapi::um::winbase::{
+    PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+};
+
+const PIPE_NAME: &str = "\\\\.\\pipe\\skyrim-search-se";
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+/// Serves the named pipe if `pipe_server` is set. A client writes a request of `api::answer_json`
+/// per line, and reads the answer as one line of JSON. One client is served at a time.
+pub(crate) fn init() {
+    if !CONFIG.pipe_server {
+        return;
+    }
+    std::thread::spawn(|| loop {
+        if serve_client().context("pipe").logging_ok().is_none() {
+            // e.g. another instance of the game has the pipe
+            return;
+        }
+    });
+}
+
+fn serve_client() -> anyhow::Result<()> {
+    let name = PIPE_NAME.encode_utf16().chain([0]).collect::<Vec<_>>();
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if pipe == INVALID_HANDLE_VALUE {
+        anyhow::bail!("CreateNamedPipeW failed: {}", unsafe { GetLastError() });
+    }
+    // closes the pipe when dropped
+    let file = unsafe { File::from_raw_handle(pipe as _) };
+    if unsafe { ConnectNamedPipe(pipe, ptr::null_mut()) } == 0 {
+        // the client may connect between CreateNamedPipeW and ConnectNamedPipe
+        let err = unsafe { GetLastError() };
+        if err != ERROR_PIPE_CONNECTED {
+            anyhow::bail!("ConnectNamedPipe failed: {}", err);
+        }
+    }
+    log::info("pipe: a client connected");
+
+    let mut writer = file.try_clone().context("try_clone")?;
+    for line in BufReader::new(file).lines() {
+        // the client has disconnected
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = api::answer_json(&line);
+        if writer
+            .write_all(format!("{}\n", answer).as_bytes())
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
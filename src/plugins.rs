@@ -0,0 +1,243 @@
+use crate::config::CONFIG;
+use crate::log::Loggable;
+use crate::{path, records};
+use anyhow::Context;
+use lazy_static::lazy_static;
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+
+const PLUGINS_TXT_PATH: &str = "\\plugins.txt";
+const DATA_DIR: &str = "Data";
+const PLUGIN_EXTENSIONS: [&str; 3] = ["esm", "esp", "esl"];
+const CCC_PATH: &str = "Skyrim.ccc";
+/// Plugins the game always loads first, whether or not `plugins.txt` mentions them.
+const IMPLICIT_PLUGINS: [&str; 5] = [
+    "Skyrim.esm",
+    "Update.esm",
+    "Dawnguard.esm",
+    "HearthFires.esm",
+    "Dragonborn.esm",
+];
+
+lazy_static! {
+    /// The active plugins as of the launch, whose position is the id of the `plugin` table.
+    static ref ACTIVE: Vec<Plugin> = active()
+        .context("active plugins")
+        .logging_ok()
+        .unwrap_or_default();
+}
+
+/// A row of the `plugin` table.
+pub(crate) struct Plugin {
+    pub(crate) name: String,
+    pub(crate) master: bool,
+    pub(crate) light: bool,
+    pub(crate) form_id_prefix: u32,
+    pub(crate) size: u64,
+    /// Seconds since the Unix epoch.
+    pub(crate) modified: Option<u64>,
+}
+
+/// 64-bit FNV-1a, whose hashes stay the same across Rust releases, unlike those of
+/// `DefaultHasher`, as the persisted index compares them with those of earlier launches.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Hashes `plugins.txt` and the name, size and modification time of every plugin in `Data`.
+/// The hash changes whenever the load order or any plugin changes.
+pub(crate) fn load_order_hash() -> anyhow::Result<String> {
+    let mut hasher = Fnv1a::new();
+
+    let plugins_txt_path = path::local_app_data_dir()? + PLUGINS_TXT_PATH;
+    match std::fs::read(&plugins_txt_path) {
+        Ok(plugins_txt) => hasher.write(&plugins_txt),
+        // a vanilla game may not have plugins.txt at all
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context("read plugins.txt"),
+    }
+
+    let mut plugins = Vec::new();
+    for entry in std::fs::read_dir(DATA_DIR).context("read_dir")? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+        let is_plugin = file_name
+            .rsplit_once('.')
+            .map_or(false, |(_, ext)| PLUGIN_EXTENSIONS.contains(&ext));
+        if !is_plugin {
+            continue;
+        }
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("metadata: {}", file_name))?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        plugins.push((file_name, metadata.len(), modified));
+    }
+    // read_dir does not guarantee any order
+    plugins.sort();
+    for (file_name, len, modified) in plugins {
+        // the NUL ends the name, which a file name cannot contain
+        hasher.write(file_name.as_bytes());
+        hasher.write(&[0]);
+        hasher.write(&len.to_le_bytes());
+        hasher.write(&modified.to_le_bytes());
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Hashes the contents of the plugin `name` in `Data`, to tell whether it changed when its size or
+/// modification time did.
+pub(crate) fn content_hash(name: &str) -> anyhow::Result<String> {
+    let mut file = std::fs::File::open(std::path::Path::new(DATA_DIR).join(name))?;
+    let mut hasher = Fnv1a::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            len => hasher.write(&buf[..len]),
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// The active plugins in the order the game loads them: the base game and its DLCs, the Creation
+/// Club plugins listed in `Skyrim.ccc`, and the plugins enabled in `plugins.txt`.
+pub(crate) fn load_order() -> anyhow::Result<Vec<String>> {
+    let mut plugins: Vec<String> = Vec::new();
+    let mut push = |plugin: &str| {
+        let plugin = plugin.trim();
+        let exists = std::path::Path::new(DATA_DIR).join(plugin).is_file();
+        if !plugin.is_empty() && exists && !plugins.iter().any(|p| p.eq_ignore_ascii_case(plugin)) {
+            plugins.push(plugin.to_string());
+        }
+    };
+
+    for plugin in IMPLICIT_PLUGINS {
+        push(plugin);
+    }
+    if let Ok(ccc) = std::fs::read_to_string(CCC_PATH) {
+        for plugin in ccc.lines() {
+            push(plugin);
+        }
+    }
+    let plugins_txt_path = path::local_app_data_dir()? + PLUGINS_TXT_PATH;
+    match std::fs::read(&plugins_txt_path) {
+        Ok(plugins_txt) => {
+            for line in String::from_utf8_lossy(&plugins_txt).lines() {
+                // enabled plugins are marked with '*', and '#' starts a comment
+                if let Some(plugin) = line.strip_prefix('*') {
+                    push(plugin);
+                }
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context("read plugins.txt"),
+    }
+
+    Ok(plugins)
+}
+
+/// The plugins of `load_order` with the flags of their headers and the metadata of their files.
+fn active() -> anyhow::Result<Vec<Plugin>> {
+    let load_order = load_order()?;
+    let prefixes = records::form_id_prefixes(&load_order);
+    let mut plugins = Vec::new();
+    for name in load_order {
+        let metadata = std::fs::metadata(std::path::Path::new(DATA_DIR).join(&name))
+            .with_context(|| format!("metadata: {}", name))?;
+        let lower = name.to_ascii_lowercase();
+        let (form_id_prefix, light) = prefixes[&lower];
+        let flags = records::read_header(&name).map_or(0, |(flags, _)| flags);
+        plugins.push(Plugin {
+            master: lower.ends_with(".esm") || flags & records::FLAG_MASTER != 0,
+            light,
+            form_id_prefix,
+            size: metadata.len(),
+            modified: modified_secs(&metadata),
+            name,
+        });
+    }
+    Ok(plugins)
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+pub(crate) fn active_plugins() -> &'static [Plugin] {
+    &ACTIVE
+}
+
+/// The active plugins as of the launch, with the size and modification time of their files now,
+/// for `ss rebuild` to tell which changed since. A plugin whose file is gone has neither.
+pub(crate) fn reread_plugins() -> Vec<Plugin> {
+    ACTIVE
+        .iter()
+        .map(|plugin| {
+            let metadata = std::fs::metadata(std::path::Path::new(DATA_DIR).join(&plugin.name));
+            Plugin {
+                name: plugin.name.clone(),
+                master: plugin.master,
+                light: plugin.light,
+                form_id_prefix: plugin.form_id_prefix,
+                size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+                modified: metadata.ok().and_then(|metadata| modified_secs(&metadata)),
+            }
+        })
+        .collect()
+}
+
+/// The id in the `plugin` table of the plugin named `name`.
+pub(crate) fn id_of(name: &str) -> Option<u32> {
+    ACTIVE
+        .iter()
+        .position(|plugin| plugin.name.eq_ignore_ascii_case(name))
+        .map(|id| id as u32)
+}
+
+/// The id in the `plugin` table of the plugin that defines `form_id`, i.e. its position in the
+/// load order. Forms created in game have none.
+pub(crate) fn plugin_id(form_id: u32) -> Option<u32> {
+    let prefix = if form_id >> 24 == 0xFE {
+        form_id & 0xFFFF_F000
+    } else {
+        form_id & 0xFF00_0000
+    };
+    ACTIVE
+        .iter()
+        .position(|plugin| plugin.form_id_prefix == prefix)
+        .map(|id| id as u32)
+}
+
+/// Whether `form_id` is indexed, as its plugin is not skipped by `skip_plugins` or
+/// `only_plugins`. Forms created in game always are.
+pub(crate) fn is_indexed(form_id: u32) -> bool {
+    if CONFIG.index.skip_plugins.is_empty() && CONFIG.index.only_plugins.is_empty() {
+        return true;
+    }
+    plugin_id(form_id)
+        .and_then(|id| ACTIVE.get(id as usize))
+        .map_or(true, |plugin| CONFIG.index.includes_plugin(&plugin.name))
+}
@@ -0,0 +1,1082 @@
+use crate::config::CONFIG;
+use crate::db;
+use crate::form::qust;
+use crate::log;
+use crate::log::Loggable;
+use crate::plugins;
+use crate::strings;
+use anyhow::{anyhow, Context};
+use byteorder::{LittleEndian, ReadBytesExt};
+use rusqlite::params;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const DATA_DIR: &str = "Data";
+/// Record types whose raw data is stored in `record_blob`, i.e. the types in the index.
+const RECORD_TYPES: [&[u8; 4]; 3] = [b"NPC_", b"CELL", b"QUST"];
+/// Record types that scripts are attached to with VMAD, whose scripts are stored in `script`.
+const SCRIPTED_TYPES: [&[u8; 4]; 28] = [
+    b"ACHR", b"ACTI", b"ALCH", b"AMMO", b"APPA", b"ARMO", b"BOOK", b"CONT", b"DOOR", b"FLOR",
+    b"FURN", b"INFO", b"INGR", b"KEYM", b"LIGH", b"MGEF", b"MISC", b"NPC_", b"PACK", b"PERK",
+    b"QUST", b"REFR", b"SCEN", b"SCRL", b"SLGM", b"SPEL", b"TACT", b"WEAP",
+];
+/// Record types whose texts are indexed by `form_fts`, and the kinds they are indexed as.
+const TEXT_TYPES: [(&[u8; 4], &str); 2] = [(b"BOOK", "book"), (b"INFO", "info")];
+/// Record types of the forms in the form tables.
+const INDEXED_TYPES: [&[u8; 4]; 4] = [b"NPC_", b"CELL", b"QUST", b"ACHR"];
+const HEADER_LEN: u64 = 24;
+pub(crate) const FLAG_MASTER: u32 = 0x1;
+/// Set in the TES4 header of plugins whose strings are in `Data\Strings`.
+const FLAG_LOCALIZED: u32 = 0x80;
+const FLAG_LIGHT: u32 = 0x200;
+const FLAG_COMPRESSED: u32 = 0x40000;
+/// Number of records inserted by one job.
+const BATCH_LEN: usize = 1000;
+/// Plugins read at once by `store_record_blobs`, at most. More would wait on the disk, or on the
+/// worker that writes the records.
+const MAX_READ_THREADS: usize = 4;
+
+/// Plugins read by `store_record_blobs`, `store_scripts` and `store_texts`.
+static RECORD_BLOB_PROGRESS: Progress = Progress::new();
+static SCRIPT_PROGRESS: Progress = Progress::new();
+static TEXT_PROGRESS: Progress = Progress::new();
+/// Set once `build` has been called.
+static BUILT: AtomicBool = AtomicBool::new(false);
+/// Set once `build_scripts` has been called, and once `script` holds the scripts of all plugins.
+static SCRIPTS_BUILT: AtomicBool = AtomicBool::new(false);
+static SCRIPTS_STORED: AtomicBool = AtomicBool::new(false);
+/// Set once `build_texts` has been called.
+static TEXTS_BUILT: AtomicBool = AtomicBool::new(false);
+
+/// How many plugins have been read, and how many there are to read.
+struct Progress {
+    read: AtomicUsize,
+    total: AtomicUsize,
+}
+
+impl Progress {
+    const fn new() -> Self {
+        Progress {
+            read: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    fn get(&self) -> Option<(usize, usize)> {
+        match self.total.load(Ordering::SeqCst) {
+            0 => None,
+            total => Some((self.read.load(Ordering::SeqCst), total)),
+        }
+    }
+}
+
+struct Record {
+    form_id: u32,
+    kind: String,
+    flags: u32,
+    data: Vec<u8>,
+}
+
+/// The names and the text of a book or a dialogue topic info, as `form_fts` indexes them.
+struct Text {
+    form_id: u32,
+    kind: &'static str,
+    editor_id: Option<String>,
+    name: Option<String>,
+    text: String,
+}
+
+/// A form of the form tables as the last plugin that has its record defines it, which `reindex`
+/// reads rather than the form the game loaded.
+struct IndexedForm {
+    form_id: u32,
+    /// The form table it is in, e.g. `npc` for `npc_form`, or `actor`.
+    table: &'static str,
+    editor_id: Option<String>,
+    name: Option<String>,
+    /// The base form of an ACHR.
+    base_form_id: Option<u32>,
+    /// The stages of a QUST, as `quest_form.stages` has them.
+    stages: Option<String>,
+}
+
+/// A script a plugin attaches with a VMAD subrecord, with the values of its properties.
+pub(crate) struct Script {
+    pub(crate) name: String,
+    pub(crate) properties: Vec<(String, String)>,
+}
+
+impl Record {
+    /// The type, size and data of each subrecord, in the order they appear.
+    fn subrecords(&self) -> Vec<([u8; 4], usize, &[u8])> {
+        let mut subrecords = Vec::new();
+        let mut pos = 0;
+        // XXXX holds the size of the next subrecord when it does not fit in 16 bits
+        let mut next_size = None;
+        while pos + 6 <= self.data.len() {
+            let kind = [
+                self.data[pos],
+                self.data[pos + 1],
+                self.data[pos + 2],
+                self.data[pos + 3],
+            ];
+            let size = u16::from_le_bytes([self.data[pos + 4], self.data[pos + 5]]) as usize;
+            let size = next_size.take().unwrap_or(size);
+            pos += 6;
+            if &kind == b"XXXX" && pos + 4 <= self.data.len() {
+                let data = &self.data[pos..pos + 4];
+                next_size = Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize);
+            } else {
+                let end = (pos + size).min(self.data.len());
+                subrecords.push((kind, size, &self.data[pos..end]));
+            }
+            pos += size;
+        }
+        subrecords
+    }
+
+    /// The subrecords as a JSON array of `{"type", "size"}` objects, in the order they appear.
+    fn subrecords_json(&self) -> String {
+        let subrecords = self
+            .subrecords()
+            .into_iter()
+            .map(|(kind, size, _)| {
+                serde_json::json!({ "type": String::from_utf8_lossy(&kind), "size": size })
+            })
+            .collect();
+        serde_json::Value::Array(subrecords).to_string()
+    }
+}
+
+/// Builds `record_blob` and the texts of `form_fts` as the game starts, unless `index.lazy`
+/// defers them to the first command that needs them.
+pub(crate) fn init() {
+    if !CONFIG.index.lazy {
+        build();
+        build_texts();
+    }
+}
+
+/// Starts storing the raw data of the records in the active plugins in `record_blob`, if
+/// `store_record_blobs` is configured and they are not stored yet. If only some plugins changed
+/// since the cached index was built, only the records of stale forms are stored again. Returns
+/// whether it started.
+pub(crate) fn build() -> bool {
+    if !CONFIG.store_record_blobs || BUILT.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    let complete = db::is_record_blob_complete();
+    if complete && db::is_cached() {
+        return false;
+    }
+    std::thread::spawn(move || {
+        store_record_blobs(complete)
+            .context("store_record_blobs")
+            .logging_ok();
+    });
+    true
+}
+
+/// How many of the plugins have been read into `record_blob`, if any are being read.
+pub(crate) fn progress() -> Option<(usize, usize)> {
+    RECORD_BLOB_PROGRESS.get()
+}
+
+/// How many of the plugins have been read into `script`, if any are being read.
+pub(crate) fn script_progress() -> Option<(usize, usize)> {
+    SCRIPT_PROGRESS.get()
+}
+
+/// Starts storing the scripts that the active plugins attach to forms in `script`, unless it
+/// was started already. Returns whether it started.
+pub(crate) fn build_scripts() -> bool {
+    if SCRIPTS_BUILT.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    std::thread::spawn(|| store_scripts().context("store_scripts").logging_ok());
+    true
+}
+
+/// Whether `script` holds the scripts of all the active plugins.
+pub(crate) fn are_scripts_stored() -> bool {
+    SCRIPTS_STORED.load(Ordering::SeqCst)
+}
+
+/// Starts indexing the texts of the books and dialogue of the active plugins in `form_fts`, if
+/// `index.texts` is configured and they are not indexed yet. Returns whether it started.
+pub(crate) fn build_texts() -> bool {
+    if !CONFIG.index.texts || TEXTS_BUILT.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    if db::are_texts_complete() && db::is_cached() {
+        return false;
+    }
+    std::thread::spawn(|| store_texts().context("store_texts").logging_ok());
+    true
+}
+
+/// How many of the plugins have been read into `form_fts`, if any are being read.
+pub(crate) fn text_progress() -> Option<(usize, usize)> {
+    TEXT_PROGRESS.get()
+}
+
+/// Calls `read` with each plugin of `load_order` on a few threads, which take the next plugin
+/// in turn, and each send what they read to the worker through their own clone of `task_queue`.
+fn read_plugins<F>(progress: &Progress, task_queue: &db::TaskQueue, load_order: &[String], read: F)
+where
+    F: Fn(&db::TaskQueue, &str) -> anyhow::Result<()> + Sync,
+{
+    progress.total.store(load_order.len(), Ordering::SeqCst);
+    let next = AtomicUsize::new(0);
+    let threads = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(MAX_READ_THREADS);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let (task_queue, next, read) = (task_queue.clone(), &next, &read);
+            scope.spawn(move || {
+                while let Some(plugin) = load_order.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    read(&task_queue, plugin)
+                        .with_context(|| plugin.clone())
+                        .logging_ok();
+                    progress.read.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    log::info(
+        format!(
+            "records: read {} plugins on {} threads",
+            load_order.len(),
+            threads
+        )
+        .as_str(),
+    );
+}
+
+fn store_record_blobs(only_stale: bool) -> anyhow::Result<()> {
+    let load_order = plugins::load_order()?;
+    let prefixes = form_id_prefixes(&load_order);
+    let task_queue = db::TASK_QUEUE.lock().unwrap().clone();
+    // the worker writes what the threads read
+    read_plugins(
+        &RECORD_BLOB_PROGRESS,
+        &task_queue,
+        &load_order,
+        |task_queue, plugin| read_plugin(task_queue, plugin, &prefixes, only_stale),
+    );
+    // after the records, in the order the worker runs the jobs
+    task_queue
+        .send(Box::new(|db| {
+            db::set_record_blob_complete(db).context("set_record_blob_complete")?;
+            Ok(())
+        }))
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+fn read_plugin(
+    task_queue: &db::TaskQueue,
+    plugin: &str,
+    prefixes: &HashMap<String, (u32, bool)>,
+    only_stale: bool,
+) -> anyhow::Result<()> {
+    if !CONFIG.index.includes_plugin(plugin) {
+        return Ok(());
+    }
+    let plugin_id = plugins::id_of(plugin);
+    let mut batch = Vec::with_capacity(BATCH_LEN);
+    // the plugins that did not change may still have stale forms, whose override was removed
+    let wanted = |kind: &[u8; 4], form_id| {
+        RECORD_TYPES.contains(&kind) && !(only_stale && db::is_form_cached(form_id))
+    };
+    let result = read_records(plugin, prefixes, wanted, |record| {
+        batch.push(record);
+        if batch.len() == BATCH_LEN {
+            send_batch(task_queue, plugin_id, std::mem::take(&mut batch))?;
+        }
+        Ok(())
+    });
+    result.with_context(|| plugin.to_string()).logging_ok();
+    send_batch(task_queue, plugin_id, batch)
+}
+
+/// Sends the records of the plugin `plugin_id` to the worker.
+fn send_batch(
+    task_queue: &db::TaskQueue,
+    plugin_id: Option<u32>,
+    batch: Vec<Record>,
+) -> anyhow::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    task_queue
+        .send_indexing(
+            "record_blob",
+            Box::new(move |db| {
+                // in the transaction of the worker. plugins later in the load order override the
+                // records of earlier ones, which may be read after them
+                let mut stmt = db
+                    .prepare_cached(
+                        "INSERT INTO record_blob \
+                         (form_id, plugin_id, type, flags, data, subrecords) \
+                         VALUES (?, ?, ?, ?, ?, ?) \
+                         ON CONFLICT(form_id) DO UPDATE SET plugin_id = excluded.plugin_id, \
+                         type = excluded.type, flags = excluded.flags, data = excluded.data, \
+                         subrecords = excluded.subrecords \
+                         WHERE coalesce(excluded.plugin_id, -1) >= coalesce(record_blob.plugin_id, -1)",
+                    )
+                    .context("record_blob prepare")?;
+                for record in batch {
+                    let subrecords = record.subrecords_json();
+                    stmt.execute(params![
+                        record.form_id,
+                        plugin_id,
+                        record.kind,
+                        record.flags,
+                        record.data,
+                        subrecords
+                    ])
+                    .context("record_blob execute")?;
+                }
+                Ok(())
+            }),
+        )
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+/// The runtime form ids of the records of `plugin` in the form tables, including the forms of its
+/// masters that it overrides. Skips the data of the records rather than reading it.
+pub(crate) fn indexed_form_ids(plugin: &str) -> anyhow::Result<Vec<u32>> {
+    let prefixes = form_id_prefixes(&plugins::load_order()?);
+    let form_ids = RefCell::new(Vec::new());
+    let wanted = |kind: &[u8; 4], form_id| {
+        if INDEXED_TYPES.contains(&kind) {
+            form_ids.borrow_mut().push(form_id);
+        }
+        false
+    };
+    read_records(plugin, &prefixes, wanted, |_| Ok(()))?;
+    Ok(form_ids.into_inner())
+}
+
+/// Maps the lower-cased name of each plugin to the prefix of its runtime form ids, e.g. 0x05000000
+/// for a regular plugin or 0xFE001000 for a light one.
+pub(crate) fn form_id_prefixes(load_order: &[String]) -> HashMap<String, (u32, bool)> {
+    let mut prefixes = HashMap::new();
+    let (mut regular, mut light) = (0u32, 0u32);
+    for plugin in load_order {
+        let is_light = plugin.to_ascii_lowercase().ends_with(".esl")
+            || read_header(plugin).map_or(false, |(flags, _)| flags & FLAG_LIGHT != 0);
+        let prefix = if is_light {
+            light += 1;
+            0xFE00_0000 | ((light - 1) << 12)
+        } else {
+            regular += 1;
+            (regular - 1) << 24
+        };
+        prefixes.insert(plugin.to_ascii_lowercase(), (prefix, is_light));
+    }
+    prefixes
+}
+
+fn open_plugin(plugin: &str) -> anyhow::Result<BufReader<std::fs::File>> {
+    Ok(BufReader::new(std::fs::File::open(
+        Path::new(DATA_DIR).join(plugin),
+    )?))
+}
+
+/// Reads the flags and the masters from the TES4 header of `plugin`.
+pub(crate) fn read_header(plugin: &str) -> anyhow::Result<(u32, Vec<String>)> {
+    let mut reader = open_plugin(plugin)?;
+    let (kind, data_len, flags, _) = read_record_header(&mut reader)?;
+    if &kind != b"TES4" {
+        anyhow::bail!("not a plugin");
+    }
+    let mut data = vec![0; data_len as usize];
+    reader.read_exact(&mut data)?;
+
+    let mut masters = Vec::new();
+    let mut cursor = std::io::Cursor::new(data.as_slice());
+    while (cursor.position() as usize) < data.len() {
+        let mut kind = [0; 4];
+        cursor.read_exact(&mut kind)?;
+        let len = cursor.read_u16::<LittleEndian>()? as usize;
+        let start = cursor.position() as usize;
+        let field = data
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("truncated header"))?;
+        if &kind == b"MAST" {
+            let name = &field[..field.iter().position(|&b| b == 0).unwrap_or(field.len())];
+            masters.push(String::from_utf8_lossy(name).to_string());
+        }
+        cursor.set_position((start + len) as u64);
+    }
+    Ok((flags, masters))
+}
+
+/// Returns the type, data length, flags and form id of the next record or group.
+fn read_record_header<R: Read>(reader: &mut R) -> std::io::Result<([u8; 4], u32, u32, u32)> {
+    let mut kind = [0; 4];
+    reader.read_exact(&mut kind)?;
+    let data_len = reader.read_u32::<LittleEndian>()?;
+    let flags = reader.read_u32::<LittleEndian>()?;
+    let form_id = reader.read_u32::<LittleEndian>()?;
+    let mut rest = [0; 8];
+    reader.read_exact(&mut rest)?;
+    Ok((kind, data_len, flags, form_id))
+}
+
+/// Calls `f` with every record of `plugin` that is `wanted` by its type and runtime form id.
+/// Groups are not descended into explicitly: their contents simply follow their headers.
+fn read_records<W, F>(
+    plugin: &str,
+    prefixes: &HashMap<String, (u32, bool)>,
+    wanted: W,
+    mut f: F,
+) -> anyhow::Result<()>
+where
+    W: Fn(&[u8; 4], u32) -> bool,
+    F: FnMut(Record) -> anyhow::Result<()>,
+{
+    let (_, masters) = read_header(plugin)?;
+    let mut reader = open_plugin(plugin)?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    while reader.stream_position()? + HEADER_LEN <= len {
+        let (kind, data_len, flags, form_id) = read_record_header(&mut reader)?;
+        if &kind == b"GRUP" {
+            continue;
+        }
+        let form_id = runtime_form_id(form_id, plugin, &masters, prefixes);
+        let form_id = match form_id {
+            Some(form_id) if wanted(&kind, form_id) => form_id,
+            _ => {
+                reader.seek_relative(data_len as i64)?;
+                continue;
+            }
+        };
+        let mut data = vec![0; data_len as usize];
+        reader.read_exact(&mut data)?;
+        if flags & FLAG_COMPRESSED != 0 {
+            data = decompress(&data)?;
+        }
+
+        f(Record {
+            form_id,
+            kind: String::from_utf8_lossy(&kind).to_string(),
+            flags,
+            data,
+        })?;
+    }
+    Ok(())
+}
+
+/// The runtime form id of `form_id` as it appears in `plugin`, whose masters are `masters`.
+fn runtime_form_id(
+    form_id: u32,
+    plugin: &str,
+    masters: &[String],
+    prefixes: &HashMap<String, (u32, bool)>,
+) -> Option<u32> {
+    // the high byte indexes the masters, and the plugin itself comes after them
+    let owner = masters
+        .get((form_id >> 24) as usize)
+        .map_or(plugin, String::as_str);
+    let (prefix, is_light) = *prefixes.get(&owner.to_ascii_lowercase())?;
+    let local_id = if is_light {
+        form_id & 0xFFF
+    } else {
+        form_id & 0xFF_FFFF
+    };
+    Some(prefix | local_id)
+}
+
+fn store_scripts() -> anyhow::Result<()> {
+    let load_order = plugins::load_order()?;
+    let prefixes = form_id_prefixes(&load_order);
+    let task_queue = db::TASK_QUEUE.lock().unwrap().clone();
+    read_plugins(
+        &SCRIPT_PROGRESS,
+        &task_queue,
+        &load_order,
+        |task_queue, plugin| read_plugin_scripts(task_queue, plugin, &prefixes),
+    );
+    // after the scripts, in the order the worker runs the jobs
+    task_queue
+        .send(Box::new(|_| {
+            SCRIPTS_STORED.store(true, Ordering::SeqCst);
+            Ok(())
+        }))
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+/// Sends the scripts that `plugin` attaches to forms to the worker, and its overrides of forms of
+/// other plugins without scripts, which remove theirs.
+fn read_plugin_scripts(
+    task_queue: &db::TaskQueue,
+    plugin: &str,
+    prefixes: &HashMap<String, (u32, bool)>,
+) -> anyhow::Result<()> {
+    let plugin_id = match plugins::id_of(plugin) {
+        Some(plugin_id) => plugin_id,
+        None => return Ok(()),
+    };
+    let (_, masters) = read_header(plugin)?;
+    let resolve = |id| runtime_form_id(id, plugin, &masters, prefixes);
+    let mut batch = Vec::with_capacity(BATCH_LEN);
+    let result = read_records(
+        plugin,
+        prefixes,
+        |kind, _| SCRIPTED_TYPES.contains(&kind),
+        |record| {
+            let vmad = record
+                .subrecords()
+                .into_iter()
+                .find(|(kind, ..)| kind == b"VMAD");
+            let scripts = match vmad {
+                Some((_, _, data)) => read_scripts(data, &resolve)
+                    .with_context(|| format!("{:08X}", record.form_id))
+                    .logging_ok()
+                    .unwrap_or_default(),
+                None if plugins::plugin_id(record.form_id) != Some(plugin_id) => vec![],
+                None => return Ok(()),
+            };
+            batch.push((record.form_id, scripts));
+            if batch.len() == BATCH_LEN {
+                send_scripts(task_queue, plugin_id, std::mem::take(&mut batch))?;
+            }
+            Ok(())
+        },
+    );
+    result.with_context(|| plugin.to_string()).logging_ok();
+    send_scripts(task_queue, plugin_id, batch)
+}
+
+/// Sends the scripts of the forms in `batch` that the plugin `plugin_id` attaches to the worker,
+/// a row for each property. A form without scripts has a row without a script.
+fn send_scripts(
+    task_queue: &db::TaskQueue,
+    plugin_id: u32,
+    batch: Vec<(u32, Vec<Script>)>,
+) -> anyhow::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    task_queue
+        .send_indexing(
+            "script",
+            Box::new(move |db| {
+                let mut stmt = db
+                    .prepare_cached(
+                        "INSERT INTO script (form_id, plugin_id, script, property, value) \
+                         VALUES (?, ?, ?, ?, ?)",
+                    )
+                    .context("script prepare")?;
+                for (form_id, scripts) in batch {
+                    if scripts.is_empty() {
+                        stmt.execute(params![
+                            form_id,
+                            plugin_id,
+                            None::<String>,
+                            None::<String>,
+                            None::<String>
+                        ])
+                        .context("script execute")?;
+                    }
+                    for script in scripts {
+                        let properties = if script.properties.is_empty() {
+                            vec![(None, None)]
+                        } else {
+                            script
+                                .properties
+                                .into_iter()
+                                .map(|(property, value)| (Some(property), Some(value)))
+                                .collect()
+                        };
+                        for (property, value) in properties {
+                            stmt.execute(params![
+                                form_id,
+                                plugin_id,
+                                &script.name,
+                                property,
+                                value
+                            ])
+                            .context("script execute")?;
+                        }
+                    }
+                }
+                Ok(())
+            }),
+        )
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+/// Reads the scripts of a VMAD subrecord, leaving out the script fragments of quests and the
+/// like that follow them.
+fn read_scripts(data: &[u8], resolve: &dyn Fn(u32) -> Option<u32>) -> anyhow::Result<Vec<Script>> {
+    let mut reader = std::io::Cursor::new(data);
+    let version = reader.read_i16::<LittleEndian>()?;
+    let object_format = reader.read_i16::<LittleEndian>()?;
+    let script_count = reader.read_u16::<LittleEndian>()?;
+    let mut scripts = Vec::new();
+    for _ in 0..script_count {
+        let name = read_wstring(&mut reader)?;
+        if version >= 4 {
+            reader.read_u8()?; // status
+        }
+        let property_count = reader.read_u16::<LittleEndian>()?;
+        let mut properties = Vec::new();
+        for _ in 0..property_count {
+            let name = read_wstring(&mut reader)?;
+            let kind = reader.read_u8()?;
+            if version >= 4 {
+                reader.read_u8()?; // status
+            }
+            let value = match kind {
+                // arrays of the types 1 to 5
+                11..=15 => {
+                    let len = reader.read_u32::<LittleEndian>()?;
+                    let values = (0..len)
+                        .map(|_| read_property(&mut reader, kind - 10, object_format, resolve))
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    format!("[{}]", values.join(", "))
+                }
+                _ => read_property(&mut reader, kind, object_format, resolve)?,
+            };
+            properties.push((name, value));
+        }
+        scripts.push(Script { name, properties });
+    }
+    Ok(scripts)
+}
+
+fn read_property<R: Read>(
+    reader: &mut R,
+    kind: u8,
+    object_format: i16,
+    resolve: &dyn Fn(u32) -> Option<u32>,
+) -> anyhow::Result<String> {
+    Ok(match kind {
+        0 => String::from("None"),
+        1 => {
+            let (form_id, alias) = if object_format == 1 {
+                let form_id = reader.read_u32::<LittleEndian>()?;
+                let alias = reader.read_i16::<LittleEndian>()?;
+                reader.read_u16::<LittleEndian>()?;
+                (form_id, alias)
+            } else {
+                reader.read_u16::<LittleEndian>()?;
+                let alias = reader.read_i16::<LittleEndian>()?;
+                (reader.read_u32::<LittleEndian>()?, alias)
+            };
+            let form = match form_id {
+                0 => String::from("None"),
+                _ => resolve(form_id).map_or_else(
+                    || format!("{:08X} (missing master)", form_id),
+                    |id| format!("{:08X}", id),
+                ),
+            };
+            match alias {
+                -1 => form,
+                alias => format!("{} alias {}", form, alias),
+            }
+        }
+        2 => read_wstring(reader)?,
+        3 => reader.read_i32::<LittleEndian>()?.to_string(),
+        4 => reader.read_f32::<LittleEndian>()?.to_string(),
+        5 => (reader.read_u8()? != 0).to_string(),
+        _ => anyhow::bail!("unknown property type {}", kind),
+    })
+}
+
+/// A string of VMAD, which is prefixed with its length.
+fn read_wstring<R: Read>(reader: &mut R) -> anyhow::Result<String> {
+    let len = reader.read_u16::<LittleEndian>()? as usize;
+    let mut s = vec![0; len];
+    reader.read_exact(&mut s)?;
+    Ok(String::from_utf8_lossy(&s).to_string())
+}
+
+/// Indexes the texts of all plugins again, as those of a cached index may be of other versions of
+/// the plugins. The plugins are read one after the other, so that the worker indexes the forms
+/// that later plugins override as they override them.
+fn store_texts() -> anyhow::Result<()> {
+    let load_order = plugins::load_order()?;
+    let prefixes = form_id_prefixes(&load_order);
+    let task_queue = db::TASK_QUEUE.lock().unwrap().clone();
+    task_queue
+        .send(Box::new(|db| {
+            db.execute_batch(
+                "DELETE FROM form_trigram WHERE form_id IN \
+                 (SELECT rowid FROM form_fts WHERE kind IN ('book', 'info')); \
+                 DELETE FROM form_fts WHERE kind IN ('book', 'info');",
+            )
+            .context("form_fts delete")?;
+            Ok(())
+        }))
+        .map_err(|e| anyhow!(e.to_string()))?;
+    TEXT_PROGRESS.read.store(0, Ordering::SeqCst);
+    TEXT_PROGRESS
+        .total
+        .store(load_order.len(), Ordering::SeqCst);
+    for plugin in &load_order {
+        read_plugin_texts(&task_queue, plugin, &prefixes)
+            .with_context(|| plugin.clone())
+            .logging_ok();
+        TEXT_PROGRESS.read.fetch_add(1, Ordering::SeqCst);
+    }
+    task_queue
+        .send(Box::new(|db| {
+            db::set_texts_complete(db).context("set_texts_complete")?;
+            Ok(())
+        }))
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+fn read_plugin_texts(
+    task_queue: &db::TaskQueue,
+    plugin: &str,
+    prefixes: &HashMap<String, (u32, bool)>,
+) -> anyhow::Result<()> {
+    if !CONFIG.index.includes_plugin(plugin) {
+        return Ok(());
+    }
+    let (flags, _) = read_header(plugin)?;
+    let tables = if flags & FLAG_LOCALIZED != 0 {
+        Some(strings::load_tables(plugin).context("load_tables")?)
+    } else {
+        None
+    };
+    let mut batch = Vec::with_capacity(BATCH_LEN);
+    let result = read_records(
+        plugin,
+        prefixes,
+        |kind, _| TEXT_TYPES.iter().any(|(text_type, _)| *text_type == kind),
+        |record| {
+            batch.push(read_text(&record, tables.as_ref()));
+            if batch.len() == BATCH_LEN {
+                send_texts(task_queue, std::mem::take(&mut batch))?;
+            }
+            Ok(())
+        },
+    );
+    result.with_context(|| plugin.to_string()).logging_ok();
+    send_texts(task_queue, batch)
+}
+
+/// The editor id, the name and the text of a BOOK, or the responses of an INFO. Localized
+/// plugins hold the ids of the strings in `tables` instead.
+fn read_text(record: &Record, tables: Option<&strings::StringTables>) -> Text {
+    let kind = TEXT_TYPES
+        .iter()
+        .find(|(text_type, _)| &text_type[..] == record.kind.as_bytes())
+        .map_or("", |(_, kind)| kind);
+    let mut text = Text {
+        form_id: record.form_id,
+        kind,
+        editor_id: None,
+        name: None,
+        text: String::new(),
+    };
+    let mut responses = Vec::new();
+    for (subrecord, _, data) in record.subrecords() {
+        match &subrecord {
+            b"EDID" => text.editor_id = Some(zstring(data)),
+            b"FULL" => text.name = lstring(data, tables.map(|tables| &tables.strings)),
+            b"DESC" => responses.extend(lstring(data, tables.map(|tables| &tables.dl_strings))),
+            b"NAM1" => responses.extend(lstring(data, tables.map(|tables| &tables.il_strings))),
+            _ => {}
+        }
+    }
+    text.text = responses.join("\n");
+    text
+}
+
+/// Sends the texts of `batch` to the worker, which replace those of the plugins before.
+fn send_texts(task_queue: &db::TaskQueue, batch: Vec<Text>) -> anyhow::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    task_queue
+        .send_indexing(
+            "form_fts",
+            Box::new(move |db| {
+                let mut delete = db
+                    .prepare_cached("DELETE FROM form_fts WHERE rowid = ?")
+                    .context("form_fts prepare")?;
+                let mut delete_trigrams = db
+                    .prepare_cached("DELETE FROM form_trigram WHERE form_id = ?")
+                    .context("form_trigram prepare")?;
+                let mut insert_trigrams = db
+                    .prepare_cached(&ss_db::insert_trigrams_sql("?1"))
+                    .context("form_trigram prepare")?;
+                let mut insert = db
+                    .prepare_cached(
+                        "INSERT INTO form_fts (rowid, kind, editor_id, name, name_localized, text) \
+                         VALUES (?, ?, ?, ?, ?, ?)",
+                    )
+                    .context("form_fts prepare")?;
+                for text in batch {
+                    let (name, name_localized) = strings::split_name(text.name);
+                    delete
+                        .execute(&[text.form_id])
+                        .context("form_fts execute")?;
+                    insert
+                        .execute(params![
+                            text.form_id,
+                            text.kind,
+                            text.editor_id,
+                            name,
+                            name_localized,
+                            text.text
+                        ])
+                        .context("form_fts execute")?;
+                    delete_trigrams
+                        .execute(&[text.form_id])
+                        .context("form_trigram execute")?;
+                    insert_trigrams
+                        .execute(&[text.form_id])
+                        .context("form_trigram execute")?;
+                }
+                Ok(())
+            }),
+        )
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+/// Indexes the stale forms of the form tables again from the plugin files, for `ss rebuild` once
+/// plugins changed while the game runs, with their records if `record_blob` is stored, and the
+/// texts of all plugins if they are indexed. The plugins are read one after the other, so that
+/// the worker replaces the forms of earlier plugins with the overrides of later ones, and a form
+/// whose override was removed is indexed as the plugins before define it. The log entries of the
+/// quest stages are those of the quests the game loaded, which the plugins only have the texts of.
+pub(crate) fn reindex<F>(task_queue: &db::TaskQueue, is_stale: F) -> anyhow::Result<()>
+where
+    F: Fn(u32) -> bool,
+{
+    let prefixes = plugins::active_plugins()
+        .iter()
+        .map(|plugin| {
+            (
+                plugin.name.to_ascii_lowercase(),
+                (plugin.form_id_prefix, plugin.light),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let mut quests = Vec::new();
+    for plugin in plugins::active_plugins() {
+        reindex_plugin(task_queue, &plugin.name, &prefixes, &is_stale, &mut quests)
+            .with_context(|| plugin.name.clone())
+            .logging_ok();
+    }
+    quests.sort_unstable();
+    quests.dedup();
+    for form_id in quests {
+        qust::index_stages(form_id);
+    }
+    // the texts are not told apart by plugin, so those of all plugins are indexed again
+    if CONFIG.index.texts {
+        TEXTS_BUILT.store(true, Ordering::SeqCst);
+        store_texts().context("store_texts")?;
+    }
+    Ok(())
+}
+
+/// Sends the stale forms that `plugin` has records of to the worker, and adds the quests among
+/// them to `quests`.
+fn reindex_plugin(
+    task_queue: &db::TaskQueue,
+    plugin: &str,
+    prefixes: &HashMap<String, (u32, bool)>,
+    is_stale: &dyn Fn(u32) -> bool,
+    quests: &mut Vec<u32>,
+) -> anyhow::Result<()> {
+    let (flags, masters) = read_header(plugin)?;
+    let tables = if flags & FLAG_LOCALIZED != 0 {
+        Some(strings::load_tables(plugin).context("load_tables")?)
+    } else {
+        None
+    };
+    let resolve = |id| runtime_form_id(id, plugin, &masters, prefixes);
+    let wanted = |kind: &[u8; 4], form_id| {
+        is_kind_indexed(kind) && is_stale(form_id) && plugins::is_indexed(form_id)
+    };
+    let plugin_id = plugins::id_of(plugin);
+    let (mut batch, mut records) = (Vec::with_capacity(BATCH_LEN), Vec::new());
+    let result = read_records(plugin, prefixes, wanted, |record| {
+        let form = read_form(&record, tables.as_ref(), &resolve);
+        if form.table == "quest" {
+            quests.push(form.form_id);
+        }
+        batch.push(form);
+        if batch.len() == BATCH_LEN {
+            send_forms(task_queue, std::mem::take(&mut batch))?;
+        }
+        if CONFIG.store_record_blobs
+            && RECORD_TYPES
+                .iter()
+                .any(|kind| &kind[..] == record.kind.as_bytes())
+        {
+            records.push(record);
+            if records.len() == BATCH_LEN {
+                send_batch(task_queue, plugin_id, std::mem::take(&mut records))?;
+            }
+        }
+        Ok(())
+    });
+    result.with_context(|| plugin.to_string()).logging_ok();
+    send_forms(task_queue, batch)?;
+    send_batch(task_queue, plugin_id, records)
+}
+
+/// Whether the forms of the record type `kind` are in the form tables, as `index` configures.
+fn is_kind_indexed(kind: &[u8; 4]) -> bool {
+    match kind {
+        b"NPC_" => CONFIG.index.npc,
+        b"CELL" => CONFIG.index.cell,
+        b"QUST" => CONFIG.index.quest,
+        b"ACHR" => true,
+        _ => false,
+    }
+}
+
+/// The editor id and the name of a record of the form tables, the base form of an ACHR, and the
+/// stages of a QUST.
+fn read_form(
+    record: &Record,
+    tables: Option<&strings::StringTables>,
+    resolve: &dyn Fn(u32) -> Option<u32>,
+) -> IndexedForm {
+    let table = match record.kind.as_str() {
+        "NPC_" => "npc",
+        "CELL" => "cell",
+        "QUST" => "quest",
+        _ => "actor",
+    };
+    let mut form = IndexedForm {
+        form_id: record.form_id,
+        table,
+        editor_id: None,
+        name: None,
+        base_form_id: None,
+        stages: None,
+    };
+    // the stage and its flags, and the number of its log entries
+    let mut stages: Vec<(u16, u16, usize)> = Vec::new();
+    for (subrecord, _, data) in record.subrecords() {
+        match &subrecord {
+            b"EDID" => form.editor_id = Some(zstring(data)),
+            b"FULL" => form.name = lstring(data, tables.map(|tables| &tables.strings)),
+            b"NAME" if table == "actor" => {
+                form.base_form_id = data
+                    .get(..4)
+                    .and_then(|id| resolve(u32::from_le_bytes([id[0], id[1], id[2], id[3]])))
+            }
+            b"INDX" if data.len() >= 3 => {
+                stages.push((u16::from_le_bytes([data[0], data[1]]), data[2].into(), 0))
+            }
+            // each log entry of the stage before starts with one
+            b"QSDT" => {
+                if let Some((_, _, logs)) = stages.last_mut() {
+                    *logs += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if table == "quest" {
+        let stages = stages
+            .into_iter()
+            .map(|(stage, flags, logs)| {
+                serde_json::json!({ "stage": stage, "flags": flags, "logs": logs })
+            })
+            .collect::<Vec<_>>();
+        form.stages = Some(serde_json::Value::Array(stages).to_string());
+    }
+    form
+}
+
+/// Sends the forms of `batch` to the worker, which replace those of the plugins before.
+fn send_forms(task_queue: &db::TaskQueue, batch: Vec<IndexedForm>) -> anyhow::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    task_queue
+        .send_indexing(
+            "reindex",
+            Box::new(move |db| {
+                for form in batch {
+                    let plugin_id = plugins::plugin_id(form.form_id);
+                    if form.table == "actor" {
+                        if let Some(base_form_id) = form.base_form_id {
+                            db.prepare_cached(
+                                "INSERT OR REPLACE INTO actor (form_id, base_form_id) \
+                                 VALUES (?, ?)",
+                            )
+                            .context("actor prepare")?
+                            .execute(params![form.form_id, base_form_id])
+                            .context("actor execute")?;
+                        }
+                        continue;
+                    }
+                    let (name, name_localized) = strings::split_name(form.name);
+                    let name_id = db::intern(db, name.as_deref())?;
+                    let name_localized_id = db::intern(db, name_localized.as_deref())?;
+                    db.prepare_cached(&format!(
+                        "INSERT OR REPLACE INTO {}_form \
+                         (form_id, plugin_id, editor_id, name_id, name_localized_id) \
+                         VALUES (?, ?, ?, ?, ?)",
+                        form.table
+                    ))
+                    .context("form prepare")?
+                    .execute(params![
+                        form.form_id,
+                        plugin_id,
+                        form.editor_id,
+                        name_id,
+                        name_localized_id
+                    ])
+                    .context("form execute")?;
+                    if let Some(stages) = form.stages {
+                        db.prepare_cached("UPDATE quest_form SET stages = ? WHERE form_id = ?")
+                            .context("quest prepare")?
+                            .execute(params![stages, form.form_id])
+                            .context("quest execute")?;
+                    }
+                }
+                Ok(())
+            }),
+        )
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
+/// A string subrecord, which localized plugins replace with the id of the string in `table`.
+fn lstring(data: &[u8], table: Option<&HashMap<u32, String>>) -> Option<String> {
+    match table {
+        Some(table) => {
+            let id = data.get(..4)?;
+            table
+                .get(&u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+                .cloned()
+        }
+        None => Some(zstring(data)),
+    }
+}
+
+/// A NUL-terminated string subrecord.
+fn zstring(data: &[u8]) -> String {
+    let s = &data[..data.iter().position(|&b| b == 0).unwrap_or(data.len())];
+    String::from_utf8_lossy(s).to_string()
+}
+
+/// Compressed record data is the decompressed length followed by a zlib stream.
+fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let decompressed_len = (&data[..]).read_u32::<LittleEndian>()?;
+    let mut decompressed = Vec::with_capacity(decompressed_len as usize);
+    flate2::read::ZlibDecoder::new(&data[4..]).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
@@ -0,0 +1,90 @@
+use crate::config::CONFIG;
+use crate::log::Loggable;
+use crate::{app, console, messages, task};
+use anyhow::Context;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+const COMMANDS_PATH: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE\\commands.txt";
+const OUTPUT_PATH: &str = "Data\\SKSE\\Plugins\\SkyrimSearchSE\\commands.out.txt";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Runs the lines appended to `commands.txt` if `command_file` is set, and appends each line with
+/// what it printed to `commands.out.txt`.
+pub(crate) fn init() {
+    if !CONFIG.command_file {
+        return;
+    }
+    std::thread::spawn(|| {
+        // the lines already in the file were meant for an earlier session
+        let mut offset = std::fs::metadata(COMMANDS_PATH).map_or(0, |metadata| metadata.len());
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if !console::is_enabled() {
+                continue;
+            }
+            let lines = read_new_lines(&mut offset)
+                .context("read_new_lines")
+                .logging_ok()
+                .unwrap_or_default();
+            for line in lines {
+                // the commands read the game, so they run on the main thread as console input does
+                task::add_task(move || run(line));
+            }
+        }
+    });
+}
+
+/// The complete lines after `offset`, which is moved past them. A line is read once it ends
+/// with a newline, and the file is read from the start if it was truncated.
+fn read_new_lines(offset: &mut u64) -> anyhow::Result<Vec<String>> {
+    let mut file = match File::open(COMMANDS_PATH) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            *offset = 0;
+            return Ok(vec![]);
+        }
+        Err(err) => return Err(err).context("open commands.txt"),
+    };
+    let len = file.metadata().context("metadata")?.len();
+    if len < *offset {
+        *offset = 0;
+    }
+    if len == *offset {
+        return Ok(vec![]);
+    }
+    file.seek(SeekFrom::Start(*offset)).context("seek")?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).context("read")?;
+    let end = match data.iter().rposition(|&b| b == b'\n') {
+        Some(end) => end + 1,
+        None => return Ok(vec![]),
+    };
+    *offset += end as u64;
+    Ok(String::from_utf8_lossy(&data[..end])
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn run(line: String) {
+    let output = console::capture(|| match app::process_console_input(&line, None) {
+        Ok(app::ProcessResult::Processed) => {}
+        Ok(app::ProcessResult::FallbackAndPrint(msg)) => console::print(msg),
+        Ok(_) => console::print(messages::get(
+            "$SkyrimSearchSE_NotACommand",
+            "not a Skyrim Search SE command",
+        )),
+        Err(err) => console::print(format!("{:#}", err)),
+    });
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(OUTPUT_PATH)
+        .and_then(|mut file| write!(file, "> {}\n{}\n", line, output))
+        .context("write commands.out.txt")
+        .logging_ok();
+}
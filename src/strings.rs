@@ -0,0 +1,338 @@
+use crate::bsa;
+use crate::config::CONFIG;
+use crate::log;
+use crate::log::Loggable;
+use crate::path;
+use crate::plugins;
+use anyhow::Context;
+use byteorder::{LittleEndian, ReadBytesExt};
+use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Cursor;
+use std::path::Path;
+
+const DATA_DIR: &str = "Data";
+const STRINGS_DIR: &str = "Data\\Strings";
+/// The folder of the string tables in the archives.
+const STRINGS_FOLDER: &str = "strings";
+const DEFAULT_LANGUAGE: &str = "english";
+
+lazy_static! {
+    static ref TRANSLATIONS: Translations = Translations::load();
+    static ref ARCHIVES: Vec<bsa::Archive> = load_archives();
+}
+
+/// Maps names displayed by the game back to their English originals, and to the names of
+/// `language` if that is another one, built from the `<plugin>_<language>.strings` tables.
+struct Translations {
+    /// What the engine returns names in.
+    game_language: String,
+    /// What `name_localized` holds.
+    language: String,
+    to_english: HashMap<String, String>,
+    to_language: HashMap<String, String>,
+}
+
+impl Translations {
+    fn load() -> Self {
+        let game_language = game_language()
+            .context("game_language")
+            .logging_ok()
+            .flatten()
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+        let language = CONFIG.language.as_ref().map_or_else(
+            || game_language.clone(),
+            |language| language.to_ascii_lowercase(),
+        );
+        let mut to_english = HashMap::new();
+        if game_language != DEFAULT_LANGUAGE {
+            load_translations(&game_language, DEFAULT_LANGUAGE, &mut to_english)
+                .context("load_translations")
+                .logging_ok();
+        }
+        let mut to_language = HashMap::new();
+        if language != DEFAULT_LANGUAGE && language != game_language {
+            load_translations(&game_language, &language, &mut to_language)
+                .context("load_translations")
+                .logging_ok();
+        }
+        log::info(
+            format!(
+                "strings: {} ({} entries), names in {} ({} entries)",
+                game_language,
+                to_english.len(),
+                language,
+                to_language.len()
+            )
+            .as_str(),
+        );
+
+        Translations {
+            game_language,
+            language,
+            to_english,
+            to_language,
+        }
+    }
+}
+
+/// Reads `sLanguage` from `Skyrim.ini`. The language is returned in lower case.
+pub(crate) fn game_language() -> anyhow::Result<Option<String>> {
+    let ini = std::fs::read(path::my_games_dir()? + "\\Skyrim.ini")?;
+    let ini = String::from_utf8_lossy(&ini);
+    let mut in_general = false;
+    for line in ini.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_general = line.eq_ignore_ascii_case("[General]");
+        } else if in_general {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim().eq_ignore_ascii_case("sLanguage") {
+                    return Ok(Some(value.trim().to_ascii_lowercase()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The archives in `Data` with string tables, in the order they override each other: those of
+/// the plugins, `<plugin>.bsa` and `<plugin> - <suffix>.bsa`, in load order after the others.
+fn load_archives() -> Vec<bsa::Archive> {
+    let stems = plugins::load_order()
+        .context("load_order")
+        .logging_ok()
+        .unwrap_or_default()
+        .iter()
+        .map(|plugin| {
+            let stem = plugin
+                .rsplit_once('.')
+                .map_or(plugin.as_str(), |(stem, _)| stem);
+            stem.to_ascii_lowercase()
+        })
+        .collect::<Vec<_>>();
+    let entries = match std::fs::read_dir(DATA_DIR).context("read_dir").logging_ok() {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+    let mut archives = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name.to_ascii_lowercase(),
+            None => continue,
+        };
+        let stem = match file_name.strip_suffix(".bsa") {
+            Some(stem) => stem,
+            None => continue,
+        };
+        let position = stems.iter().position(|plugin| {
+            stem == plugin
+                || stem
+                    .strip_prefix(plugin.as_str())
+                    .map_or(false, |suffix| suffix.starts_with(" - "))
+        });
+        let archive = bsa::Archive::open(&path, STRINGS_FOLDER)
+            .with_context(|| format!("{}", path.display()))
+            .logging_ok();
+        if let Some(archive) = archive.filter(|archive| archive.names().next().is_some()) {
+            archives.push((position, archive));
+        }
+    }
+    archives.sort_by_key(|(position, _)| *position);
+    archives.into_iter().map(|(_, archive)| archive).collect()
+}
+
+/// The lower-cased names of the string tables in `Data\Strings` and in the archives.
+fn table_names() -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    if let Ok(entries) = std::fs::read_dir(STRINGS_DIR) {
+        for entry in entries.flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.insert(name.to_ascii_lowercase());
+            }
+        }
+    }
+    for archive in ARCHIVES.iter() {
+        names.extend(archive.names().map(String::from));
+    }
+    names
+}
+
+/// Reads the string table `name`, e.g. `skyrim_english.strings`, from `Data\Strings`, or else
+/// from the last archive that has it, as the game does.
+fn read_table(name: &str) -> anyhow::Result<Vec<u8>> {
+    let path = Path::new(STRINGS_DIR).join(name);
+    if path.exists() {
+        return std::fs::read(&path).with_context(|| format!("{}", path.display()));
+    }
+    for archive in ARCHIVES.iter().rev() {
+        if let Some(data) = archive.read(name)? {
+            return Ok(data);
+        }
+    }
+    anyhow::bail!("{} is neither in {} nor in an archive", name, STRINGS_DIR)
+}
+
+/// Maps the strings of `from` to those of `to` with the same id, for each plugin that has both.
+fn load_translations(
+    from: &str,
+    to: &str,
+    translations: &mut HashMap<String, String>,
+) -> anyhow::Result<()> {
+    let suffix = format!("_{}.strings", from);
+    for name in table_names() {
+        let plugin = match name.strip_suffix(suffix.as_str()) {
+            Some(plugin) => plugin,
+            None => continue,
+        };
+        let to_name = format!("{}_{}.strings", plugin, to);
+        let result: anyhow::Result<()> = (|| {
+            let from_strings = read_strings(&read_table(&name)?, false)?;
+            let to_strings = read_strings(&read_table(&to_name)?, false)?;
+            for (id, text) in from_strings {
+                if let Some(to_text) = to_strings.get(&id) {
+                    translations.entry(text).or_insert_with(|| to_text.clone());
+                }
+            }
+            Ok(())
+        })();
+        result.with_context(|| name.clone()).logging_ok();
+    }
+    Ok(())
+}
+
+/// The string tables of a localized plugin in the language of the game, which its records hold
+/// the ids of strings of rather than the strings: `strings` for names, `dl_strings` for
+/// descriptions and the texts of books, and `il_strings` for dialogue.
+pub(crate) struct StringTables {
+    pub(crate) strings: HashMap<u32, String>,
+    pub(crate) dl_strings: HashMap<u32, String>,
+    pub(crate) il_strings: HashMap<u32, String>,
+}
+
+/// Reads the string tables of `plugin`, `<plugin>_<language>.<ext>`.
+pub(crate) fn load_tables(plugin: &str) -> anyhow::Result<StringTables> {
+    let stem = plugin.rsplit_once('.').map_or(plugin, |(stem, _)| stem);
+    let read = |extension: &str| -> anyhow::Result<HashMap<u32, String>> {
+        let name = format!("{}_{}.{}", stem, TRANSLATIONS.game_language, extension);
+        read_strings(&read_table(&name)?, extension != "strings").with_context(|| name.clone())
+    };
+    Ok(StringTables {
+        strings: read("strings")?,
+        dl_strings: read("dlstrings")?,
+        il_strings: read("ilstrings")?,
+    })
+}
+
+/// Parses a string table: a directory of (id, offset) pairs followed by the strings, which are
+/// NUL-terminated, and in `.dlstrings` and `.ilstrings`, `length_prefixed` by their length, too.
+fn read_strings(data: &[u8], length_prefixed: bool) -> anyhow::Result<HashMap<u32, String>> {
+    let mut cursor = Cursor::new(data);
+    let count = cursor.read_u32::<LittleEndian>()?;
+    let _data_size = cursor.read_u32::<LittleEndian>()?;
+    let base = 8 + count as usize * 8;
+    let mut strings = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+        let id = cursor.read_u32::<LittleEndian>()?;
+        let offset = base + cursor.read_u32::<LittleEndian>()? as usize;
+        let offset = if length_prefixed { offset + 4 } else { offset };
+        let s = data
+            .get(offset..)
+            .ok_or_else(|| anyhow::anyhow!("invalid offset {:#x}", offset))?;
+        let s = &s[..s.iter().position(|&b| b == 0).unwrap_or(s.len())];
+        strings.insert(id, String::from_utf8_lossy(s).to_string());
+    }
+    Ok(strings)
+}
+
+/// Whether names have a `name_localized` besides the English `name`, as they do unless `language`
+/// (or the game's language, if it is not configured) is English.
+pub(crate) fn is_localized() -> bool {
+    TRANSLATIONS.language != DEFAULT_LANGUAGE
+}
+
+/// The language of `name_localized`, e.g. `german`, which an index has to be rebuilt for.
+pub(crate) fn language() -> &'static str {
+    &TRANSLATIONS.language
+}
+
+/// Splits a name as the engine returned it into its English original and its name in `language`.
+/// The localized name is `None` unless `is_localized`, and is the name as it is if it has no
+/// translation.
+pub(crate) fn split_name(name: Option<String>) -> (Option<String>, Option<String>) {
+    let name = match name {
+        Some(name) => name,
+        None => return (None, None),
+    };
+    let translate = |translations: &HashMap<String, String>| {
+        translations
+            .get(&name)
+            .cloned()
+            .unwrap_or_else(|| name.clone())
+    };
+    let english = if TRANSLATIONS.game_language == DEFAULT_LANGUAGE {
+        name.clone()
+    } else {
+        translate(&TRANSLATIONS.to_english)
+    };
+    let localized = if !is_localized() {
+        None
+    } else if TRANSLATIONS.language == TRANSLATIONS.game_language {
+        Some(name.clone())
+    } else {
+        Some(translate(&TRANSLATIONS.to_language))
+    };
+    (Some(english), localized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A string table of `strings`, whose entries are prefixed with their length if
+    /// `length_prefixed`.
+    fn table(strings: &[(u32, &str)], length_prefixed: bool) -> Vec<u8> {
+        let mut directory = Vec::new();
+        let mut data = Vec::new();
+        for (id, s) in strings {
+            directory.extend_from_slice(&id.to_le_bytes());
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            if length_prefixed {
+                data.extend_from_slice(&(s.len() as u32 + 1).to_le_bytes());
+            }
+            data.extend_from_slice(s.as_bytes());
+            data.push(0);
+        }
+        let mut table = Vec::new();
+        table.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        table.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        table.extend(directory);
+        table.extend(data);
+        table
+    }
+
+    #[test]
+    fn strings_are_read_by_id() {
+        let strings = read_strings(&table(&[(1, "Lydia"), (0x2A, "Whiterun")], false), false);
+        let strings = strings.unwrap();
+        assert_eq!(strings.len(), 2);
+        assert_eq!(strings[&1], "Lydia");
+        assert_eq!(strings[&0x2A], "Whiterun");
+    }
+
+    #[test]
+    fn dlstrings_are_read_past_their_length() {
+        let data = table(&[(7, "The Lusty Argonian Maid"), (8, "")], true);
+        let strings = read_strings(&data, true).unwrap();
+        assert_eq!(strings[&7], "The Lusty Argonian Maid");
+        assert_eq!(strings[&8], "");
+    }
+
+    #[test]
+    fn invalid_offset_is_an_error() {
+        let mut data = table(&[(1, "Lydia")], false);
+        data[12..16].copy_from_slice(&100u32.to_le_bytes());
+        assert!(read_strings(&data, false).is_err());
+    }
+}
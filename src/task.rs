@@ -0,0 +1,69 @@
+use crate::log;
+use crate::SKSEInterface;
+use late_static::LateStatic;
+use std::sync::atomic::{AtomicU32, Ordering};
+use winapi::ctypes::c_void;
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+
+const INTERFACE_TASK: u32 = 4;
+
+#[repr(C)]
+struct SKSETaskInterface {
+    interface_version: u32,
+    add_task: extern "C" fn(*mut TaskDelegate),
+    add_ui_task: extern "C" fn(*mut c_void),
+}
+
+/// `TaskDelegate`, whose vtable has `Run` and `Dispose`. SKSE calls `Dispose` after `Run`.
+#[repr(C)]
+struct TaskDelegate {
+    vtable: *const [extern "C" fn(*mut TaskDelegate); 2],
+    f: Option<Box<dyn FnOnce() + Send>>,
+}
+
+static TASK_DELEGATE_VTABLE: [extern "C" fn(*mut TaskDelegate); 2] = [run, dispose];
+
+extern "C" fn run(task: *mut TaskDelegate) {
+    if let Some(f) = unsafe { (*task).f.take() } {
+        log::catch_panic("task", f);
+    }
+}
+
+extern "C" fn dispose(task: *mut TaskDelegate) {
+    drop(unsafe { Box::from_raw(task) });
+}
+
+struct State {
+    task: *const SKSETaskInterface,
+}
+unsafe impl Sync for State {}
+static S: LateStatic<State> = LateStatic::new();
+
+/// The thread SKSE loads the plugin on, which runs the frames and the console.
+static MAIN_THREAD_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Whether this is the main thread of the game, the only one that may read forms and references
+/// as the game changes them.
+pub(crate) fn is_main_thread() -> bool {
+    MAIN_THREAD_ID.load(Ordering::SeqCst) == unsafe { GetCurrentThreadId() }
+}
+
+/// Runs `f` on the main thread of the game between frames, e.g. to print to the console from
+/// another thread.
+pub(crate) fn add_task<F: FnOnce() + Send + 'static>(f: F) {
+    let task = Box::new(TaskDelegate {
+        vtable: &TASK_DELEGATE_VTABLE,
+        f: Some(Box::new(f)),
+    });
+    unsafe { ((*S.task).add_task)(Box::into_raw(task)) };
+}
+
+pub(crate) fn init(skse: &SKSEInterface) -> anyhow::Result<()> {
+    let task = (skse.query_interface)(INTERFACE_TASK) as *const SKSETaskInterface;
+    if task.is_null() {
+        anyhow::bail!("no task interface");
+    }
+    unsafe { LateStatic::assign(&S, State { task }) };
+    MAIN_THREAD_ID.store(unsafe { GetCurrentThreadId() }, Ordering::SeqCst);
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! Runs the queries of `ss` on a database that `ss export` wrote or `persist` kept, without the
+//! game.
+
+use anyhow::Context;
+use clap::{App, AppSettings, Arg, ArgMatches};
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, ToSql, NO_PARAMS};
+use ss_db::function;
+use ss_db::table::{convert_row, rows_table};
+
+/// The subcommands of `ss` that run on the database alone. The others need the game.
+const SUBCOMMANDS: [&str; 8] = [
+    "raw", "run", "runfile", "explain", "grep", "npc", "cell", "quest",
+];
+
+/// The subcommands of `ss` that `ss-cli` does not run, for `--help`.
+fn unsupported_help() -> String {
+    let unsupported = ss_db::cli::subcommands()
+        .iter()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .filter(|name| !SUBCOMMANDS.contains(&name.as_str()))
+        .collect::<Vec<_>>();
+    format!(
+        "These subcommands of ss need the game, and ss-cli does not run them: {}. \
+         --exec does not run either, and runfile takes the path of the file.",
+        unsupported.join(", ")
+    )
+}
+
+fn get_clap<'a, 'b>(after_help: &'b str) -> App<'a, 'b> {
+    App::new("ss-cli")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Runs the queries of Skyrim Search SE on a database outside the game")
+        .after_help(after_help)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("database")
+                .required(true)
+                .help("e.g. skyrim-search-se.db"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .takes_value(true)
+                .help("attaches this database as user, e.g. skyrim-search-se-user.db"),
+        )
+        .subcommands(
+            ss_db::cli::subcommands()
+                .into_iter()
+                .filter(|subcommand| SUBCOMMANDS.contains(&subcommand.get_name())),
+        )
+}
+
+/// Opens the database, read-only unless `write` is given, as are the attached ones. The temp
+/// schema stays writable for scratch tables.
+fn open(matches: &ArgMatches, write: bool) -> anyhow::Result<Connection> {
+    let path = matches.value_of("database").unwrap();
+    let flags = if write {
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+    } else {
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+    };
+    let conn = Connection::open_with_flags(path, flags)
+        .with_context(|| format!("open error: {}", path))?;
+    function::register(&conn).context("function::register")?;
+    if let Some(user) = matches.value_of("user") {
+        conn.execute("ATTACH DATABASE ? AS user", &[user])
+            .with_context(|| format!("attach error: {}", user))?;
+    }
+    Ok(conn)
+}
+
+fn print_rows(conn: &Connection, sql: &str, params: &[&dyn ToSql]) -> anyhow::Result<()> {
+    let mut stmt = conn.prepare(sql).context("prepare error")?;
+    if stmt.column_count() == 0 {
+        let changes = stmt.execute(params).context("execute error")?;
+        println!("{} rows changed", changes);
+        return Ok(());
+    }
+    let rows = stmt.query(params).context("query error")?;
+    rows_table(rows, convert_row)?.printstd();
+    Ok(())
+}
+
+/// The values of `--param`, bound to the placeholders of the query in turn.
+fn param_values(matches: &ArgMatches) -> Vec<Value> {
+    matches
+        .values_of("param")
+        .map(|params| params.map(|param| Value::from(param.to_string())).collect())
+        .unwrap_or_default()
+}
+
+/// Whether the database was built from localized strings, as `ss grep` then shows both names.
+fn is_localized(conn: &Connection) -> bool {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM npc WHERE name_localized IS NOT NULL)",
+        NO_PARAMS,
+        |row| row.get(0),
+    )
+    .unwrap_or(false)
+}
+
+/// Runs `ss npc`, `ss cell` and `ss quest`, by name and editor id, or with `--fuzzy` by edit
+/// distance.
+fn search(conn: &Connection, kind: &str, matches: &ArgMatches) -> anyhow::Result<()> {
+    let query = matches.values_of("query").unwrap().collect::<Vec<_>>();
+    let query = query.join(" ");
+    if matches.is_present("fuzzy") {
+        let sql = format!(
+            "SELECT form_id, editor_id, name, \
+             fuzzydist(?1, name, name_localized, editor_id) AS distance \
+             FROM {} WHERE distance <= ?2 ORDER BY distance",
+            kind
+        );
+        return print_rows(conn, &sql, &[&query, &function::fuzzy_threshold(&query)]);
+    }
+    let (query, substring) = (ss_db::fts_query(&query), ss_db::substring_query(&query));
+    if kind == "grep" {
+        let sql = ss_db::grep_sql(is_localized(conn), false);
+        print_rows(conn, &sql, &[&query, &substring])
+    } else {
+        let sql = ss_db::grep_sql(is_localized(conn), true);
+        print_rows(conn, &sql, &[&query, &substring, &kind])
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let after_help = unsupported_help();
+    let matches = get_clap(&after_help).get_matches();
+    let (name, subcommand) = matches.subcommand();
+    let subcommand = subcommand.unwrap();
+    if subcommand.is_present("exec") {
+        anyhow::bail!("--exec runs console commands, which needs the game");
+    }
+    let conn = open(&matches, subcommand.is_present("write"))?;
+
+    match name {
+        "raw" | "explain" => {
+            let sql = subcommand.values_of("sql").unwrap().collect::<Vec<_>>();
+            let sql = sql.join(" ");
+            let sql = if name == "explain" {
+                format!("EXPLAIN QUERY PLAN {}", sql)
+            } else {
+                sql
+            };
+            let params = param_values(subcommand);
+            let params = params.iter().map(|p| p as &dyn ToSql).collect::<Vec<_>>();
+            print_rows(&conn, &sql, &params)
+        }
+        "run" => {
+            let query_name = match subcommand.value_of("name") {
+                Some(query_name) => query_name,
+                None => {
+                    return print_rows(
+                        &conn,
+                        "SELECT name, sql FROM user.saved_query ORDER BY name",
+                        &[],
+                    )
+                }
+            };
+            let sql: String = conn
+                .query_row(
+                    "SELECT sql FROM user.saved_query WHERE name = ?",
+                    &[query_name],
+                    |row| row.get(0),
+                )
+                .with_context(|| format!("no saved query named {}", query_name))?;
+            let params = param_values(subcommand);
+            let params = params.iter().map(|p| p as &dyn ToSql).collect::<Vec<_>>();
+            print_rows(&conn, &sql, &params)
+        }
+        "runfile" => {
+            let path = subcommand.value_of("file").unwrap();
+            let path = if !std::path::Path::new(path).exists() && !path.ends_with(".sql") {
+                format!("{}.sql", path)
+            } else {
+                path.to_string()
+            };
+            let script =
+                std::fs::read_to_string(&path).with_context(|| format!("read error: {}", path))?;
+            let statements = ss_db::split_statements(&script);
+            for (i, statement) in statements.iter().enumerate() {
+                println!("[{}/{}] {}", i + 1, statements.len(), statement);
+                print_rows(&conn, statement, &[])
+                    .with_context(|| format!("statement {} of {}", i + 1, path))?;
+            }
+            Ok(())
+        }
+        kind => search(&conn, kind, subcommand),
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,445 @@
+//! The subcommands of `ss`, which the plugin parses the console commands with, and `ss-cli` the
+//! ones it runs outside the game with.
+
+use clap::{App, AppSettings, Arg, SubCommand};
+
+/// The subcommands of `ss`, in the order `ss --help` lists them.
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("raw")
+            .alias("query")
+            .about("execute raw query. quote your query as in unix shell if needed.")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(
+                Arg::with_name("write")
+                    .long("write")
+                    .help("allow statements that modify the database"),
+            )
+            .arg(
+                Arg::with_name("param")
+                    .long("param")
+                    .short("p")
+                    .help("value bound to the next ? placeholder (repeatable)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("sql")
+                    .help("SQLite SQL")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("save")
+            .about("save a query to run it later with `ss run <name>`")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(
+                Arg::with_name("name")
+                    .help("name of the query")
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("sql")
+                    .help("SQLite SQL")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("run")
+            .about("run a saved query, or list them if no name is given")
+            .arg(
+                Arg::with_name("write")
+                    .long("write")
+                    .help("allow statements that modify the database"),
+            )
+            .arg(
+                Arg::with_name("param")
+                    .long("param")
+                    .short("p")
+                    .help("value bound to the next ? placeholder (repeatable)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(Arg::with_name("name").help("name of the query")),
+        SubCommand::with_name("runfile")
+            .about("run the statements of a .sql file in Data\\SKSE\\Plugins\\SkyrimSearchSE\\scripts")
+            .arg(
+                Arg::with_name("write")
+                    .long("write")
+                    .help("allow statements that modify the database"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .help("file name of the script (.sql may be omitted)")
+                    .required(true),
+            ),
+        SubCommand::with_name("attach")
+            .about("attach a database in Data\\SKSE\\Plugins\\SkyrimSearchSE\\databases")
+            .arg(
+                Arg::with_name("file")
+                    .help("file name of the database (.db may be omitted)")
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("alias")
+                    .help("schema name to refer to the database by (e.g. prices)")
+                    .required(true),
+            ),
+        SubCommand::with_name("detach")
+            .about("detach a database attached by `ss attach`")
+            .arg(
+                Arg::with_name("alias")
+                    .help("schema name of the database")
+                    .required(true),
+            ),
+        SubCommand::with_name("temp")
+            .about("list or drop the temp tables and views of this session")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list").about("list temp tables and views"))
+            .subcommand(
+                SubCommand::with_name("drop")
+                    .about("drop temp tables or views")
+                    .arg(
+                        Arg::with_name("name")
+                            .help("name of the table or view")
+                            .required(true)
+                            .multiple(true),
+                    ),
+            ),
+        SubCommand::with_name("config")
+            .about("reload the config file, or override a setting until the game quits")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("reload")
+                    .about("read the config file again, dropping the overrides"),
+            )
+            .subcommand(
+                SubCommand::with_name("set")
+                    .about("override a setting, e.g. row_limit 50 or aliases.n npc")
+                    .arg(Arg::with_name("key").required(true))
+                    .arg(
+                        Arg::with_name("value")
+                            .help("TOML, e.g. 50, true or \"text\"; bare text is a string")
+                            .required(true),
+                    ),
+            ),
+        SubCommand::with_name("profile")
+            .about("switch to a profile of the config until the game quits, or list them")
+            .arg(Arg::with_name("name").help("name of the profile, e.g. author")),
+        SubCommand::with_name("lasterror")
+            .about("show the error of the last command that failed in full, with its causes"),
+        SubCommand::with_name("log")
+            .about("show the last messages of SkyrimSearchSE.log, e.g. indexing warnings")
+            .arg(
+                Arg::with_name("n")
+                    .help("number of messages to show, most recent last (default: 20)")
+                    .validator(|v| {
+                        v.parse::<usize>()
+                            .map(|_| ())
+                            .map_err(|_| "n must be a number".to_string())
+                    }),
+            )
+            .arg(
+                Arg::with_name("level")
+                    .long("level")
+                    .short("l")
+                    .help("show only the messages of this level and the more severe ones")
+                    .takes_value(true)
+                    .possible_values(&["error", "warn", "info", "debug"]),
+            ),
+        SubCommand::with_name("version")
+            .about("show the versions and the addresses of the hooks, for bug reports"),
+        SubCommand::with_name("watch")
+            .about("run queries periodically and print their results when they change")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("add")
+                    .about("watch the result of a query")
+                    .arg(
+                        Arg::with_name("sql")
+                            .help("the query, quoted as for ss raw")
+                            .required(true)
+                            .multiple(true),
+                    ),
+            )
+            .subcommand(SubCommand::with_name("list").about("list the watched queries"))
+            .subcommand(
+                SubCommand::with_name("remove")
+                    .about("stop watching a query")
+                    .arg(
+                        Arg::with_name("id")
+                            .help("id of the watch, as ss watch list shows it")
+                            .required(true)
+                            .validator(|v| {
+                                v.parse::<u32>()
+                                    .map(|_| ())
+                                    .map_err(|_| "id must be a number".to_string())
+                            }),
+                    ),
+            ),
+        SubCommand::with_name("export")
+            .about("write a copy of the database to My Games\\Skyrim Special Edition\\SKSE")
+            .arg(
+                Arg::with_name("file")
+                    .help("file name of the copy (.db may be omitted)")
+                    .required(true),
+            ),
+        SubCommand::with_name("dump")
+            .about("write a table as CSV or JSON to My Games\\Skyrim Special Edition\\SKSE\\dumps, shaped like xEdit exports")
+            .arg(
+                Arg::with_name("table")
+                    .help("table of the index, or all of them (e.g. npc, plugin, all)")
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&["csv", "json"])
+                    .default_value("csv"),
+            ),
+        SubCommand::with_name("import")
+            .about("import a CSV file in Data\\SKSE\\Plugins\\SkyrimSearchSE\\imports into a user table")
+            .arg(
+                Arg::with_name("replace")
+                    .long("replace")
+                    .help("replace the table if it exists"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .help("file name of the CSV file (.csv may be omitted)")
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("table")
+                    .help("name of the table in the user database")
+                    .required(true),
+            ),
+        SubCommand::with_name("integrity").about(
+            "check the database, and compare the index with the forms the game has loaded",
+        ),
+        SubCommand::with_name("npc")
+            .alias("npcs")
+            .about("search npc/reference")
+            .args(&exec_args())
+            .arg(
+                Arg::with_name("query")
+                    .help("search query (e.g. name, edid, form_id, ref_id)")
+                    .required(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("fuzzy")
+                    .long("fuzzy")
+                    .help("tolerate typos (results are ranked by edit distance)"),
+            ),
+        SubCommand::with_name("cell")
+            .alias("cells")
+            .about("search cell (location)")
+            .args(&exec_args())
+            .arg(
+                Arg::with_name("query")
+                    .help("search query (e.g. name, edid, form_id)")
+                    .required(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("fuzzy")
+                    .long("fuzzy")
+                    .help("tolerate typos (results are ranked by edit distance)"),
+            ),
+        SubCommand::with_name("quest")
+            .alias("quests")
+            .about("search quest")
+            .args(&exec_args())
+            .arg(
+                Arg::with_name("query")
+                    .help("search query (e.g. name, edid, form_id)")
+                    .required(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("fuzzy")
+                    .long("fuzzy")
+                    .help("tolerate typos (results are ranked by edit distance)"),
+            ),
+        SubCommand::with_name("quest_stage")
+            .alias("quest_stages")
+            .alias("qs")
+            .alias("queststage")
+            .alias("queststages")
+            .about("search quest (prints additional stage information)")
+            .args(&exec_args())
+            .arg(
+                Arg::with_name("query")
+                    .help("search query (e.g. name, edid, form_id)")
+                    .required(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("fuzzy")
+                    .long("fuzzy")
+                    .help("tolerate typos (results are ranked by edit distance)"),
+            ),
+        SubCommand::with_name("cancel").about("stop printing the output of the last command"),
+        SubCommand::with_name("disable")
+            .about("make the console work as without this plugin until `ss enable`"),
+        SubCommand::with_name("enable").about("undo `ss disable`"),
+        SubCommand::with_name("history")
+            .about("search the console lines of this and earlier sessions")
+            .arg(
+                Arg::with_name("limit")
+                    .long("limit")
+                    .short("n")
+                    .help("number of lines to show, most recent last (default: 20)")
+                    .takes_value(true)
+                    .validator(|v| {
+                        v.parse::<u32>()
+                            .map(|_| ())
+                            .map_err(|_| "limit must be a number".to_string())
+                    }),
+            )
+            .arg(
+                Arg::with_name("stats")
+                    .long("stats")
+                    .help("list the ss commands that ran instead, with their duration and rows"),
+            )
+            .arg(
+                Arg::with_name("pattern")
+                    .help("text the lines contain")
+                    .multiple(true),
+            ),
+        SubCommand::with_name("rebuild")
+            .about("open the database again if it failed to, or index changed plugins again"),
+        SubCommand::with_name("status")
+            .about("show the progress of indexing: rows per table and pending jobs"),
+        SubCommand::with_name("stats")
+            .about("show how long indexing took per table, for bug reports about slow indexing"),
+        SubCommand::with_name("bench")
+            .about("time typical searches and index operations, next to the last run of them")
+            .arg(
+                Arg::with_name("runs")
+                    .long("runs")
+                    .short("n")
+                    .help("times to run each, keeping the fastest (default: 3)")
+                    .takes_value(true)
+                    .validator(|v| match v.parse::<u32>() {
+                        Ok(runs) if runs > 0 => Ok(()),
+                        _ => Err("runs must be a positive number".to_string()),
+                    }),
+            ),
+        SubCommand::with_name("mem")
+            .about("show the memory SQLite uses, and the size of each table and index"),
+        SubCommand::with_name("explain")
+            .about("show how SQLite executes a query (EXPLAIN QUERY PLAN)")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(
+                Arg::with_name("sql")
+                    .help("SQLite SQL")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("globals")
+            .about("show the current values of globals next to those the plugins define")
+            .arg(
+                Arg::with_name("changed")
+                    .long("changed")
+                    .help("only show the globals whose value differs from the plugins"),
+            )
+            .arg(
+                Arg::with_name("pattern")
+                    .help("text the editor ids contain"),
+            ),
+        SubCommand::with_name("grep")
+            .about("full-text search over names and editor ids of all forms")
+            .args(&exec_args())
+            .arg(
+                Arg::with_name("query")
+                    .help("search query (words are matched by prefix)")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("actor")
+            .about("show the actor values, active effects and state of a loaded actor")
+            .arg(
+                Arg::with_name("ref")
+                    .help("ref_id or name of a loaded actor (e.g. sel)")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("papyrus")
+            .about("list the scripts and property values the plugins attach to a form")
+            .arg(
+                Arg::with_name("form")
+                    .help("form_id, or ref_id or name of a loaded reference (e.g. sel)")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("rel")
+            .about("show the relationship ranks of an npc, and the one with the player")
+            .arg(
+                Arg::with_name("npc")
+                    .help("form_id, name or editor_id of an npc, or a loaded reference of one")
+                    .required(true)
+                    .multiple(true),
+            ),
+        SubCommand::with_name("inv")
+            .alias("inventory")
+            .about("snapshot the inventory of a reference into the inventory table and print it")
+            .arg(
+                Arg::with_name("ref")
+                    .help("ref_id or name of a loaded reference (default: player, e.g. sel)")
+                    .multiple(true),
+            ),
+        SubCommand::with_name("bounty")
+            .about("show the crime gold of the player per crime faction"),
+        SubCommand::with_name("team")
+            .about("list the loaded teammates of the player, e.g. followers, with their packages"),
+        SubCommand::with_name("time")
+            .about("show the date and time of the game, the timescale and the days passed"),
+        SubCommand::with_name("hooks")
+            .about("show the hooks of the console by this and other plugins, in the order they run"),
+        SubCommand::with_name("loaded")
+            .about("list the attached cells with the number of references and actors in each"),
+        SubCommand::with_name("near")
+            .about("list the loaded references around the player, nearest first")
+            .arg(
+                Arg::with_name("distance")
+                    .help("maximum distance in game units (default: 1000)")
+                    .validator(|v| {
+                        v.parse::<f32>()
+                            .map(|_| ())
+                            .map_err(|_| "distance must be a number".to_string())
+                    }),
+            ),
+        SubCommand::with_name("refs")
+            .about("search the references in the loaded cells")
+            .arg(
+                Arg::with_name("query")
+                    .help("base form name, base form_id, or ref_id (lists references of the same base)")
+                    .required(true)
+                    .multiple(true),
+            ),
+    ]
+}
+
+/// `--exec`, `--select` and `--yes` of the search commands.
+fn exec_args<'a, 'b>() -> [Arg<'a, 'b>; 3] {
+    [
+        Arg::with_name("exec")
+            .long("exec")
+            .help("console command to run for each result, e.g. \"{form_id}.disable\"")
+            .takes_value(true),
+        Arg::with_name("yes")
+            .long("yes")
+            .requires("exec")
+            .help("run the --exec commands rather than only listing them"),
+        Arg::with_name("select")
+            .long("select")
+            .requires("exec")
+            .takes_value(true)
+            .help(
+                "column of the reference to select for each command, e.g. ref_id for \"disable\"",
+            ),
+    ]
+}
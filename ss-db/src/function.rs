@@ -0,0 +1,341 @@
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ValueRef;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::panic::AssertUnwindSafe;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Registers the user-defined SQL functions on `conn`.
+pub fn register(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    register_regexp(conn)?;
+    register_editdist(conn)?;
+    register_fold(conn)?;
+    register_form_id(conn)?;
+    register_hexdump(conn)?;
+    Ok(())
+}
+
+fn user_function_error<E>(err: E) -> rusqlite::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    rusqlite::Error::UserFunctionError(Box::new(err))
+}
+
+fn value_to_text(value: ValueRef) -> Option<Cow<str>> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(v) => Some(v.to_string().into()),
+        ValueRef::Real(v) => Some(v.to_string().into()),
+        ValueRef::Text(v) | ValueRef::Blob(v) => Some(String::from_utf8_lossy(v)),
+    }
+}
+
+/// `X REGEXP Y` is evaluated as `regexp(Y, X)`.
+fn register_regexp(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // a query usually matches every row against the same pattern, so keep the last one compiled.
+    let mut cache = AssertUnwindSafe(None::<(String, regex::Regex)>);
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let pattern = ctx.get::<String>(0)?;
+            let text = match value_to_text(ctx.get_raw(1)) {
+                Some(text) => text,
+                None => return Ok(None),
+            };
+            if cache
+                .as_ref()
+                .map_or(true, |(cached, _)| *cached != pattern)
+            {
+                let regex = regex::Regex::new(&pattern).map_err(user_function_error)?;
+                *cache = Some((pattern, regex));
+            }
+            let (_, regex) = cache.as_ref().unwrap();
+            Ok(Some(regex.is_match(&text)))
+        },
+    )
+}
+
+/// Levenshtein distance between `a` and `b`, counted in characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Edit distance, ignoring case and diacritics, between `query` and the closest run of as many words in
+/// `text`, so that "ulfirc" is close to "Ulfric Stormcloak".
+fn fuzzy_distance(query: &str, text: &str) -> usize {
+    let query = fold(query);
+    let query_words: Vec<&str> = query.split_whitespace().collect();
+    let query = query_words.join(" ");
+    let text = fold(text);
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    let mut distance = levenshtein(&query, &text);
+    for window in words.windows(query_words.len().max(1)) {
+        distance = distance.min(levenshtein(&query, &window.join(" ")));
+    }
+    distance
+}
+
+/// `editdist(a, b)` is the Levenshtein distance of `a` and `b`.
+/// `fuzzydist(query, text, ...)` is the smallest `fuzzy_distance` of `query` to any of the texts,
+/// ignoring NULLs.
+fn register_editdist(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "editdist",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let a = value_to_text(ctx.get_raw(0));
+            let b = value_to_text(ctx.get_raw(1));
+            Ok(match (a, b) {
+                (Some(a), Some(b)) => Some(levenshtein(&a, &b) as i64),
+                _ => None,
+            })
+        },
+    )?;
+    conn.create_scalar_function(
+        "fuzzydist",
+        -1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let query = match ctx.len() {
+                0 => return Ok(None),
+                _ => ctx.get::<String>(0)?,
+            };
+            Ok((1..ctx.len())
+                .filter_map(|i| value_to_text(ctx.get_raw(i)))
+                .map(|text| fuzzy_distance(&query, &text) as i64)
+                .min())
+        },
+    )
+}
+
+/// The largest edit distance at which `--fuzzy` searches still report a match.
+pub fn fuzzy_threshold(query: &str) -> i64 {
+    (query.chars().count() as i64 / 3).max(1)
+}
+
+/// Folds case and diacritics, so that "Éric", "ERIC" and "eric" become the same string.
+pub fn fold(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    for c in s.nfd().filter(|&c| !is_combining_mark(c)) {
+        // letters that have no canonical decomposition
+        match c {
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'æ' | 'Æ' => folded.push_str("ae"),
+            'œ' | 'Œ' => folded.push_str("oe"),
+            'ø' | 'Ø' => folded.push('o'),
+            'đ' | 'Đ' => folded.push('d'),
+            'ł' | 'Ł' => folded.push('l'),
+            c => folded.extend(c.to_lowercase()),
+        }
+    }
+    folded
+}
+
+/// Compares `a` and `b` as `fold` folds them, without folding strings that are ASCII.
+pub fn fold_cmp(a: &str, b: &str) -> Ordering {
+    fn lower(s: &str) -> impl Iterator<Item = u8> + '_ {
+        s.bytes().map(|b| b.to_ascii_lowercase())
+    }
+    if a.is_ascii() && b.is_ascii() {
+        lower(a).cmp(lower(b))
+    } else {
+        fold(a).cmp(&fold(b))
+    }
+}
+
+/// `fold(x)` folds case and diacritics of `x`, and `COLLATE fold` compares folded strings, as the
+/// names and editor ids of the index are declared.
+fn register_fold(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "fold",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(value_to_text(ctx.get_raw(0)).map(|text| fold(&text))),
+    )?;
+    conn.create_collation("fold", fold_cmp)
+}
+
+/// Load order index of light plugins, which share it and are told apart by bits 12-23.
+const LIGHT_PLUGIN_INDEX: u32 = 0xFE;
+
+fn is_light_form_id(id: u32) -> bool {
+    id >> 24 == LIGHT_PLUGIN_INDEX
+}
+
+/// The load order prefix of a runtime form id: `"05"` for a regular plugin, `"FE:001"` for a
+/// light plugin and `"FF"` for forms created in game.
+pub fn form_id_plugin(id: u32) -> String {
+    if is_light_form_id(id) {
+        format!("FE:{:03X}", (id >> 12) & 0xFFF)
+    } else {
+        format!("{:02X}", id >> 24)
+    }
+}
+
+/// The form id relative to its plugin, i.e. without the load order prefix.
+pub fn form_id_local(id: u32) -> u32 {
+    if is_light_form_id(id) {
+        id & 0xFFF
+    } else {
+        id & 0xFF_FFFF
+    }
+}
+
+/// Form ids are integers in most tables, but stringified ones are accepted, too.
+fn value_to_form_id(value: ValueRef) -> Option<u32> {
+    match value {
+        ValueRef::Integer(v) => Some(v as u32),
+        ValueRef::Text(v) => {
+            let v = std::str::from_utf8(v).ok()?.trim();
+            match v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => v.parse().ok(),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `formid_plugin(id)`, `formid_local(id)` and `formid_hex(id)` split a runtime form id into its
+/// load order prefix and the plugin-relative id.
+fn register_form_id(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("formid_plugin", 1, flags, |ctx| {
+        Ok(value_to_form_id(ctx.get_raw(0)).map(form_id_plugin))
+    })?;
+    conn.create_scalar_function("formid_local", 1, flags, |ctx| {
+        Ok(value_to_form_id(ctx.get_raw(0)).map(|id| form_id_local(id) as i64))
+    })?;
+    conn.create_scalar_function("formid_hex", 1, flags, |ctx| {
+        Ok(value_to_form_id(ctx.get_raw(0)).map(|id| format!("{:08X}", id)))
+    })
+}
+
+/// Formats `data` like `xxd`: an offset, 16 bytes in hex and the same bytes as ASCII per line.
+/// Offsets start at `offset`.
+pub fn hexdump(data: &[u8], offset: usize) -> String {
+    let mut lines = Vec::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex = (0..16)
+            .map(|j| {
+                chunk
+                    .get(j)
+                    .map_or("  ".to_string(), |b| format!("{:02x}", b))
+            })
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|pair| pair.concat())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        lines.push(format!("{:08x}: {}  {}", offset + i * 16, hex, ascii));
+    }
+    lines.join("\n")
+}
+
+/// `hexdump(blob[, offset[, len]])` dumps `len` bytes of `blob` starting at `offset`.
+fn register_hexdump(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "hexdump",
+        -1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            if ctx.is_empty() || ctx.len() > 3 {
+                return Err(rusqlite::Error::UserFunctionError(
+                    "hexdump(blob[, offset[, len]]) takes 1 to 3 arguments".into(),
+                ));
+            }
+            let data = match ctx.get_raw(0) {
+                ValueRef::Blob(v) | ValueRef::Text(v) => v,
+                _ => return Ok(None),
+            };
+            let offset = match ctx.len() {
+                1 => 0,
+                _ => ctx.get::<i64>(1)?.max(0) as usize,
+            }
+            .min(data.len());
+            let len = match ctx.len() {
+                3 => ctx.get::<i64>(2)?.max(0) as usize,
+                _ => data.len(),
+            };
+            let end = offset.saturating_add(len).min(data.len());
+            Ok(Some(hexdump(&data[offset..end], offset)))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(0, levenshtein("", ""));
+        assert_eq!(6, levenshtein("kitten", ""));
+        assert_eq!(3, levenshtein("kitten", "sitting"));
+        assert_eq!(2, levenshtein("ulfirc", "ulfric"));
+        assert_eq!(1, levenshtein("Éric", "Eric"));
+        assert_eq!(2, fuzzy_distance("Ulfirc", "Ulfric Stormcloak"));
+        assert_eq!(
+            0,
+            fuzzy_distance("jarl balgruuf", "Jarl Balgruuf the Greater")
+        );
+    }
+
+    #[test]
+    fn fold_collation_ignores_case_and_diacritics() {
+        assert_eq!(Ordering::Equal, fold_cmp("Lydia", "LYDIA"));
+        assert_eq!(Ordering::Equal, fold_cmp("Rivière", "riviere"));
+        assert_eq!(Ordering::Equal, fold_cmp("Straße", "STRASSE"));
+        assert_eq!(Ordering::Less, fold_cmp("apple", "Banana"));
+        assert_eq!(Ordering::Less, fold_cmp("Éa", "eb"));
+    }
+
+    #[test]
+    fn form_id_parts() {
+        assert_eq!("00", form_id_plugin(0x000A2C8E));
+        assert_eq!(0xA2C8E, form_id_local(0x000A2C8E));
+        assert_eq!("03", form_id_plugin(0x0300521E));
+        assert_eq!("FE:012", form_id_plugin(0xFE012803));
+        assert_eq!(0x803, form_id_local(0xFE012803));
+        assert_eq!("FF", form_id_plugin(0xFF000ABC));
+    }
+
+    #[test]
+    fn hexdump_lines() {
+        assert_eq!(
+            "00000010: 4544 4944 0500 4c79 6469 6100            EDID..Lydia.",
+            hexdump(b"EDID\x05\x00Lydia\x00", 16)
+        );
+    }
+}
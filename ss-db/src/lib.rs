@@ -0,0 +1,290 @@
+//! The parts of Skyrim Search SE that do not need the game: the subcommands of `ss`, the SQL
+//! functions, the read-only guard, and the way queries and rows are written. The plugin and
+//! `ss-cli` share them.
+
+pub mod cli;
+pub mod function;
+pub mod table;
+
+use rusqlite::ffi;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+/// The query of `ss grep`, whose `?1` is the query made by `fts_query` and `?2` the query made
+/// by `substring_query`. The forms with words that start with the words of the query come first,
+/// best first, then those whose names or editor ids contain it elsewhere, e.g. `orc` in
+/// `Borcus`. `text` is the part of the text of a book or dialogue response that matched.
+/// `localized` adds the `name_localized` column, and with `by_kind`, only the forms of the kind
+/// `?3` match, e.g. `npc`.
+pub fn grep_sql(localized: bool, by_kind: bool) -> String {
+    let name_columns = if localized {
+        "editor_id, name, name_localized"
+    } else {
+        "editor_id, name"
+    };
+    let kind = if by_kind { " AND kind = ?3" } else { "" };
+    format!(
+        "SELECT kind, form_id, {0}, text FROM (\
+         SELECT 0 AS substring, rank, kind, rowid AS form_id, {0}, \
+         snippet(form_fts, 4, '', '', '...', 12) AS text \
+         FROM form_fts WHERE form_fts MATCH ?1{1} \
+         UNION ALL \
+         SELECT 1, 0, kind, form_fts.rowid, {0}, NULL \
+         FROM ({2}) found JOIN form_fts ON form_fts.rowid = found.form_id \
+         WHERE form_fts.rowid NOT IN (SELECT rowid FROM form_fts WHERE form_fts MATCH ?1{1}){1}\
+         ) ORDER BY substring, rank",
+        name_columns,
+        kind,
+        substring_sql()
+    )
+}
+
+/// The starts of the trigrams that `form_trigram` holds of a name or editor id, which are found
+/// by the substrings of their first `MAX_TRIGRAM_OFFSET + 2` characters.
+pub const MAX_TRIGRAM_OFFSET: usize = 256;
+
+/// `form_trigram` has the trigrams of the editor ids and names of `form_fts`, folded by
+/// `function::fold`, and `trigram_offset` the starts of the trigrams of a string.
+pub fn trigram_schema() -> String {
+    format!(
+        "CREATE TABLE form_trigram (\
+         trigram TEXT NOT NULL, \
+         form_id INTEGER NOT NULL, \
+         PRIMARY KEY (trigram, form_id)\
+         ) WITHOUT ROWID; \
+         CREATE INDEX form_trigram_form_id ON form_trigram (form_id); \
+         CREATE TABLE trigram_offset (i INTEGER PRIMARY KEY); \
+         WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {}) \
+         INSERT INTO trigram_offset SELECT i FROM n;",
+        MAX_TRIGRAM_OFFSET
+    )
+}
+
+/// Inserts the trigrams of the row of `form_fts` whose rowid is `form_id` into `form_trigram`,
+/// where `form_id` is an SQL expression, e.g. `new.form_id` in a trigger.
+pub fn insert_trigrams_sql(form_id: &str) -> String {
+    format!(
+        "INSERT OR IGNORE INTO form_trigram (trigram, form_id) \
+         SELECT substr(value, i, 3), form_id FROM (\
+         SELECT rowid AS form_id, fold(editor_id) AS value FROM form_fts WHERE rowid = {0} \
+         UNION ALL SELECT rowid, fold(name) FROM form_fts WHERE rowid = {0} \
+         UNION ALL SELECT rowid, fold(name_localized) FROM form_fts WHERE rowid = {0}\
+         ) JOIN trigram_offset ON i <= length(value) - 2",
+        form_id
+    )
+}
+
+/// The form ids of `form_fts` whose editor ids or names contain `?2`, the query made by
+/// `substring_query`. They are found by their trigrams in `form_trigram`, then checked, so a
+/// query of fewer than three characters finds none.
+pub fn substring_sql() -> &'static str {
+    "SELECT form_fts.rowid AS form_id FROM (\
+     SELECT form_id FROM form_trigram \
+     WHERE trigram IN (SELECT substr(?2, i, 3) FROM trigram_offset WHERE i <= length(?2) - 2) \
+     GROUP BY form_id HAVING count(*) = \
+     (SELECT count(DISTINCT substr(?2, i, 3)) FROM trigram_offset WHERE i <= length(?2) - 2)\
+     ) trigram JOIN form_fts ON form_fts.rowid = trigram.form_id \
+     WHERE instr(fold(editor_id), ?2) OR instr(fold(name), ?2) OR instr(fold(name_localized), ?2)"
+}
+
+/// Splits an SQL script into its statements, each ending with `;` unless it is the last one.
+/// A `;` inside a string literal, comment or trigger body does not end a statement.
+pub fn split_statements(script: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for (end, _) in script.match_indices(';') {
+        let statement = &script[start..=end];
+        let complete = match CString::new(statement) {
+            Ok(statement) => unsafe { ffi::sqlite3_complete(statement.as_ptr()) != 0 },
+            Err(_) => false,
+        };
+        if complete {
+            statements.push(statement.trim());
+            start = end + 1;
+        }
+    }
+    statements.push(script[start..].trim());
+    statements.retain(|statement| !statement.is_empty() && *statement != ";");
+    statements
+}
+
+/// Converts a search query into an FTS5 query that matches rows containing every word of
+/// `query` as a prefix of a token.
+pub fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Folds a search query as the trigrams of `form_trigram` are, for `substring_sql`.
+pub fn substring_query(query: &str) -> String {
+    function::fold(query.trim())
+}
+
+/// Pragmas that only read the schema even when they take an argument.
+const INFO_PRAGMAS: [&str; 7] = [
+    "table_info",
+    "table_xinfo",
+    "table_list",
+    "index_list",
+    "index_info",
+    "index_xinfo",
+    "foreign_key_list",
+];
+
+/// Makes statements prepared on the connection fail with `AuthorizationForStatementDenied`
+/// if they would modify the database, until the guard is dropped. Tables, views and indexes of
+/// the temp database may still be created and written, for scratch tables, but not triggers,
+/// which could write to the other databases as they fire once the guard is gone.
+pub struct ReadOnlyGuard<'conn>(&'conn rusqlite::Connection);
+
+impl<'conn> ReadOnlyGuard<'conn> {
+    pub fn new(conn: &'conn rusqlite::Connection) -> Self {
+        unsafe {
+            ffi::sqlite3_set_authorizer(conn.handle(), Some(authorize_read), std::ptr::null_mut());
+        }
+        ReadOnlyGuard(conn)
+    }
+}
+
+impl Drop for ReadOnlyGuard<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_set_authorizer(self.0.handle(), None, std::ptr::null_mut());
+        }
+    }
+}
+
+unsafe extern "C" fn authorize_read(
+    _user_data: *mut c_void,
+    action: c_int,
+    arg1: *const c_char,
+    arg2: *const c_char,
+    db_name: *const c_char,
+    _trigger: *const c_char,
+) -> c_int {
+    match action {
+        ffi::SQLITE_SELECT
+        | ffi::SQLITE_READ
+        | ffi::SQLITE_FUNCTION
+        | ffi::SQLITE_RECURSIVE
+        | ffi::SQLITE_TRANSACTION
+        | ffi::SQLITE_SAVEPOINT => ffi::SQLITE_OK,
+        // `PRAGMA x` only reads, but `PRAGMA x = y` may write
+        ffi::SQLITE_PRAGMA if arg2.is_null() || is_info_pragma(CStr::from_ptr(arg1)) => {
+            ffi::SQLITE_OK
+        }
+        ffi::SQLITE_CREATE_TABLE
+        | ffi::SQLITE_CREATE_TEMP_TABLE
+        | ffi::SQLITE_CREATE_VIEW
+        | ffi::SQLITE_CREATE_TEMP_VIEW
+        | ffi::SQLITE_CREATE_INDEX
+        | ffi::SQLITE_CREATE_TEMP_INDEX
+        | ffi::SQLITE_DROP_TABLE
+        | ffi::SQLITE_DROP_TEMP_TABLE
+        | ffi::SQLITE_DROP_VIEW
+        | ffi::SQLITE_DROP_TEMP_VIEW
+        | ffi::SQLITE_DROP_INDEX
+        | ffi::SQLITE_DROP_TEMP_INDEX
+        | ffi::SQLITE_DROP_TEMP_TRIGGER
+        | ffi::SQLITE_REINDEX
+        | ffi::SQLITE_INSERT
+        | ffi::SQLITE_UPDATE
+        | ffi::SQLITE_DELETE
+            if is_temp(db_name) =>
+        {
+            ffi::SQLITE_OK
+        }
+        // the database is the first argument here
+        ffi::SQLITE_ALTER_TABLE if is_temp(arg1) => ffi::SQLITE_OK,
+        _ => ffi::SQLITE_DENY,
+    }
+}
+
+unsafe fn is_temp(db_name: *const c_char) -> bool {
+    !db_name.is_null() && CStr::from_ptr(db_name).to_bytes() == b"temp"
+}
+
+fn is_info_pragma(pragma: &CStr) -> bool {
+    let pragma = pragma.to_string_lossy();
+    INFO_PRAGMAS
+        .iter()
+        .any(|info| info.eq_ignore_ascii_case(&pragma))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::NO_PARAMS;
+
+    #[test]
+    fn read_only_guard_allows_only_scratch_tables() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE npc (name); CREATE TABLE log (line);")
+            .unwrap();
+        let guard = ReadOnlyGuard::new(&conn);
+        conn.execute_batch(
+            "CREATE TEMP TABLE scratch AS SELECT * FROM npc; INSERT INTO scratch VALUES ('a'); \
+             CREATE INDEX temp.scratch_name ON scratch (name); \
+             CREATE TEMP VIEW names AS SELECT name FROM npc; DROP VIEW names;",
+        )
+        .unwrap();
+        // a trigger would write to main once the guard is dropped
+        for sql in [
+            "CREATE TEMP TRIGGER bypass AFTER INSERT ON main.npc \
+             BEGIN INSERT INTO main.log VALUES ('x'); END;",
+            "CREATE TEMP TRIGGER bypass AFTER INSERT ON scratch \
+             BEGIN DELETE FROM main.npc; END;",
+            "CREATE TRIGGER temp.bypass AFTER INSERT ON main.npc BEGIN DELETE FROM main.npc; END;",
+            "INSERT INTO npc VALUES ('b');",
+            "CREATE TABLE main.other (x);",
+        ] {
+            assert!(conn.execute_batch(sql).is_err(), "{}", sql);
+        }
+        drop(guard);
+        conn.execute_batch("INSERT INTO npc VALUES ('c');").unwrap();
+        let logged: i64 = conn
+            .query_row("SELECT count(*) FROM log", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(logged, 0);
+    }
+
+    #[test]
+    fn grep_finds_prefixes_first_then_substrings() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        function::register(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE form_fts USING fts5 \
+             (kind UNINDEXED, editor_id, name, name_localized, text); \
+             INSERT INTO form_fts (rowid, kind, editor_id, name) VALUES \
+             (1, 'npc', 'Borcus', 'Borcus'), (2, 'npc', 'OrcStrongholdChief', 'Orc Chief'), \
+             (3, 'cell', 'Dushnikh', 'Dushnikh Yal'); \
+             INSERT INTO form_fts (rowid, kind, editor_id, text) VALUES \
+             (4, 'book', 'BookOrcs', 'The orcs of Wrothgar');",
+        )
+        .unwrap();
+        conn.execute_batch(&trigram_schema()).unwrap();
+        for form_id in 1..=4 {
+            conn.execute(&insert_trigrams_sql("?1"), &[form_id])
+                .unwrap();
+        }
+        let grep = |by_kind: bool| {
+            let sql = grep_sql(false, by_kind);
+            let mut stmt = conn.prepare(&sql).unwrap();
+            let mut values = vec![fts_query("orc"), substring_query("orc")];
+            if by_kind {
+                values.push("npc".to_string());
+            }
+            stmt.query_map(&values, |row| row.get::<_, i64>(1))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .unwrap()
+        };
+        let all = grep(false);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all.last(), Some(&1));
+        assert_eq!(grep(true), vec![2, 1]);
+    }
+}
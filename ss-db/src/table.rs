@@ -0,0 +1,82 @@
+use rusqlite::types::ValueRef;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HEX_IDS: AtomicBool = AtomicBool::new(true);
+
+/// Whether `repr_column` prints the id columns in hexadecimal, as it does by default.
+pub fn hex_ids() -> bool {
+    HEX_IDS.load(Ordering::Relaxed)
+}
+
+pub fn set_hex_ids(hex_ids: bool) {
+    HEX_IDS.store(hex_ids, Ordering::Relaxed);
+}
+
+/// The rows as a table, each converted by `f`, with the column names as titles.
+pub fn rows_table<F>(mut rows: rusqlite::Rows, f: F) -> anyhow::Result<prettytable::Table>
+where
+    F: Fn(&rusqlite::Row) -> prettytable::Row,
+{
+    if rows.column_count().is_none() {
+        anyhow::bail!("no data");
+    }
+
+    let mut ptable = prettytable::Table::new();
+    set_titles(&mut rows, &mut ptable);
+    loop {
+        let row = match rows.next().map_err(anyhow::Error::new) {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            Err(err) => anyhow::bail!(err.context("rows.next() error")),
+        };
+        ptable.add_row(f(row));
+    }
+    Ok(ptable)
+}
+
+pub fn convert_row(row: &rusqlite::Row) -> prettytable::Row {
+    let column_count = row.column_count();
+    let mut cells = Vec::with_capacity(column_count);
+    for i in 0..column_count {
+        let column = row.get_raw(i);
+        let repr = repr_column(row.column_name(i).ok(), column);
+        cells.push(prettytable::Cell::new(repr.as_str()));
+    }
+    prettytable::Row::new(cells)
+}
+
+pub fn repr_column(name: Option<&str>, column: ValueRef) -> String {
+    match column {
+        ValueRef::Null => String::from("<null>"),
+        ValueRef::Integer(v) => match name {
+            Some(name) if name.contains("id") && hex_ids() => format!("{:08X}", v),
+            _ => v.to_string(),
+        },
+        ValueRef::Real(v) => v.to_string(),
+        ValueRef::Text(v) => String::from_utf8_lossy(v).to_string(),
+        ValueRef::Blob(v) => format!("<{}-byte blob>", v.len()),
+    }
+}
+
+/// An empty table formatted as `rows_table` formats them, with `titles` unless there are none,
+/// e.g. for the pages after the first one of a long result.
+pub fn titled_table(titles: &[String]) -> prettytable::Table {
+    let mut table = prettytable::Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    if !titles.is_empty() {
+        table.set_titles(
+            titles
+                .iter()
+                .map(|title| prettytable::Cell::new(title))
+                .collect(),
+        );
+    }
+    table
+}
+
+fn set_titles(rows: &mut rusqlite::Rows, table: &mut prettytable::Table) -> Option<()> {
+    let names = rows.column_names()?;
+    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(names.into_iter().map(prettytable::Cell::new).collect());
+    Some(())
+}